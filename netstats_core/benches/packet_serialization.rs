@@ -0,0 +1,44 @@
+// Encode/decode cost of CustomPacket for the two wire encodings (TestConfig::bincode_fixint):
+// varint bincode, and the fixed-width layout where every header field has a constant size.
+// `cargo bench` for measurements; `cargo test --benches` runs each case once as a smoke test.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use netstats_core::packet::CustomPacket;
+
+const PAYLOAD_SIZES: [usize; 4] = [0, 64, 1024, 8192];
+
+fn encodings() -> [(&'static str, bool); 2] {
+    [("bincode_varint", false), ("fixed_binary", true)]
+}
+
+fn bench_to_bytes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("to_bytes");
+    for size in PAYLOAD_SIZES {
+        let packet = CustomPacket::new_echo_request(u32::MAX / 2, size);
+        group.throughput(Throughput::Bytes(size as u64));
+        for (name, fixint) in encodings() {
+            group.bench_with_input(BenchmarkId::new(name, size), &packet, |b, packet| {
+                b.iter(|| black_box(packet).to_bytes_with(fixint).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_from_bytes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("from_bytes");
+    for size in PAYLOAD_SIZES {
+        let packet = CustomPacket::new_echo_request(u32::MAX / 2, size);
+        group.throughput(Throughput::Bytes(size as u64));
+        for (name, fixint) in encodings() {
+            let bytes = packet.to_bytes_with(fixint).unwrap();
+            group.bench_with_input(BenchmarkId::new(name, size), &bytes, |b, bytes| {
+                b.iter(|| CustomPacket::from_bytes_with(black_box(bytes), fixint).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_to_bytes, bench_from_bytes);
+criterion_main!(benches);
@@ -1,29 +1,491 @@
 // Logic for detecting defined network anomalies
 
+use crate::metrics::TestMetrics;
+use std::collections::VecDeque;
+
+// Width of the rolling RTT average used for sustained latency detection
+const SUSTAINED_LATENCY_WINDOW_MS: u128 = 1000;
+// Out-of-order or duplicate packets less than BURST_GAP_MS apart belong to one burst,
+// reported once it has at least BURST_MIN_PACKETS packets
+const BURST_GAP_MS: u128 = 1000;
+const BURST_MIN_PACKETS: usize = 5;
+// Consecutive bandwidth sample intervals over the loss threshold that make a sustained loss window
+const SUSTAINED_LOSS_MIN_INTERVALS: usize = 2;
+
 // Example structure for an anomaly event
-#[derive(Debug, Clone, serde::Serialize)] // Added Clone and Serialize
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)] // Added Clone and Serialize
 pub enum AnomalyType {
     PacketLoss,
     OutOfOrder,
     DuplicatePacket,
     HighLatencySpike,
+    SustainedLatency,
     JitterSpike,
     // TCP specific
     SynTimeout,
     ConnectionReset,
     ExcessiveRetransmissions,
+    FramingError, // TCP length prefix out of bounds; the connection is closed
+    Corrupted,    // Echo reply payload differs from what was sent (return path)
+    CorruptPayload, // Received payload doesn't match its header checksum
+    NatDetected,    // UDP bidirectional peer's source port was remapped on the way
+    ForeignSession, // Packets of another test run arrived and were dropped
 }
 
-#[derive(Debug, Clone, serde::Serialize)] // Added Clone and Serialize
+impl AnomalyType {
+    /// Every anomaly type, e.g. for rendering a legend or filter in a UI.
+    pub fn all() -> &'static [AnomalyType] {
+        &[
+            AnomalyType::PacketLoss,
+            AnomalyType::OutOfOrder,
+            AnomalyType::DuplicatePacket,
+            AnomalyType::HighLatencySpike,
+            AnomalyType::SustainedLatency,
+            AnomalyType::JitterSpike,
+            AnomalyType::SynTimeout,
+            AnomalyType::ConnectionReset,
+            AnomalyType::ExcessiveRetransmissions,
+            AnomalyType::FramingError,
+            AnomalyType::Corrupted,
+            AnomalyType::CorruptPayload,
+            AnomalyType::NatDetected,
+            AnomalyType::ForeignSession,
+        ]
+    }
+
+    /// Short human-readable name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnomalyType::PacketLoss => "Packet Loss",
+            AnomalyType::OutOfOrder => "Out of Order",
+            AnomalyType::DuplicatePacket => "Duplicate Packet",
+            AnomalyType::HighLatencySpike => "High Latency Spike",
+            AnomalyType::SustainedLatency => "Sustained High Latency",
+            AnomalyType::JitterSpike => "Jitter Spike",
+            AnomalyType::SynTimeout => "SYN Timeout",
+            AnomalyType::ConnectionReset => "Connection Reset",
+            AnomalyType::ExcessiveRetransmissions => "Excessive Retransmissions",
+            AnomalyType::FramingError => "Framing Error",
+            AnomalyType::Corrupted => "Corrupted Payload",
+            AnomalyType::CorruptPayload => "Checksum Mismatch",
+            AnomalyType::NatDetected => "NAT Detected",
+            AnomalyType::ForeignSession => "Foreign Session",
+        }
+    }
+
+    /// One-sentence explanation of what this anomaly means.
+    pub fn describe(&self) -> &'static str {
+        match self {
+            AnomalyType::PacketLoss => "Packets were sent but never arrived, or loss exceeded the configured threshold.",
+            AnomalyType::OutOfOrder => "A packet arrived after one with a higher sequence number.",
+            AnomalyType::DuplicatePacket => "The same sequence number was received more than once.",
+            AnomalyType::HighLatencySpike => "A round-trip time exceeded the latency spike threshold.",
+            AnomalyType::SustainedLatency => "The rolling average RTT stayed well above the minimum RTT for a long stretch (e.g. bufferbloat).",
+            AnomalyType::JitterSpike => "The change between successive RTTs exceeded the jitter spike threshold.",
+            AnomalyType::SynTimeout => "A TCP connection attempt got no SYN-ACK in time.",
+            AnomalyType::ConnectionReset => "The peer or a middlebox reset the connection.",
+            AnomalyType::ExcessiveRetransmissions => "TCP had to retransmit an unusually large share of segments.",
+            AnomalyType::FramingError => "A TCP length prefix was outside the valid packet size range, e.g. peers disagree on its byte order.",
+            AnomalyType::Corrupted => "An echo reply came back with a payload different from the request's (needs verify_echo_payload).",
+            AnomalyType::CorruptPayload => "A received payload didn't match its CRC32 checksum: it was altered in transit yet passed the UDP/TCP checksum.",
+            AnomalyType::NatDetected => "The peer's packets arrive from a different source port than it sends from: a NAT remaps it, so traffic to its advertised port may not reach it.",
+            AnomalyType::ForeignSession => "Packets of another test run arrived, e.g. stragglers of an earlier test on the same port. They were dropped, not counted.",
+        }
+    }
+}
+
+impl std::fmt::Display for AnomalyType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)] // Added Clone and Serialize
 pub struct AnomalyEvent {
     pub timestamp_ms: u128, // When the anomaly was detected or occurred
     pub anomaly_type: AnomalyType,
     pub description: String, // More details, e.g., sequence numbers involved
+    // For threshold-based anomalies: the configured limit that was crossed
+    #[serde(default)]
+    pub threshold: Option<AnomalyThreshold>,
+}
+
+impl AnomalyEvent {
+    /// Seconds since the test started, as shown in the report's anomaly list.
+    pub fn timestamp_secs(&self) -> f64 {
+        self.timestamp_ms as f64 / 1000.0
+    }
+
+    /// The description, followed by the threshold that fired it if any, e.g.
+    /// "RTT: 250.00 ms (250 exceeded latency_spike_threshold_ms=200)".
+    pub fn explanation(&self) -> String {
+        match &self.threshold {
+            Some(threshold) => format!("{} ({})", self.description, threshold),
+            None => self.description.clone(),
+        }
+    }
+}
+
+/// A configured threshold and the value that crossed it, both in the config field's unit.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AnomalyThreshold {
+    pub config_field: String, // TestConfig field the limit comes from
+    pub limit: f64,
+    pub observed: f64,
 }
 
-pub fn detect_anomalies() -> Vec<AnomalyEvent> {
-    // This function will analyze a stream of packet data or events
-    // and identify anomalies.
-    // For now, it's a placeholder.
-    Vec::new()
+impl AnomalyThreshold {
+    pub fn new(config_field: &str, limit: f64, observed: f64) -> Self {
+        AnomalyThreshold { config_field: config_field.to_string(), limit, observed }
+    }
+}
+
+impl std::fmt::Display for AnomalyThreshold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Rounded like the descriptions; the limit is shown as configured
+        write!(f, "{} exceeded {}={}", (self.observed * 100.0).round() / 100.0, self.config_field, self.limit)
+    }
+}
+
+/// Anomalies that only show up across many samples, derived from the finished test's metrics:
+/// sustained latency, bursts of out-of-order or duplicate packets, and windows of sustained
+/// loss. Per-packet anomalies (spikes, each reordered or duplicate packet) are recorded
+/// inline while the test runs, so they also reach the live feed.
+pub fn detect_anomalies(metrics: &TestMetrics) -> Vec<AnomalyEvent> {
+    let mut anomalies = Vec::new();
+    if let Some((multiplier, min_duration_ms)) = metrics.sustained_latency {
+        anomalies.extend(detect_sustained_latency(metrics, multiplier, min_duration_ms));
+    }
+    anomalies.extend(detect_bursts(&metrics.out_of_order_arrivals_ms, AnomalyType::OutOfOrder, "arrived out of order"));
+    anomalies.extend(detect_bursts(&metrics.duplicate_arrivals_ms, AnomalyType::DuplicatePacket, "were duplicated"));
+    if let Some(threshold_percent) = metrics.loss_window_threshold_percent {
+        anomalies.extend(detect_loss_windows(metrics, threshold_percent));
+    }
+    anomalies
+}
+
+/// One event per burst of `arrivals_ms` (see BURST_GAP_MS and BURST_MIN_PACKETS).
+fn detect_bursts(arrivals_ms: &[u128], anomaly_type: AnomalyType, what_happened: &str) -> Vec<AnomalyEvent> {
+    burst_spans(arrivals_ms)
+        .into_iter()
+        .map(|(start_ms, end_ms, packets)| AnomalyEvent {
+            timestamp_ms: start_ms,
+            anomaly_type,
+            description: format!(
+                "Burst: {} packets {} within {:.2} s",
+                packets, what_happened, (end_ms - start_ms) as f64 / 1000.0
+            ),
+            threshold: None,
+        })
+        .collect()
+}
+
+/// (first ms, last ms, packets) of every burst in `arrivals_ms`.
+fn burst_spans(arrivals_ms: &[u128]) -> Vec<(u128, u128, usize)> {
+    let mut sorted = arrivals_ms.to_vec(); // Parse workers can record slightly out of time order
+    sorted.sort_unstable();
+    let mut spans = Vec::new();
+    let mut burst_start = 0;
+    for i in 1..=sorted.len() {
+        if i < sorted.len() && sorted[i] - sorted[i - 1] < BURST_GAP_MS {
+            continue;
+        }
+        let burst = &sorted[burst_start..i];
+        if burst.len() >= BURST_MIN_PACKETS {
+            spans.push((burst[0], burst[burst.len() - 1], burst.len()));
+        }
+        burst_start = i;
+    }
+    spans
+}
+
+/// Adds `detect_anomalies` to the anomalies recorded during the test, reporting each packet
+/// once: the per-packet OutOfOrder and DuplicatePacket events inside a burst give way to the
+/// burst's event, and `overall_loss` (the whole-test loss event, if over the threshold) is
+/// only added when no sustained loss window already reports that loss.
+pub fn merge_detected_anomalies(metrics: &mut TestMetrics, overall_loss: Option<AnomalyEvent>) {
+    let detected = detect_anomalies(metrics);
+    for (arrivals_ms, anomaly_type) in [
+        (&metrics.out_of_order_arrivals_ms, AnomalyType::OutOfOrder),
+        (&metrics.duplicate_arrivals_ms, AnomalyType::DuplicatePacket),
+    ] {
+        let spans = burst_spans(arrivals_ms);
+        metrics.anomalies.retain(|anomaly| {
+            anomaly.anomaly_type != anomaly_type
+                || !spans.iter().any(|&(start_ms, end_ms, _)| (start_ms..=end_ms).contains(&anomaly.timestamp_ms))
+        });
+    }
+    let has_loss_window = detected.iter().any(|anomaly| anomaly.anomaly_type == AnomalyType::PacketLoss);
+    for anomaly in overall_loss.filter(|_| !has_loss_window).into_iter().chain(detected) {
+        metrics.record_anomaly(anomaly);
+    }
+}
+
+/// One PacketLoss event per run of at least SUSTAINED_LOSS_MIN_INTERVALS bandwidth sample
+/// intervals whose sequence-gap loss is at or above `threshold_percent`.
+fn detect_loss_windows(metrics: &TestMetrics, threshold_percent: f64) -> Vec<AnomalyEvent> {
+    let mut anomalies = Vec::new();
+    let close_window = |window: Option<(u128, u128, u64, u64, usize)>, anomalies: &mut Vec<AnomalyEvent>| {
+        if let Some((start_ms, end_ms, expected, lost, intervals)) = window {
+            if intervals >= SUSTAINED_LOSS_MIN_INTERVALS {
+                anomalies.push(AnomalyEvent {
+                    timestamp_ms: start_ms,
+                    anomaly_type: AnomalyType::PacketLoss,
+                    description: format!(
+                        "Sustained loss: {:.1}% of packets lost from {:.1} s to {:.1} s",
+                        lost as f64 * 100.0 / expected as f64, start_ms as f64 / 1000.0, end_ms as f64 / 1000.0
+                    ),
+                    threshold: Some(AnomalyThreshold::new(
+                        "packet_loss_threshold_percent",
+                        threshold_percent,
+                        lost as f64 * 100.0 / expected as f64,
+                    )),
+                });
+            }
+        }
+    };
+
+    let mut window: Option<(u128, u128, u64, u64, usize)> = None; // (start ms, end ms, expected, lost, intervals)
+    let (mut previous_end_ms, mut previous_expected) = (0, 0);
+    for (&(end_ms, _, received), &expected_so_far) in metrics.bandwidth_samples.iter().zip(&metrics.expected_packets_at_sample) {
+        let expected = expected_so_far.saturating_sub(previous_expected);
+        let lost = expected.saturating_sub(received);
+        let start_ms = previous_end_ms;
+        (previous_end_ms, previous_expected) = (end_ms, previous_expected.max(expected_so_far));
+
+        if expected > 0 && lost as f64 * 100.0 / expected as f64 >= threshold_percent {
+            window = Some(match window {
+                Some((start_ms, _, window_expected, window_lost, intervals)) => {
+                    (start_ms, end_ms, window_expected + expected, window_lost + lost, intervals + 1)
+                }
+                None => (start_ms, end_ms, expected, lost, 1),
+            });
+        } else {
+            close_window(window.take(), &mut anomalies);
+        }
+    }
+    close_window(window, &mut anomalies);
+    anomalies
+}
+
+/// One event per stretch where the rolling average RTT (over SUSTAINED_LATENCY_WINDOW_MS)
+/// exceeds `multiplier` x the minimum RTT for at least `min_duration_ms`. Individual samples
+/// never need to reach the spike threshold, so a slowly filling buffer is caught too.
+fn detect_sustained_latency(metrics: &TestMetrics, multiplier: f64, min_duration_ms: u128) -> Vec<AnomalyEvent> {
+    let mut anomalies = Vec::new();
+    let Some(min_rtt) = metrics.min_rtt_micros else {
+        return anomalies;
+    };
+    let threshold_micros = min_rtt as f64 * multiplier;
+
+    let mut window: VecDeque<(u128, u128)> = VecDeque::new(); // (time ms, rtt micros)
+    let mut window_sum: u128 = 0;
+    let mut episode: Option<(u128, u128, f64)> = None; // (start ms, last ms, peak rolling avg)
+
+    let close_episode = |episode: Option<(u128, u128, f64)>, anomalies: &mut Vec<AnomalyEvent>| {
+        if let Some((start_ms, end_ms, peak_avg)) = episode {
+            if end_ms - start_ms >= min_duration_ms {
+                anomalies.push(AnomalyEvent {
+                    timestamp_ms: start_ms,
+                    anomaly_type: AnomalyType::SustainedLatency,
+                    description: format!(
+                        "Rolling avg RTT elevated for {:.1} s, peak {:.2} ms (min RTT {:.2} ms)",
+                        (end_ms - start_ms) as f64 / 1000.0, peak_avg / 1000.0, min_rtt as f64 / 1000.0
+                    ),
+                    threshold: Some(AnomalyThreshold::new("sustained_latency_multiplier", multiplier, peak_avg / min_rtt as f64)),
+                });
+            }
+        }
+    };
+
+    // A full latency sample reservoir is out of time order
+    let mut samples: Vec<(u128, u128)> = metrics.rtt_sample_times_ms.iter().copied().zip(metrics.rtt_samples.iter().copied()).collect();
+    samples.sort_by_key(|&(time_ms, _)| time_ms);
+    for (time_ms, rtt) in samples {
+        window.push_back((time_ms, rtt));
+        window_sum += rtt;
+        while let Some(&(oldest_ms, oldest_rtt)) = window.front() {
+            if time_ms - oldest_ms < SUSTAINED_LATENCY_WINDOW_MS {
+                break;
+            }
+            window.pop_front();
+            window_sum -= oldest_rtt;
+        }
+        let rolling_avg = window_sum as f64 / window.len() as f64;
+
+        if rolling_avg > threshold_micros {
+            episode = Some(match episode {
+                Some((start_ms, _, peak)) => (start_ms, time_ms, peak.max(rolling_avg)),
+                None => (time_ms, time_ms, rolling_avg),
+            });
+        } else {
+            close_episode(episode.take(), &mut anomalies);
+        }
+    }
+    close_episode(episode, &mut anomalies);
+    anomalies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_anomaly_types_are_listed_and_described() {
+        let all = AnomalyType::all();
+        // Exhaustive match: adding a variant without listing it in all() fails to compile here
+        for anomaly_type in all {
+            match anomaly_type {
+                AnomalyType::PacketLoss
+                | AnomalyType::OutOfOrder
+                | AnomalyType::DuplicatePacket
+                | AnomalyType::HighLatencySpike
+                | AnomalyType::SustainedLatency
+                | AnomalyType::JitterSpike
+                | AnomalyType::SynTimeout
+                | AnomalyType::ConnectionReset
+                | AnomalyType::ExcessiveRetransmissions
+                | AnomalyType::FramingError
+                | AnomalyType::Corrupted
+                | AnomalyType::CorruptPayload
+                | AnomalyType::NatDetected
+                | AnomalyType::ForeignSession => {}
+            }
+            assert!(!anomaly_type.describe().is_empty(), "{:?} has no description", anomaly_type);
+            assert!(!anomaly_type.to_string().is_empty());
+        }
+        assert_eq!(all.len(), 14);
+
+        let unique: std::collections::HashSet<_> = all.iter().collect();
+        assert_eq!(unique.len(), all.len(), "all() must not repeat variants");
+        assert_eq!(AnomalyType::HighLatencySpike.to_string(), "High Latency Spike");
+    }
+
+    /// Synthetic RTT timeline: one sample every 50 ms, RTT given by `rtt_ms_at(time_ms)`.
+    fn metrics_with_rtts(duration_ms: u128, rtt_ms_at: impl Fn(u128) -> f64) -> TestMetrics {
+        let mut metrics = TestMetrics::new();
+        metrics.configure_anomaly_detection(&crate::config::TestConfig::default());
+        for time_ms in (0..duration_ms).step_by(50) {
+            let rtt = (rtt_ms_at(time_ms) * 1000.0) as u128;
+            metrics.rtt_samples.push(rtt);
+            metrics.rtt_sample_times_ms.push(time_ms);
+            metrics.min_rtt_micros = Some(metrics.min_rtt_micros.map_or(rtt, |min| min.min(rtt)));
+        }
+        metrics
+    }
+
+    #[test]
+    fn test_sustained_latency_detected_without_spikes() {
+        // Bufferbloat: RTT climbs from 10 ms to 110 ms over 10 s, far below the 200 ms spike threshold
+        let metrics = metrics_with_rtts(10_000, |t| 10.0 + t as f64 / 100.0);
+        assert!(metrics.rtt_samples.iter().all(|&rtt| rtt < 200_000));
+
+        let anomalies = detect_anomalies(&metrics);
+        assert_eq!(anomalies.len(), 1, "{:?}", anomalies);
+        assert_eq!(anomalies[0].anomaly_type, AnomalyType::SustainedLatency);
+        // Rolling average crosses 30 ms (3x min) a bit after the RTT itself does at 2 s
+        assert!((2_000..3_000).contains(&anomalies[0].timestamp_ms), "{:?}", anomalies[0]);
+    }
+
+    #[test]
+    fn test_out_of_order_and_duplicate_bursts() {
+        let mut metrics = TestMetrics::new();
+        metrics.out_of_order_arrivals_ms = vec![100, 120, 150, 170, 200, 5_000];
+        metrics.duplicate_arrivals_ms = vec![300, 310, 320, 330]; // One short of a burst
+
+        let anomalies = detect_anomalies(&metrics);
+        assert_eq!(anomalies.len(), 1, "{:?}", anomalies);
+        assert_eq!(anomalies[0].anomaly_type, AnomalyType::OutOfOrder);
+        assert_eq!(anomalies[0].timestamp_ms, 100);
+        assert!(anomalies[0].description.contains("5 packets"), "{}", anomalies[0].description);
+
+        metrics.duplicate_arrivals_ms.push(900);
+        let duplicates: Vec<_> = detect_anomalies(&metrics)
+            .into_iter()
+            .filter(|anomaly| anomaly.anomaly_type == AnomalyType::DuplicatePacket)
+            .collect();
+        assert_eq!(duplicates.len(), 1, "{:?}", duplicates);
+        assert!(duplicates[0].description.contains("5 packets were duplicated"), "{}", duplicates[0].description);
+    }
+
+    #[test]
+    fn test_each_reordered_or_lost_packet_is_reported_once() {
+        let mut metrics = TestMetrics::new();
+        metrics.configure_anomaly_detection(&crate::config::TestConfig::default());
+        metrics.record_sequence(100, 0);
+        for (i, seq) in (0..6).enumerate() {
+            metrics.record_sequence(seq, 100 + i as u128 * 20); // A burst of 6, 100-200 ms
+        }
+        metrics.record_sequence(200, 3_000);
+        metrics.record_sequence(150, 3_010); // On its own
+        assert_eq!(metrics.anomalies.len(), 7);
+
+        let overall_loss = AnomalyEvent { timestamp_ms: 5_000, anomaly_type: AnomalyType::PacketLoss, description: "Overall".into(), threshold: None };
+        merge_detected_anomalies(&mut metrics, Some(overall_loss.clone()));
+        let descriptions: Vec<_> = metrics.anomalies.iter().map(|anomaly| anomaly.description.as_str()).collect();
+        assert_eq!(descriptions, ["UDP Packet Seq: 150 received after 200", "Overall", "Burst: 6 packets arrived out of order within 0.10 s"]);
+
+        // Loss already reported by a sustained loss window isn't reported again for the whole test
+        let mut lossy = TestMetrics::new();
+        lossy.configure_anomaly_detection(&crate::config::TestConfig::default());
+        for interval in 0..3u32 {
+            for seq in (interval * 100..(interval + 1) * 100).filter(|seq| seq % 2 == 0) {
+                lossy.record_sequence(seq, interval as u128 * 1000);
+            }
+            lossy.take_bandwidth_sample((interval as u128 + 1) * 1000);
+        }
+        merge_detected_anomalies(&mut lossy, Some(overall_loss));
+        assert_eq!(lossy.anomalies.len(), 1, "{:?}", lossy.anomalies);
+        assert!(lossy.anomalies[0].description.starts_with("Sustained loss"), "{}", lossy.anomalies[0].description);
+
+        // Nothing is kept for burst detection while it is disabled
+        let mut disabled = TestMetrics::new();
+        disabled.configure_anomaly_detection(&crate::config::TestConfig { anomaly_detection_enabled: false, ..Default::default() });
+        disabled.record_sequence(10, 0);
+        disabled.record_sequence(5, 10);
+        disabled.record_sequence(5, 20);
+        disabled.take_bandwidth_sample(1000);
+        assert!(disabled.out_of_order_arrivals_ms.is_empty() && disabled.duplicate_arrivals_ms.is_empty());
+        assert!(disabled.expected_packets_at_sample.is_empty());
+    }
+
+    #[test]
+    fn test_sustained_loss_window() {
+        let mut metrics = TestMetrics::new();
+        metrics.configure_anomaly_detection(&crate::config::TestConfig::default()); // 5% loss threshold
+        // 100 packets per 1 s interval; intervals 3-5 lose 20%, interval 8 alone loses half
+        for interval in 0..10u32 {
+            for seq in interval * 100..(interval + 1) * 100 {
+                let lost = match interval {
+                    3..=5 => seq % 5 == 0,
+                    8 => seq % 2 == 0 && seq % 100 != 99,
+                    _ => false,
+                };
+                if !lost {
+                    metrics.record_packet_received(64, 0);
+                    metrics.record_sequence(seq, interval as u128 * 1000 + (seq % 100) as u128 * 10);
+                }
+            }
+            metrics.take_bandwidth_sample((interval as u128 + 1) * 1000);
+        }
+
+        let anomalies = detect_anomalies(&metrics);
+        assert_eq!(anomalies.len(), 1, "{:?}", anomalies);
+        assert_eq!(anomalies[0].anomaly_type, AnomalyType::PacketLoss);
+        assert_eq!(anomalies[0].timestamp_ms, 3_000);
+        assert!(anomalies[0].description.contains("20.0% of packets lost from 3.0 s to 6.0 s"), "{}", anomalies[0].description);
+        // The threshold is named once, from AnomalyEvent::threshold
+        assert_eq!(anomalies[0].explanation(), "Sustained loss: 20.0% of packets lost from 3.0 s to 6.0 s (20 exceeded packet_loss_threshold_percent=5)");
+    }
+
+    #[test]
+    fn test_short_latency_bump_is_not_sustained() {
+        // 40 ms for one second only, default requires 2 s above 3x min
+        let metrics = metrics_with_rtts(10_000, |t| if (4_000..5_000).contains(&t) { 40.0 } else { 10.0 });
+        assert!(detect_anomalies(&metrics).is_empty());
+
+        let mut disabled = metrics_with_rtts(10_000, |t| 10.0 + t as f64 / 100.0);
+        disabled.configure_anomaly_detection(&crate::config::TestConfig { sustained_latency_multiplier: None, ..Default::default() });
+        assert!(detect_anomalies(&disabled).is_empty());
+    }
 }
@@ -0,0 +1,235 @@
+// Link guarantees embedded in the config ("loss < 1%", "p95_rtt < 30ms"), checked after the test
+
+use crate::config::ConfigError;
+use crate::metrics::TestMetrics;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssertionMetric {
+    PacketLossPercent,
+    AvgRttMs,
+    P95RttMs,
+    MaxRttMs,
+    AvgJitterMs,
+    ThroughputMbps,
+}
+
+impl AssertionMetric {
+    /// Name used in assertion strings, e.g. "p95_rtt" in "p95_rtt < 30ms".
+    pub fn name(&self) -> &'static str {
+        match self {
+            AssertionMetric::PacketLossPercent => "loss",
+            AssertionMetric::AvgRttMs => "avg_rtt",
+            AssertionMetric::P95RttMs => "p95_rtt",
+            AssertionMetric::MaxRttMs => "max_rtt",
+            AssertionMetric::AvgJitterMs => "jitter",
+            AssertionMetric::ThroughputMbps => "throughput",
+        }
+    }
+
+    /// Unit the threshold is expressed in; may be written after the number.
+    pub fn unit(&self) -> &'static str {
+        match self {
+            AssertionMetric::PacketLossPercent => "%",
+            AssertionMetric::AvgRttMs
+            | AssertionMetric::P95RttMs
+            | AssertionMetric::MaxRttMs
+            | AssertionMetric::AvgJitterMs => "ms",
+            AssertionMetric::ThroughputMbps => "mbps",
+        }
+    }
+
+    fn all() -> &'static [AssertionMetric] {
+        &[
+            AssertionMetric::PacketLossPercent,
+            AssertionMetric::AvgRttMs,
+            AssertionMetric::P95RttMs,
+            AssertionMetric::MaxRttMs,
+            AssertionMetric::AvgJitterMs,
+            AssertionMetric::ThroughputMbps,
+        ]
+    }
+
+    /// The measured value, or None if the test produced no data for it (e.g. no RTT samples).
+    pub fn measure(&self, metrics: &TestMetrics, duration_secs: f64) -> Option<f64> {
+        match self {
+            AssertionMetric::PacketLossPercent => Some(metrics.packet_loss_percentage()),
+            AssertionMetric::AvgRttMs => metrics.average_rtt_micros().map(|us| us / 1000.0),
+            AssertionMetric::P95RttMs => metrics.rtt_p95().map(|us| us / 1000.0),
+            AssertionMetric::MaxRttMs => metrics.max_rtt_micros.map(|us| us as f64 / 1000.0),
+            AssertionMetric::AvgJitterMs => metrics.average_jitter_micros().map(|us| us / 1000.0),
+            AssertionMetric::ThroughputMbps => Some(metrics.overall_throughput_bps(duration_secs) / 1_000_000.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    LessThan,
+    AtMost,
+    GreaterThan,
+    AtLeast,
+}
+
+impl Comparison {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::LessThan => "<",
+            Comparison::AtMost => "<=",
+            Comparison::GreaterThan => ">",
+            Comparison::AtLeast => ">=",
+        }
+    }
+
+    pub fn holds(&self, actual: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::LessThan => actual < threshold,
+            Comparison::AtMost => actual <= threshold,
+            Comparison::GreaterThan => actual > threshold,
+            Comparison::AtLeast => actual >= threshold,
+        }
+    }
+}
+
+/// One expectation about the finished test, e.g. `loss < 1` (percent).
+/// Config files write assertions as strings, parsed with `FromStr`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Assertion {
+    pub metric: AssertionMetric,
+    pub comparison: Comparison,
+    pub threshold: f64, // In metric.unit()
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}{}", self.metric.name(), self.comparison.symbol(), self.threshold, self.metric.unit())
+    }
+}
+
+impl FromStr for Assertion {
+    type Err = ConfigError;
+
+    /// Parses `<metric> <op> <number>[unit]`, e.g. "loss < 1%", "p95_rtt <= 30ms",
+    /// "throughput >= 50mbps". Metrics are listed in `AssertionMetric::name`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: String| ConfigError::InvalidAssertion { input: input.to_string(), reason };
+
+        // Two-character operators first so "<=" isn't read as "<"
+        let operators = [
+            ("<=", Comparison::AtMost),
+            (">=", Comparison::AtLeast),
+            ("<", Comparison::LessThan),
+            (">", Comparison::GreaterThan),
+        ];
+        let (metric_str, comparison, value_str) = operators
+            .iter()
+            .find_map(|(op, comparison)| {
+                input.split_once(op).map(|(lhs, rhs)| (lhs.trim(), *comparison, rhs.trim()))
+            })
+            .ok_or_else(|| invalid("expected one of <, <=, >, >=".to_string()))?;
+
+        let metric = AssertionMetric::all()
+            .iter()
+            .copied()
+            .find(|m| m.name().eq_ignore_ascii_case(metric_str))
+            .ok_or_else(|| {
+                let names: Vec<_> = AssertionMetric::all().iter().map(|m| m.name()).collect();
+                invalid(format!("unknown metric '{}', expected one of {}", metric_str, names.join(", ")))
+            })?;
+
+        let lower = value_str.to_ascii_lowercase();
+        let number = lower.strip_suffix(metric.unit()).unwrap_or(&lower).trim();
+        let threshold: f64 = number
+            .parse()
+            .map_err(|_| invalid(format!("'{}' is not a number (in {})", value_str, metric.unit())))?;
+
+        Ok(Assertion { metric, comparison, threshold })
+    }
+}
+
+impl From<Assertion> for String {
+    fn from(assertion: Assertion) -> Self {
+        assertion.to_string()
+    }
+}
+
+impl TryFrom<String> for Assertion {
+    type Error = ConfigError;
+
+    fn try_from(input: String) -> Result<Self, Self::Error> {
+        input.parse()
+    }
+}
+
+/// Outcome of one assertion against a finished test.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AssertionResult {
+    pub assertion: Assertion,
+    pub actual: Option<f64>, // None if the metric could not be measured; counts as a failure
+    pub passed: bool,
+}
+
+pub fn evaluate(assertions: &[Assertion], metrics: &TestMetrics, duration_secs: f64) -> Vec<AssertionResult> {
+    assertions
+        .iter()
+        .map(|assertion| {
+            let actual = assertion.metric.measure(metrics, duration_secs);
+            let passed = actual.is_some_and(|value| assertion.comparison.holds(value, assertion.threshold));
+            AssertionResult { assertion: assertion.clone(), actual, passed }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_assertions() {
+        let loss: Assertion = "loss < 1%".parse().unwrap();
+        assert_eq!(loss, Assertion { metric: AssertionMetric::PacketLossPercent, comparison: Comparison::LessThan, threshold: 1.0 });
+
+        let p95: Assertion = "p95_rtt<=30ms".parse().unwrap();
+        assert_eq!(p95.metric, AssertionMetric::P95RttMs);
+        assert_eq!(p95.comparison, Comparison::AtMost);
+        assert_eq!(p95.threshold, 30.0);
+        assert_eq!(p95.to_string(), "p95_rtt <= 30ms");
+
+        let throughput: Assertion = "throughput >= 50Mbps".parse().unwrap();
+        assert_eq!(throughput.comparison, Comparison::AtLeast);
+
+        assert!("latency < 5".parse::<Assertion>().is_err());
+        assert!("loss = 1".parse::<Assertion>().is_err());
+        assert!("loss < lots".parse::<Assertion>().is_err());
+    }
+
+    #[test]
+    fn test_evaluate_passing_and_failing_assertions() {
+        let mut metrics = TestMetrics::new();
+        for rtt_ms in 1..=100u128 {
+            metrics.record_packet_received(0, rtt_ms * 1000);
+        }
+        metrics.packets_sent = 1000;
+        metrics.packets_received = 995; // 0.5% loss
+
+        let assertions: Vec<Assertion> = ["loss < 1%", "p95_rtt < 30ms", "avg_rtt <= 60ms"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let results = evaluate(&assertions, &metrics, 1.0);
+
+        assert!(results[0].passed, "{:?}", results[0]);
+        assert!((results[0].actual.unwrap() - 0.5).abs() < 1e-9);
+        assert!(!results[1].passed, "p95 of 1..=100ms is 95ms");
+        let p95 = results[1].actual.unwrap();
+        assert!((p95 - 95.0).abs() / 95.0 < 0.01, "{}", p95); // rtt_histogram's resolution
+        assert!(results[2].passed);
+
+        // No RTT samples at all: the RTT assertion fails rather than passing vacuously
+        let empty = evaluate(&assertions[1..2], &TestMetrics::new(), 1.0);
+        assert_eq!(empty[0].actual, None);
+        assert!(!empty[0].passed);
+    }
+}
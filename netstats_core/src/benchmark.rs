@@ -1,25 +1,81 @@
 use crate::config::{TestConfig, Protocol, TestMode};
 use crate::metrics::TestMetrics;
 use crate::network::{run_network_test, NetworkError};
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use crate::packet::CustomPacket;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// estimate_max_pps: length of the burst and payload of its (small, so rate-bound) packets
+const PPS_ESTIMATE_BURST: Duration = Duration::from_millis(200);
+const PPS_ESTIMATE_PAYLOAD_BYTES: usize = 64;
+// A test sending at this share of the loopback ceiling or more was likely limited by the host
+pub(crate) const HOST_LIMITED_FRACTION: f64 = 0.8;
+// TCP frames each packet with a u32 length, counted in both sides' byte totals
+const TCP_LENGTH_PREFIX_BYTES: u64 = 4;
+// Server bandwidth sample interval: short enough for a series even from a 1 s benchmark
+const BENCHMARK_SAMPLE_INTERVAL_MS: u64 = 250;
 
 #[derive(Debug, Clone)]
 pub struct BenchmarkSummary {
+    pub protocol: Protocol,
     pub duration_secs: u64,
     pub packet_payload_size_bytes: usize,
     pub client_packets_sent: u64,
+    pub client_bytes_sent: u64,
     pub server_packets_received: u64,
-    pub server_bytes_received: u64,
+    pub server_bytes_received: u64, // Includes framing_bytes
+    pub framing_bytes: u64,         // TCP length prefixes received; 0 for UDP
     pub client_pps: f64,
     pub server_pps: f64,
     pub server_mbps: f64,
+    pub loss_percentage: f64,                     // Client packets sent that the server never received
+    pub avg_rtt_micros: Option<f64>,              // From the client's echo replies
+    pub server_bandwidth_samples: Vec<(f64, f64)>, // (time_sec, mbps) at the server, as in a test summary
+    pub runtime_worker_threads: usize, // Of the tokio runtime the benchmark ran on
+}
+
+/// Runs the loopback benchmark for `protocol` on a runtime of its own with `worker_threads`
+/// workers (see `runner::build_runtime`), for callers outside async code (e.g. the GUI's
+/// worker thread). Failing to create the runtime is an error, not a panic.
+pub fn run_loopback_benchmark_blocking(
+    protocol: Protocol,
+    duration_secs: u64,
+    packet_payload_size: usize,
+    worker_threads: Option<usize>,
+) -> Result<BenchmarkSummary, NetworkError> {
+    let runtime = crate::runner::build_runtime(worker_threads)?;
+    runtime.block_on(run_loopback_benchmark(protocol, duration_secs, packet_payload_size))
+}
+
+/// `run_loopback_benchmark_blocking` for UDP, on a default runtime.
+pub fn run_udp_loopback_benchmark_blocking(
+    duration_secs: u64,
+    packet_payload_size: usize,
+) -> Result<BenchmarkSummary, NetworkError> {
+    run_loopback_benchmark_blocking(Protocol::Udp, duration_secs, packet_payload_size, None)
 }
 
 /// Runs a self-contained UDP loopback benchmark.
 pub async fn run_udp_loopback_benchmark(
     duration_secs: u64,
     packet_payload_size: usize,
+) -> Result<BenchmarkSummary, NetworkError> {
+    run_loopback_benchmark(Protocol::Udp, duration_secs, packet_payload_size).await
+}
+
+/// Runs a self-contained TCP loopback benchmark, to compare against the UDP one: the
+/// difference is TCP's framing and flow control on the same machine.
+pub async fn run_tcp_loopback_benchmark(
+    duration_secs: u64,
+    packet_payload_size: usize,
+) -> Result<BenchmarkSummary, NetworkError> {
+    run_loopback_benchmark(Protocol::Tcp, duration_secs, packet_payload_size).await
+}
+
+async fn run_loopback_benchmark(
+    protocol: Protocol,
+    duration_secs: u64,
+    packet_payload_size: usize,
 ) -> Result<BenchmarkSummary, NetworkError> {
     let port = популярных_портов::BENCHMARK_PORT; // Use a dedicated port, e.g., 5202 or from a const
 
@@ -31,12 +87,14 @@ pub async fn run_udp_loopback_benchmark(
         tick_rate_hz: 1000, // Server tick rate for its loops, not directly relevant for packet processing speed.
         packet_size_bytes: packet_payload_size, // To know what to expect if it were validating
         packet_size_range: None,
-        protocol: Protocol::Udp,
+        protocol,
         test_mode: TestMode::Server,
         tcp_bidirectional_mode: None,
         latency_spike_threshold_ms: None, // Disable anomaly detection for benchmark
         jitter_spike_threshold_ms: None,
         packet_loss_threshold_percent: None,
+        bandwidth_sample_interval_ms: BENCHMARK_SAMPLE_INTERVAL_MS,
+        ..Default::default()
     });
     let server_metrics = Arc::new(Mutex::new(TestMetrics::default()));
 
@@ -59,12 +117,13 @@ pub async fn run_udp_loopback_benchmark(
         tick_rate_hz: 0, // AFAP mode!
         packet_size_bytes: packet_payload_size,
         packet_size_range: None,
-        protocol: Protocol::Udp,
+        protocol,
         test_mode: TestMode::Client,
         tcp_bidirectional_mode: None,
         latency_spike_threshold_ms: None,
         jitter_spike_threshold_ms: None,
         packet_loss_threshold_percent: None,
+        ..Default::default()
     });
     let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
 
@@ -78,17 +137,87 @@ pub async fn run_udp_loopback_benchmark(
     // Or, implement a shutdown signal. For now, simple join.
     let server_shutdown_result = server_handle.await;
 
-    if let Err(e) = client_result {
-        return Err(e);
+    client_result?;
+    server_shutdown_result.map_err(|e| NetworkError::Other(format!("Benchmark server task failed: {}", e)))??;
+
+    summarize(protocol, duration_secs, packet_payload_size, &client_metrics, &server_metrics)
+}
+
+/// Rough ceiling on the packet rate this machine can push through loopback: a short
+/// as-fast-as-possible burst of small packets, each serialized and parsed as in a real test.
+/// Meant to be run before a remote test, see `host_limit_note`. Returns 0 if the loopback
+/// sockets can't be set up.
+pub fn estimate_max_pps() -> u64 {
+    match measure_loopback_pps(PPS_ESTIMATE_BURST) {
+        Ok(pps) => pps,
+        Err(e) => {
+            eprintln!("Loopback PPS estimate failed: {}", e);
+            0
+        }
     }
-    if let Err(e) = server_shutdown_result.unwrap_or(Ok(())) { // Handle panic from server task
-         return Err(e);
+}
+
+fn measure_loopback_pps(burst: Duration) -> std::io::Result<u64> {
+    let receiver = std::net::UdpSocket::bind("127.0.0.1:0")?;
+    receiver.set_read_timeout(Some(Duration::from_millis(100)))?;
+    let sender = std::net::UdpSocket::bind("127.0.0.1:0")?;
+    sender.connect(receiver.local_addr()?)?;
+
+    // Counts parsed packets until the sender has gone quiet for a read timeout
+    let receiver_thread = std::thread::spawn(move || {
+        let mut buf = vec![0u8; 2048];
+        let mut received = 0u64;
+        while let Ok(len) = receiver.recv(&mut buf) {
+            if CustomPacket::from_bytes(&buf[..len]).is_ok() {
+                received += 1;
+            }
+        }
+        received
+    });
+
+    let start = Instant::now();
+    let mut sequence_number: u32 = 0;
+    while start.elapsed() < burst {
+        let bytes = CustomPacket::new_data_packet(sequence_number, PPS_ESTIMATE_PAYLOAD_BYTES)
+            .to_bytes()
+            .map_err(std::io::Error::other)?;
+        let _ = sender.send(&bytes); // A full socket buffer (ENOBUFS) just means the ceiling was hit
+        sequence_number = sequence_number.wrapping_add(1);
     }
+    let elapsed = start.elapsed();
+    let received = receiver_thread
+        .join()
+        .map_err(|_| std::io::Error::other("Loopback receiver thread panicked"))?;
+    Ok((received as f64 / elapsed.as_secs_f64()) as u64)
+}
 
+/// A warning for the report when a test's packet rate came close to `max_pps` (from
+/// `estimate_max_pps`): the sending host, not the network, was probably the bottleneck.
+pub fn host_limit_note(achieved_pps: f64, max_pps: u64) -> Option<String> {
+    (max_pps > 0 && achieved_pps >= max_pps as f64 * HOST_LIMITED_FRACTION).then(|| format!(
+        "{:.0} packets/s is {:.0}% of this machine's loopback ceiling ({} packets/s): results are likely CPU/loopback-limited",
+        achieved_pps, achieved_pps / max_pps as f64 * 100.0, max_pps
+    ))
+}
 
-    // --- Process Results ---
-    let final_client_metrics = client_metrics.lock().unwrap();
-    let final_server_metrics = server_metrics.lock().unwrap();
+/// Unlike `metrics::lock_metrics`, a poisoned lock is an error: a benchmark side that
+/// panicked mid-update has no numbers worth summarizing.
+fn lock_benchmark_metrics<'a>(metrics: &'a Mutex<TestMetrics>, side: &str) -> Result<MutexGuard<'a, TestMetrics>, NetworkError> {
+    metrics
+        .lock()
+        .map_err(|_| NetworkError::Other(format!("Benchmark {} metrics are unusable: a task panicked while holding them", side)))
+}
+
+// --- Process Results ---
+fn summarize(
+    protocol: Protocol,
+    duration_secs: u64,
+    packet_payload_size: usize,
+    client_metrics: &Mutex<TestMetrics>,
+    server_metrics: &Mutex<TestMetrics>,
+) -> Result<BenchmarkSummary, NetworkError> {
+    let final_client_metrics = lock_benchmark_metrics(client_metrics, "client")?;
+    let final_server_metrics = lock_benchmark_metrics(server_metrics, "server")?;
 
     let client_pps = if duration_secs > 0 {
         final_client_metrics.packets_sent as f64 / duration_secs as f64
@@ -102,18 +231,101 @@ pub async fn run_udp_loopback_benchmark(
         (final_server_metrics.bytes_received * 8) as f64 / (duration_secs as f64 * 1_000_000.0)
     } else { 0.0 };
 
+    let loss_percentage = if final_client_metrics.packets_sent > 0 {
+        let lost = final_client_metrics.packets_sent.saturating_sub(final_server_metrics.packets_received);
+        lost as f64 / final_client_metrics.packets_sent as f64 * 100.0
+    } else { 0.0 };
+
+    let framing_bytes = match protocol {
+        Protocol::Tcp => final_server_metrics.packets_received * TCP_LENGTH_PREFIX_BYTES,
+        Protocol::Udp => 0,
+    };
+
     Ok(BenchmarkSummary {
+        protocol,
         duration_secs,
         packet_payload_size_bytes: packet_payload_size,
         client_packets_sent: final_client_metrics.packets_sent,
+        client_bytes_sent: final_client_metrics.bytes_sent,
         server_packets_received: final_server_metrics.packets_received,
         server_bytes_received: final_server_metrics.bytes_received,
+        framing_bytes,
         client_pps,
         server_pps,
         server_mbps,
+        loss_percentage,
+        avg_rtt_micros: final_client_metrics.average_rtt_micros(),
+        server_bandwidth_samples: crate::reporter::process_bandwidth_samples(&final_server_metrics),
+        runtime_worker_threads: tokio::runtime::Handle::try_current().map_or(0, |handle| handle.metrics().num_workers()),
     })
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poisoned_metrics_are_an_error_not_a_panic() {
+        let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        let server_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        let poisoner = Arc::clone(&server_metrics);
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("receive loop died holding the metrics lock");
+        })
+        .join();
+        assert!(server_metrics.is_poisoned());
+
+        match summarize(Protocol::Udp, 1, 64, &client_metrics, &server_metrics) {
+            Err(NetworkError::Other(message)) => assert!(message.contains("server"), "{}", message),
+            other => panic!("expected NetworkError::Other, got {:?}", other),
+        }
+        assert!(summarize(Protocol::Udp, 1, 64, &client_metrics, &Mutex::new(TestMetrics::default())).is_ok());
+    }
+
+    #[test]
+    fn test_summary_reports_loss_and_rtt() {
+        let mut client = TestMetrics::default();
+        client.packets_sent = 200;
+        client.total_rtt_micros = 400;
+        client.rtt_count = 2;
+        let mut server = TestMetrics::default();
+        server.packets_received = 190;
+        server.bandwidth_samples = vec![(1000, 125_000, 95), (2000, 125_000, 95)];
+
+        let summary = summarize(Protocol::Udp, 2, 64, &Mutex::new(client), &Mutex::new(server)).unwrap();
+        assert!((summary.loss_percentage - 5.0).abs() < 1e-9, "{}", summary.loss_percentage);
+        assert_eq!(summary.avg_rtt_micros, Some(200.0));
+        assert_eq!(summary.server_bandwidth_samples, vec![(1.0, 1.0), (2.0, 1.0)]);
+
+        let idle = summarize(Protocol::Tcp, 2, 64, &Mutex::new(TestMetrics::default()), &Mutex::new(TestMetrics::default())).unwrap();
+        assert_eq!(idle.loss_percentage, 0.0);
+        assert!(idle.avg_rtt_micros.is_none());
+    }
+
+    #[test]
+    fn test_benchmark_completes_on_one_and_several_worker_threads() {
+        for worker_threads in [1, 4] {
+            let summary = run_loopback_benchmark_blocking(Protocol::Udp, 1, 64, Some(worker_threads))
+                .unwrap_or_else(|e| panic!("Benchmark on {} worker threads failed: {:?}", worker_threads, e));
+            assert_eq!(summary.runtime_worker_threads, worker_threads);
+            assert!(summary.client_packets_sent > 0 && summary.server_packets_received > 0, "{:?}", summary);
+        }
+    }
+
+    #[test]
+    fn test_estimate_max_pps_is_plausible() {
+        let max_pps = estimate_max_pps();
+        // Even a slow, loaded CI machine moves well over a thousand small packets a second
+        assert!(max_pps > 1_000, "Loopback ceiling of only {} packets/s", max_pps);
+        assert!(max_pps < 100_000_000, "Implausible loopback ceiling of {} packets/s", max_pps);
+
+        assert!(host_limit_note(max_pps as f64 * 0.9, max_pps).is_some());
+        assert!(host_limit_note(max_pps as f64 * 0.1, max_pps).is_none());
+        assert!(host_limit_note(1_000.0, 0).is_none());
+    }
+}
+
 // Placeholder for a dedicated port, ideally from a constants module or config
 mod популярных_портов {
     pub const BENCHMARK_PORT: u16 = 5202;
@@ -0,0 +1,292 @@
+// Command-line arguments of the headless netstats-cli and netstats-echo binaries
+
+use crate::config::{ConfigError, MultiAddressPolicy, Protocol, TestConfig, TestMode};
+use crate::units::{parse_bitrate_bps, parse_frequency_hz, parse_size_bytes};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: netstats-cli [OPTIONS]
+
+Runs one test and prints its summary. Exits non-zero if the test fails.
+
+Options:
+      --plan <FILE>          Run the test plan in FILE instead, and print the combined
+                             report; with --report, FILE is a directory for all the
+                             reports. Takes no other options. Exits non-zero unless
+                             every phase passes
+  -c, --config <FILE>        Start from a TOML config file instead of the defaults
+  -t, --target <IP>          Target IP address or hostname (default 127.0.0.1)
+      --addresses <POLICY>   Hostname target: test the first address it resolves to, or
+                             all of them, sequential or parallel (default first)
+  -p, --port <PORT>          Target port (default 5001)
+      --listen-port <PORT>   Bidirectional mode: local port to listen on (default: --port)
+      --protocol <udp|tcp>   Transport protocol (default udp)
+  -m, --mode <MODE>          client, server or bidirectional (default client)
+  -d, --duration <SECS>      Test duration in seconds (default 10)
+  -n, --packets <COUNT>      Stop sending after COUNT packets (allows --duration 0)
+      --tick-rate <RATE>     Packets per second, e.g. 20 or 1kHz (default 20)
+  -s, --packet-size <SIZE>   Packet size, e.g. 1400 or 1KiB (default 1024)
+  -b, --bitrate <RATE>       TCP: send at RATE, e.g. 10Mbps, instead of one packet per tick
+  -P, --parallel <COUNT>     TCP: connections to test over at once (default 1)
+      --report <FILE>        Also write the HTML report to FILE (numbered per address when
+                             there are several)
+      --pre-cmd <CMD>        Shell command to run before the test, e.g. to start a capture
+      --post-cmd <CMD>       Shell command to run after it, with the results in NETSTATS_*
+                             environment variables
+      --loopback-ceiling <auto|PPS>
+                             Flag results near this machine's loopback packet rate as
+                             host-limited: measured before the test (auto) or given
+  -h, --help                 Print this help";
+
+pub const ECHO_USAGE: &str = "\
+Usage: netstats-echo [OPTIONS]
+
+Answers the EchoRequests of netstats UDP clients until Ctrl-C, so they can test against
+this host without a full test running here.
+
+Options:
+  -p, --port <PORT>          UDP port to listen on (default 5001)
+      --bind <IP>            Local address to listen on (default: every IPv4 and IPv6
+                             address)
+  -h, --help                 Print this help";
+
+/// A parsed command line.
+#[derive(Debug)]
+pub struct CliArgs {
+    pub config: TestConfig,
+    pub report_path: Option<PathBuf>, // --report: where to write the HTML report
+    pub plan_path: Option<PathBuf>,   // --plan: run this plan::TestPlan instead of one test
+    pub pre_cmd: Option<String>,      // --pre-cmd, see runner::TestHooks::shell_commands
+    pub post_cmd: Option<String>,     // --post-cmd
+    pub loopback_ceiling: Option<LoopbackCeiling>, // --loopback-ceiling
+    pub help: bool,                   // --help: print USAGE and exit
+}
+
+/// Where the loopback packet rate for `reporter::diagnose_bottleneck` comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopbackCeiling {
+    Measure,  // "auto": benchmark::estimate_max_pps, run before the test
+    Pps(u64), // A rate measured earlier, in packets per second
+}
+
+/// Parses the arguments after the program name. Options take their value as the next
+/// argument or after `=` (`--port 5001`, `--port=5001`); later options override earlier
+/// ones and `--config`, wherever it appears, is applied first.
+pub fn parse_args<I>(args: I) -> Result<CliArgs, ConfigError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let (options, help) = split_options(args)?;
+    let mut config = match options.iter().rev().find(|(name, _)| name == "-c" || name == "--config") {
+        Some((_, path)) => TestConfig::from_toml_file(path)?,
+        None => TestConfig::default(),
+    };
+    let (mut report_path, mut plan_path, mut pre_cmd, mut post_cmd, mut loopback_ceiling) = (None, None, None, None, None);
+    for (name, value) in &options {
+        let bad_value = |reason: &str| invalid(&format!("{} {}", name, value), reason);
+        match name.as_str() {
+            "-c" | "--config" => {} // Applied above
+            "-t" | "--target" => config.target_ip = value.clone(),
+            "--addresses" => {
+                config.multi_address_policy = match value.to_ascii_lowercase().as_str() {
+                    "first" => MultiAddressPolicy::First,
+                    "sequential" => MultiAddressPolicy::AllSequential,
+                    "parallel" => MultiAddressPolicy::AllParallel,
+                    _ => return Err(bad_value("expected first, sequential or parallel")),
+                }
+            }
+            "-p" | "--port" => config.target_port = value.parse().map_err(|_| bad_value("expected a port number"))?,
+            "--listen-port" => {
+                config.local_listen_port = Some(value.parse().map_err(|_| bad_value("expected a port number"))?);
+            }
+            "--protocol" => {
+                config.protocol = match value.to_ascii_lowercase().as_str() {
+                    "udp" => Protocol::Udp,
+                    "tcp" => Protocol::Tcp,
+                    _ => return Err(bad_value("expected udp or tcp")),
+                }
+            }
+            "-m" | "--mode" => {
+                config.test_mode = match value.to_ascii_lowercase().as_str() {
+                    "client" => TestMode::Client,
+                    "server" => TestMode::Server,
+                    "bidirectional" | "bidi" => TestMode::Bidirectional,
+                    _ => return Err(bad_value("expected client, server or bidirectional")),
+                }
+            }
+            "-d" | "--duration" => {
+                config.test_duration_secs = value.parse().map_err(|_| bad_value("expected whole seconds"))?;
+                config.test_duration_ms = None;
+            }
+            "-n" | "--packets" => {
+                config.stop_after_packets = Some(value.parse().map_err(|_| bad_value("expected a packet count"))?);
+            }
+            "--tick-rate" => config.tick_rate_hz = parse_frequency_hz(value)?,
+            "-s" | "--packet-size" => config.packet_size_bytes = parse_size_bytes(value)?,
+            "-b" | "--bitrate" => config.target_bitrate_bps = Some(parse_bitrate_bps(value)?),
+            "-P" | "--parallel" => {
+                config.parallel_streams = value.parse().map_err(|_| bad_value("expected a connection count"))?;
+            }
+            "--report" => report_path = Some(PathBuf::from(value)),
+            "--plan" => plan_path = Some(PathBuf::from(value)),
+            "--pre-cmd" => pre_cmd = Some(value.clone()),
+            "--post-cmd" => post_cmd = Some(value.clone()),
+            "--loopback-ceiling" => {
+                loopback_ceiling = Some(match value.to_ascii_lowercase().as_str() {
+                    "auto" => LoopbackCeiling::Measure,
+                    pps => LoopbackCeiling::Pps(pps.parse().map_err(|_| bad_value("expected auto or packets per second"))?),
+                })
+            }
+            _ => return Err(invalid(name, "unknown option, see --help")),
+        }
+    }
+    if plan_path.is_some() {
+        // The plan file sets every phase's config; only where its reports go comes from here
+        if let Some((name, _)) = options.iter().find(|(name, _)| name != "--plan" && name != "--report") {
+            return Err(invalid(name, "can't be combined with --plan, set it in the plan file"));
+        }
+    }
+    Ok(CliArgs { config, report_path, plan_path, pre_cmd, post_cmd, loopback_ceiling, help })
+}
+
+/// A parsed netstats-echo command line.
+#[derive(Debug, PartialEq, Eq)]
+pub struct EchoArgs {
+    pub port: u16,
+    pub bind_ip: Option<IpAddr>, // --bind; None = network::bind_udp_dual_stack
+    pub help: bool,
+}
+
+/// Parses the arguments of netstats-echo, in the same syntax as `parse_args`.
+pub fn parse_echo_args<I>(args: I) -> Result<EchoArgs, ConfigError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let (options, help) = split_options(args)?;
+    let (mut port, mut bind_ip) = (TestConfig::default().target_port, None);
+    for (name, value) in &options {
+        let bad_value = |reason: &str| invalid(&format!("{} {}", name, value), reason);
+        match name.as_str() {
+            "-p" | "--port" => port = value.parse().map_err(|_| bad_value("expected a port number"))?,
+            "--bind" => bind_ip = Some(value.parse().map_err(|_| bad_value("expected an IP address"))?),
+            _ => return Err(invalid(name, "unknown option, see --help")),
+        }
+    }
+    Ok(EchoArgs { port, bind_ip, help })
+}
+
+/// Splits the arguments into (option, value) pairs in order, and whether help was asked for.
+fn split_options<I>(args: I) -> Result<(Vec<(String, String)>, bool), ConfigError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options: Vec<(String, String)> = Vec::new();
+    let mut help = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            help = true;
+            continue;
+        }
+        if !arg.starts_with('-') {
+            return Err(invalid(&arg, "unexpected positional argument"));
+        }
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => {
+                let value = args.next().ok_or_else(|| invalid(&arg, "missing value"))?;
+                (arg, value)
+            }
+        };
+        options.push((name, value));
+    }
+    Ok((options, help))
+}
+
+fn invalid(argument: &str, reason: &str) -> ConfigError {
+    ConfigError::InvalidArgument { argument: argument.to_string(), reason: reason.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliArgs, ConfigError> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args_into_test_config() {
+        let cli = parse(&[
+            "--target", "10.0.0.5", "-p=6000", "--protocol", "TCP", "--mode", "server",
+            "-d", "3", "--tick-rate", "1kHz", "--packet-size", "1KiB", "--report", "out.html", "-n", "500",
+            "--listen-port", "6001", "-P", "4", "--addresses", "Parallel", "-b", "10Mbps",
+            "--pre-cmd", "tcpdump -w run.pcap", "--post-cmd=echo done", "--loopback-ceiling", "250000",
+        ])
+        .unwrap();
+        assert_eq!(cli.config.target_ip, "10.0.0.5");
+        assert_eq!(cli.config.target_port, 6000);
+        assert_eq!(cli.config.protocol, Protocol::Tcp);
+        assert_eq!(cli.config.test_mode, TestMode::Server);
+        assert_eq!(cli.config.test_duration_secs, 3);
+        assert_eq!(cli.config.tick_rate_hz, 1000);
+        assert_eq!(cli.config.packet_size_bytes, 1024);
+        assert_eq!(cli.config.stop_after_packets, Some(500));
+        assert_eq!(cli.config.local_listen_port, Some(6001));
+        assert_eq!(cli.config.parallel_streams, 4);
+        assert_eq!(cli.config.multi_address_policy, MultiAddressPolicy::AllParallel);
+        assert_eq!(cli.config.target_bitrate_bps, Some(10_000_000));
+        assert_eq!(cli.report_path, Some(PathBuf::from("out.html")));
+        assert_eq!(cli.pre_cmd.as_deref(), Some("tcpdump -w run.pcap"));
+        assert_eq!(cli.post_cmd.as_deref(), Some("echo done"));
+        assert_eq!(cli.loopback_ceiling, Some(LoopbackCeiling::Pps(250_000)));
+        assert!(!cli.help);
+
+        let defaults = parse(&[]).unwrap();
+        assert_eq!(defaults.config.target_port, TestConfig::default().target_port);
+        assert!(defaults.report_path.is_none() && defaults.plan_path.is_none());
+        assert!(defaults.pre_cmd.is_none() && defaults.post_cmd.is_none());
+        assert_eq!(defaults.config.target_bitrate_bps, None);
+        assert_eq!(defaults.loopback_ceiling, None);
+        assert_eq!(parse(&["--loopback-ceiling", "Auto"]).unwrap().loopback_ceiling, Some(LoopbackCeiling::Measure));
+        assert!(parse(&["-h"]).unwrap().help);
+
+        let plan = parse(&["--plan", "nightly.toml", "--report", "reports"]).unwrap();
+        assert_eq!((plan.plan_path, plan.report_path), (Some(PathBuf::from("nightly.toml")), Some(PathBuf::from("reports"))));
+    }
+
+    #[test]
+    fn test_parse_args_rejects_bad_input() {
+        for args in [
+            &["--port", "70000"][..],
+            &["--protocol", "sctp"],
+            &["--mode", "relay"],
+            &["--addresses", "all"],
+            &["--loopback-ceiling", "fast"],
+            &["--duration"],
+            &["--packets", "-1"],
+            &["--bogus", "1"],
+            &["--plan", "nightly.toml", "--port", "6000"],
+            &["10.0.0.5"],
+        ] {
+            assert!(matches!(parse(args), Err(ConfigError::InvalidArgument { .. })), "{:?}", args);
+        }
+        assert!(matches!(parse(&["--tick-rate", "fast"]), Err(ConfigError::InvalidUnit { .. })));
+        assert!(matches!(parse(&["--bitrate", "10 furlongs"]), Err(ConfigError::InvalidUnit { .. })));
+        assert!(matches!(parse(&["--pre-cmd"]), Err(ConfigError::InvalidArgument { .. })));
+        assert!(matches!(parse(&["--config", "/nonexistent/netstats.toml"]), Err(ConfigError::IoError(_))));
+    }
+
+    #[test]
+    fn test_parse_echo_args() {
+        let parse_echo = |args: &[&str]| parse_echo_args(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(parse_echo(&["--port", "7001"]).unwrap(), EchoArgs { port: 7001, bind_ip: None, help: false });
+        assert_eq!(parse_echo(&[]).unwrap().port, 5001);
+        assert_eq!(parse_echo(&["--bind", "::1"]).unwrap().bind_ip, Some("::1".parse().unwrap()));
+        assert!(parse_echo(&["-h"]).unwrap().help);
+        assert!(matches!(parse_echo(&["--target", "10.0.0.5"]), Err(ConfigError::InvalidArgument { .. })));
+        assert!(matches!(parse_echo(&["-p=99999"]), Err(ConfigError::InvalidArgument { .. })));
+        assert!(matches!(parse_echo(&["--bind", "localhost"]), Err(ConfigError::InvalidArgument { .. })));
+    }
+}
@@ -1,70 +1,713 @@
 // Test configuration structures
 
+use crate::packet::PayloadPattern;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
 use std::time::Duration;
+use tokio::sync::mpsc;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Errors from loading or validating configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    IoError(std::io::Error),
+    InvalidTarget { line_number: usize, line: String, reason: String },
+    InvalidAssertion { input: String, reason: String },
+    InvalidConfigFile(String), // TOML syntax or field type errors
+    InvalidUnit { input: String, reason: String }, // Sizes and rates like "1MiB", see units
+    InvalidArgument { argument: String, reason: String }, // Command-line arguments, see cli
+    ZeroDuration, // test_duration of 0 without stop_after_packets, see TestConfig::validate_duration
+    CountLimitedReceiver, // A count-limited test outside client mode, see TestConfig::validate_duration
+    // Rejected by TestConfig::validate
+    EmptyTargetIp, // A client or bidirectional test has no target to send to
+    InvalidPacketSizeRange { min: usize, max: usize }, // packet_size_range with min 0 or above max
+    InvalidPortRange { first: u16, last: u16 }, // dest_port_range with first above last
+    DscpOutOfRange(u8), // Above 63, DSCP is 6 bits
+    ZeroParallelStreams, // parallel_streams of 0, a TCP test needs at least one connection
+    ZeroWorkerThreads, // runtime_worker_threads of Some(0), a runtime needs at least one worker
+    BufferPoolWithoutParseWorkers, // recv_buffer_pool without parse_workers, which it feeds
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::IoError(e) => write!(f, "I/O error: {}", e),
+            ConfigError::InvalidTarget { line_number, line, reason } => {
+                write!(f, "Invalid target on line {} ('{}'): {}", line_number, line, reason)
+            }
+            ConfigError::InvalidAssertion { input, reason } => {
+                write!(f, "Invalid assertion '{}': {}", input, reason)
+            }
+            ConfigError::InvalidConfigFile(reason) => write!(f, "Invalid config file: {}", reason),
+            ConfigError::InvalidUnit { input, reason } => write!(f, "Invalid value '{}': {}", input, reason),
+            ConfigError::InvalidArgument { argument, reason } => write!(f, "Invalid argument '{}': {}", argument, reason),
+            ConfigError::ZeroDuration => {
+                write!(f, "The test duration is 0: set a duration, or stop_after_packets for a count-based test")
+            }
+            ConfigError::CountLimitedReceiver => {
+                write!(f, "A count-based test (duration 0) runs in client mode only: a receiver can't tell when the count is reached")
+            }
+            ConfigError::EmptyTargetIp => write!(f, "No target IP: a client or bidirectional test needs one"),
+            ConfigError::InvalidPacketSizeRange { min, max } => {
+                write!(f, "Invalid random packet size range {}-{}: sizes start at 1 and min must not exceed max", min, max)
+            }
+            ConfigError::InvalidPortRange { first, last } => write!(f, "Destination port range {}-{} is empty", first, last),
+            ConfigError::DscpOutOfRange(dscp) => write!(f, "dscp {} is out of range, expected 0-63", dscp),
+            ConfigError::ZeroParallelStreams => write!(f, "parallel_streams is 0, expected at least 1"),
+            ConfigError::ZeroWorkerThreads => write!(f, "runtime_worker_threads is 0, expected at least 1"),
+            ConfigError::BufferPoolWithoutParseWorkers => {
+                write!(f, "recv_buffer_pool needs parse_workers: the buffers are handed to the parse workers")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> Self {
+        ConfigError::IoError(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Protocol {
     Tcp,
     Udp,
 }
 
-#[derive(Debug, Clone)]
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Tcp => write!(f, "TCP"),
+            Protocol::Udp => write!(f, "UDP"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)] // Fields missing from a config file keep their defaults; misspelled ones are errors
 pub struct TestConfig {
     pub target_ip: String,
     pub target_port: u16,
     pub test_duration_secs: u64,
+    pub test_duration_ms: Option<u64>, // Sub-second test length, e.g. 200; overrides test_duration_secs when set
+    // Primary send loops stop after this many packets, even before the duration is up. With a
+    // duration of 0 the count alone ends sending (receivers still wait out their grace period,
+    // or stop on the udp_control_port End).
+    pub stop_after_packets: Option<u64>,
+    // Uncounted packets sent back-to-back before the first tick, to wake links that idle into
+    // power saving (e.g. LTE) so the first measured packets don't pay for it. 0 = off
+    pub prewarm_packets: u32,
+    #[serde(deserialize_with = "crate::units::deserialize_frequency")] // 100 or "50kHz"
     pub tick_rate_hz: u32,
-    pub packet_size_bytes: usize, // Base packet size, or default if range not specified
+    pub packets_per_tick: u32, // Burst sent on each tick, so packet rate = tick_rate_hz * packets_per_tick. 0 acts as 1
+    pub send_jitter_ms: Option<u64>, // Random ±jitter on each tick's send time, so many clients don't send in lockstep
+    // Seed of the send loops' random choices (send_jitter_ms delays, packet_size_range sizes,
+    // dest_port_range ports), to replay a run's traffic pattern. None = a new seed each test
+    pub send_rng_seed: Option<u64>,
+    #[serde(deserialize_with = "crate::units::deserialize_size")] // 1400 or "1400B", "64KiB"
+    pub packet_size_bytes: usize, // Base packet size, or default if range not specified. 0 = auto: largest that fits the path MTU
     pub packet_size_range: Option<(usize, usize)>, // (min_bytes, max_bytes) for random packet sizes
     pub protocol: Protocol,
     pub test_mode: TestMode,
+    #[serde(with = "off_when_none")]
     pub tcp_bidirectional_mode: Option<TcpBidirectionalMode>, // Only relevant if protocol is TCP and mode is Bidirectional
+    // Bidirectional mode: port this side listens on for the peer's traffic. None = target_port,
+    // which two peers on one host can't both bind; give each the other's as target_port.
+    pub local_listen_port: Option<u16>,
+    // Bidirectional mode: per-direction tick rates, falling back to tick_rate_hz. tx paces the
+    // primary send loop (the traffic this side originates), rx the secondary send loop that
+    // answers the peer. With the same config on both peers the initiator sends at tx and
+    // receives at rx, e.g. slow upstream requests and fast downstream data.
+    pub tx_tick_rate_hz: Option<u32>,
+    pub rx_tick_rate_hz: Option<u32>,
+    // Pace the primary send loop to this many bits per second (as counted in bytes_sent)
+    // instead of by ticks: each packet waits for its own transmission time at this rate, so
+    // the rate holds for any packet size. Overrides tick_rate_hz, packets_per_tick and
+    // send_jitter_ms for that loop.
+    #[serde(deserialize_with = "crate::units::deserialize_bitrate_opt")] // 10000000 or "10Mbps"
+    pub target_bitrate_bps: Option<u64>,
 
-    // Anomaly detection thresholds
+    // Anomaly detection thresholds. anomaly_detection_enabled = false skips every check and
+    // record, for pure throughput benchmarking; the loss/reorder/duplicate counters are still kept.
+    // The thresholds default to on, so a config file turns one off with "off".
+    pub anomaly_detection_enabled: bool,
+    #[serde(with = "off_when_none")]
     pub latency_spike_threshold_ms: Option<u64>,
+    #[serde(with = "off_when_none")]
     pub jitter_spike_threshold_ms: Option<u64>,
+    #[serde(with = "off_when_none")]
     pub packet_loss_threshold_percent: Option<f64>,
+    // Sustained latency (bufferbloat): the rolling average RTT stays above
+    // multiplier x the minimum RTT for at least sustained_latency_secs. None disables it.
+    #[serde(with = "off_when_none")]
+    pub sustained_latency_multiplier: Option<f64>,
+    pub sustained_latency_secs: u64,
+    // Reorder tolerance: a packet arriving up to this many sequence numbers behind the highest
+    // received is accepted as in order. 0 = flag every packet that was overtaken.
+    pub reorder_window: u32,
+
+    // How long the client waits for an EchoReply before the request counts as lost.
+    // Also bounds the drain phase at the end of the test so in-flight replies are still counted.
+    pub rtt_timeout_ms: u64,
+
+    // Echo replies whose RTT is left out of the RTT statistics (min/avg/percentiles/jitter),
+    // e.g. 1 to drop the first reply, which pays for ARP and route cache population.
+    // The packets still count as received.
+    pub rtt_skip_first: u32,
+    // RTT samples kept for the median/trimmed statistics and sustained latency detection.
+    // Past this many, a uniform random subset is kept (reservoir sampling). 0 = keep all.
+    pub max_latency_samples: usize,
+
+    // Sample this process's CPU% and memory once per bandwidth interval, to tell a CPU-bound
+    // sender/receiver apart from a network limit.
+    pub monitor_process: bool,
+    // UDP receiver: histogram of the gaps between arriving datagrams, to show pacing and
+    // burstiness (see TestMetrics::interarrival_histogram)
+    pub record_interarrival: bool,
+    // Receiver: histogram of the payload sizes of received data packets, to confirm the
+    // sender's size distribution (packet_size_range) arrived intact, without truncation
+    // (see TestMetrics::received_size_histogram)
+    pub record_size_histogram: bool,
+    // UDP receiver: keep the exact set of sequence numbers received, as run-length ranges
+    // (TestMetrics::received_sequence_ranges), and export it in the JSON report for offline
+    // loss/reorder analysis. Memory grows with the number of gaps, not packets.
+    pub record_sequence_ranges: bool,
+
+    // Largest TCP frame (encoded packet) the receiver accepts. A length prefix outside
+    // [smallest possible packet, this] means the stream is mis-framed and the connection is
+    // closed with a FramingError anomaly. Raise it for packets over 1 MiB.
+    #[serde(deserialize_with = "crate::units::deserialize_size")]
+    pub tcp_max_frame_bytes: usize,
+    // TCP client and server: connections to test over at once, e.g. to fill a link one
+    // connection's window can't. Both sides need the same value. The metrics cover all of
+    // them together, and target_bitrate_bps is split evenly between them. UDP ignores it.
+    pub parallel_streams: u32,
+
+    // UDP receiver: parse and record datagrams on this many worker tasks, so the receive loop
+    // only drains the socket. Helps at very high packet rates. 0 = parse inline.
+    pub parse_workers: usize,
+    // With parse_workers: copy datagrams into this many preallocated buffers, handed back by
+    // the workers after parsing, instead of allocating a buffer per datagram. While all are
+    // in use, extra buffers are allocated (and reused if there's room). 0 = no pool; without
+    // parse_workers the config is rejected, as inline parsing reads from one fixed buffer.
+    pub recv_buffer_pool: usize,
+    // UDP receivers: ask the OS for a socket receive buffer (SO_RCVBUF) of this many bytes, so
+    // bursts at high packet rates aren't dropped before the loop reads them. The OS may grant
+    // less (Linux caps it at net.core.rmem_max); the granted size is logged. None = OS default.
+    #[serde(deserialize_with = "crate::units::deserialize_size_opt")] // 4194304 or "4MiB"
+    pub recv_buffer_bytes: Option<usize>,
+    // Set SO_REUSEADDR on the sockets a server or bidirectional peer receives on. With it, two
+    // UDP tests can share a port (Linux then hands each datagram to one of them), so leave it
+    // off unless a port must be reused at once. A port in use fails with NetworkError::AddressInUse.
+    pub reuse_addr: bool,
+    // Worker threads of the tokio runtime that blocking entry points (netstats-cli, the GUI)
+    // build for the test, see runner::build_runtime. None = one per CPU core, tokio's default.
+    pub runtime_worker_threads: Option<usize>,
+    // Re-run the whole test up to this many times if it fails to start with a transient error
+    // (port still in use, connection refused while the server starts), backing off in between.
+    // See runner::run_prepared_test. 0 = fail on the first error.
+    pub max_test_retries: u32,
+
+    // UDP client: force the IP Don't Fragment bit on (Some(true), e.g. to probe for PMTU
+    // black holes) or off (Some(false), let routers fragment). None keeps the OS default.
+    // A DF datagram over the path MTU fails with NetworkError::PacketTooLarge. Linux only.
+    pub set_df: Option<bool>,
+
+    // DiffServ codepoint (0-63) to mark test traffic with, to check how a network's QoS
+    // policy treats it, e.g. 46 (EF) for voice. Set as the IPv4 ToS / IPv6 traffic class
+    // (dscp << 2) on the UDP send socket and the TCP streams. None keeps the OS default.
+    pub dscp: Option<u8>,
+
+    // UDP client: fill each EchoRequest payload with a pattern derived from its sequence
+    // number and check that the EchoReply carries it back unchanged. A mismatch is recorded
+    // as a Corrupted anomaly for the return direction.
+    pub verify_echo_payload: bool,
+
+    // Bytes the data and echo packet payloads are filled with. All zeros compress to nothing
+    // on WAN optimizers and compressing VPNs, overstating throughput; Random doesn't.
+    // verify_echo_payload takes precedence for the EchoRequests it checks.
+    pub payload_pattern: PayloadPattern,
+
+    // Reports show the forward and return one-way delays (from the echo server's receive
+    // timestamp) as columns beside RTT. Only meaningful if both clocks are synchronized.
+    pub report_one_way_delays: bool,
+
+    // Wire encoding for packets. true = bincode fixint (constant header size, the encoding
+    // `bincode::serialize` has always used); false = varint (smaller, but sizes vary).
+    // The two are not wire compatible, so both peers must use the same setting.
+    pub bincode_fixint: bool,
+
+    pub latency_stat: LatencyStat, // Statistic headlined as the RTT figure in reports
+
+    // Initial ramp-up period. Traffic is sent and charted as usual, but the headline
+    // (steady-state) throughput only counts bytes received after it. 0 = no warmup.
+    pub warmup_secs: u64,
+
+    // Transient UDP send errors (WouldBlock, ENOBUFS) are retried with exponential backoff
+    // starting at send_retry_backoff_ms; the send only fails after send_max_retries retries.
+    pub send_max_retries: u32,
+    pub send_retry_backoff_ms: u64,
+
+    pub final_sample_timing: FinalSampleTiming, // Where the last bandwidth interval ends
+    pub bandwidth_sample_interval_ms: u64, // Length of one bandwidth (and process usage) sample interval
+
+    // Expectations checked against the finished test, see TestSummary::check_assertions
+    pub assertions: Vec<crate::assertions::Assertion>,
+
+    pub repeat_interval_secs: u64, // Daemon mode: pause between the end of one run and the next
+
+    // Multicast: the server joins this group (on the default interface) and clients send to
+    // group:target_port instead of target_ip. Echo replies still come back unicast.
+    pub multicast_group: Option<IpAddr>,
+    pub multicast_ttl: u32, // Hop limit for multicast sends (IPv4 groups)
+
+    // UDP: spread packets over the destination ports first..=last (inclusive), e.g. to
+    // exercise port-based load balancing or ECMP hashing. Clients pick a random port in the
+    // range for each packet; servers also listen on every port in it. None = target_port only.
+    pub dest_port_range: Option<(u16, u16)>,
+
+    // UDP client/server: start and end-of-test signals go to a separate socket on this port,
+    // so the data socket carries nothing but data. On End the server stops shortly after
+    // instead of waiting out its grace period. None = no control channel.
+    pub udp_control_port: Option<u16>,
+    // What receive loops do with a well-formed packet of a type they don't serve, e.g. an Ack
+    // where Data is expected. Such packets are always counted in
+    // TestMetrics::unexpected_type_packets.
+    pub unexpected_packet_handling: UnexpectedPacketHandling,
+    // Which of target_ip's addresses to test when it is a hostname resolving to several
+    // (e.g. both IPv4 and IPv6). Applied by runner::run_test_per_address, which netstats-cli
+    // runs (--addresses); the GUI and the other runner entry points test the first.
+    pub multi_address_policy: MultiAddressPolicy,
+
+    pub output_formats: Vec<ReportFormat>, // Report files written after the test, one per format
+    // Longer series are decimated to this many points for the HTML charts (at least 3 are
+    // kept); the JSON and CSV exports always have every point. None ("off" in files) = no limit.
+    #[serde(with = "off_when_none")]
+    pub max_chart_points: Option<usize>,
+
+    // Live anomaly feed, e.g. for a UI: every anomaly is also sent here as it is recorded.
+    // Sends never block the test; events are dropped while the channel is full.
+    #[serde(skip)]
+    pub anomaly_tx: Option<mpsc::Sender<crate::anomalies::AnomalyEvent>>,
+}
+
+/// Report file formats written by `reporter::write_run_artifacts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ReportFormat {
+    Html,     // Full report with charts
+    Json,     // The whole TestSummary, for dashboards and records
+    Csv,      // Bandwidth-over-time series: time_sec,mbps
+    Markdown, // Summary tables for pasting into tickets
+    OpenMetrics, // Counters and an RTT histogram with an exemplar, for Prometheus-style scrapers
+}
+
+impl ReportFormat {
+    /// File name of this format's report inside a run's output directory.
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            ReportFormat::Html => "report.html",
+            ReportFormat::Json => "report.json",
+            ReportFormat::Csv => "bandwidth.csv",
+            ReportFormat::Markdown => "report.md",
+            ReportFormat::OpenMetrics => "metrics.txt",
+        }
+    }
+}
+
+/// Timestamp used for the final bandwidth sample when the receiver shuts down.
+/// The receiver lingers for a grace period after the test to catch trailing packets;
+/// ending the last interval there would stretch it and drag down the final chart point.
+/// A cancelled test's configured duration is cut short at the cancel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FinalSampleTiming {
+    LastPacket,         // When the last packet was received (falls back to the configured duration)
+    ConfiguredDuration, // At test_duration_secs, regardless of traffic
+}
+
+/// Which statistic is headlined as "the" RTT in reports.
+/// All raw RTT stats (min/max/mean) stay available regardless of this choice.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LatencyStat {
+    Mean,
+    Median,
+    TrimmedMean(f64), // Percentage of samples dropped from EACH end before averaging, e.g. 10.0
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+impl std::fmt::Display for LatencyStat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LatencyStat::Mean => write!(f, "Mean"),
+            LatencyStat::Median => write!(f, "Median"),
+            LatencyStat::TrimmedMean(percent) => write!(f, "Trimmed Mean ({}%)", percent),
+        }
+    }
+}
+
+/// What a receive loop does with a packet of a type it doesn't serve (see
+/// `TestConfig::unexpected_packet_handling`). Control packets on a data socket are
+/// dropped either way: they belong on the control channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnexpectedPacketHandling {
+    Drop,   // Left out of the receive metrics
+    Accept, // Also recorded as a received packet, like Data
+}
+
+/// Which resolved addresses of a hostname target get tested (see
+/// `TestConfig::multi_address_policy`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MultiAddressPolicy {
+    First,         // Only the first address the resolver returns
+    AllSequential, // Each address in turn, one summary per address
+    AllParallel,   // All addresses at once, one summary per address
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TcpBidirectionalMode {
     DualStream, // Each peer initiates a separate stream for sending
     SingleStream, // One peer initiates, both use that single stream
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+impl fmt::Display for TcpBidirectionalMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TcpBidirectionalMode::DualStream => write!(f, "Dual Stream"),
+            TcpBidirectionalMode::SingleStream => write!(f, "Single Stream"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TestMode {
     Client,       // Only sends data, receives ACKs/responses if applicable
     Server,       // Only receives data, sends ACKs/responses if applicable
     Bidirectional, // Both sends and receives test data streams simultaneously
 }
 
+impl fmt::Display for TestMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestMode::Client => write!(f, "Client"),
+            TestMode::Server => write!(f, "Server"),
+            TestMode::Bidirectional => write!(f, "Bidirectional"),
+        }
+    }
+}
+
 impl Default for TestConfig {
     fn default() -> Self {
         TestConfig {
             target_ip: "127.0.0.1".to_string(),
             target_port: 5001, // Common for iperf
             test_duration_secs: 10,
+            test_duration_ms: None,
+            stop_after_packets: None,
+            prewarm_packets: 0,
+            local_listen_port: None,
             tick_rate_hz: 20,    // e.g., 20 ticks per second
+            packets_per_tick: 1,
+            send_jitter_ms: None,
+            send_rng_seed: None,
             packet_size_bytes: 1024,
             packet_size_range: None, // Default to fixed size
             protocol: Protocol::Udp,
             test_mode: TestMode::Client, // Default to client mode
             tcp_bidirectional_mode: Some(TcpBidirectionalMode::DualStream), // Default for TCP BiDi
+            tx_tick_rate_hz: None,
+            rx_tick_rate_hz: None,
+            target_bitrate_bps: None,
+            anomaly_detection_enabled: true,
             latency_spike_threshold_ms: Some(200), // Default 200ms for latency spike
             jitter_spike_threshold_ms: Some(50),   // Default 50ms for jitter spike
             packet_loss_threshold_percent: Some(5.0), // Default 5% packet loss threshold
+            sustained_latency_multiplier: Some(3.0), // 3x the minimum RTT...
+            sustained_latency_secs: 2,               // ...for 2 seconds
+            reorder_window: 0,
+            rtt_timeout_ms: 200,
+            bincode_fixint: true,
+            latency_stat: LatencyStat::Mean,
+            warmup_secs: 0,
+            send_max_retries: 5,
+            send_retry_backoff_ms: 1,
+            final_sample_timing: FinalSampleTiming::LastPacket,
+            bandwidth_sample_interval_ms: 1000,
+            assertions: Vec::new(),
+            repeat_interval_secs: 60,
+            multicast_group: None,
+            multicast_ttl: 1, // Stay on the local network segment
+            dest_port_range: None,
+            udp_control_port: None,
+            unexpected_packet_handling: UnexpectedPacketHandling::Drop,
+            multi_address_policy: MultiAddressPolicy::First,
+            output_formats: vec![ReportFormat::Html],
+            anomaly_tx: None,
+            rtt_skip_first: 0,
+            max_latency_samples: 100_000,
+            monitor_process: false,
+            record_interarrival: false,
+            record_size_histogram: false,
+            record_sequence_ranges: false,
+            max_chart_points: Some(2000),
+            tcp_max_frame_bytes: 1024 * 1024,
+            parallel_streams: 1,
+            parse_workers: 0,
+            recv_buffer_pool: 0,
+            recv_buffer_bytes: None,
+            reuse_addr: false,
+            runtime_worker_threads: None,
+            max_test_retries: 0,
+            set_df: None,
+            dscp: None,
+            verify_echo_payload: false,
+            payload_pattern: PayloadPattern::Zeros,
+            report_one_way_delays: true,
         }
     }
 }
 
 impl TestConfig {
+    /// Packets sent per tick, at least 1.
+    pub fn tick_burst(&self) -> u32 {
+        self.packets_per_tick.max(1)
+    }
+
+    /// Tick rate that sends `bits_per_sec` with the current packet size and burst, at least 1 Hz.
+    /// E.g. 100 Mbps of 1250-byte packets, one per tick, is 10 kHz. Use `units::parse_bitrate_bps`
+    /// for strings like "100Mbps".
+    pub fn tick_rate_for_bitrate(&self, bits_per_sec: u64) -> u32 {
+        let bits_per_tick = (self.packet_size_bytes.max(1) * 8) as f64 * self.tick_burst() as f64;
+        (bits_per_sec as f64 / bits_per_tick).round().clamp(1.0, u32::MAX as f64) as u32
+    }
+
+    /// Tick rate of a send loop. In bidirectional mode the primary (tx) and secondary (rx)
+    /// loops may override tick_rate_hz, see `tx_tick_rate_hz`.
+    pub fn send_tick_rate_hz(&self, is_primary_sender: bool) -> u32 {
+        let direction_override = match (self.test_mode, is_primary_sender) {
+            (TestMode::Bidirectional, true) => self.tx_tick_rate_hz,
+            (TestMode::Bidirectional, false) => self.rx_tick_rate_hz,
+            _ => None,
+        };
+        direction_override.unwrap_or(self.tick_rate_hz)
+    }
+
+    /// Zero for a tick_rate_hz of 0, which means "as fast as possible": there is no tick.
     pub fn tick_interval(&self) -> Duration {
-        Duration::from_secs_f64(1.0 / self.tick_rate_hz as f64)
+        match self.tick_rate_hz {
+            0 => Duration::ZERO,
+            tick_rate_hz => Duration::from_secs_f64(1.0 / tick_rate_hz as f64),
+        }
     }
 
     pub fn total_duration(&self) -> Duration {
-        Duration::from_secs(self.test_duration_secs)
+        match self.test_duration_ms {
+            Some(ms) => Duration::from_millis(ms),
+            None => Duration::from_secs(self.test_duration_secs),
+        }
+    }
+
+    /// True for a zero duration with `stop_after_packets`: sending ends on the count alone.
+    pub fn is_count_limited(&self) -> bool {
+        self.total_duration().is_zero() && self.stop_after_packets.is_some()
+    }
+
+    /// Rejects a zero test duration, which would end the test as it starts with empty
+    /// results, unless `stop_after_packets` ends the test instead. That only works for a
+    /// client: server and bidirectional receive sides run for the duration, and a count
+    /// gives them no end time.
+    pub fn validate_duration(&self) -> Result<(), ConfigError> {
+        if self.total_duration().is_zero() && self.stop_after_packets.is_none() {
+            return Err(ConfigError::ZeroDuration);
+        }
+        if self.is_count_limited() && self.test_mode != TestMode::Client {
+            return Err(ConfigError::CountLimitedReceiver);
+        }
+        Ok(())
+    }
+
+    /// Checks for settings no test can run with, before anything is bound or sent. The one
+    /// check shared by run_network_test, the CLI and the GUI.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.validate_duration()?;
+        if self.target_ip.trim().is_empty() && self.test_mode != TestMode::Server {
+            return Err(ConfigError::EmptyTargetIp);
+        }
+        if let Some((min, max)) = self.packet_size_range {
+            if min == 0 || min > max {
+                return Err(ConfigError::InvalidPacketSizeRange { min, max });
+            }
+        }
+        if let Some((first, last)) = self.dest_port_range {
+            if first > last {
+                return Err(ConfigError::InvalidPortRange { first, last });
+            }
+        }
+        if self.parallel_streams == 0 {
+            return Err(ConfigError::ZeroParallelStreams);
+        }
+        if self.runtime_worker_threads == Some(0) {
+            return Err(ConfigError::ZeroWorkerThreads);
+        }
+        if self.recv_buffer_pool > 0 && self.parse_workers == 0 {
+            return Err(ConfigError::BufferPoolWithoutParseWorkers);
+        }
+        match self.dscp {
+            Some(dscp) if dscp > 63 => Err(ConfigError::DscpOutOfRange(dscp)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn bandwidth_sample_interval(&self) -> Duration {
+        Duration::from_millis(self.bandwidth_sample_interval_ms.max(1))
+    }
+
+    /// True if the test ends before the first regular bandwidth sample would be taken.
+    /// Unknown for a count-limited test, so false.
+    pub fn is_shorter_than_sample_interval(&self) -> bool {
+        !self.is_count_limited() && self.total_duration() < self.bandwidth_sample_interval()
+    }
+
+    pub fn warmup_duration(&self) -> Duration {
+        Duration::from_secs(self.warmup_secs)
+    }
+
+    pub fn rtt_timeout(&self) -> Duration {
+        Duration::from_millis(self.rtt_timeout_ms)
+    }
+
+    pub fn repeat_interval(&self) -> Duration {
+        Duration::from_secs(self.repeat_interval_secs)
+    }
+
+    /// Reads a TOML config file. Keys are the field names; missing keys keep their defaults,
+    /// e.g. a file with just `target_ip = "10.0.0.5"` and `protocol = "Tcp"` is valid.
+    pub fn from_toml_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::from_toml_str(&contents)
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<Self, ConfigError> {
+        toml::from_str(contents).map_err(|e| ConfigError::InvalidConfigFile(e.to_string()))
+    }
+
+    /// Writes every field to a TOML file that `from_toml_file` reads back to the same config
+    /// (`anomaly_tx` excepted). Options left at None are omitted, or written as "off" for
+    /// those whose default is Some.
+    pub fn to_toml_file(&self, path: impl AsRef<Path>) -> Result<(), ConfigError> {
+        std::fs::write(path, self.to_toml_string()?)?;
+        Ok(())
+    }
+
+    pub fn to_toml_string(&self) -> Result<String, ConfigError> {
+        toml::to_string(self).map_err(|e| ConfigError::InvalidConfigFile(e.to_string()))
+    }
+}
+
+/// Serde for the Option fields that default to Some: None is written as "off", since TOML
+/// has no null and leaving the key out would load the default again. Binary formats (the
+/// bincode report) keep the plain Option encoding.
+mod off_when_none {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    const OFF: &str = "off";
+
+    pub fn serialize<T: Serialize, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return value.serialize(serializer);
+        }
+        match value {
+            Some(value) => value.serialize(serializer),
+            None => serializer.serialize_str(OFF),
+        }
+    }
+
+    pub fn deserialize<'de, T: Deserialize<'de>, D: Deserializer<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+        if !deserializer.is_human_readable() {
+            return Option::deserialize(deserializer);
+        }
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum ValueOrOff<T> {
+            Value(T),
+            Text(String),
+        }
+        match ValueOrOff::deserialize(deserializer)? {
+            ValueOrOff::Value(value) => Ok(Some(value)),
+            ValueOrOff::Text(text) if text.eq_ignore_ascii_case(OFF) => Ok(None),
+            ValueOrOff::Text(text) => Err(serde::de::Error::custom(format!("invalid value '{}', expected a value or \"{}\"", text, OFF))),
+        }
+    }
+}
+
+/// One entry of a multi-target run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetSpec {
+    pub host: String, // IP or hostname, without IPv6 brackets
+    pub port: u16,
+    pub label: Option<String>,
+}
+
+/// A list of targets to test with the same parameters, e.g. a fleet of hosts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MultiTargetConfig {
+    pub targets: Vec<TargetSpec>,
+}
+
+impl MultiTargetConfig {
+    /// Reads a target list file. Each line is `host:port` optionally followed by a label,
+    /// e.g. `10.0.0.5:5001 rack-3 switch`. IPv6 hosts use brackets: `[::1]:5001`.
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let mut targets = Vec::new();
+        for (index, raw_line) in contents.lines().enumerate() {
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| ConfigError::InvalidTarget {
+                line_number: index + 1,
+                line: line.to_string(),
+                reason: reason.to_string(),
+            };
+
+            let (address, label) = match line.split_once(char::is_whitespace) {
+                Some((address, rest)) => (address, Some(rest.trim().to_string())),
+                None => (line, None),
+            };
+            let (host, port) = address.rsplit_once(':').ok_or_else(|| invalid("expected host:port"))?;
+            let host = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+            if host.is_empty() {
+                return Err(invalid("empty host"));
+            }
+            if host.contains(':') && !address.starts_with('[') {
+                return Err(invalid("IPv6 addresses must be written as [addr]:port"));
+            }
+            let port: u16 = port.parse().map_err(|_| invalid("port must be a number between 1 and 65535"))?;
+            if port == 0 {
+                return Err(invalid("port must be a number between 1 and 65535"));
+            }
+
+            targets.push(TargetSpec { host: host.to_string(), port, label });
+        }
+        Ok(MultiTargetConfig { targets })
+    }
+
+    /// One TestConfig per target, each a copy of `base` pointed at that target.
+    pub fn to_test_configs(&self, base: &TestConfig) -> Vec<TestConfig> {
+        self.targets
+            .iter()
+            .map(|target| TestConfig {
+                target_ip: target.host.clone(),
+                target_port: target.port,
+                ..base.clone()
+            })
+            .collect()
     }
 }
 
@@ -76,16 +719,179 @@ mod tests {
     fn test_default_config() {
         let config = TestConfig::default();
         assert_eq!(config.target_ip, "127.0.0.1");
-        assert_eq!(config.target_port, 5201); // As per current default in appwindow.slint (oops, core default is 5001)
-                                            // Let's ensure core default is consistent or test against its actual value
-        assert_eq!(config.target_port, 5001); // Corrected to actual TestConfig default
+        assert_eq!(config.target_port, 5001);
         assert_eq!(config.test_duration_secs, 10);
+        assert_eq!(config.stop_after_packets, None);
+        assert_eq!(config.prewarm_packets, 0);
+        assert_eq!(config.local_listen_port, None);
+        assert_eq!(config.test_duration_ms, None);
         assert_eq!(config.tick_rate_hz, 20);
+        assert_eq!(config.packets_per_tick, 1);
+        assert_eq!(config.send_jitter_ms, None);
+        assert_eq!(config.send_rng_seed, None);
         assert_eq!(config.packet_size_bytes, 1024);
         assert_eq!(config.protocol, Protocol::Udp);
         assert_eq!(config.test_mode, TestMode::Client);
         assert!(config.packet_size_range.is_none());
         assert_eq!(config.tcp_bidirectional_mode, Some(TcpBidirectionalMode::DualStream));
+        assert_eq!(config.tx_tick_rate_hz, None);
+        assert_eq!(config.target_bitrate_bps, None);
+        assert_eq!(config.rx_tick_rate_hz, None);
+        assert_eq!(config.rtt_timeout_ms, 200);
+        assert!(config.bincode_fixint);
+        assert_eq!(config.latency_stat, LatencyStat::Mean);
+        assert_eq!(config.warmup_secs, 0);
+        assert_eq!(config.send_max_retries, 5);
+        assert_eq!(config.final_sample_timing, FinalSampleTiming::LastPacket);
+        assert_eq!(config.bandwidth_sample_interval_ms, 1000);
+        assert!(config.assertions.is_empty());
+        assert_eq!(config.sustained_latency_multiplier, Some(3.0));
+        assert_eq!(config.sustained_latency_secs, 2);
+        assert_eq!(config.reorder_window, 0);
+        assert_eq!(config.repeat_interval_secs, 60);
+        assert!(config.multicast_group.is_none());
+        assert_eq!(config.multicast_ttl, 1);
+        assert_eq!(config.dest_port_range, None);
+        assert_eq!(config.udp_control_port, None);
+        assert_eq!(config.unexpected_packet_handling, UnexpectedPacketHandling::Drop);
+        assert_eq!(config.multi_address_policy, MultiAddressPolicy::First);
+        assert_eq!(config.output_formats, vec![ReportFormat::Html]);
+        assert!(config.anomaly_tx.is_none());
+        assert_eq!(config.rtt_skip_first, 0);
+        assert_eq!(config.max_latency_samples, 100_000);
+        assert!(!config.monitor_process);
+        assert!(!config.record_interarrival);
+        assert!(!config.record_size_histogram);
+        assert!(!config.record_sequence_ranges);
+        assert_eq!(config.tcp_max_frame_bytes, 1024 * 1024);
+        assert_eq!(config.parallel_streams, 1);
+        assert_eq!(config.parse_workers, 0);
+        assert_eq!(config.recv_buffer_pool, 0);
+        assert_eq!(config.recv_buffer_bytes, None);
+        assert!(!config.reuse_addr);
+        assert_eq!(config.runtime_worker_threads, None);
+        assert_eq!(config.max_test_retries, 0);
+        assert_eq!(config.set_df, None);
+        assert_eq!(config.dscp, None);
+        assert!(!config.verify_echo_payload);
+        assert_eq!(config.payload_pattern, PayloadPattern::Zeros);
+        assert!(config.report_one_way_delays);
+        assert!(config.anomaly_detection_enabled);
+        assert_eq!(config.max_chart_points, Some(2000));
+    }
+
+    #[test]
+    fn test_config_from_partial_toml() {
+        let config = TestConfig::from_toml_str(
+            "target_ip = \"10.0.0.5\"\nprotocol = \"Tcp\"\nassertions = [\"loss < 1%\"]\nrepeat_interval_secs = 300\n",
+        )
+        .unwrap();
+        assert_eq!(config.target_ip, "10.0.0.5");
+        assert_eq!(config.protocol, Protocol::Tcp);
+        assert_eq!(config.assertions.len(), 1);
+        assert_eq!(config.repeat_interval(), Duration::from_secs(300));
+        assert_eq!(config.target_port, 5001); // Not in the file: default
+
+        assert!(matches!(TestConfig::from_toml_str("protocol = \"Carrier Pigeon\""), Err(ConfigError::InvalidConfigFile(_))));
+        assert!(matches!(TestConfig::from_toml_str("assertions = [\"loss = 1\"]"), Err(ConfigError::InvalidConfigFile(_))));
+    }
+
+    fn assert_toml_round_trip(config: &TestConfig) {
+        let path = std::env::temp_dir().join(format!("netstats_config_{}_{}.toml", std::process::id(), config.target_port));
+        config.to_toml_file(&path).expect("Writing the config failed");
+        let loaded = TestConfig::from_toml_file(&path);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        // TestConfig isn't PartialEq (anomaly_tx), but its Debug output covers every field
+        assert_eq!(format!("{:?}", loaded.expect("Reading the config back failed")), format!("{:?}", config), "{}", contents);
+    }
+
+    #[test]
+    fn test_config_toml_round_trip_fully_populated() {
+        let config = TestConfig {
+            target_ip: "fe80::1%eth0".to_string(),
+            target_port: 6100,
+            test_duration_secs: 30,
+            test_duration_ms: Some(250),
+            tick_rate_hz: 120,
+            packets_per_tick: 3,
+            send_jitter_ms: Some(4),
+            send_rng_seed: Some(42),
+            packet_size_bytes: 900,
+            packet_size_range: Some((64, 1400)),
+            protocol: Protocol::Tcp,
+            test_mode: TestMode::Bidirectional,
+            tcp_bidirectional_mode: Some(TcpBidirectionalMode::SingleStream),
+            tx_tick_rate_hz: Some(10),
+            rx_tick_rate_hz: Some(500),
+            target_bitrate_bps: Some(10_000_000),
+            latency_spike_threshold_ms: Some(80),
+            jitter_spike_threshold_ms: Some(15),
+            packet_loss_threshold_percent: Some(0.5),
+            sustained_latency_multiplier: Some(2.5),
+            set_df: Some(true),
+            dscp: Some(46),
+            latency_stat: LatencyStat::TrimmedMean(10.0),
+            final_sample_timing: FinalSampleTiming::ConfiguredDuration,
+            assertions: vec!["loss < 1%".parse().unwrap(), "p95_rtt <= 30ms".parse().unwrap()],
+            multicast_group: Some("239.1.2.3".parse().unwrap()),
+            dest_port_range: Some((7000, 7010)),
+            udp_control_port: Some(6101),
+            unexpected_packet_handling: UnexpectedPacketHandling::Accept,
+            multi_address_policy: MultiAddressPolicy::AllParallel,
+            output_formats: vec![ReportFormat::Json, ReportFormat::Csv, ReportFormat::OpenMetrics],
+            max_chart_points: Some(500),
+            runtime_worker_threads: Some(2),
+            max_test_retries: 3,
+            parallel_streams: 4,
+            recv_buffer_bytes: Some(4 * 1024 * 1024),
+            payload_pattern: PayloadPattern::Fixed(0xAB),
+            ..Default::default()
+        };
+        assert_toml_round_trip(&config);
+    }
+
+    #[test]
+    fn test_config_toml_round_trip_optionals_off() {
+        let config = TestConfig {
+            target_port: 6102,
+            tcp_bidirectional_mode: None,
+            latency_spike_threshold_ms: None,
+            jitter_spike_threshold_ms: None,
+            packet_loss_threshold_percent: None,
+            sustained_latency_multiplier: None,
+            max_chart_points: None,
+            output_formats: Vec::new(),
+            ..Default::default()
+        };
+        assert_toml_round_trip(&config);
+        assert_toml_round_trip(&TestConfig::default());
+
+        let contents = config.to_toml_string().unwrap();
+        assert!(contents.contains("latency_spike_threshold_ms = \"off\"\n"), "{}", contents);
+        assert!(!contents.contains("send_jitter_ms"), "Defaults-to-None options are left out:\n{}", contents);
+        assert!(matches!(TestConfig::from_toml_str("max_chart_points = \"never\""), Err(ConfigError::InvalidConfigFile(_))));
+    }
+
+    #[test]
+    fn test_config_units_in_toml() {
+        let config = TestConfig::from_toml_str("packet_size_bytes = \"1KiB\"\ntick_rate_hz = \"2kHz\"\n").unwrap();
+        assert_eq!(config.packet_size_bytes, 1024);
+        assert_eq!(config.tick_rate_hz, 2000);
+        let plain = TestConfig::from_toml_str("packet_size_bytes = 1400\ntick_rate_hz = 50\n").unwrap();
+        assert_eq!((plain.packet_size_bytes, plain.tick_rate_hz), (1400, 50));
+        assert_eq!(TestConfig::from_toml_str("target_bitrate_bps = \"10Mbps\"").unwrap().target_bitrate_bps, Some(10_000_000));
+        assert_eq!(TestConfig::from_toml_str("target_bitrate_bps = 64000").unwrap().target_bitrate_bps, Some(64_000));
+        assert_eq!(TestConfig::from_toml_str("recv_buffer_bytes = \"4MiB\"").unwrap().recv_buffer_bytes, Some(4 * 1024 * 1024));
+
+        match TestConfig::from_toml_str("packet_size_bytes = \"12 parsecs\"") {
+            Err(ConfigError::InvalidConfigFile(reason)) => assert!(reason.contains("unknown unit 'parsecs'"), "{}", reason),
+            other => panic!("expected InvalidConfigFile, got {:?}", other),
+        }
+
+        let config = TestConfig { packet_size_bytes: 1250, ..Default::default() };
+        let bps = crate::units::parse_bitrate_bps("100Mbps").unwrap();
+        assert_eq!(config.tick_rate_for_bitrate(bps), 10_000);
     }
 
     #[test]
@@ -98,6 +904,64 @@ mod tests {
 
         let config_1000hz = TestConfig { tick_rate_hz: 1000, ..Default::default() };
         assert_eq!(config_1000hz.tick_interval(), Duration::from_millis(1));
+
+        let config_afap = TestConfig { tick_rate_hz: 0, ..Default::default() };
+        assert_eq!(config_afap.tick_interval(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_validate_rejects_each_invalid_setting() {
+        let valid = TestConfig {
+            packet_size_range: Some((64, 64)), dest_port_range: Some((7000, 7000)), dscp: Some(63),
+            parse_workers: 2, recv_buffer_pool: 64,
+            ..Default::default()
+        };
+        assert!(valid.validate().is_ok());
+        assert!(TestConfig { target_ip: String::new(), test_mode: TestMode::Server, ..Default::default() }.validate().is_ok());
+
+        let invalid = [
+            (TestConfig { test_duration_secs: 0, ..Default::default() }, "ZeroDuration"),
+            (TestConfig { target_ip: " ".to_string(), ..Default::default() }, "EmptyTargetIp"),
+            (TestConfig { target_ip: String::new(), test_mode: TestMode::Bidirectional, ..Default::default() }, "EmptyTargetIp"),
+            (TestConfig { packet_size_range: Some((1400, 64)), ..Default::default() }, "InvalidPacketSizeRange"),
+            (TestConfig { packet_size_range: Some((0, 64)), ..Default::default() }, "InvalidPacketSizeRange"),
+            (TestConfig { dest_port_range: Some((7010, 7000)), ..Default::default() }, "InvalidPortRange"),
+            (TestConfig { dscp: Some(64), ..Default::default() }, "DscpOutOfRange"),
+            (TestConfig { parallel_streams: 0, ..Default::default() }, "ZeroParallelStreams"),
+            (TestConfig { runtime_worker_threads: Some(0), ..Default::default() }, "ZeroWorkerThreads"),
+            (TestConfig { recv_buffer_pool: 64, ..Default::default() }, "BufferPoolWithoutParseWorkers"),
+        ];
+        for (config, expected) in invalid {
+            match config.validate() {
+                Err(e) => {
+                    assert!(format!("{:?}", e).starts_with(expected), "Expected {}, got {:?}", expected, e);
+                    assert!(!e.to_string().is_empty());
+                }
+                Ok(()) => panic!("Expected {} for {:?}", expected, config),
+            }
+        }
+    }
+
+    #[test]
+    fn test_zero_duration_needs_a_packet_count() {
+        assert!(TestConfig::default().validate_duration().is_ok());
+        let zero = TestConfig { test_duration_secs: 0, ..Default::default() };
+        assert!(matches!(zero.validate_duration(), Err(ConfigError::ZeroDuration)));
+        let zero_ms = TestConfig { test_duration_ms: Some(0), ..Default::default() };
+        assert!(matches!(zero_ms.validate_duration(), Err(ConfigError::ZeroDuration)));
+        assert!(!zero.is_count_limited());
+
+        let counted = TestConfig { test_duration_secs: 0, stop_after_packets: Some(100), ..Default::default() };
+        assert!(counted.validate_duration().is_ok());
+        assert!(counted.is_count_limited());
+        assert!(!counted.is_shorter_than_sample_interval());
+        for test_mode in [TestMode::Server, TestMode::Bidirectional] {
+            let receiver = TestConfig { test_mode, ..counted.clone() };
+            assert!(matches!(receiver.validate_duration(), Err(ConfigError::CountLimitedReceiver)));
+            // With a duration as well, the count just ends sending early
+            let both = TestConfig { test_duration_secs: 5, ..receiver };
+            assert!(both.validate_duration().is_ok());
+        }
     }
 
     #[test]
@@ -107,6 +971,11 @@ mod tests {
 
         let config_1s = TestConfig { test_duration_secs: 1, ..Default::default() };
         assert_eq!(config_1s.total_duration(), Duration::from_secs(1));
+        assert!(!config_1s.is_shorter_than_sample_interval());
+
+        let config_200ms = TestConfig { test_duration_secs: 1, test_duration_ms: Some(200), ..Default::default() };
+        assert_eq!(config_200ms.total_duration(), Duration::from_millis(200));
+        assert!(config_200ms.is_shorter_than_sample_interval());
     }
 
     #[test]
@@ -121,6 +990,7 @@ mod tests {
             protocol: Protocol::Tcp,
             test_mode: TestMode::Bidirectional,
             tcp_bidirectional_mode: Some(TcpBidirectionalMode::SingleStream),
+            ..Default::default()
         };
         assert_eq!(config.target_ip, "192.168.1.100");
         assert_eq!(config.target_port, 8888);
@@ -132,4 +1002,49 @@ mod tests {
         assert_eq!(config.test_mode, TestMode::Bidirectional);
         assert_eq!(config.tcp_bidirectional_mode, Some(TcpBidirectionalMode::SingleStream));
     }
+
+    #[test]
+    fn test_multi_target_config_from_file() {
+        let path = std::env::temp_dir().join(format!("netstats_targets_test_{}.txt", std::process::id()));
+        std::fs::write(&path, "\
+# Fleet targets
+10.0.0.5:5001 rack-3 switch
+
+  # indented comment
+edge.example.com:5201
+[::1]:6000 loopback-v6
+").unwrap();
+
+        let multi = MultiTargetConfig::from_file(&path).expect("Target file should parse");
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(multi.targets, vec![
+            TargetSpec { host: "10.0.0.5".to_string(), port: 5001, label: Some("rack-3 switch".to_string()) },
+            TargetSpec { host: "edge.example.com".to_string(), port: 5201, label: None },
+            TargetSpec { host: "::1".to_string(), port: 6000, label: Some("loopback-v6".to_string()) },
+        ]);
+
+        let base = TestConfig { test_duration_secs: 3, ..Default::default() };
+        let configs = multi.to_test_configs(&base);
+        assert_eq!(configs.len(), 3);
+        assert_eq!(configs[1].target_ip, "edge.example.com");
+        assert_eq!(configs[1].target_port, 5201);
+        assert_eq!(configs[1].test_duration_secs, 3);
+    }
+
+    #[test]
+    fn test_multi_target_config_rejects_bad_lines() {
+        for (contents, bad_line) in [
+            ("10.0.0.1:5001\nno-port-here\n", 2),
+            ("10.0.0.1:99999\n", 1),
+            ("# comment\n:5001\n", 2),
+            ("::1:5001\n", 1),
+        ] {
+            match MultiTargetConfig::parse(contents) {
+                Err(ConfigError::InvalidTarget { line_number, .. }) => assert_eq!(line_number, bad_line, "{:?}", contents),
+                other => panic!("Expected InvalidTarget for {:?}, got {:?}", contents, other),
+            }
+        }
+        assert!(matches!(MultiTargetConfig::from_file("/nonexistent/netstats/targets.txt"), Err(ConfigError::IoError(_))));
+    }
 }
@@ -0,0 +1,162 @@
+// Run-time control of a test in progress (pause/resume, cancel)
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+#[derive(Debug, Default)]
+struct PauseClock {
+    paused_since: Option<Instant>,
+    paused_total: Duration, // Completed pauses only
+}
+
+/// Shared pause switch for a running test. While paused the send loops stop sending
+/// (connections stay open) and the duration clock stops: the test runs for
+/// `test_duration_secs` of *active* time. Pausing only affects the local loops; a remote
+/// peer keeps its own clock, so pause both ends or expect the peer to time out first.
+///
+/// `cancel` ends the test early instead: the loops stop as at the end of the duration, so
+/// the test still returns Ok and its summary covers the part that ran.
+#[derive(Default)]
+pub struct TestControl {
+    paused: AtomicBool,
+    clock: Mutex<PauseClock>,
+    resumed: Notify,
+    cancelled: AtomicBool,
+    cancel_requested: Notify,
+}
+
+impl TestControl {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Pauses the test. No-op if already paused.
+    pub fn pause(&self) {
+        let mut clock = self.clock.lock().unwrap();
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            clock.paused_since = Some(Instant::now());
+        }
+    }
+
+    /// Resumes a paused test. No-op if not paused.
+    pub fn resume(&self) {
+        let mut clock = self.clock.lock().unwrap();
+        if self.paused.swap(false, Ordering::SeqCst) {
+            if let Some(since) = clock.paused_since.take() {
+                clock.paused_total += since.elapsed();
+            }
+            self.resumed.notify_waiters();
+        }
+    }
+
+    /// Stops the test early. Also releases a paused test, which then ends without resuming.
+    pub fn cancel(&self) {
+        if !self.cancelled.swap(true, Ordering::SeqCst) {
+            self.cancel_requested.notify_waiters();
+            self.resumed.notify_waiters();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns once the test is cancelled, for a `tokio::select!` arm.
+    pub async fn cancelled(&self) {
+        loop {
+            let cancel_requested = self.cancel_requested.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            cancel_requested.await;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Total time spent paused so far, including a pause still in progress.
+    pub fn paused_duration(&self) -> Duration {
+        let clock = self.clock.lock().unwrap();
+        clock.paused_total + clock.paused_since.map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    /// Active (unpaused) time since `start`.
+    pub fn active_elapsed(&self, start: Instant) -> Duration {
+        start.elapsed().saturating_sub(self.paused_duration())
+    }
+
+    /// Returns once the test is not paused (immediately if it isn't) or is cancelled.
+    pub async fn wait_until_resumed(&self) {
+        loop {
+            // Register before checking, so a resume in between isn't missed
+            let resumed = self.resumed.notified();
+            if !self.is_paused() || self.is_cancelled() {
+                return;
+            }
+            resumed.await;
+        }
+    }
+}
+
+impl std::fmt::Debug for TestControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TestControl")
+            .field("paused", &self.is_paused())
+            .field("paused_duration", &self.paused_duration())
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pause_resume_tracks_paused_time() {
+        let control = std::sync::Arc::new(TestControl::new());
+        assert!(!control.is_paused());
+        control.wait_until_resumed().await; // Not paused: returns immediately
+
+        control.pause();
+        control.pause(); // Repeated pause doesn't restart the clock
+        assert!(control.is_paused());
+
+        let waiter = {
+            let control = std::sync::Arc::clone(&control);
+            tokio::spawn(async move { control.wait_until_resumed().await })
+        };
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!waiter.is_finished());
+
+        control.resume();
+        tokio::time::timeout(Duration::from_secs(1), waiter).await.expect("Waiter not woken").unwrap();
+
+        let paused = control.paused_duration();
+        assert!(paused >= Duration::from_millis(100), "paused for {:?}", paused);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(control.paused_duration(), paused, "clock must stop after resume");
+    }
+
+    #[tokio::test]
+    async fn test_cancel_wakes_waiters_and_paused_loops() {
+        let control = std::sync::Arc::new(TestControl::new());
+        control.pause();
+        let waiters = {
+            let control = std::sync::Arc::clone(&control);
+            tokio::spawn(async move { tokio::join!(control.cancelled(), control.wait_until_resumed()) })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiters.is_finished());
+
+        control.cancel();
+        tokio::time::timeout(Duration::from_secs(1), waiters).await.expect("Waiters not woken").unwrap();
+        assert!(control.is_cancelled());
+        assert!(control.is_paused(), "cancel doesn't resume");
+        control.cancelled().await; // Already cancelled: returns immediately
+    }
+}
@@ -0,0 +1,161 @@
+// Long-running service mode: repeat the test from a config file, reloading it on SIGHUP
+
+use crate::config::{ConfigError, TestConfig};
+use crate::runner::{run_test_with_hooks, TestHooks};
+use std::path::Path;
+use tokio::sync::mpsc;
+
+/// Control messages for `run_monitor`, normally produced by the signal handlers in `run_daemon`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DaemonSignal {
+    Reload,   // Re-read the config file and restart the monitor loop with it (SIGHUP)
+    Shutdown, // Stop after cleaning up the current run (SIGINT/SIGTERM)
+}
+
+/// Runs as a service: repeats the test described by the TOML file at `config_path` every
+/// `repeat_interval_secs`, until SIGINT or SIGTERM. SIGHUP re-reads the file and restarts
+/// the loop with the new parameters without exiting; the current run is abandoned.
+///
+/// Fails only if the initial config can't be loaded or the signal handlers can't be installed.
+pub async fn run_daemon(config_path: impl AsRef<Path>) -> Result<(), ConfigError> {
+    let (signal_tx, signal_rx) = mpsc::channel(4);
+    install_signal_handlers(signal_tx)?;
+    run_monitor(config_path.as_ref(), signal_rx, &TestHooks::default()).await
+}
+
+#[cfg(unix)]
+fn install_signal_handlers(signal_tx: mpsc::Sender<DaemonSignal>) -> Result<(), ConfigError> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::spawn(async move {
+        loop {
+            let daemon_signal = tokio::select! {
+                _ = hangup.recv() => DaemonSignal::Reload,
+                _ = terminate.recv() => DaemonSignal::Shutdown,
+                _ = interrupt.recv() => DaemonSignal::Shutdown,
+            };
+            if signal_tx.send(daemon_signal).await.is_err() {
+                break; // Monitor has exited
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn install_signal_handlers(signal_tx: mpsc::Sender<DaemonSignal>) -> Result<(), ConfigError> {
+    // No SIGHUP here: reloading requires a restart
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            let _ = signal_tx.send(DaemonSignal::Shutdown).await;
+        }
+    });
+    Ok(())
+}
+
+/// The monitor loop behind `run_daemon`, driven by an explicit signal channel.
+/// Test errors are logged and the loop carries on; a config file that fails to parse on
+/// reload is logged too, and the previous config stays in effect.
+/// Returns when it receives `Shutdown` or the channel closes.
+pub async fn run_monitor(
+    config_path: &Path,
+    mut signals: mpsc::Receiver<DaemonSignal>,
+    hooks: &TestHooks,
+) -> Result<(), ConfigError> {
+    let mut config = TestConfig::from_toml_file(config_path)?;
+    println!("Daemon: Loaded {} (repeating every {}s)", config_path.display(), config.repeat_interval_secs);
+
+    loop {
+        let run_once = async {
+            match run_test_with_hooks(&config, hooks).await {
+                Ok(summary) => println!(
+                    "Daemon: Run finished, {} sent / {} received, {:.2}% loss",
+                    summary.overall_metrics.packets_sent,
+                    summary.overall_metrics.packets_received,
+                    summary.overall_metrics.packet_loss_percentage()
+                ),
+                Err(e) => eprintln!("Daemon: Run failed: {:?}", e),
+            }
+            tokio::time::sleep(config.repeat_interval()).await;
+        };
+
+        tokio::select! {
+            _ = run_once => {}
+            signal = signals.recv() => match signal {
+                Some(DaemonSignal::Reload) => match TestConfig::from_toml_file(config_path) {
+                    Ok(new_config) => {
+                        println!("Daemon: Reloaded {} (repeating every {}s)", config_path.display(), new_config.repeat_interval_secs);
+                        config = new_config;
+                    }
+                    Err(e) => eprintln!("Daemon: Reload failed, keeping the previous config: {}", e),
+                },
+                Some(DaemonSignal::Shutdown) | None => {
+                    println!("Daemon: Shutting down.");
+                    return Ok(());
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Interval of the next run's config, if a run starts within `within`.
+    async fn next_start(rx: &mut mpsc::UnboundedReceiver<u64>, within: Duration) -> Option<u64> {
+        tokio::time::timeout(within, rx.recv()).await.ok().flatten()
+    }
+
+    #[tokio::test]
+    async fn test_reload_applies_new_repeat_interval() {
+        let config_path = std::env::temp_dir().join(format!("netstats_daemon_test_{}.toml", std::process::id()));
+        // 1 ms runs against a closed port: only the start hook and the interval matter here
+        let write_config = |interval_secs: u64| {
+            let contents = format!(
+                "target_ip = \"127.0.0.1\"\ntarget_port = 6009\ntest_duration_ms = 1\nrtt_timeout_ms = 10\nrepeat_interval_secs = {}\n",
+                interval_secs
+            );
+            std::fs::write(&config_path, contents).unwrap();
+        };
+        write_config(3600);
+
+        let (started_tx, mut started_rx) = mpsc::unbounded_channel();
+        let hooks = TestHooks {
+            on_start: Some(Box::new(move |config: &TestConfig| {
+                let _ = started_tx.send(config.repeat_interval_secs);
+            })),
+            on_sample: None,
+            on_finish: None,
+        };
+        let (signal_tx, signal_rx) = mpsc::channel(4);
+        let monitor = {
+            let config_path = config_path.clone();
+            tokio::spawn(async move { run_monitor(&config_path, signal_rx, &hooks).await })
+        };
+
+        assert_eq!(next_start(&mut started_rx, Duration::from_secs(2)).await, Some(3600));
+        // The hour-long interval holds off the next run...
+        assert_eq!(next_start(&mut started_rx, Duration::from_millis(500)).await, None);
+
+        // ...until a reload brings the interval down to a second
+        write_config(1);
+        signal_tx.send(DaemonSignal::Reload).await.unwrap();
+        assert_eq!(next_start(&mut started_rx, Duration::from_secs(2)).await, Some(1));
+        assert_eq!(next_start(&mut started_rx, Duration::from_secs(3)).await, Some(1));
+
+        // A broken file on reload keeps the running config
+        std::fs::write(&config_path, "repeat_interval_secs = \"soon\"").unwrap();
+        signal_tx.send(DaemonSignal::Reload).await.unwrap();
+        assert_eq!(next_start(&mut started_rx, Duration::from_secs(2)).await, Some(1));
+
+        signal_tx.send(DaemonSignal::Shutdown).await.unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(2), monitor).await.expect("Monitor did not stop");
+        let _ = std::fs::remove_file(&config_path);
+        assert!(result.unwrap().is_ok());
+    }
+}
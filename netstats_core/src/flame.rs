@@ -0,0 +1,56 @@
+//! Timing trace of the send/receive hot loops for flamegraphs (the `flame` feature).
+//!
+//! The loops mark their steps (serialize, send, recv, parse, metrics_update) with `hot_span!`,
+//! which compiles to nothing without the feature. With it, `FlameTrace::start` records those
+//! spans as folded stacks, ready for `inferno-flamegraph < trace.folded > flame.svg`.
+
+/// Runs `$body` inside a trace span named `$name` (`flame` feature only; otherwise just `$body`).
+/// `hot_span!(future "name", fut)` instead wraps a future, so the span is entered on each poll
+/// rather than held across `.await`s, and returns it for the caller to await.
+macro_rules! hot_span {
+    (future $name:literal, $future:expr) => {{
+        #[cfg(feature = "flame")]
+        let future = tracing::Instrument::instrument($future, tracing::trace_span!($name));
+        #[cfg(not(feature = "flame"))]
+        let future = $future;
+        future
+    }};
+    ($name:literal, $body:expr) => {{
+        #[cfg(feature = "flame")]
+        let _span = tracing::trace_span!($name).entered();
+        $body
+    }};
+}
+pub(crate) use hot_span;
+
+#[cfg(feature = "flame")]
+pub use trace::FlameTrace;
+
+#[cfg(feature = "flame")]
+mod trace {
+    use std::fs::File;
+    use std::io::{self, BufWriter};
+    use std::path::Path;
+    use tracing_subscriber::prelude::*;
+
+    /// Records the hot-loop spans of every test in this process into a folded-stack file
+    /// until dropped (or flushed). Only one trace per process: the recorder is installed as
+    /// the global tracing subscriber, since the loops run on the runtime's worker threads.
+    pub struct FlameTrace {
+        flush_guard: tracing_flame::FlushGuard<BufWriter<File>>,
+    }
+
+    impl FlameTrace {
+        pub fn start(path: &Path) -> io::Result<FlameTrace> {
+            let (flame_layer, flush_guard) = tracing_flame::FlameLayer::with_file(path).map_err(io::Error::other)?;
+            tracing::subscriber::set_global_default(tracing_subscriber::registry().with(flame_layer))
+                .map_err(|_| io::Error::other("A flame trace (or other tracing subscriber) is already running in this process"))?;
+            Ok(FlameTrace { flush_guard })
+        }
+
+        /// Writes the spans recorded so far to the file.
+        pub fn flush(&self) -> io::Result<()> {
+            self.flush_guard.flush().map_err(io::Error::other)
+        }
+    }
+}
@@ -3,19 +3,29 @@
 // analysis, and report generation.
 
 pub mod anomalies;   // Logic for detecting defined network anomalies
+pub mod assertions;  // Expectations on test results (loss, latency, throughput)
+pub mod cli;         // Argument parsing for the headless netstats-cli binary
 pub mod config;      // Test configuration structures
+pub mod control;     // Pause/resume of a running test
+pub mod daemon;      // Service mode: repeated runs, config reload on SIGHUP
+pub mod flame;       // Flamegraph timing trace of the hot loops (flame feature)
 pub mod metrics;     // Logic for calculating metrics (loss, latency, jitter, bandwidth)
 pub mod network;     // TCP/UDP client/server logic
 pub mod packet;      // Packet definitions, serialization/deserialization
+pub mod packet_tape; // Lock-free ring of the most recent packet events
+pub mod plan;        // Test plans: phases with assertions and a combined report, from one TOML
+pub mod process_monitor; // CPU and memory usage of this process during a test
 pub mod reporter;    // Data aggregation and preparing data for reports
 pub mod benchmark;   // For self-contained benchmark logic
+pub mod runner;      // High-level test runs (single, combined UDP + TCP)
+pub mod units;       // Parsing "1MiB", "100Mbps", "50kHz" into numeric config values
 
 pub fn greet() {
     println!("Hello from netstats_core library! This is the place for core logic.");
 }
 
-// The main test execution logic is now in network::run_network_test
-// and is called directly from the GUI's worker thread.
+// The main test execution logic is in network::run_network_test; runner wraps it
+// into complete runs (summary included) and is called from the GUI's worker thread.
 // The old run_test placeholder is removed.
 
 #[cfg(test)]
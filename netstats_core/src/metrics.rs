@@ -1,44 +1,173 @@
 // Logic for calculating metrics (loss, latency, jitter, bandwidth)
-use serde::Serialize; // For #[serde(skip)] if TestMetrics is ever serialized
-// use std::collections::VecDeque; // Unused
-use std::time::Instant; // Duration was unused
+use serde::{Deserialize, Serialize}; // Serialized in binary reports; skipped fields are run-time state
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Instant, SystemTime}; // Duration was unused
 
-#[derive(Debug, Default, Serialize)] // Added Serialize for skip attribute
+/// Locks shared metrics, recovering them if a task panicked while holding the lock. The
+/// counters stay usable (at worst missing that task's last update), so one bad packet
+/// doesn't cascade into a panic in every other loop touching the metrics.
+pub fn lock_metrics(metrics: &Mutex<TestMetrics>) -> MutexGuard<'_, TestMetrics> {
+    metrics.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Duplicates are detected among the sequences this far behind the highest received; older
+// ones are forgotten, so a long test's memory stays bounded. A packet that much further behind
+// can't be told from a copy, so it counts as a late duplicate rather than a distinct sequence
+// (which would hide a loss)
+const DUPLICATE_WINDOW: u32 = 1 << 16;
+// A packet of a new peer session is only taken for the start of a new run this close to sequence 0
+const NEW_SESSION_WINDOW: u32 = 1024;
+// Out-of-order and duplicate arrival times kept for burst detection, per kind; later ones are
+// still counted but not timed, so a badly reordering link can't grow them without bound
+const MAX_BURST_ARRIVALS: usize = 100_000;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct TestMetrics {
     pub packets_sent: u64,
     pub packets_received: u64,
     pub bytes_sent: u64,
     pub bytes_received: u64,
+    pub payload_bytes_received: u64, // Goodput: bytes_received minus packet headers and framing
 
     pub total_rtt_micros: u128,
     pub rtt_count: u64,
     pub min_rtt_micros: Option<u128>,
     pub max_rtt_micros: Option<u128>,
+    // Individual RTT samples, for median/trimmed statistics. Capped by max_latency_samples:
+    // once full, they are a uniform random subset of every RTT and no longer in time order.
+    pub rtt_samples: Vec<u128>,
+    pub rtt_sample_times_ms: Vec<u128>, // When each rtt_samples entry was recorded (ms since test start)
+    pub rtt_histogram: RttHistogram, // Every RTT in fixed-size buckets, for percentiles of long tests
+    // TCP client: how long connect() took, i.e. the SYN/SYN-ACK handshake. Kept apart from the
+    // data RTT above, which comes from echo replies. None until a connection is made.
+    pub connect_rtt_micros: Option<u64>,
+
+    // One-way delay estimates from the echo server's receive timestamp (client side).
+    // Only meaningful if both clocks are synchronized; may be negative otherwise.
+    pub forward_delay_samples_micros: Vec<i64>,
+    pub return_delay_samples_micros: Vec<i64>,
 
     // For jitter calculation (sum of differences between successive RTTs)
     pub inter_arrival_jitter_micros_sum: u128,
     pub jitter_count: u64,
+    // RFC 3550 interarrival jitter of received packets, see record_transit
+    pub smoothed_jitter_estimate_micros: Option<f64>,
 
     // For bandwidth over time
-    // (timestamp_ms_since_test_start, bytes_received_in_this_sample_interval)
-    pub bandwidth_samples: Vec<(u128, u64)>,
+    // (timestamp_ms_since_test_start, bytes_received_in_this_sample_interval, packets_received_in_it)
+    pub bandwidth_samples: Vec<(u128, u64, u64)>,
     #[serde(skip)] // Skip serialization for non-persistent state used during test
     last_bandwidth_sample_time_ms: Option<u128>,
     #[serde(skip)]
     bytes_since_last_bandwidth_sample: u64,
     #[serde(skip)]
+    packets_since_last_sample: u64,
+    #[serde(skip)]
+    last_packet_received_ms: Option<u128>, // For ending the final bandwidth interval
+    #[serde(skip)]
     pub test_start_time: Option<Instant>, // To calculate elapsed time for samples
+    pub test_start_wall_time: Option<SystemTime>, // Wall-clock time of test_start_time, for reports
     #[serde(skip)]
     last_rtt_micros: Option<u128>, // For jitter calculation
+    #[serde(skip)]
+    last_transit_micros: Option<i64>, // For the RFC 3550 jitter
+    #[serde(skip)]
+    rtt_skip_remaining: u32, // Warmup replies still to leave out of the RTT statistics
+    #[serde(skip)]
+    max_latency_samples: usize, // TestConfig::max_latency_samples; 0 = keep all
+    #[serde(skip)]
+    reservoir_rng: Option<rand::rngs::StdRng>, // Created when the reservoir fills up
 
     // Store anomalies detected directly related to metrics processing
     pub anomalies: Vec<crate::anomalies::AnomalyEvent>,
     #[serde(skip)]
+    anomaly_detection_disabled: bool, // TestConfig::anomaly_detection_enabled, inverted so Default keeps it on
+    #[serde(skip)]
     latency_spike_threshold_micros: Option<u128>,
     #[serde(skip)]
     jitter_spike_threshold_micros: Option<u128>,
+    #[serde(skip)]
+    pub(crate) sustained_latency: Option<(f64, u128)>, // (multiplier of min RTT, minimum duration ms)
+    #[serde(skip)]
+    pub(crate) anomaly_tx: Option<tokio::sync::mpsc::Sender<crate::anomalies::AnomalyEvent>>, // Live feed, from the config
+
+    pub out_of_order_count: u64, // For out-of-order packets (late ones included)
+    pub late_count: u64,         // Out-of-order packets that arrived after the late threshold
+    pub duplicate_count: u64,    // Sequence numbers received more than once
+    #[serde(skip)]
+    seen_sequences: HashSet<u32>, // Within DUPLICATE_WINDOW of the highest
+    #[serde(skip)]
+    distinct_sequences_received: u64,
+    #[serde(skip)]
+    highest_sequence: Option<(u32, u128)>, // (sequence, arrival ms since test start)
+    #[serde(skip)]
+    late_threshold_ms: Option<u128>,
+    #[serde(skip)]
+    reorder_window: u32,
+    // Inputs of anomalies::detect_anomalies, only recorded while it is enabled: arrival times
+    // (ms since test start) of the first MAX_BURST_ARRIVALS out-of-order and duplicate packets,
+    // and the sequences expected so far (highest + 1; 0 before the first) at each bandwidth
+    // sample, parallel to bandwidth_samples (empty without a loss threshold)
+    #[serde(skip)]
+    pub(crate) out_of_order_arrivals_ms: Vec<u128>,
+    #[serde(skip)]
+    pub(crate) duplicate_arrivals_ms: Vec<u128>,
+    #[serde(skip)]
+    pub(crate) expected_packets_at_sample: Vec<u64>,
+    #[serde(skip)]
+    pub(crate) loss_window_threshold_percent: Option<f64>,
+    pub parse_queue_depth: u64, // Peak datagrams waiting for a parse worker (TestConfig::parse_workers)
+    pub recv_buffer_allocations: u64, // Datagram buffers allocated for the parse workers (TestConfig::recv_buffer_pool)
+    pub send_retries: u64, // Sends retried after a transient error (WouldBlock, ENOBUFS)
+    pub control_messages_received: u64, // On the UDP control channel; never counted as data
+    pub unexpected_type_packets: u64, // Well-formed packets of a type the receiver doesn't serve (e.g. Ack)
+    // UDP bidirectional: the port the peer's packets arrive from, if it differs from the one
+    // its Hello advertised (a NAT remapped it). None if no remapping was seen.
+    pub nat_mapped_port: Option<u16>,
+    // This run's random id, stamped on every packet it sends (see start_session), and the peer's,
+    // adopted from its data packets. Packets of any other session, e.g. stragglers of an
+    // earlier test on the same port, are dropped and counted in foreign_session_packets.
+    pub session_id: u32,
+    pub peer_session_id: Option<u32>,
+    pub foreign_session_packets: u64,
+    #[serde(skip)]
+    retired_peer_sessions: HashSet<u32>, // Sessions the peer moved on from, never adopted again
+    #[serde(skip)]
+    sequences_expected_before_session: u64, // Expected packets of the peer's earlier sessions
+
+    // Warmup boundary: bytes received when the warmup period ended, for steady-state throughput
+    pub warmup_end_ms: Option<u128>,
+    pub bytes_received_at_warmup_end: Option<u64>,
+    #[serde(skip)]
+    warmup_ms: Option<u128>,
+
+    // Start of each named test phase: (ms since test start, label). Drawn on the report charts.
+    pub phase_marks: Vec<(u128, String)>,
+
+    // Optional live view of the latest packets. Set it before the test and keep a clone;
+    // the receive loops push to it without holding the metrics lock.
+    #[serde(skip)]
+    pub packet_tape: Option<Arc<crate::packet_tape::PacketTape>>,
+
+    // Inter-arrival gaps of received datagrams, one count per INTERARRIVAL_BUCKET_BOUNDS_MICROS
+    // bucket plus the open-ended last one (TestConfig::record_interarrival). Empty if unused.
+    pub interarrival_gap_counts: Vec<u64>,
+
+    // Payload sizes of received data packets, one count per SIZE_BUCKET_BYTES-wide bucket up to
+    // the largest seen (TestConfig::record_size_histogram). Empty if unused.
+    pub received_size_counts: Vec<u64>,
 
-    pub out_of_order_count: u64, // For out-of-order packets
+    // Every distinct sequence number received, as inclusive ranges (TestConfig::record_sequence_ranges).
+    // None if not recorded.
+    pub received_sequence_ranges: Option<SequenceRanges>,
+
+    // CPU%/RSS of this process, one sample per bandwidth interval (TestConfig::monitor_process)
+    pub process_samples: Vec<crate::process_monitor::ProcessSample>,
+
+    // Pause/resume switch for the running test; clone it before the test starts.
+    #[serde(skip)]
+    pub control: Arc<crate::control::TestControl>,
 }
 
 impl TestMetrics {
@@ -47,13 +176,162 @@ impl TestMetrics {
     }
 
     pub fn configure_anomaly_detection(&mut self, config: &crate::config::TestConfig) {
-        self.latency_spike_threshold_micros = config.latency_spike_threshold_ms.map(|ms| ms as u128 * 1000);
-        self.jitter_spike_threshold_micros = config.jitter_spike_threshold_ms.map(|ms| ms as u128 * 1000);
+        let enabled = config.anomaly_detection_enabled;
+        self.anomaly_detection_disabled = !enabled;
+        // Without thresholds the per-packet checks are skipped outright
+        self.latency_spike_threshold_micros = config.latency_spike_threshold_ms.filter(|_| enabled).map(|ms| ms as u128 * 1000);
+        self.jitter_spike_threshold_micros = config.jitter_spike_threshold_ms.filter(|_| enabled).map(|ms| ms as u128 * 1000);
+        self.sustained_latency = config
+            .sustained_latency_multiplier
+            .filter(|_| enabled)
+            .map(|multiplier| (multiplier, config.sustained_latency_secs as u128 * 1000));
+        self.loss_window_threshold_percent = config.packet_loss_threshold_percent.filter(|_| enabled);
+        // A packet overtaken for longer than the sender waits for a reply would have counted as lost
+        self.late_threshold_ms = Some(config.rtt_timeout_ms as u128);
+        self.reorder_window = config.reorder_window;
+        self.anomaly_tx = config.anomaly_tx.clone();
+        self.rtt_skip_remaining = config.rtt_skip_first;
+    }
+
+    /// Adds an anomaly to the run, and forwards it to the live feed if one is configured.
+    /// Never blocks: the event is dropped from the feed (not the run) if the channel is full.
+    /// Does nothing if anomaly detection is disabled.
+    pub fn record_anomaly(&mut self, event: crate::anomalies::AnomalyEvent) {
+        if self.anomaly_detection_disabled {
+            return;
+        }
+        if let Some(tx) = &self.anomaly_tx {
+            let _ = tx.try_send(event.clone());
+        }
+        self.anomalies.push(event);
+    }
+
+    pub fn configure_warmup(&mut self, config: &crate::config::TestConfig) {
+        self.warmup_ms = if config.warmup_secs > 0 { Some(config.warmup_duration().as_millis()) } else { None };
+    }
+
+    pub fn configure_latency_samples(&mut self, config: &crate::config::TestConfig) {
+        self.max_latency_samples = config.max_latency_samples;
+    }
+
+    pub fn configure_sequence_ranges(&mut self, config: &crate::config::TestConfig) {
+        if config.record_sequence_ranges && self.received_sequence_ranges.is_none() {
+            self.received_sequence_ranges = Some(SequenceRanges::default());
+        }
+    }
+
+    /// Starts this run's session: a fresh non-zero id for the packets it sends, and no peer
+    /// session until the first data packet arrives.
+    pub fn start_session(&mut self) {
+        use rand::Rng;
+        self.session_id = rand::thread_rng().gen_range(1..=u32::MAX);
+        self.peer_session_id = None;
+    }
+
+    /// Whether a data packet of `session_id` with `sequence_number` belongs to the peer's
+    /// session. The first packet sets the peer's session. A packet of another session whose
+    /// sequence restarts below the peer's highest, and within NEW_SESSION_WINDOW of sequence 0,
+    /// is a new run of the peer, e.g. the next phase of a test plan: it becomes the peer's
+    /// session and sequence tracking starts over. Anything else is a straggler and doesn't
+    /// belong, e.g. a late packet of an earlier run arriving while the current run is still
+    /// below it, as is any packet of a session the peer already moved on from. Packets without
+    /// a session (0) always belong.
+    pub fn accept_peer_session(&mut self, session_id: u32, sequence_number: u32) -> bool {
+        if session_id == 0 {
+            return true;
+        }
+        match self.peer_session_id {
+            Some(peer_session_id) if peer_session_id == session_id => return true,
+            Some(peer_session_id) => {
+                let restarts_sequence = self.highest_sequence
+                    .is_some_and(|(highest_seq, _)| sequence_number < highest_seq.min(NEW_SESSION_WINDOW));
+                if !restarts_sequence || self.retired_peer_sessions.contains(&session_id) {
+                    self.record_foreign_session(session_id);
+                    return false;
+                }
+                println!("ReceiveLoop: Peer started a new session {:#010x}", session_id);
+                self.retired_peer_sessions.insert(peer_session_id);
+                self.start_peer_sequence();
+            }
+            None => {}
+        }
+        self.peer_session_id = Some(session_id);
+        true
+    }
+
+    /// Starts sequence tracking over for a new peer session, whose numbers start again at 0.
+    /// The earlier sessions' expected packets carry over, so loss and the other counts still
+    /// cover the whole test.
+    fn start_peer_sequence(&mut self) {
+        if let Some((highest_seq, _)) = self.highest_sequence.take() {
+            self.sequences_expected_before_session += highest_seq as u64 + 1;
+        }
+        self.seen_sequences.clear();
+    }
+
+    /// Whether an EchoReply of `session_id` answers a request of this run's session.
+    pub fn accept_reply_session(&mut self, session_id: u32) -> bool {
+        if session_id == 0 || session_id == self.session_id {
+            return true;
+        }
+        self.record_foreign_session(session_id);
+        false
+    }
+
+    /// Counts a dropped packet of another session; the first one is also a ForeignSession anomaly.
+    fn record_foreign_session(&mut self, session_id: u32) {
+        self.foreign_session_packets += 1;
+        if self.foreign_session_packets == 1 {
+            self.record_anomaly(crate::anomalies::AnomalyEvent {
+                timestamp_ms: self.test_start_time.map_or(0, |st| st.elapsed().as_millis()),
+                anomaly_type: crate::anomalies::AnomalyType::ForeignSession,
+                description: format!("Dropped a packet of session {:#010x}, not this test's", session_id),
+                threshold: None,
+            });
+        }
+    }
+
+    /// Keeps an RTT sample, or once `max_latency_samples` are kept, replaces a random one
+    /// with probability max/seen (Algorithm R), so every RTT is equally likely to be kept.
+    fn retain_rtt_sample(&mut self, rtt_micros: u128, time_ms: u128) {
+        if self.max_latency_samples == 0 || self.rtt_samples.len() < self.max_latency_samples {
+            self.rtt_samples.push(rtt_micros);
+            self.rtt_sample_times_ms.push(time_ms);
+            return;
+        }
+        use rand::{Rng, SeedableRng};
+        let rng = self.reservoir_rng.get_or_insert_with(rand::rngs::StdRng::from_entropy);
+        let slot = rng.gen_range(0..self.rtt_count) as usize; // rtt_count includes this sample
+        if slot < self.rtt_samples.len() {
+            self.rtt_samples[slot] = rtt_micros;
+            self.rtt_sample_times_ms[slot] = time_ms;
+        }
+    }
+
+    /// Snapshots the received byte count the first time we notice the warmup period has elapsed.
+    /// Must run before the current packet's bytes are added, so they count as post-warmup.
+    fn check_warmup_boundary(&mut self) {
+        if let (Some(warmup_ms), None) = (self.warmup_ms, self.bytes_received_at_warmup_end) {
+            let elapsed_ms = self.test_start_time.map_or(0, |st| st.elapsed().as_millis());
+            if elapsed_ms >= warmup_ms {
+                self.warmup_end_ms = Some(warmup_ms);
+                self.bytes_received_at_warmup_end = Some(self.bytes_received);
+            }
+        }
+    }
+
+    /// Forgets everything a failed attempt recorded, so a retried test (TestConfig::max_test_retries)
+    /// starts from scratch. The control and packet tape stay, as the caller holds clones of them,
+    /// and so do phase marks the caller set before the test, e.g. a test plan's phase label.
+    pub fn reset_for_retry(&mut self) {
+        let phase_marks = std::mem::take(&mut self.phase_marks).into_iter().filter(|&(ms, _)| ms == 0).collect();
+        *self = TestMetrics { control: Arc::clone(&self.control), packet_tape: self.packet_tape.take(), phase_marks, ..Default::default() };
     }
 
     pub fn init_start_time(&mut self) {
         if self.test_start_time.is_none() {
             self.test_start_time = Some(Instant::now());
+            self.test_start_wall_time = Some(SystemTime::now());
             self.last_bandwidth_sample_time_ms = Some(0); // Start of test
             self.bytes_since_last_bandwidth_sample = 0;
         }
@@ -67,27 +345,28 @@ impl TestMetrics {
 
     pub fn record_packet_received(&mut self, size_bytes: usize, rtt_micros: u128) {
         self.init_start_time(); // Ensure start time is set
+        self.check_warmup_boundary();
         self.packets_received += 1;
         self.bytes_received += size_bytes as u64;
         self.bytes_since_last_bandwidth_sample += size_bytes as u64;
+        self.packets_since_last_sample += 1;
+        self.last_packet_received_ms = self.test_start_time.map(|st| st.elapsed().as_millis());
 
         // RTT calculations (only if rtt_micros is meaningful, e.g., > 0 for client)
-        if rtt_micros > 0 {
+        if rtt_micros > 0 && self.rtt_skip_remaining > 0 {
+            self.rtt_skip_remaining -= 1; // Warmup reply: received, but not a representative RTT
+        } else if rtt_micros > 0 {
             self.total_rtt_micros += rtt_micros;
             self.rtt_count += 1;
 
             self.min_rtt_micros = Some(self.min_rtt_micros.map_or(rtt_micros, |min| min.min(rtt_micros)));
             self.max_rtt_micros = Some(self.max_rtt_micros.map_or(rtt_micros, |max| max.max(rtt_micros)));
+            self.retain_rtt_sample(rtt_micros, self.last_packet_received_ms.unwrap_or(0));
+            self.rtt_histogram.record(rtt_micros);
 
             // Calculate jitter based on this RTT and the previous RTT
             if let Some(last_rtt) = self.last_rtt_micros {
-                // abs_diff, works for u128 if order is known, otherwise cast or use helper
-                let jitter_sample = if rtt_micros >= last_rtt {
-                    rtt_micros - last_rtt
-                } else {
-                    last_rtt - rtt_micros
-                };
-                self.record_jitter_value(jitter_sample);
+                self.record_jitter_value(rtt_micros.abs_diff(last_rtt));
             }
             self.last_rtt_micros = Some(rtt_micros);
 
@@ -96,10 +375,15 @@ impl TestMetrics {
 
             if let Some(threshold_micros) = self.latency_spike_threshold_micros {
                 if rtt_micros > threshold_micros {
-                    self.anomalies.push(crate::anomalies::AnomalyEvent {
+                    self.record_anomaly(crate::anomalies::AnomalyEvent {
                         timestamp_ms: current_test_time_ms,
                         anomaly_type: crate::anomalies::AnomalyType::HighLatencySpike,
                         description: format!("RTT: {:.2} ms", rtt_micros as f64 / 1000.0),
+                        threshold: Some(crate::anomalies::AnomalyThreshold::new(
+                            "latency_spike_threshold_ms",
+                            threshold_micros as f64 / 1000.0,
+                            rtt_micros as f64 / 1000.0,
+                        )),
                     });
                 }
             }
@@ -111,10 +395,77 @@ impl TestMetrics {
             // The current record_jitter_value doesn't return the sample.
             // Let's adjust record_jitter_value to also perform this check.
         }
+        // The RFC 3550 jitter comes from the packets' own timestamps, see record_transit
+    }
+
+    /// Updates the RFC 3550 interarrival jitter with a received packet: its transit time is
+    /// arrival minus send time, and each change in transit between consecutive arrivals moves
+    /// the estimate 1/16 of the way, J(i) = J(i-1) + (|D(i-1,i)| - J(i-1)) / 16. The clocks
+    /// needn't be synchronized, as their offset cancels out of D. `send_timestamp_ms` is the
+    /// header's `timestamp_ms`, so the estimate has millisecond granularity.
+    pub fn record_transit(&mut self, send_timestamp_ms: u64, arrival_unix_micros: u64) {
+        let transit_micros = arrival_unix_micros as i64 - (send_timestamp_ms as i64).saturating_mul(1000);
+        if let Some(last_transit_micros) = self.last_transit_micros.replace(transit_micros) {
+            let d = transit_micros.abs_diff(last_transit_micros) as f64;
+            let jitter = self.smoothed_jitter_estimate_micros.unwrap_or(0.0);
+            self.smoothed_jitter_estimate_micros = Some(jitter + (d - jitter) / 16.0);
+        }
+    }
 
-        // Basic jitter calculation could be refined here or in analysis step
-        // This is a placeholder for now. A common way is RFC 3550's method.
-        // For now, let's assume we get inter-arrival times from packet timestamps.
+    /// Records the application payload of a packet already counted by `record_packet_received`.
+    pub fn record_payload_received(&mut self, payload_bytes: usize) {
+        self.payload_bytes_received += payload_bytes as u64;
+    }
+
+    pub fn record_received_size(&mut self, payload_bytes: usize) {
+        let bucket = payload_bytes / SIZE_BUCKET_BYTES;
+        if bucket >= self.received_size_counts.len() {
+            self.received_size_counts.resize(bucket + 1, 0);
+        }
+        self.received_size_counts[bucket] += 1;
+    }
+
+    /// Received payload size histogram, smallest sizes first. Only buckets that received
+    /// something are listed.
+    pub fn received_size_histogram(&self) -> Vec<SizeBucket> {
+        self.received_size_counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(i, &count)| SizeBucket {
+                min_bytes: i * SIZE_BUCKET_BYTES,
+                max_bytes: (i + 1) * SIZE_BUCKET_BYTES - 1,
+                count,
+            })
+            .collect()
+    }
+
+    pub fn record_interarrival_gap(&mut self, gap_micros: u64) {
+        if self.interarrival_gap_counts.is_empty() {
+            self.interarrival_gap_counts = vec![0; INTERARRIVAL_BUCKET_BOUNDS_MICROS.len() + 1];
+        }
+        let bucket = INTERARRIVAL_BUCKET_BOUNDS_MICROS.partition_point(|&bound| bound <= gap_micros);
+        self.interarrival_gap_counts[bucket] += 1;
+    }
+
+    /// Inter-arrival gap histogram, smallest gaps first; empty if no gaps were recorded.
+    pub fn interarrival_histogram(&self) -> Vec<InterarrivalBucket> {
+        self.interarrival_gap_counts
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| InterarrivalBucket {
+                min_micros: if i == 0 { 0 } else { INTERARRIVAL_BUCKET_BOUNDS_MICROS[i - 1] },
+                max_micros: INTERARRIVAL_BUCKET_BOUNDS_MICROS.get(i).copied(),
+                count,
+            })
+            .collect()
+    }
+
+    /// Marks the start of a new test phase (e.g. a rate change) at the current test time.
+    pub fn mark_phase(&mut self, label: impl Into<String>) {
+        self.init_start_time();
+        let now_ms = self.test_start_time.map_or(0, |st| st.elapsed().as_millis());
+        self.phase_marks.push((now_ms, label.into()));
     }
 
     /// Call this periodically (e.g., every N milliseconds or after X packets)
@@ -123,19 +474,133 @@ impl TestMetrics {
         if self.test_start_time.is_none() { // Should have been initialized by packet send/recv
             self.init_start_time();
         }
+        self.check_warmup_boundary();
 
         let sample_time = current_test_time_ms;
         // Ensure last_bandwidth_sample_time_ms is initialized, defaulting to 0 if it's the first sample.
         let last_sample_time = self.last_bandwidth_sample_time_ms.unwrap_or(0);
 
         if self.bytes_since_last_bandwidth_sample > 0 || sample_time > last_sample_time {
-            self.bandwidth_samples.push((sample_time, self.bytes_since_last_bandwidth_sample));
+            self.bandwidth_samples.push((sample_time, self.bytes_since_last_bandwidth_sample, self.packets_since_last_sample));
+            if self.loss_window_threshold_percent.is_some() {
+                self.expected_packets_at_sample.push(self.expected_sequences().unwrap_or(0));
+            }
         }
 
         self.bytes_since_last_bandwidth_sample = 0;
+        self.packets_since_last_sample = 0;
         self.last_bandwidth_sample_time_ms = Some(sample_time);
     }
 
+    /// Takes the last bandwidth sample when the receiver shuts down, ending the interval at the
+    /// time chosen by `timing` instead of "now" (which is usually the end of the grace period).
+    pub fn take_final_bandwidth_sample(&mut self, timing: crate::config::FinalSampleTiming, configured_duration_ms: u128) {
+        let end_ms = match timing {
+            crate::config::FinalSampleTiming::LastPacket => self.last_packet_received_ms.unwrap_or(configured_duration_ms),
+            crate::config::FinalSampleTiming::ConfiguredDuration => configured_duration_ms,
+        };
+        // Never end before the previous sample, or the interval would have a negative length
+        let end_ms = end_ms.max(self.last_bandwidth_sample_time_ms.unwrap_or(0));
+        self.take_bandwidth_sample(end_ms);
+    }
+
+    /// Receiver-side sequence tracking: classifies an arriving sequence number against
+    /// everything received so far, updates the counters and logs an anomaly if it is not in order.
+    /// Sequence numbers compare in serial number arithmetic (RFC 1982), so a wrap from u32::MAX
+    /// to 0 reads as moving forward and a straggler from before the wrap as behind.
+    pub fn record_sequence(&mut self, seq: u32, arrival_ms: u128) -> SequenceArrival {
+        if let Some(ranges) = &mut self.received_sequence_ranges {
+            ranges.insert(seq);
+        }
+        let behind_highest = self.highest_sequence.map_or(0, |(highest_seq, _)| highest_seq.wrapping_sub(seq));
+        let is_behind = behind_highest > 0 && behind_highest <= u32::MAX / 2;
+        let in_duplicate_window = !is_behind || behind_highest < DUPLICATE_WINDOW;
+        if !in_duplicate_window || !self.seen_sequences.insert(seq) {
+            self.duplicate_count += 1;
+            if !self.anomaly_detection_disabled {
+                keep_burst_arrival(&mut self.duplicate_arrivals_ms, arrival_ms);
+            }
+            self.record_anomaly(crate::anomalies::AnomalyEvent {
+                timestamp_ms: arrival_ms,
+                anomaly_type: crate::anomalies::AnomalyType::DuplicatePacket,
+                description: if in_duplicate_window {
+                    format!("UDP Packet Seq: {} received again", seq)
+                } else {
+                    format!("UDP Packet Seq: {} received {} behind the highest, too late to tell from a copy", seq, behind_highest)
+                },
+                threshold: None,
+            });
+            return SequenceArrival::Duplicate;
+        }
+        self.distinct_sequences_received += 1;
+
+        let (highest_seq, highest_arrival_ms) = match self.highest_sequence {
+            Some(highest) if is_behind => highest,
+            _ => {
+                self.highest_sequence = Some((seq, arrival_ms));
+                self.forget_old_sequences();
+                return SequenceArrival::InOrder;
+            }
+        };
+
+        // Overtaken by highest_seq; how long ago that one arrived decides reordered vs late
+        let overtaken_for_ms = arrival_ms.saturating_sub(highest_arrival_ms);
+        let arrival = match self.late_threshold_ms {
+            Some(threshold_ms) if overtaken_for_ms > threshold_ms => SequenceArrival::Late,
+            _ if behind_highest <= self.reorder_window => return SequenceArrival::InOrder, // Tolerated
+            _ => SequenceArrival::Reordered,
+        };
+        self.out_of_order_count += 1;
+        if !self.anomaly_detection_disabled {
+            keep_burst_arrival(&mut self.out_of_order_arrivals_ms, arrival_ms);
+        }
+        if arrival == SequenceArrival::Late {
+            self.late_count += 1;
+        }
+        self.record_anomaly(crate::anomalies::AnomalyEvent {
+            timestamp_ms: arrival_ms,
+            anomaly_type: crate::anomalies::AnomalyType::OutOfOrder,
+            description: match arrival {
+                SequenceArrival::Late => format!("UDP Packet Seq: {} received {} ms after {}", seq, overtaken_for_ms, highest_seq),
+                _ => format!("UDP Packet Seq: {} received after {}", seq, highest_seq),
+            },
+            threshold: None,
+        });
+        arrival
+    }
+
+    /// Drops the seen sequences that fell out of DUPLICATE_WINDOW, once there are twice as many
+    /// as it holds, so the pruning cost is spread over the packets in between.
+    fn forget_old_sequences(&mut self) {
+        let Some((highest_seq, _)) = self.highest_sequence else {
+            return;
+        };
+        if self.seen_sequences.len() > 2 * DUPLICATE_WINDOW as usize {
+            self.seen_sequences.retain(|&seq| highest_seq.wrapping_sub(seq) < DUPLICATE_WINDOW);
+        }
+    }
+
+    /// Loss breakdown from receiver-side sequence tracking; None if no sequences were recorded.
+    /// Loss is gap-based, so packets lost after the highest received sequence are not counted.
+    pub fn loss_breakdown(&self) -> Option<LossBreakdown> {
+        let expected = self.expected_sequences()?;
+        Some(LossBreakdown {
+            lost: expected.saturating_sub(self.distinct_sequences_received),
+            reordered: self.out_of_order_count - self.late_count,
+            duplicated: self.duplicate_count,
+            late: self.late_count,
+        })
+    }
+
+    /// Packets the peer's sequence numbers say it sent, over all its sessions (sequence numbers
+    /// start at 0 in each); None before any were recorded.
+    fn expected_sequences(&self) -> Option<u64> {
+        match self.highest_sequence {
+            Some((highest_seq, _)) => Some(self.sequences_expected_before_session + highest_seq as u64 + 1),
+            None => Some(self.sequences_expected_before_session).filter(|&expected| expected > 0),
+        }
+    }
+
     pub fn record_jitter_value(&mut self, jitter_sample_micros: u128) {
         self.init_start_time();
         self.inter_arrival_jitter_micros_sum += jitter_sample_micros;
@@ -145,10 +610,15 @@ impl TestMetrics {
         if let Some(threshold_micros) = self.jitter_spike_threshold_micros {
             if jitter_sample_micros > threshold_micros {
                 let current_test_time_ms = self.test_start_time.map_or(0, |st| Instant::now().duration_since(st).as_millis());
-                self.anomalies.push(crate::anomalies::AnomalyEvent {
+                self.record_anomaly(crate::anomalies::AnomalyEvent {
                     timestamp_ms: current_test_time_ms,
                     anomaly_type: crate::anomalies::AnomalyType::JitterSpike,
                     description: format!("Jitter: {:.2} ms", jitter_sample_micros as f64 / 1000.0),
+                    threshold: Some(crate::anomalies::AnomalyThreshold::new(
+                        "jitter_spike_threshold_ms",
+                        threshold_micros as f64 / 1000.0,
+                        jitter_sample_micros as f64 / 1000.0,
+                    )),
                 });
             }
         }
@@ -166,6 +636,74 @@ impl TestMetrics {
         }
     }
 
+    /// `connect_rtt_micros` in milliseconds, for the reports.
+    pub fn connect_rtt_ms(&self) -> Option<f64> {
+        self.connect_rtt_micros.map(|micros| micros as f64 / 1000.0)
+    }
+
+    /// Percentile (0-100) of every RTT in the test, in microseconds, from `rtt_histogram`:
+    /// within about 1% of the exact value, with memory bounded however long the test runs.
+    /// The only RTT percentile source, so the reports and assertions agree.
+    pub fn rtt_percentile(&self, p: f64) -> Option<f64> {
+        self.rtt_histogram.percentile(p)
+    }
+
+    pub fn rtt_p50(&self) -> Option<f64> {
+        self.rtt_percentile(50.0)
+    }
+
+    pub fn rtt_p95(&self) -> Option<f64> {
+        self.rtt_percentile(95.0)
+    }
+
+    pub fn rtt_p99(&self) -> Option<f64> {
+        self.rtt_percentile(99.0)
+    }
+
+    pub fn median_rtt_micros(&self) -> Option<f64> {
+        self.trimmed_mean_rtt_micros(50.0)
+    }
+
+    /// Mean of the RTT samples after dropping `trim_percent` of them from each end.
+    /// 0 gives the plain mean; 50 collapses to the median.
+    pub fn trimmed_mean_rtt_micros(&self, trim_percent: f64) -> Option<f64> {
+        if self.rtt_samples.is_empty() {
+            return None;
+        }
+        let mut sorted = self.rtt_samples.clone();
+        sorted.sort_unstable();
+
+        let n = sorted.len();
+        let trim_percent = trim_percent.clamp(0.0, 50.0);
+        // Always keep at least the middle one (odd n) or two (even n) samples
+        let max_trim = (n - 1) / 2;
+        let trim = ((n as f64 * trim_percent / 100.0).floor() as usize).min(max_trim);
+        let kept = &sorted[trim..n - trim];
+        Some(kept.iter().sum::<u128>() as f64 / kept.len() as f64)
+    }
+
+    /// The RTT figure to headline in reports, according to the configured statistic.
+    pub fn headline_rtt_micros(&self, stat: crate::config::LatencyStat) -> Option<f64> {
+        match stat {
+            crate::config::LatencyStat::Mean => self.average_rtt_micros(),
+            crate::config::LatencyStat::Median => self.median_rtt_micros(),
+            crate::config::LatencyStat::TrimmedMean(percent) => self.trimmed_mean_rtt_micros(percent),
+        }
+    }
+
+    pub fn record_one_way_delays(&mut self, forward_micros: i64, return_micros: i64) {
+        self.forward_delay_samples_micros.push(forward_micros);
+        self.return_delay_samples_micros.push(return_micros);
+    }
+
+    pub fn average_forward_delay_micros(&self) -> Option<f64> {
+        average_i64(&self.forward_delay_samples_micros)
+    }
+
+    pub fn average_return_delay_micros(&self) -> Option<f64> {
+        average_i64(&self.return_delay_samples_micros)
+    }
+
     pub fn packet_loss_percentage(&self) -> f64 {
         if self.packets_sent == 0 {
             0.0
@@ -175,6 +713,24 @@ impl TestMetrics {
         }
     }
 
+    /// Goodput efficiency: payload bytes as a percentage of all received bytes, i.e. how much
+    /// of the traffic is left after our packet header and (TCP) length prefix. IP/UDP/TCP
+    /// headers are not included in either count. None if nothing was received.
+    pub fn efficiency_percentage(&self) -> Option<f64> {
+        if self.bytes_received == 0 {
+            None
+        } else {
+            Some(self.payload_bytes_received as f64 / self.bytes_received as f64 * 100.0)
+        }
+    }
+
+    /// RFC 3550 interarrival jitter (as iperf reports it), in microseconds. Unlike
+    /// `average_jitter_micros`, which averages the changes between successive RTTs, this is
+    /// a smoothed estimate of one-way delay variation, from the receiving side.
+    pub fn smoothed_jitter_micros(&self) -> Option<f64> {
+        self.smoothed_jitter_estimate_micros
+    }
+
     pub fn average_jitter_micros(&self) -> Option<f64> {
         if self.jitter_count == 0 {
             None
@@ -191,13 +747,327 @@ impl TestMetrics {
             (self.bytes_received * 8) as f64 / duration_secs
         }
     }
+
+    /// Coefficient of variation of the per-interval throughput (stddev / mean of the
+    /// bandwidth samples' rates) as a percentage; lower is steadier. None with fewer than
+    /// two intervals or no traffic.
+    pub fn throughput_cv(&self) -> Option<f64> {
+        let mut rates = Vec::with_capacity(self.bandwidth_samples.len());
+        let mut last_sample_time_ms = 0;
+        for &(sample_end_time_ms, bytes_in_interval, _) in &self.bandwidth_samples {
+            let interval_ms = sample_end_time_ms.saturating_sub(last_sample_time_ms);
+            last_sample_time_ms = sample_end_time_ms;
+            if interval_ms > 0 { // Same-millisecond samples have no rate, as in the charts
+                rates.push(bytes_in_interval as f64 * 8.0 / (interval_ms as f64 / 1000.0));
+            }
+        }
+        if rates.len() < 2 {
+            return None;
+        }
+        let mean = rates.iter().sum::<f64>() / rates.len() as f64;
+        if mean <= 0.0 {
+            return None;
+        }
+        let variance = rates.iter().map(|rate| (rate - mean).powi(2)).sum::<f64>() / rates.len() as f64;
+        Some(variance.sqrt() / mean * 100.0)
+    }
+
+    /// Stability indicator for `throughput_cv`: "Steady" under 10%, "Variable" under 30%,
+    /// "Unstable" above.
+    pub fn throughput_stability(&self) -> Option<&'static str> {
+        self.throughput_cv().map(|cv| match cv {
+            cv if cv < 10.0 => "Steady",
+            cv if cv < 30.0 => "Variable",
+            _ => "Unstable",
+        })
+    }
+
+    /// Highest throughput sustained over any `window_secs` of consecutive bandwidth sample
+    /// intervals, in Mbps: the link's peak capacity without the ramp-up and ramp-down that
+    /// the overall average includes. Windows fall on sample boundaries, each the shortest
+    /// run of intervals covering `window_secs`. None if the samples span less than that.
+    pub fn best_window_throughput_mbps(&self, window_secs: f64) -> Option<f64> {
+        let window_ms = window_secs * 1000.0;
+        let samples = &self.bandwidth_samples;
+        let start_ms = |i: usize| if i == 0 { 0 } else { samples[i - 1].0 }; // Of interval i
+        let mut best: Option<f64> = None;
+        let (mut first, mut window_bytes) = (0, 0u64);
+        for (last, &(end_ms, bytes, _)) in samples.iter().enumerate() {
+            window_bytes += bytes;
+            // Drop leading intervals the window can do without
+            while first < last && (end_ms - start_ms(first + 1)) as f64 >= window_ms {
+                window_bytes -= samples[first].1;
+                first += 1;
+            }
+            let span_ms = end_ms.saturating_sub(start_ms(first));
+            if span_ms > 0 && span_ms as f64 >= window_ms {
+                let mbps = window_bytes as f64 * 8.0 / (span_ms as f64 * 1000.0);
+                best = Some(best.map_or(mbps, |best: f64| best.max(mbps)));
+            }
+        }
+        best
+    }
+
+    /// Throughput over the post-warmup window only, in bits per second.
+    /// None if no warmup was configured or the test ended before the warmup did.
+    pub fn steady_state_throughput_bps(&self, duration_secs: f64) -> Option<f64> {
+        let warmup_end_secs = self.warmup_end_ms? as f64 / 1000.0;
+        let steady_bytes = self.bytes_received.saturating_sub(self.bytes_received_at_warmup_end?);
+        let steady_duration_secs = duration_secs - warmup_end_secs;
+        if steady_duration_secs <= 0.0 {
+            return None;
+        }
+        Some((steady_bytes * 8) as f64 / steady_duration_secs)
+    }
+
+    /// The current state of a running test. Throughput covers the bytes sent and received
+    /// since `previous` (since the start if None), so successive snapshots give a live rate.
+    pub fn snapshot(&self, previous: Option<&MetricsSnapshot>) -> MetricsSnapshot {
+        let elapsed_ms = self.test_start_time.map_or(0, |start| self.control.active_elapsed(start).as_millis());
+        let total_bytes = self.bytes_sent + self.bytes_received;
+        let (since_ms, since_bytes) = previous.map_or((0, 0), |p| (p.elapsed_ms, p.total_bytes));
+        let interval_ms = elapsed_ms.saturating_sub(since_ms);
+        MetricsSnapshot {
+            elapsed_ms,
+            total_bytes,
+            throughput_mbps: if interval_ms == 0 {
+                0.0
+            } else {
+                total_bytes.saturating_sub(since_bytes) as f64 * 8.0 / (interval_ms as f64 * 1000.0)
+            },
+            packets_sent: self.packets_sent,
+            packets_received: self.packets_received,
+            packet_loss_percent: self.packet_loss_percentage(),
+            average_rtt_ms: self.average_rtt_micros().map(|micros| micros / 1000.0),
+        }
+    }
+}
+
+/// Instantaneous values of a running test, for live displays (see `runner::TestHooks::on_sample`).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MetricsSnapshot {
+    pub elapsed_ms: u128, // Active test time, pauses excluded
+    pub total_bytes: u64, // Sent + received so far
+    pub throughput_mbps: f64, // Sent + received since the previous snapshot
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub packet_loss_percent: f64, // Over the whole test so far
+    pub average_rtt_ms: Option<f64>,
+}
+
+/// How an arriving sequence number relates to those already received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceArrival {
+    InOrder,   // Highest so far
+    Reordered, // Overtaken by a higher sequence, but arrived within the late threshold
+    Late,      // Overtaken for longer than the late threshold
+    Duplicate, // Already received
+}
+
+/// Keeps an arrival time for burst detection, up to MAX_BURST_ARRIVALS.
+fn keep_burst_arrival(arrivals_ms: &mut Vec<u128>, arrival_ms: u128) {
+    if arrivals_ms.len() < MAX_BURST_ARRIVALS {
+        arrivals_ms.push(arrival_ms);
+    }
+}
+
+/// 1-based nearest rank of percentile `p` (0-100) among `count` values: the smallest rank
+/// with at least p% of the values at or below it. Every percentile in the reports uses it.
+pub(crate) fn nearest_rank(p: f64, count: usize) -> usize {
+    ((p.clamp(0.0, 100.0) / 100.0 * count as f64).ceil() as usize).clamp(1, count.max(1))
 }
 
-// Further details for jitter calculation (e.g., using RFC 3550)
-// D(i,j) = (Rj - Ri) - (Sj - Si) = (Rj - Sj) - (Ri - Si)
-// J(i) = J(i-1) + (|D(i-1,i)| - J(i-1))/16
-// This often requires storing previous arrival and sent timestamps.
-// For simplicity, we might start with average difference of packet inter-arrival times vs inter-send times.
+// RttHistogram: values below 2^SUB_BUCKET_BITS+1 us get a bucket each; above that, every
+// power of two is split into 2^SUB_BUCKET_BITS buckets (relative width under 1/64)
+const SUB_BUCKET_BITS: u32 = 6;
+const LINEAR_BUCKETS: u64 = 1 << (SUB_BUCKET_BITS + 1);
+
+/// Log-linear histogram of microsecond values (HdrHistogram-style). Grows one bucket at a
+/// time up to the largest value seen, so 1 s of RTT needs under 1000 counters.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RttHistogram {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl RttHistogram {
+    pub fn record(&mut self, micros: u128) {
+        let index = Self::bucket_index(micros.min(u64::MAX as u128) as u64);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+        self.total += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    /// Values recorded at or below `micros`, for cumulative (Prometheus-style) buckets. The
+    /// bucket holding `micros` counts in full, so values up to 1/64 above it may be included.
+    pub fn count_at_most(&self, micros: u64) -> u64 {
+        self.counts
+            .iter()
+            .enumerate()
+            .take_while(|&(index, _)| Self::bucket_bounds(index).0 <= micros)
+            .map(|(_, &count)| count)
+            .sum()
+    }
+
+    /// Nearest-rank percentile (0-100), reported as the middle of its bucket.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        if self.total == 0 {
+            return None;
+        }
+        let rank = nearest_rank(p, self.total as usize) as u64;
+        let mut seen = 0;
+        let index = self.counts.iter().position(|&count| {
+            seen += count;
+            seen >= rank
+        })?;
+        let (low, high) = Self::bucket_bounds(index);
+        Some((low + high) as f64 / 2.0)
+    }
+
+    fn bucket_index(micros: u64) -> usize {
+        if micros < LINEAR_BUCKETS {
+            return micros as usize;
+        }
+        let magnitude = 63 - micros.leading_zeros(); // >= SUB_BUCKET_BITS + 1
+        let sub_bucket = (micros >> (magnitude - SUB_BUCKET_BITS)) & ((1 << SUB_BUCKET_BITS) - 1);
+        (LINEAR_BUCKETS + (((magnitude - SUB_BUCKET_BITS - 1) as u64) << SUB_BUCKET_BITS) + sub_bucket) as usize
+    }
+
+    /// Inclusive range of values that land in bucket `index`.
+    fn bucket_bounds(index: usize) -> (u64, u64) {
+        let index = index as u64;
+        if index < LINEAR_BUCKETS {
+            return (index, index);
+        }
+        let magnitude = ((index - LINEAR_BUCKETS) >> SUB_BUCKET_BITS) as u32 + SUB_BUCKET_BITS + 1;
+        let sub_bucket = (index - LINEAR_BUCKETS) & ((1 << SUB_BUCKET_BITS) - 1);
+        let width = 1u64 << (magnitude - SUB_BUCKET_BITS);
+        let low = (1u64 << magnitude) + sub_bucket * width;
+        (low, low + (width - 1))
+    }
+}
+
+// Upper bounds (exclusive) of the inter-arrival gap histogram buckets, in microseconds
+pub const INTERARRIVAL_BUCKET_BOUNDS_MICROS: [u64; 10] =
+    [100, 1_000, 2_000, 5_000, 10_000, 20_000, 50_000, 100_000, 200_000, 500_000];
+
+/// One bucket of `TestMetrics::interarrival_histogram`: gaps in [min_micros, max_micros).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterarrivalBucket {
+    pub min_micros: u64,
+    pub max_micros: Option<u64>, // None for the open-ended last bucket
+    pub count: u64,
+}
+
+impl InterarrivalBucket {
+    /// e.g. "2-5 ms", "< 0.1 ms", ">= 500 ms"
+    pub fn label(&self) -> String {
+        let ms = |micros: u64| format!("{}", micros as f64 / 1000.0);
+        match (self.min_micros, self.max_micros) {
+            (0, Some(max)) => format!("< {} ms", ms(max)),
+            (min, Some(max)) => format!("{}-{} ms", ms(min), ms(max)),
+            (min, None) => format!(">= {} ms", ms(min)),
+        }
+    }
+}
+
+// Width of the received payload size histogram buckets, in bytes
+pub const SIZE_BUCKET_BYTES: usize = 64;
+
+/// One bucket of `TestMetrics::received_size_histogram`: payloads of min_bytes to max_bytes, inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeBucket {
+    pub min_bytes: usize,
+    pub max_bytes: usize,
+    pub count: u64,
+}
+
+impl SizeBucket {
+    /// e.g. "1344-1407 B"
+    pub fn label(&self) -> String {
+        format!("{}-{} B", self.min_bytes, self.max_bytes)
+    }
+}
+
+/// Receiver-side loss breakdown. The categories are disjoint: a late packet is not also
+/// counted as reordered, and lost only counts sequences that never arrived.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LossBreakdown {
+    pub lost: u64,
+    pub reordered: u64,
+    pub duplicated: u64,
+    pub late: u64,
+}
+
+/// A set of sequence numbers stored as sorted, disjoint, non-adjacent inclusive ranges, so
+/// memory grows with the number of gaps rather than packets. Serializes as `[[start, end], ...]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SequenceRanges {
+    ranges: Vec<(u32, u32)>,
+}
+
+impl SequenceRanges {
+    /// Adds `seq`, extending or joining the ranges next to it. In-order arrivals only ever
+    /// extend the last range.
+    pub fn insert(&mut self, seq: u32) {
+        let next = self.ranges.partition_point(|&(start, _)| start <= seq); // First range after seq
+        let joins_next = next < self.ranges.len() && self.ranges[next].0 == seq + 1;
+        if next > 0 {
+            let end = self.ranges[next - 1].1;
+            if seq <= end {
+                return; // Already received
+            }
+            if seq == end + 1 {
+                if joins_next {
+                    self.ranges[next - 1].1 = self.ranges[next].1;
+                    self.ranges.remove(next);
+                } else {
+                    self.ranges[next - 1].1 = seq;
+                }
+                return;
+            }
+        }
+        if joins_next {
+            self.ranges[next].0 = seq;
+        } else {
+            self.ranges.insert(next, (seq, seq));
+        }
+    }
+
+    pub fn ranges(&self) -> &[(u32, u32)] {
+        &self.ranges
+    }
+
+    pub fn contains(&self, seq: u32) -> bool {
+        let next = self.ranges.partition_point(|&(start, _)| start <= seq);
+        next > 0 && seq <= self.ranges[next - 1].1
+    }
+}
+
+fn average_i64(samples: &[i64]) -> Option<f64> {
+    if samples.is_empty() {
+        None
+    } else {
+        Some(samples.iter().map(|&v| v as f64).sum::<f64>() / samples.len() as f64)
+    }
+}
+
+/// Splits an RTT into (forward, return) one-way delays, in microseconds.
+/// The return leg is the time from the server receiving the request to the client receiving
+/// the reply (server processing time included); the forward leg is the rest of the RTT.
+/// Assumes the client and server clocks are synchronized.
+pub fn split_one_way_delays(rtt_micros: u128, server_receive_micros: u64, client_receive_micros: u64) -> (i64, i64) {
+    let return_micros = client_receive_micros as i64 - server_receive_micros as i64;
+    let forward_micros = rtt_micros as i64 - return_micros;
+    (forward_micros, return_micros)
+}
 
 #[cfg(test)]
 mod metrics_tests {
@@ -273,6 +1143,22 @@ mod metrics_tests {
         assert!(metrics.max_rtt_micros.is_none());
     }
 
+    #[test]
+    fn test_rtt_skip_first_discards_warmup_reply() {
+        let mut metrics = TestMetrics::new();
+        metrics.configure_anomaly_detection(&crate::config::TestConfig { rtt_skip_first: 1, ..Default::default() });
+        metrics.record_packet_received(100, 500_000); // First reply: 500ms while caches warm up
+        metrics.record_packet_received(100, 12_000);
+        metrics.record_packet_received(100, 10_000);
+
+        assert_eq!(metrics.packets_received, 3, "Skipped replies still count as received");
+        assert_eq!(metrics.rtt_count, 2);
+        assert_eq!(metrics.min_rtt_micros, Some(10_000));
+        assert_eq!(metrics.max_rtt_micros, Some(12_000));
+        assert_eq!(metrics.average_rtt_micros(), Some(11_000.0));
+        assert_eq!(metrics.jitter_count, 1, "No jitter sample against the skipped RTT");
+    }
+
     #[test]
     fn test_record_jitter_value_separate() { // Renamed to avoid conflict if any other test is named similarly
         let mut metrics = TestMetrics::new();
@@ -290,25 +1176,28 @@ mod metrics_tests {
         metrics.init_start_time();
 
         metrics.bytes_since_last_bandwidth_sample = 1000;
+        metrics.packets_since_last_sample = 4;
         let sample_time_ms_1 = 1000;
         metrics.take_bandwidth_sample(sample_time_ms_1);
         assert_eq!(metrics.bandwidth_samples.len(), 1);
-        assert_eq!(metrics.bandwidth_samples[0], (sample_time_ms_1, 1000));
+        assert_eq!(metrics.bandwidth_samples[0], (sample_time_ms_1, 1000, 4));
+        assert_eq!(metrics.packets_since_last_sample, 0);
         assert_eq!(metrics.bytes_since_last_bandwidth_sample, 0);
         assert_eq!(metrics.last_bandwidth_sample_time_ms, Some(sample_time_ms_1));
 
         metrics.bytes_since_last_bandwidth_sample = 500;
+        metrics.packets_since_last_sample = 1;
         let sample_time_ms_2 = 1500;
         metrics.take_bandwidth_sample(sample_time_ms_2);
         assert_eq!(metrics.bandwidth_samples.len(), 2);
-        assert_eq!(metrics.bandwidth_samples[1], (sample_time_ms_2, 500));
+        assert_eq!(metrics.bandwidth_samples[1], (sample_time_ms_2, 500, 1));
         assert_eq!(metrics.bytes_since_last_bandwidth_sample, 0);
         assert_eq!(metrics.last_bandwidth_sample_time_ms, Some(sample_time_ms_2));
 
         let sample_time_ms_3 = 2000; // Time moved
         metrics.take_bandwidth_sample(sample_time_ms_3); // 0 bytes in this interval
         assert_eq!(metrics.bandwidth_samples.len(), 3);
-        assert_eq!(metrics.bandwidth_samples[2], (sample_time_ms_3, 0));
+        assert_eq!(metrics.bandwidth_samples[2], (sample_time_ms_3, 0, 0));
     }
 
     #[test]
@@ -320,6 +1209,60 @@ mod metrics_tests {
         assert_eq!(metrics.average_rtt_micros(), Some(15000.0));
     }
 
+    #[test]
+    fn test_median_and_trimmed_mean_rtt() {
+        let mut metrics = TestMetrics::new();
+        assert!(metrics.median_rtt_micros().is_none());
+        assert!(metrics.trimmed_mean_rtt_micros(10.0).is_none());
+
+        // Mostly ~10ms with two large outliers
+        for rtt in [10_000, 11_000, 9_000, 10_500, 9_500, 10_000, 11_000, 9_000, 500_000, 900_000] {
+            metrics.record_packet_received(100, rtt);
+        }
+        let mean = metrics.average_rtt_micros().unwrap();
+        let trimmed = metrics.trimmed_mean_rtt_micros(10.0).unwrap(); // Drops 1 sample from each end
+        let median = metrics.median_rtt_micros().unwrap();
+
+        assert_eq!(mean, 148_000.0);
+        assert_eq!(trimmed, 71_375.0);
+        assert_eq!(median, 10_250.0);
+        assert!(trimmed < mean / 2.0, "Trimmed mean should be far less affected by outliers");
+
+        // 20% trim removes both outliers
+        let trimmed_20 = metrics.trimmed_mean_rtt_micros(20.0).unwrap();
+        assert!((trimmed_20 - 10_333.33).abs() < 0.01);
+        assert_eq!(metrics.trimmed_mean_rtt_micros(0.0), Some(mean));
+
+        use crate::config::LatencyStat;
+        assert_eq!(metrics.headline_rtt_micros(LatencyStat::Mean), Some(mean));
+        assert_eq!(metrics.headline_rtt_micros(LatencyStat::Median), Some(median));
+        assert_eq!(metrics.headline_rtt_micros(LatencyStat::TrimmedMean(20.0)), Some(trimmed_20));
+        // Raw stats are untouched
+        assert_eq!(metrics.min_rtt_micros, Some(9_000));
+        assert_eq!(metrics.max_rtt_micros, Some(900_000));
+    }
+
+    #[test]
+    fn test_split_one_way_delays() {
+        // Request sent at t=1_000_000µs (client clock), server received it at 1_020_000,
+        // reply back at the client at 1_030_000 -> RTT 30ms = 20ms forward + 10ms return.
+        let (forward, ret) = split_one_way_delays(30_000, 1_020_000, 1_030_000);
+        assert_eq!(forward, 20_000);
+        assert_eq!(ret, 10_000);
+
+        let mut metrics = TestMetrics::new();
+        assert!(metrics.average_forward_delay_micros().is_none());
+        metrics.record_one_way_delays(forward, ret);
+        metrics.record_one_way_delays(24_000, 12_000);
+        assert_eq!(metrics.average_forward_delay_micros(), Some(22_000.0));
+        assert_eq!(metrics.average_return_delay_micros(), Some(11_000.0));
+
+        // Skewed clocks can produce negative estimates; they are kept rather than clamped
+        let (forward, ret) = split_one_way_delays(30_000, 1_040_000, 1_030_000);
+        assert_eq!(ret, -10_000);
+        assert_eq!(forward, 40_000);
+    }
+
     #[test]
     fn test_packet_loss_percentage() {
         let mut metrics = TestMetrics::new();
@@ -345,6 +1288,37 @@ mod metrics_tests {
         assert_eq!(metrics.average_jitter_micros(), Some(150.0));
     }
 
+    #[test]
+    fn test_steady_state_throughput_excludes_warmup() {
+        let config = crate::config::TestConfig { warmup_secs: 1, ..Default::default() };
+        let mut metrics = TestMetrics::new();
+        metrics.configure_warmup(&config);
+        metrics.init_start_time();
+        assert!(metrics.steady_state_throughput_bps(3.0).is_none());
+
+        // During warmup: 10 packets of 1000 bytes
+        for _ in 0..10 {
+            metrics.record_packet_received(1000, 0);
+        }
+        assert!(metrics.bytes_received_at_warmup_end.is_none());
+
+        // Pretend the warmup second has passed, then 5 more packets
+        metrics.test_start_time = Instant::now().checked_sub(Duration::from_secs(2));
+        for _ in 0..5 {
+            metrics.record_packet_received(1000, 0);
+        }
+        assert_eq!(metrics.warmup_end_ms, Some(1000));
+        assert_eq!(metrics.bytes_received_at_warmup_end, Some(10_000));
+
+        // Steady state: 5000 bytes over the 2s after warmup; overall: 15000 bytes over 3s
+        let steady_bps = metrics.steady_state_throughput_bps(3.0).unwrap();
+        assert!((steady_bps - 20_000.0).abs() < 0.01);
+        assert!((metrics.overall_throughput_bps(3.0) - 40_000.0).abs() < 0.01);
+
+        let no_warmup = TestMetrics::new();
+        assert!(no_warmup.steady_state_throughput_bps(3.0).is_none());
+    }
+
     #[test]
     fn test_overall_throughput_bps() {
         let mut metrics = TestMetrics::new();
@@ -354,4 +1328,339 @@ mod metrics_tests {
         metrics.bytes_received = 0;
         assert_eq!(metrics.overall_throughput_bps(10.0), 0.0);
     }
+
+    #[test]
+    fn test_final_bandwidth_sample_ends_at_test_end_not_grace_timeout() {
+        use crate::config::FinalSampleTiming;
+        let configured_duration_ms = 3000;
+
+        let mut metrics = TestMetrics::new();
+        metrics.init_start_time();
+        metrics.take_bandwidth_sample(1000);
+        metrics.take_bandwidth_sample(2000);
+        // Last packet arrives shortly before the configured end of the test...
+        metrics.test_start_time = Instant::now().checked_sub(Duration::from_millis(2900));
+        metrics.record_packet_received(1000, 0);
+        // ...and the receiver only shuts down after the 5s grace period
+        metrics.test_start_time = Instant::now().checked_sub(Duration::from_millis(8000));
+        metrics.take_final_bandwidth_sample(FinalSampleTiming::LastPacket, configured_duration_ms);
+
+        let (last_ts, last_bytes, _) = *metrics.bandwidth_samples.last().unwrap();
+        assert!((2900..=configured_duration_ms).contains(&last_ts), "final sample at {}ms", last_ts);
+        assert_eq!(last_bytes, 1000);
+
+        let mut metrics = TestMetrics::new();
+        metrics.init_start_time();
+        metrics.record_packet_received(1000, 0);
+        metrics.test_start_time = Instant::now().checked_sub(Duration::from_millis(8000));
+        metrics.take_final_bandwidth_sample(FinalSampleTiming::ConfiguredDuration, configured_duration_ms);
+        assert_eq!(metrics.bandwidth_samples.last(), Some(&(configured_duration_ms, 1000, 1)));
+    }
+
+    #[test]
+    fn test_loss_breakdown_categories() {
+        let config = crate::config::TestConfig { rtt_timeout_ms: 200, ..Default::default() };
+        let mut metrics = TestMetrics::new();
+        metrics.configure_anomaly_detection(&config);
+        assert!(metrics.loss_breakdown().is_none());
+
+        // 0..=9 expected; 3 and 7 never arrive, 2 is reordered, 5 is late, 4 and 8 are duplicated
+        let arrivals = [
+            (0, 0), (1, 10), (4, 20), (2, 25), (4, 30), (6, 40),
+            (8, 50), (8, 55), (9, 60), (5, 400),
+        ];
+        let classes: Vec<_> = arrivals.iter().map(|&(seq, ms)| metrics.record_sequence(seq, ms)).collect();
+        assert_eq!(classes[3], SequenceArrival::Reordered);
+        assert_eq!(classes[4], SequenceArrival::Duplicate);
+        assert_eq!(classes[9], SequenceArrival::Late);
+
+        let breakdown = metrics.loss_breakdown().unwrap();
+        assert_eq!(breakdown, LossBreakdown { lost: 2, reordered: 1, duplicated: 2, late: 1 });
+        assert_eq!(metrics.out_of_order_count, 2);
+        assert_eq!(metrics.anomalies.len(), 4);
+    }
+
+    #[test]
+    fn test_rtt_percentiles() {
+        let mut metrics = TestMetrics::new();
+        assert_eq!(metrics.rtt_percentile(50.0), None);
+        assert_eq!(metrics.rtt_p99(), None);
+
+        metrics.record_packet_received(100, 100); // Small values have exact buckets
+        assert_eq!(metrics.rtt_p50(), Some(100.0));
+        assert_eq!(metrics.rtt_percentile(0.0), Some(100.0));
+        assert_eq!(metrics.rtt_p99(), Some(100.0));
+
+        // 1..=1000 ms, uniformly: pN is N% of a second
+        let mut metrics = TestMetrics::new();
+        for rtt_ms in 1..=1000u128 {
+            metrics.record_packet_received(100, rtt_ms * 1000);
+        }
+        for (actual, expected) in [(metrics.rtt_p50(), 500_000.0), (metrics.rtt_p95(), 950_000.0), (metrics.rtt_p99(), 990_000.0)] {
+            let actual = actual.unwrap();
+            assert!((actual - expected).abs() / expected < 0.01, "{} vs {}", actual, expected);
+        }
+        let p100 = metrics.rtt_percentile(100.0).unwrap();
+        assert!((p100 - 1_000_000.0).abs() / 1_000_000.0 < 0.01, "{}", p100);
+    }
+
+    #[test]
+    fn test_latency_sample_reservoir() {
+        let mut metrics = TestMetrics::new();
+        metrics.configure_latency_samples(&crate::config::TestConfig { max_latency_samples: 1000, ..Default::default() });
+        // Uniform 1..=100000 us, fed in a shuffled-looking order
+        for i in 0..100_000u128 {
+            metrics.record_packet_received(100, (i * 7_919) % 100_000 + 1);
+        }
+        assert_eq!(metrics.rtt_count, 100_000);
+        assert_eq!(metrics.rtt_samples.len(), 1000);
+        assert_eq!(metrics.rtt_sample_times_ms.len(), 1000);
+
+        let mut sorted = metrics.rtt_samples.clone();
+        sorted.sort_unstable();
+        for (percentile, expected) in [(50.0, 50_000.0), (95.0, 95_000.0)] {
+            let actual = sorted[nearest_rank(percentile, sorted.len()) - 1] as f64;
+            assert!((actual - expected).abs() < 5_000.0, "p{}: {} vs {}", percentile, actual, expected);
+        }
+        let median = metrics.median_rtt_micros().unwrap();
+        assert!((median - 50_000.0).abs() < 5_000.0, "{}", median);
+
+        let mut unlimited = TestMetrics::new();
+        unlimited.configure_latency_samples(&crate::config::TestConfig { max_latency_samples: 0, ..Default::default() });
+        for rtt in 1..=5_000u128 {
+            unlimited.record_packet_received(100, rtt);
+        }
+        assert_eq!(unlimited.rtt_samples.len(), 5_000);
+    }
+
+    #[test]
+    fn test_rtt_histogram_memory_is_bounded() {
+        let mut histogram = RttHistogram::default();
+        for i in 0..1_000_000u128 {
+            histogram.record(i % 1_000_000 + 1); // Up to 1 s
+        }
+        assert_eq!(histogram.count(), 1_000_000);
+        assert!(histogram.counts.len() < 1000, "{} buckets", histogram.counts.len());
+        for micros in [0u64, 127, 128, 255, 256, 999_999, u64::MAX] {
+            let (low, high) = RttHistogram::bucket_bounds(RttHistogram::bucket_index(micros));
+            assert!(low <= micros && micros <= high, "{} not in [{}, {}]", micros, low, high);
+        }
+    }
+
+    #[test]
+    fn test_latency_spike_carries_the_threshold_it_crossed() {
+        let config = crate::config::TestConfig { latency_spike_threshold_ms: Some(200), ..Default::default() };
+        let mut metrics = TestMetrics::new();
+        metrics.configure_anomaly_detection(&config);
+        metrics.record_packet_received(100, 150_000);
+        metrics.record_packet_received(100, 250_000);
+
+        let spike = metrics.anomalies.iter()
+            .find(|a| a.anomaly_type == crate::anomalies::AnomalyType::HighLatencySpike)
+            .expect("No spike recorded");
+        let threshold = spike.threshold.as_ref().expect("Spike without its threshold");
+        assert_eq!(threshold.config_field, "latency_spike_threshold_ms");
+        assert_eq!(threshold.limit, 200.0);
+        assert_eq!(threshold.observed, 250.0);
+        assert_eq!(spike.explanation(), "RTT: 250.00 ms (250 exceeded latency_spike_threshold_ms=200)");
+    }
+
+    #[test]
+    fn test_disabled_anomaly_detection_records_nothing_but_keeps_counters() {
+        let config = crate::config::TestConfig {
+            anomaly_detection_enabled: false,
+            latency_spike_threshold_ms: Some(1),
+            jitter_spike_threshold_ms: Some(1),
+            ..Default::default()
+        };
+        let mut metrics = TestMetrics::new();
+        metrics.configure_anomaly_detection(&config);
+
+        // Every RTT and jitter sample is far over the 1 ms thresholds
+        for rtt_micros in [50_000, 150_000, 20_000] {
+            metrics.record_packet_received(100, rtt_micros);
+        }
+        for (seq, ms) in [(0, 0), (2, 10), (1, 20), (2, 30)] {
+            metrics.record_sequence(seq, ms);
+        }
+
+        assert!(metrics.anomalies.is_empty(), "{:?}", metrics.anomalies);
+        assert_eq!(metrics.packets_received, 3);
+        assert_eq!(metrics.rtt_count, 3);
+        assert_eq!(metrics.max_rtt_micros, Some(150_000));
+        assert_eq!(metrics.jitter_count, 2);
+        assert_eq!(metrics.out_of_order_count, 1);
+        assert_eq!(metrics.duplicate_count, 1);
+    }
+
+    #[test]
+    fn test_sequence_tracking_across_wraparound() {
+        let mut metrics = TestMetrics::new();
+        for (i, seq) in [u32::MAX - 1, u32::MAX, 0, 1].into_iter().enumerate() {
+            assert_eq!(metrics.record_sequence(seq, i as u128 * 10), SequenceArrival::InOrder, "seq {}", seq);
+        }
+        // Copies from both sides of the wrap are still duplicates
+        assert_eq!(metrics.record_sequence(u32::MAX, 50), SequenceArrival::Duplicate);
+        assert_eq!(metrics.record_sequence(0, 60), SequenceArrival::Duplicate);
+        assert_eq!(metrics.duplicate_count, 2);
+
+        // A straggler from before the wrap is behind the highest, not a new highest
+        let mut metrics = TestMetrics::new();
+        for (i, seq) in [u32::MAX - 3, u32::MAX - 2, 0, 1].into_iter().enumerate() {
+            metrics.record_sequence(seq, i as u128 * 10);
+        }
+        assert_eq!(metrics.record_sequence(u32::MAX, 50), SequenceArrival::Reordered);
+        assert_eq!(metrics.highest_sequence, Some((1, 30)));
+    }
+
+    #[test]
+    fn test_duplicate_window_stays_bounded() {
+        let mut metrics = TestMetrics::new();
+        let packets = 5 * DUPLICATE_WINDOW;
+        for seq in 0..packets {
+            metrics.record_sequence(seq, seq as u128);
+        }
+        assert!(metrics.seen_sequences.len() <= 2 * DUPLICATE_WINDOW as usize + 1, "{} remembered", metrics.seen_sequences.len());
+        assert_eq!(metrics.record_sequence(packets - 10, packets as u128), SequenceArrival::Duplicate);
+        assert_eq!(metrics.loss_breakdown().unwrap().lost, 0);
+
+        // A copy of a sequence past the window is a late duplicate, not a new distinct one
+        assert_eq!(metrics.record_sequence(5, packets as u128), SequenceArrival::Duplicate);
+        assert_eq!(metrics.loss_breakdown().unwrap(), LossBreakdown { lost: 0, reordered: 0, duplicated: 2, late: 0 });
+    }
+
+    #[test]
+    fn test_sequence_ranges_merge_gaps_and_ignore_duplicates() {
+        let mut metrics = TestMetrics::new();
+        metrics.record_sequence(0, 0);
+        assert!(metrics.received_sequence_ranges.is_none(), "Recorded without record_sequence_ranges");
+
+        metrics.configure_sequence_ranges(&crate::config::TestConfig { record_sequence_ranges: true, ..Default::default() });
+        for (arrival_ms, seq) in [0, 1, 4, 7, 3, 1, 8, 2, u32::MAX, 5].into_iter().enumerate() {
+            metrics.record_sequence(seq, arrival_ms as u128);
+        }
+        let ranges = metrics.received_sequence_ranges.as_ref().unwrap();
+        assert_eq!(ranges.ranges(), &[(0, 5), (7, 8), (u32::MAX, u32::MAX)]);
+        assert!(ranges.contains(4) && !ranges.contains(6) && !ranges.contains(9));
+    }
+
+    #[test]
+    fn test_reorder_window_tolerates_small_displacement() {
+        // Seq 1 arrives 2 positions behind the highest (3)
+        let arrivals = [(0, 0), (2, 10), (3, 20), (1, 30)];
+        let classify_last = |reorder_window: u32| {
+            let mut metrics = TestMetrics::new();
+            metrics.configure_anomaly_detection(&crate::config::TestConfig { reorder_window, ..Default::default() });
+            let classes: Vec<_> = arrivals.iter().map(|&(seq, ms)| metrics.record_sequence(seq, ms)).collect();
+            (classes[3], metrics.out_of_order_count, metrics.anomalies.len())
+        };
+
+        assert_eq!(classify_last(3), (SequenceArrival::InOrder, 0, 0));
+        assert_eq!(classify_last(2), (SequenceArrival::InOrder, 0, 0));
+        assert_eq!(classify_last(1), (SequenceArrival::Reordered, 1, 1));
+        assert_eq!(classify_last(0), (SequenceArrival::Reordered, 1, 1));
+    }
+
+    #[test]
+    fn test_efficiency_percentage() {
+        let mut metrics = TestMetrics::new();
+        assert!(metrics.efficiency_percentage().is_none());
+
+        // 4 TCP packets: 32 byte header + 4 byte length prefix around 64 payload bytes each
+        for _ in 0..4 {
+            metrics.record_packet_received(32 + 4 + 64, 0);
+            metrics.record_payload_received(64);
+        }
+        assert_eq!(metrics.payload_bytes_received, 256);
+        assert!((metrics.efficiency_percentage().unwrap() - 64.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_throughput_cv_separates_steady_from_spiky() {
+        let series = |bytes: &[u64]| TestMetrics {
+            bandwidth_samples: bytes.iter().enumerate().map(|(i, &b)| ((i as u128 + 1) * 1000, b, 1)).collect(),
+            ..Default::default()
+        };
+        let steady = series(&[125_000, 126_000, 124_000, 125_000, 125_500]);
+        let spiky = series(&[125_000, 10_000, 400_000, 20_000, 125_000]);
+
+        let steady_cv = steady.throughput_cv().unwrap();
+        let spiky_cv = spiky.throughput_cv().unwrap();
+        assert!(steady_cv < 1.0, "Steady CV {}", steady_cv);
+        assert!(spiky_cv > 50.0, "Spiky CV {}", spiky_cv);
+        assert_eq!(steady.throughput_stability(), Some("Steady"));
+        assert_eq!(spiky.throughput_stability(), Some("Unstable"));
+
+        // Equal bytes over unequal intervals is not steady throughput
+        let uneven = TestMetrics { bandwidth_samples: vec![(1000, 125_000, 1), (3000, 125_000, 1)], ..Default::default() };
+        assert!((uneven.throughput_cv().unwrap() - 100.0 / 3.0).abs() < 1e-9);
+        assert_eq!(series(&[125_000]).throughput_cv(), None);
+        assert_eq!(series(&[0, 0, 0]).throughput_cv(), None);
+    }
+
+    #[test]
+    fn test_best_window_throughput_finds_the_sustained_peak() {
+        // 1 s samples ramping up to 8 Mbps for 3 s mid-test, then back down
+        let mbps = [1.0, 2.0, 8.0, 8.0, 8.0, 2.0, 1.0];
+        let metrics = TestMetrics {
+            bandwidth_samples: mbps.iter().enumerate().map(|(i, &m)| ((i as u128 + 1) * 1000, (m * 125_000.0) as u64, 1)).collect(),
+            ..Default::default()
+        };
+        let overall_mbps = mbps.iter().sum::<f64>() / mbps.len() as f64;
+
+        let best_3s = metrics.best_window_throughput_mbps(3.0).unwrap();
+        assert!((best_3s - 8.0).abs() < 1e-9, "{}", best_3s);
+        assert!(best_3s > overall_mbps);
+        assert!((metrics.best_window_throughput_mbps(5.0).unwrap() - 28.0 / 5.0).abs() < 1e-9); // 2 + 8 x 3 + 2
+        // A window between sample boundaries takes the next whole interval too
+        assert!((metrics.best_window_throughput_mbps(3.5).unwrap() - 26.0 / 4.0).abs() < 1e-9);
+        assert!((metrics.best_window_throughput_mbps(7.0).unwrap() - overall_mbps).abs() < 1e-9);
+        assert_eq!(metrics.best_window_throughput_mbps(7.5), None);
+    }
+
+    #[test]
+    fn test_smoothed_jitter_follows_rfc3550() {
+        let mut metrics = TestMetrics::default();
+        assert_eq!(metrics.smoothed_jitter_micros(), None);
+        // Sent every 20 ms; arrivals with transit times 10, 12, 11, 15 ms (+ a clock offset)
+        let offset_micros = 1_700_000_000_000_000;
+        for (i, transit_ms) in [10, 12, 11, 15].into_iter().enumerate() {
+            let send_ms = 1_000_000 + i as u64 * 20;
+            metrics.record_transit(send_ms, offset_micros + (send_ms + transit_ms) * 1000);
+        }
+        // |D| = 2000, 1000, 4000 us: J = 125, then 125 + (1000 - 125) / 16, then ...
+        let mut expected: f64 = 0.0;
+        for d in [2000.0, 1000.0, 4000.0] {
+            expected += (d - expected) / 16.0;
+        }
+        let jitter = metrics.smoothed_jitter_micros().unwrap();
+        assert!((jitter - expected).abs() < 1e-9, "{} vs {}", jitter, expected);
+        assert!((jitter - 418.45703125).abs() < 1e-9);
+
+        // A constant transit time (whatever the clock offset) decays the estimate toward 0
+        for i in 4..200u64 {
+            let send_ms = 1_000_000 + i * 20;
+            metrics.record_transit(send_ms, offset_micros + (send_ms + 15) * 1000);
+        }
+        assert!(metrics.smoothed_jitter_micros().unwrap() < 1.0);
+    }
+
+    #[test]
+    fn test_interarrival_histogram_buckets_gaps() {
+        let mut metrics = TestMetrics::default();
+        assert!(metrics.interarrival_histogram().is_empty());
+        for gap_micros in [3_000, 30_000, 2_000, 300_000, 4_999, 20_000, 50, 5_000, 600_000] {
+            metrics.record_interarrival_gap(gap_micros);
+        }
+        let histogram = metrics.interarrival_histogram();
+        assert_eq!(histogram.len(), INTERARRIVAL_BUCKET_BOUNDS_MICROS.len() + 1);
+        let count_in = |label: &str| histogram.iter().find(|b| b.label() == label).unwrap().count;
+        assert_eq!(count_in("< 0.1 ms"), 1);
+        assert_eq!(count_in("2-5 ms"), 3, "{:?}", histogram); // Lower bounds are inclusive
+        assert_eq!(count_in("5-10 ms"), 1);
+        assert_eq!(count_in("20-50 ms"), 2);
+        assert_eq!(count_in("200-500 ms"), 1);
+        assert_eq!(count_in(">= 500 ms"), 1);
+        assert_eq!(histogram.iter().map(|b| b.count).sum::<u64>(), 9);
+    }
 }
@@ -1,13 +1,16 @@
 // network.rs
-use crate::config::{Protocol, TestConfig, TestMode, TcpBidirectionalMode};
-use crate::packet::CustomPacket;
-use crate::metrics::TestMetrics;
-use std::net::SocketAddr;
+use crate::config::{ConfigError, Protocol, TestConfig, TestMode, TcpBidirectionalMode, UnexpectedPacketHandling};
+use crate::packet::{ControlMessage, CustomPacket, PacketType};
+use crate::flame::hot_span;
+use crate::metrics::{lock_metrics, TestMetrics};
+use crate::packet_tape::{PacketEvent, PacketTape};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::io;
 use tokio::net::{TcpStream, TcpListener, UdpSocket};
-// use tokio::sync::mpsc; // Unused: For potential internal signaling if needed
+use tokio::sync::{mpsc, Notify};
 
 #[derive(Debug)] // Added Debug derive
 pub enum NetworkError {
@@ -18,14 +21,64 @@ pub enum NetworkError {
     Other(String),
     InvalidAddress(String), // More specific error type
     UnsupportedMode(String), // For unsupported combinations
+    PacketTooLarge { size: usize, mtu: Option<usize> }, // EMSGSIZE: datagram over the path MTU with DF set
+    InvalidConfig(ConfigError), // Rejected before the test starts
+    AddressInUse(SocketAddr), // A receive socket couldn't bind: another socket holds the port
 }
 
+impl NetworkError {
+    /// Errors of a test that started while the server wasn't listening yet, or while its port
+    /// was still held by a previous run. Worth retrying (TestConfig::max_test_retries).
+    pub fn is_transient(&self) -> bool {
+        match self {
+            NetworkError::AddressInUse(_) => true,
+            NetworkError::IoError(e) => matches!(e.kind(), io::ErrorKind::AddrInUse | io::ErrorKind::ConnectionRefused),
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetworkError::IoError(e) => write!(f, "I/O error: {}", e),
+            NetworkError::SerializationError(reason) => write!(f, "Serialization error: {}", reason),
+            NetworkError::HandshakeError(reason) => write!(f, "Handshake failed: {}", reason),
+            NetworkError::Timeout => write!(f, "Timed out"),
+            NetworkError::Other(reason) => write!(f, "{}", reason),
+            NetworkError::InvalidAddress(reason) => write!(f, "Invalid address: {}", reason),
+            NetworkError::UnsupportedMode(reason) => write!(f, "Unsupported mode: {}", reason),
+            NetworkError::PacketTooLarge { size, mtu: Some(mtu) } => {
+                write!(f, "A {} byte datagram is over the path MTU of {} with Don't Fragment set", size, mtu)
+            }
+            NetworkError::PacketTooLarge { size, mtu: None } => {
+                write!(f, "A {} byte datagram is over the path MTU with Don't Fragment set", size)
+            }
+            NetworkError::InvalidConfig(e) => write!(f, "Invalid config: {}", e),
+            NetworkError::AddressInUse(addr) => write!(
+                f,
+                "{} is already in use, e.g. by another test or one that hasn't released it yet. \
+                 Stop it, wait a moment, use another port, or set reuse_addr",
+                addr
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
 impl From<std::io::Error> for NetworkError {
     fn from(err: std::io::Error) -> Self {
         NetworkError::IoError(err)
     }
 }
 
+impl From<ConfigError> for NetworkError {
+    fn from(err: ConfigError) -> Self {
+        NetworkError::InvalidConfig(err)
+    }
+}
+
 impl From<bincode::Error> for NetworkError {
     fn from(err: bincode::Error) -> Self {
         NetworkError::SerializationError(err.to_string())
@@ -38,67 +91,108 @@ pub async fn run_network_test(
     config: Arc<TestConfig>,
     metrics: Arc<Mutex<TestMetrics>>,
 ) -> Result<(), NetworkError> {
+    config.validate()?;
     // Initialize metrics start time and configure anomaly detection thresholds
-    if let Ok(mut m) = metrics.lock() {
+    {
+        let mut m = lock_metrics(&metrics);
         m.init_start_time();
         m.configure_anomaly_detection(&config); // Pass the config to set thresholds
-    } else {
-        return Err(NetworkError::Other("Failed to lock metrics for init/config.".to_string()));
+        m.configure_warmup(&config);
+        m.configure_latency_samples(&config);
+        m.configure_sequence_ranges(&config);
+        m.start_session();
     }
+    // Stops sampling when dropped, however the test ends
+    let _process_monitor = config.monitor_process.then(|| {
+        crate::process_monitor::ProcessMonitor::start(Arc::clone(&metrics), config.bandwidth_sample_interval())
+    });
 
+    if config.dest_port_range.is_some() && config.protocol == Protocol::Tcp {
+        return Err(NetworkError::UnsupportedMode("A destination port range requires UDP".to_string()));
+    }
+    if config.parallel_streams > 1 && config.protocol == Protocol::Tcp && config.test_mode == TestMode::Bidirectional {
+        return Err(NetworkError::UnsupportedMode("Parallel TCP streams require client or server mode".to_string()));
+    }
 
     match config.test_mode {
         TestMode::Client => {
             println!("Mode: Client, Protocol: {:?}", config.protocol);
-            let remote_addr = format!("{}:{}", config.target_ip, config.target_port)
-                .parse::<SocketAddr>()
-                .map_err(|e| NetworkError::InvalidAddress(format!("Invalid target address: {} - {}", config.target_ip, e)))?;
+            let remote_addr = match multicast_group(&config)? {
+                Some(group) if config.protocol == Protocol::Udp => SocketAddr::new(group, config.target_port),
+                Some(_) => return Err(NetworkError::UnsupportedMode("Multicast requires UDP".to_string())),
+                None => resolve_target(&config.target_ip, config.target_port).await?[0], // runner::run_test_per_address splits the others off
+            };
             match config.protocol {
-                Protocol::Udp => udp_send_loop(Arc::clone(&config), remote_addr, metrics, true).await?, // is_primary_sender = true
+                Protocol::Udp => {
+                    let control_addr = config.udp_control_port.map(|port| SocketAddr::new(remote_addr.ip(), port));
+                    let session_id = lock_metrics(&metrics).session_id;
+                    if let Some(control_addr) = control_addr {
+                        send_control_message(control_addr, ControlMessage::Start, &config, session_id).await?;
+                    }
+                    udp_send_loop(Arc::clone(&config), remote_addr, metrics, true).await?; // is_primary_sender = true
+                    if let Some(control_addr) = control_addr {
+                        send_control_message(control_addr, ControlMessage::End, &config, session_id).await?;
+                    }
+                }
+                Protocol::Tcp if config.parallel_streams > 1 => tcp_parallel_client(config, remote_addr, metrics).await?,
                 Protocol::Tcp => {
-                    let stream = tcp_connect(remote_addr).await?;
-                    let (_reader, writer) = tokio::io::split(stream); // _reader is unused for now
-                    // In client-only mode, primarily sends. Receiving might be for ACKs.
-                    // For now, just run send_loop. Acks would require a receive_loop too.
-                    tcp_send_loop(Arc::clone(&config), writer, metrics, true).await?;
+                    let stream = tcp_connect(remote_addr, &metrics).await?;
+                    let (reader, connection) = TcpConnection::split(stream, &config)?;
+                    // The receive loop reads the server's echo replies, until the server closes the connection
+                    tokio::try_join!(
+                        tcp_send_loop(Arc::clone(&config), Arc::clone(&connection), Arc::clone(&metrics), true),
+                        tcp_receive_loop(Arc::clone(&config), reader, connection, metrics),
+                    )?;
                 }
             }
         }
         TestMode::Server => {
             println!("Mode: Server, Protocol: {:?}", config.protocol);
-            let listen_addr = format!("0.0.0.0:{}", config.target_port)
-                .parse::<SocketAddr>()
-                .map_err(|e| NetworkError::InvalidAddress(format!("Invalid listen address: {}", e)))?;
+            let listen_addr = listen_socket_addr(&config.target_ip, config.target_port);
             match config.protocol {
                 Protocol::Udp => {
-                    let socket = Arc::new(UdpSocket::bind(listen_addr).await?);
-                    udp_receive_loop(Arc::clone(&config), socket, metrics).await?;
+                    let socket = Arc::new(bind_udp_listener(&config, listen_addr).await?);
+                    let control_channel = match config.udp_control_port {
+                        Some(port) => {
+                            let control_socket = bind_udp(SocketAddr::new(listen_addr.ip(), port), config.reuse_addr)?;
+                            let end_of_test = Arc::new(Notify::new());
+                            let task = tokio::spawn(udp_control_loop(control_socket, config.bincode_fixint, Arc::clone(&metrics), Arc::clone(&end_of_test)));
+                            Some((task, end_of_test))
+                        }
+                        None => None,
+                    };
+                    let port_range_receivers = spawn_port_range_receivers(&config, listen_addr, &metrics).await?;
+                    let end_of_test = control_channel.as_ref().map(|(_, end_of_test)| Arc::clone(end_of_test));
+                    let result = udp_receive_loop(Arc::clone(&config), socket, metrics, end_of_test, true).await;
+                    if let Some((task, _)) = control_channel {
+                        task.abort();
+                    }
+                    let port_range_result = stop_port_range_receivers(port_range_receivers).await;
+                    result?;
+                    port_range_result?;
                 }
+                Protocol::Tcp if config.parallel_streams > 1 => tcp_parallel_server(config, listen_addr, metrics).await?,
                 Protocol::Tcp => {
-                    let listener = tcp_listen(listen_addr).await?;
+                    let listener = tcp_listen(listen_addr, &config)?;
                     println!("TCP Server: Waiting for a connection on {}...", listen_addr);
-                    let (stream, client_addr) = listener.accept().await?;
+                    let Some((stream, client_addr)) = tcp_accept(&listener, &metrics).await? else {
+                        return Ok(()); // Cancelled before a client connected
+                    };
                     println!("TCP Server: Accepted connection from {}", client_addr);
-                    let (reader, _writer) = tokio::io::split(stream); // _writer is unused for now
-                    // In server-only mode, primarily receives. Sending might be for ACKs.
-                    // For now, just run receive_loop. ACKs would require a send_loop too.
-                    tcp_receive_loop(Arc::clone(&config), reader, metrics).await?;
+                    let (reader, connection) = TcpConnection::split(stream, &config)?;
+                    // Receives, and answers the client's EchoRequests on the same connection
+                    tcp_receive_loop(Arc::clone(&config), reader, connection, metrics).await?;
                 }
             }
         }
         TestMode::Bidirectional => {
             println!("Mode: Bidirectional, Protocol: {:?}", config.protocol);
-            let remote_addr = format!("{}:{}", config.target_ip, config.target_port)
-                .parse::<SocketAddr>()
-                .map_err(|e| NetworkError::InvalidAddress(format!("Invalid target address for sending: {} - {}", config.target_ip, e)))?;
-
-            // Local listen port for receiving part of bidirectional test.
-            // For now, assume it's the same as target_port. This might need refinement
-            // if client and server are on the same machine or for more complex setups.
-            let local_listen_port = config.target_port; // Could be a separate config field: config.local_listen_port
-            let listen_addr = format!("0.0.0.0:{}", local_listen_port)
-                .parse::<SocketAddr>()
-                .map_err(|e| NetworkError::InvalidAddress(format!("Invalid listen address for receiving: {}", e)))?;
+            let remote_addr = resolve_target(&config.target_ip, config.target_port).await?[0];
+
+            // Local listen port for receiving part of bidirectional test. Defaults to the
+            // target port, as on two hosts; peers on one machine need distinct ports.
+            let local_listen_port = config.local_listen_port.unwrap_or(config.target_port);
+            let listen_addr = listen_socket_addr(&config.target_ip, local_listen_port);
 
             match config.protocol {
                 Protocol::Udp => {
@@ -107,14 +201,14 @@ pub async fn run_network_test(
                     let metrics_send = Arc::clone(&metrics);
                     let metrics_recv = Arc::clone(&metrics);
 
-                    let listen_socket = Arc::new(UdpSocket::bind(listen_addr).await?);
+                    let listen_socket = Arc::new(bind_udp_listener(&config, listen_addr).await?);
                     let recv_socket_clone = Arc::clone(&listen_socket);
 
                     let send_handle = tokio::spawn(async move {
                         udp_send_loop(send_config, remote_addr, metrics_send, true).await // is_primary_sender = true
                     });
                     let recv_handle = tokio::spawn(async move {
-                        udp_receive_loop(recv_config, recv_socket_clone, metrics_recv).await
+                        udp_receive_loop(recv_config, recv_socket_clone, metrics_recv, None, true).await
                     });
 
                     // Wait for both tasks to complete
@@ -132,26 +226,16 @@ pub async fn run_network_test(
                             let client_send_config = Arc::clone(&config);
                             let client_metrics = Arc::clone(&metrics);
                             let client_handle = tokio::spawn(async move {
-                                let stream = tcp_connect(remote_addr).await?;
+                                let stream = tcp_connect(remote_addr, &client_metrics).await?;
                                 let peer_display = stream.peer_addr().map_or("unknown peer".to_string(), |a| a.to_string());
                                 println!("TCP BiDi (Dual): Connected to {} for sending.", peer_display);
-                                let (reader, writer) = tokio::io::split(stream);
-
-                                // For dual stream, the "client" task primarily sends on its outgoing connection
-                                // and might receive ACKs or control messages.
-                                // The "server" task primarily receives on its incoming connection
-                                // and might send ACKs or control messages.
-                                // If full data is to be exchanged both ways on EACH stream, then both loops run fully.
-                                // For now, let's assume the client task is primary sender on its stream,
-                                // and server task is primary receiver on its stream.
-                                // Any "return" traffic on these streams (like ACKs) would be handled by the other loop.
+                                let (reader, connection) = TcpConnection::split(stream, &client_send_config)?;
+
+                                // The client task is the primary sender on its outgoing connection. Its
+                                // receive loop reads the echo replies and the peer's secondary data.
                                 let _ = tokio::try_join!(
-                                    tcp_send_loop(Arc::clone(&client_send_config), writer, Arc::clone(&client_metrics), true),
-                                    // Secondary receive loop on the client's outgoing stream (e.g., for control/acks)
-                                    // This receive loop should not run for the full test_duration if it's just for ACKs.
-                                    // This needs careful thought: what does this reader do? If it's expecting data, it needs to run.
-                                    // For now, assume it's a full receive loop.
-                                    tcp_receive_loop(Arc::clone(&client_send_config), reader, Arc::clone(&client_metrics))
+                                    tcp_send_loop(Arc::clone(&client_send_config), Arc::clone(&connection), Arc::clone(&client_metrics), true),
+                                    tcp_receive_loop(Arc::clone(&client_send_config), reader, connection, Arc::clone(&client_metrics))
                                 );
                                 Ok::<(), NetworkError>(())
                             });
@@ -160,16 +244,18 @@ pub async fn run_network_test(
                             let server_recv_config = Arc::clone(&config);
                             let server_metrics = Arc::clone(&metrics);
                             let server_handle = tokio::spawn(async move {
-                                let listener = tcp_listen(listen_addr).await?;
+                                let listener = tcp_listen(listen_addr, &server_recv_config)?;
                                 println!("TCP BiDi (Dual): Listening on {} for incoming connection.", listen_addr);
-                                let (stream, client_addr) = listener.accept().await?;
+                                let Some((stream, client_addr)) = tcp_accept(&listener, &server_metrics).await? else {
+                                    return Ok(());
+                                };
                                 println!("TCP BiDi (Dual): Accepted connection from {} for receiving.", client_addr);
-                                let (reader, writer) = tokio::io::split(stream);
+                                let (reader, connection) = TcpConnection::split(stream, &server_recv_config)?;
 
                                 let _ = tokio::try_join!(
-                                    tcp_receive_loop(Arc::clone(&server_recv_config), reader, Arc::clone(&server_metrics)),
-                                    // Secondary send loop on the server's incoming stream (e.g., for control/acks)
-                                    tcp_send_loop(Arc::clone(&server_recv_config), writer, Arc::clone(&server_metrics), false) // is_primary_sender = false
+                                    tcp_receive_loop(Arc::clone(&server_recv_config), reader, Arc::clone(&connection), Arc::clone(&server_metrics)),
+                                    // Secondary send loop on the server's incoming stream; echo replies share its writer
+                                    tcp_send_loop(Arc::clone(&server_recv_config), connection, Arc::clone(&server_metrics), false) // is_primary_sender = false
                                 );
                                 Ok::<(), NetworkError>(())
                             });
@@ -216,26 +302,28 @@ pub async fn run_network_test(
                             let stream: TcpStream; // Not Arc needed before split
                             if should_initiate_connection {
                                 println!("TCP BiDi (Single): Initiating connection to {}", remote_addr);
-                                stream = tcp_connect(remote_addr).await?;
+                                stream = tcp_connect(remote_addr, &metrics).await?;
                                 let peer_display = stream.peer_addr().map_or("unknown peer".to_string(), |a| a.to_string());
                                 println!("TCP BiDi (Single): Connected to {}", peer_display);
                             } else {
-                                let listener = tcp_listen(listen_addr).await?;
+                                let listener = tcp_listen(listen_addr, &config)?;
                                 println!("TCP BiDi (Single): Listening on {} for incoming connection.", listen_addr);
-                                let (accepted_stream, client_addr) = listener.accept().await?;
+                                let Some((accepted_stream, client_addr)) = tcp_accept(&listener, &metrics).await? else {
+                                    return Ok(());
+                                };
                                 stream = accepted_stream;
                                 println!("TCP BiDi (Single): Accepted connection from {}", client_addr);
                             }
 
-                            let (reader, writer) = tokio::io::split(stream);
+                            let (reader, connection) = TcpConnection::split(stream, &config)?;
+                            let recv_connection = Arc::clone(&connection);
 
                             let send_handle = tokio::spawn(async move {
-                                // One side needs to be primary sender, the other can be too, or just for ACKs.
-                                // The heuristic for `should_initiate_connection` can also decide primary sender role.
-                                tcp_send_loop(send_config, writer, metrics_send, should_initiate_connection).await
+                                // The initiator is the primary sender; the other side's send loop is secondary
+                                tcp_send_loop(send_config, connection, metrics_send, should_initiate_connection).await
                             });
                             let recv_handle = tokio::spawn(async move {
-                                tcp_receive_loop(recv_config, reader, metrics_recv).await
+                                tcp_receive_loop(recv_config, reader, recv_connection, metrics_recv).await
                             });
 
                             let (send_result, recv_result) = tokio::join!(send_handle, recv_handle);
@@ -251,6 +339,188 @@ pub async fn run_network_test(
 }
 
 
+/// `config.multicast_group`, checked to actually be a multicast address.
+fn multicast_group(config: &TestConfig) -> Result<Option<IpAddr>, NetworkError> {
+    match config.multicast_group {
+        Some(group) if !group.is_multicast() => {
+            Err(NetworkError::InvalidAddress(format!("{} is not a multicast group address", group)))
+        }
+        group => Ok(group),
+    }
+}
+
+/// `config.dest_port_range` as a range of ports (TestConfig::validate rejects empty ones).
+fn dest_port_range(config: &TestConfig) -> Option<std::ops::RangeInclusive<u16>> {
+    config.dest_port_range.map(|(first, last)| first..=last)
+}
+
+/// Address to send to for `target_ip`, an IPv4 or IPv6 address (optionally in brackets).
+/// IPv6 addresses may carry a scope, e.g. `fe80::1%eth0` or `fe80::1%2`, which link-local
+/// targets need to pick the interface; it becomes the scope id of the `SocketAddrV6`.
+pub fn target_socket_addr(target_ip: &str, port: u16) -> Result<SocketAddr, NetworkError> {
+    let invalid = |reason: &str| NetworkError::InvalidAddress(format!("Invalid target address: {} - {}", target_ip, reason));
+    let unbracketed = target_ip.strip_prefix('[').and_then(|ip| ip.strip_suffix(']')).unwrap_or(target_ip);
+    let (ip, scope) = match unbracketed.split_once('%') {
+        Some((ip, scope)) => (ip, Some(scope)),
+        None => (unbracketed, None),
+    };
+    match (ip.parse::<IpAddr>().map_err(|e| invalid(&e.to_string()))?, scope) {
+        (ip, None) => Ok(SocketAddr::new(ip, port)),
+        (IpAddr::V6(ip), Some(scope)) => {
+            let scope_id = scope
+                .parse::<u32>()
+                .ok()
+                .or_else(|| interface_index(scope))
+                .ok_or_else(|| invalid(&format!("unknown interface '{}'", scope)))?;
+            Ok(SocketAddr::V6(std::net::SocketAddrV6::new(ip, port, 0, scope_id)))
+        }
+        (IpAddr::V4(_), Some(_)) => Err(invalid("a scope (%) is only valid on IPv6 addresses")),
+    }
+}
+
+/// Addresses to test for `target_ip`: the address itself for an IP literal (see
+/// `target_socket_addr`), otherwise every address the hostname resolves to, in the
+/// resolver's order and without duplicates.
+pub async fn resolve_target(target_ip: &str, port: u16) -> Result<Vec<SocketAddr>, NetworkError> {
+    match target_socket_addr(target_ip, port) {
+        Ok(addr) => return Ok(vec![addr]),
+        Err(e) if target_ip.contains([':', '%', '[']) => return Err(e), // A malformed IP literal, not a hostname
+        Err(_) => {}
+    }
+    let resolved = tokio::net::lookup_host((target_ip, port))
+        .await
+        .map_err(|e| NetworkError::InvalidAddress(format!("Cannot resolve {}: {}", target_ip, e)))?;
+    let mut addresses: Vec<SocketAddr> = Vec::new();
+    for addr in resolved {
+        if !addresses.contains(&addr) {
+            addresses.push(addr);
+        }
+    }
+    if addresses.is_empty() {
+        return Err(NetworkError::InvalidAddress(format!("{} resolves to no addresses", target_ip)));
+    }
+    Ok(addresses)
+}
+
+/// Index of the named network interface, for IPv6 scope ids.
+#[cfg(target_os = "linux")]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: name is a valid NUL-terminated string that outlives the call.
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    (index != 0).then_some(index)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None // Numeric scope ids only
+}
+
+/// Wildcard address to listen on: `[::]` when `target_ip` is an IPv6 address, so IPv6-only
+/// hosts can be servers, and `0.0.0.0` otherwise (also when it's empty, as servers may leave it).
+fn listen_socket_addr(target_ip: &str, port: u16) -> SocketAddr {
+    let unspecified: IpAddr = match target_socket_addr(target_ip, port) {
+        Ok(SocketAddr::V6(_)) => Ipv6Addr::UNSPECIFIED.into(),
+        _ => Ipv4Addr::UNSPECIFIED.into(),
+    };
+    SocketAddr::new(unspecified, port)
+}
+
+// An extra receive loop of a server's dest_port_range, and its stop signal
+type PortRangeReceiver = (tokio::task::JoinHandle<Result<(), NetworkError>>, Arc<Notify>);
+
+/// Receive loops on the ports of `config.dest_port_range` other than `listen_addr`'s, so
+/// the server catches a client spreading its packets over the range. They share `metrics`
+/// with the main loop on `listen_addr`, which does the bandwidth sampling.
+async fn spawn_port_range_receivers(
+    config: &Arc<TestConfig>,
+    listen_addr: SocketAddr,
+    metrics: &Arc<Mutex<TestMetrics>>,
+) -> Result<Vec<PortRangeReceiver>, NetworkError> {
+    let mut receivers = Vec::new();
+    for port in dest_port_range(config).into_iter().flatten().filter(|&port| port != listen_addr.port()) {
+        let socket = Arc::new(bind_udp_listener(config, SocketAddr::new(listen_addr.ip(), port)).await?);
+        let stop = Arc::new(Notify::new());
+        let task = tokio::spawn(udp_receive_loop(Arc::clone(config), socket, Arc::clone(metrics), Some(Arc::clone(&stop)), false));
+        receivers.push((task, stop));
+    }
+    Ok(receivers)
+}
+
+/// Stops the loops from `spawn_port_range_receivers` (after the usual end-of-test grace)
+/// once the main receive loop is done, returning the first error any of them hit.
+async fn stop_port_range_receivers(receivers: Vec<PortRangeReceiver>) -> Result<(), NetworkError> {
+    for (_, stop) in &receivers {
+        stop.notify_one(); // Stored if the loop isn't waiting right now
+    }
+    let mut result = Ok(());
+    for (task, _) in receivers {
+        let task_result = task.await.unwrap_or_else(|e| Err(NetworkError::Other(format!("Port range receiver failed: {}", e))));
+        if result.is_ok() {
+            result = task_result;
+        }
+    }
+    result
+}
+
+/// Binds the UDP receive socket and, if configured, joins the multicast group on it.
+/// IPv6 groups need an IPv6 socket, so the wildcard address follows the group's family.
+async fn bind_udp_listener(config: &TestConfig, listen_addr: SocketAddr) -> Result<UdpSocket, NetworkError> {
+    match multicast_group(config)? {
+        Some(IpAddr::V4(group)) => {
+            let socket = bind_udp(listen_addr, config.reuse_addr)?;
+            socket.join_multicast_v4(group, Ipv4Addr::UNSPECIFIED)?;
+            println!("UDP: Joined multicast group {} on {}", group, socket.local_addr()?);
+            Ok(socket)
+        }
+        Some(IpAddr::V6(group)) => {
+            let socket = bind_udp(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), listen_addr.port()), config.reuse_addr)?;
+            socket.join_multicast_v6(&group, 0)?; // Interface 0: let the OS choose
+            println!("UDP: Joined multicast group {} on {}", group, socket.local_addr()?);
+            Ok(socket)
+        }
+        None => bind_udp(listen_addr, config.reuse_addr),
+    }
+}
+
+/// Creates a socket of `socket_type` bound to `addr`, with SO_REUSEADDR if `reuse_addr`. A
+/// port held by another socket is `NetworkError::AddressInUse` rather than a bare IoError.
+fn bind_socket(addr: SocketAddr, socket_type: socket2::Type, reuse_addr: bool) -> Result<socket2::Socket, NetworkError> {
+    let socket = socket2::Socket::new(socket2::Domain::for_address(addr), socket_type, None)?;
+    if reuse_addr {
+        socket.set_reuse_address(true)?;
+    }
+    socket.set_nonblocking(true)?; // For tokio
+    socket.bind(&addr.into()).map_err(|e| bind_error(addr, e))?;
+    Ok(socket)
+}
+
+fn bind_error(addr: SocketAddr, e: io::Error) -> NetworkError {
+    match e.kind() {
+        io::ErrorKind::AddrInUse => NetworkError::AddressInUse(addr),
+        _ => NetworkError::IoError(e),
+    }
+}
+
+/// A UDP receive socket bound to `addr`, see `bind_socket`.
+fn bind_udp(addr: SocketAddr, reuse_addr: bool) -> Result<UdpSocket, NetworkError> {
+    let socket = bind_socket(addr, socket2::Type::DGRAM, reuse_addr)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// A UDP socket on `port` of every local address, IPv4 and IPv6: `[::]` with IPV6_V6ONLY off,
+/// or `0.0.0.0` on a host without IPv6.
+pub fn bind_udp_dual_stack(port: u16) -> Result<UdpSocket, NetworkError> {
+    let addr = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port);
+    let Ok(socket) = socket2::Socket::new(socket2::Domain::IPV6, socket2::Type::DGRAM, None) else {
+        return bind_udp(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port), false);
+    };
+    socket.set_only_v6(false)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into()).map_err(|e| bind_error(addr, e))?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
 // --- UDP Loops ---
 async fn udp_send_loop(
     config: Arc<TestConfig>,
@@ -262,33 +532,124 @@ async fn udp_send_loop(
     // For BiDi, the socket might be shared if we want to receive ACKs on the same one.
     // Or, it could be a dedicated sending socket.
     // For simplicity, let's use a new socket for sending. The receive_loop will use the listening one.
-    let socket = UdpSocket::bind("0.0.0.0:0").await?;
-    socket.connect(remote_addr).await?; // Connects the UDP socket to a default remote address
+    let config = with_direction_tick_rate(config, is_primary_sender);
+    let local_addr: SocketAddr = if remote_addr.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" }.parse().unwrap();
+    let socket = Arc::new(UdpSocket::bind(local_addr).await?);
+    // Multicast replies come from the members' unicast addresses, which a socket connected
+    // to the group would filter out, so multicast sockets stay unconnected and use send_to.
+    // Likewise for a port range: replies come from whichever port the packet went to.
+    let is_multicast = remote_addr.ip().is_multicast();
+    let port_range = dest_port_range(&config);
+    let is_connected = !is_multicast && port_range.is_none();
+    if is_multicast && remote_addr.is_ipv4() {
+        socket.set_multicast_ttl_v4(config.multicast_ttl)?;
+    }
+    if is_connected {
+        socket.connect(remote_addr).await?; // Connects the UDP socket to a default remote address
+    }
+    if let Some(df) = config.set_df {
+        set_dont_fragment(&socket, remote_addr.is_ipv6(), df)?;
+    }
+    if let Some(dscp) = config.dscp {
+        set_dscp(&socket, remote_addr.is_ipv6(), dscp)?;
+    }
     println!("UDP SendLoop: Sending to {} from local addr {}", remote_addr, socket.local_addr()?);
+    let config = resolve_auto_packet_size(config, path_mtu(&socket, remote_addr.is_ipv6()), remote_addr.is_ipv6());
 
-    let test_start_time = metrics.lock().unwrap().test_start_time.unwrap_or_else(Instant::now);
+    let test_start_time = lock_metrics(&metrics).test_start_time.unwrap_or_else(Instant::now);
+    let packet_tape = lock_metrics(&metrics).packet_tape.clone();
+    let control = Arc::clone(&lock_metrics(&metrics).control);
+    let session_id = lock_metrics(&metrics).session_id;
     let test_duration = config.total_duration();
     let tick_interval = config.tick_interval();
 
-    use rand::rngs::StdRng;
-    use rand::SeedableRng;
-    let mut rng = if config.packet_size_range.is_some() { Some(StdRng::from_entropy()) } else { None };
+    let mut rng = send_rng(&config);
     let mut sequence_number: u32 = 0;
 
-    let mut ticker = if config.tick_rate_hz > 0 { // Normal tick-based sending
-        Some(tokio::time::interval_at(tokio::time::Instant::now() + tick_interval, tick_interval))
+    // EchoRequests still awaiting a reply: sequence number -> send time. Replies are read by
+    // their own task (only for the primary sender), so waiting for them never holds up sending.
+    let rtt_timeout = config.rtt_timeout();
+    let outstanding_echoes: Arc<Mutex<HashMap<u32, Instant>>> = Arc::new(Mutex::new(HashMap::new()));
+    let all_replied = Arc::new(tokio::sync::Notify::new());
+    let reply_task = is_primary_sender.then(|| {
+        tokio::spawn(udp_reply_loop(
+            Arc::clone(&socket),
+            Arc::clone(&outstanding_echoes),
+            Arc::clone(&all_replied),
+            Arc::clone(&config),
+            Arc::clone(&metrics),
+            packet_tape.clone(),
+        ))
+    });
+
+    // Tells the peer which port we send from, so it can tell if a NAT remaps it. Best effort:
+    // the peer may not be listening yet (a refused send is the ICMP error of an earlier copy),
+    // so it goes out again every HELLO_INTERVAL; without one the peer just can't check.
+    let hello = if is_primary_sender && config.test_mode == TestMode::Bidirectional {
+        Some(CustomPacket::new_hello(socket.local_addr()?.port()).in_session(session_id).to_bytes_with(config.bincode_fixint)?)
+    } else {
+        None
+    };
+    let mut last_hello = Instant::now();
+    if let Some(hello) = &hello {
+        for _ in 0..HELLO_COPIES {
+            send_hello(&socket, hello, is_connected, remote_addr).await;
+        }
+    }
+
+    if is_primary_sender && config.prewarm_packets > 0 {
+        // Not counted anywhere: receivers drop Prewarm packets and the first tick comes after
+        let prewarm = CustomPacket::new_prewarm(config.packet_size_bytes).in_session(session_id).to_bytes_with(config.bincode_fixint)?;
+        for _ in 0..config.prewarm_packets {
+            if is_connected { socket.send(&prewarm).await?; } else { socket.send_to(&prewarm, remote_addr).await?; }
+        }
+    }
+
+    let mut pacer = config.target_bitrate_bps.filter(|_| is_primary_sender).map(BitratePacer::new);
+    let mut ticker = if pacer.is_some() {
+        None // Paced by bitrate instead, once each packet's size is known
+    } else if config.tick_rate_hz > 0 { // Normal tick-based sending
+        Some(tokio::time::interval_at(tokio::time::Instant::now() + first_tick_delay(&config, tick_interval), tick_interval))
     } else { // Tick rate of 0 means "as fast as possible" (AFAP) for benchmark
         println!("UDP SendLoop: AFAP mode enabled (tick_rate_hz == 0)");
         None
     };
 
     // Only the primary sender respects the full test duration for sending.
-    let loop_duration = if is_primary_sender { test_duration } else { Duration::MAX };
-
-    while Instant::now().duration_since(test_start_time) < loop_duration {
+    let loop_duration = if is_primary_sender { primary_send_duration(&config) } else { Duration::MAX };
+    let mut burst_remaining: u32 = 0; // Packets still to send for the current tick
+    let mut packets_sent: u64 = 0; // By this loop, for stop_after_packets
+
+    while !control.is_cancelled() && control.active_elapsed(test_start_time) < loop_duration {
+        if control.is_paused() {
+            control.wait_until_resumed().await;
+            if let Some(ref mut t) = ticker {
+                t.reset_after(first_tick_delay(&config, tick_interval)); // Don't burst out the ticks missed while paused
+            }
+            if let Some(ref mut p) = pacer {
+                p.reset();
+            }
+            continue; // Re-check the (active) duration
+        }
+        if is_primary_sender && reached_packet_count(&config, packets_sent) {
+            break;
+        }
         if is_primary_sender {
             if let Some(ref mut t) = ticker { // Normal tick-based
-                t.tick().await;
+                if burst_remaining == 0 {
+                    tokio::select! {
+                        _ = t.tick() => {}
+                        _ = control.cancelled() => break,
+                    }
+                    if let Some(jitter_ms) = config.send_jitter_ms {
+                        tokio::select! {
+                            _ = tokio::time::sleep(send_jitter_delay(jitter_ms, &mut rng)) => {}
+                            _ = control.cancelled() => break,
+                        }
+                    }
+                    burst_remaining = config.tick_burst();
+                }
+                burst_remaining -= 1;
             } else { // AFAP mode for primary sender
                 tokio::task::yield_now().await; // Yield to allow other tasks (like receiver) to run
             }
@@ -297,7 +658,10 @@ async fn udp_send_loop(
             // If it were, it would need its own rate control or be event-driven.
             // For now, assume non-primary senders are not in AFAP mode or this loop isn't hit in that benchmark.
             if config.tick_rate_hz > 0 { // Ensure tick_interval is valid
-                 tokio::time::sleep(tick_interval).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(tick_interval) => {}
+                    _ = control.cancelled() => break,
+                }
             } else {
                 // If non-primary and main config is AFAP, this is undefined; yield to be safe.
                 tokio::task::yield_now().await;
@@ -305,10 +669,7 @@ async fn udp_send_loop(
         }
 
         let current_packet_size = match config.packet_size_range {
-            Some((min_size, max_size)) => {
-                if let Some(ref mut r) = rng { use rand::Rng; r.gen_range(min_size..=max_size) }
-                else { config.packet_size_bytes }
-            }
+            Some((min_size, max_size)) => { use rand::Rng; rng.gen_range(min_size..=max_size) }
             None => config.packet_size_bytes,
         };
 
@@ -323,137 +684,578 @@ async fn udp_send_loop(
         // It should probably send DataPacket, not EchoRequest, unless we want bidi RTT from both sides.
         // For now, both primary and secondary UDP senders in bidi mode will send EchoRequest
         // to simplify and allow RTT measurement from both perspectives if desired (though only primary currently processes replies).
-        let packet = CustomPacket::new_echo_request(sequence_number, current_packet_size);
-
-        let sent_payload = packet.to_bytes()?;
-        let send_time = Instant::now();
-        socket.send(&sent_payload).await?;
+        let mut packet = CustomPacket::new_echo_request_with(sequence_number, current_packet_size, config.payload_pattern, &mut rng).in_session(session_id);
+        if config.verify_echo_payload {
+            packet.set_payload(CustomPacket::pattern_payload(sequence_number, current_packet_size));
+        }
 
-        metrics.lock().unwrap().record_packet_sent(sent_payload.len());
+        let mut destination = remote_addr; // Keeps an IPv6 scope id
+        if let Some(ports) = &port_range {
+            use rand::Rng;
+            destination.set_port(rng.gen_range(ports.clone()));
+        }
 
-        // Try to receive EchoReply for RTT - only if this loop is primary sender
+        let sent_payload = hot_span!("serialize", packet.to_bytes_with(config.bincode_fixint))?;
+        if let Some(ref mut p) = pacer {
+            p.wait_to_send(sent_payload.len()).await;
+        }
         if is_primary_sender {
-            let mut recv_buf = vec![0u8; 2048]; // Buffer for the reply
-            // Set a timeout for receiving the reply, e.g., 500ms or related to tick_interval
-            // A simple way is to use tokio::time::timeout.
-            // If the main loop is driven by `ticker.tick().await`, waiting here can mess with timing.
-            // This receive should be non-blocking or very short timeout.
-            // For a proper RTT test, the send loop might be simpler: send, try recv with timeout, repeat.
-            // Or, have a separate task for receiving replies.
-
-            // Simplified non-blocking attempt for this pass:
-            // This is not ideal as try_recv is not async.
-            // A better approach: use socket.recv() in a tokio::select! with a timeout.
-            match tokio::time::timeout(Duration::from_millis(200), socket.recv(&mut recv_buf)).await {
-                Ok(Ok(len)) => { // Received something within timeout
-                    let rtt = send_time.elapsed().as_micros();
-                    match CustomPacket::from_bytes(&recv_buf[..len]) {
-                        Ok(reply_packet) => {
-                            if reply_packet.header.packet_type == crate::packet::PacketType::EchoReply &&
-                               reply_packet.header.sequence_number == sequence_number {
-                                metrics.lock().unwrap().record_packet_received(len, rtt);
-                            } else {
-                                // Received unexpected packet or old reply
-                                println!("UDP SendLoop: Received unexpected packet type {:?} or seq {} (expected EchoReply for seq {})",
-                                         reply_packet.header.packet_type, reply_packet.header.sequence_number, sequence_number);
-                            }
-                        }
-                        Err(_e) => { /* Malformed reply */ }
-                    }
-                }
-                Ok(Err(_e)) => { /* Socket error on recv */ }
-                Err(_elapsed) => { /* Timeout waiting for EchoReply */ }
+            // Tracked before sending so a fast reply can't beat its own entry to the map
+            lock_outstanding(&outstanding_echoes).insert(sequence_number, Instant::now());
+        }
+        let retries = hot_span!(future "send", send_with_retry(
+            || async {
+                if is_connected { socket.send(&sent_payload).await } else { socket.send_to(&sent_payload, destination).await }
+            },
+            config.send_max_retries,
+            Duration::from_millis(config.send_retry_backoff_ms),
+        )).await.map_err(|e| {
+            if is_message_too_large(&e) {
+                NetworkError::PacketTooLarge { size: sent_payload.len(), mtu: path_mtu(&socket, remote_addr.is_ipv6()) }
+            } else {
+                NetworkError::IoError(e)
             }
+        })?;
+
+        hot_span!("metrics_update", {
+            let mut metrics_guard = lock_metrics(&metrics);
+            metrics_guard.record_packet_sent(sent_payload.len());
+            metrics_guard.send_retries += retries as u64;
+        });
+        packets_sent += 1;
+        if let Some(hello) = hello.as_ref().filter(|_| last_hello.elapsed() >= HELLO_INTERVAL) {
+            send_hello(&socket, hello, is_connected, remote_addr).await;
+            last_hello = Instant::now();
         }
 
+
         sequence_number = sequence_number.wrapping_add(1);
 
-        if !is_primary_sender && Instant::now().duration_since(test_start_time) >= test_duration {
+        if !is_primary_sender && control.active_elapsed(test_start_time) >= test_duration {
             // If this is the secondary sender in a bidi test, stop after main duration.
             break;
         }
     }
+
+    // Drain: the last packets are still in flight when sending stops. Wait up to one
+    // rtt_timeout for their replies so a clean link doesn't report spurious loss.
+    if let Some(reply_task) = reply_task {
+        let _ = tokio::time::timeout(rtt_timeout, async {
+            while !lock_outstanding(&outstanding_echoes).is_empty() {
+                all_replied.notified().await;
+            }
+        })
+        .await;
+        reply_task.abort();
+        let not_received = lock_outstanding(&outstanding_echoes).len();
+        if not_received > 0 {
+            println!("UDP SendLoop to {}: {} echo replies not received before drain timeout.", remote_addr, not_received);
+        }
+    }
     println!("UDP SendLoop to {}: Finished.", remote_addr);
     Ok(())
 }
 
+// Receive loops run this long past the test duration to catch trailing packets
+const RECEIVE_GRACE: Duration = Duration::from_secs(5);
+
+// A Hello goes out a few times at the start of a UDP bidirectional test in case one is lost,
+// then once every HELLO_INTERVAL for a peer that started listening late
+const HELLO_COPIES: u32 = 3;
+const HELLO_INTERVAL: Duration = Duration::from_secs(1);
+
+async fn send_hello(socket: &UdpSocket, hello: &[u8], is_connected: bool, remote_addr: SocketAddr) {
+    let sent = if is_connected { socket.send(hello).await } else { socket.send_to(hello, remote_addr).await };
+    if let Err(e) = sent {
+        eprintln!("UDP SendLoop: Hello to {} not sent: {}", remote_addr, e);
+    }
+}
+
+/// How long a primary send loop runs: the test duration, or until `stop_after_packets` ends
+/// it in a count-limited test.
+fn primary_send_duration(config: &TestConfig) -> Duration {
+    if config.is_count_limited() { Duration::MAX } else { config.total_duration() }
+}
+
+fn reached_packet_count(config: &TestConfig, packets_sent: u64) -> bool {
+    config.stop_after_packets.is_some_and(|count| packets_sent >= count)
+}
+
+/// The send loops' RNG, from `send_rng_seed` when set so that a run can be replayed.
+fn send_rng(config: &TestConfig) -> rand::rngs::StdRng {
+    use rand::SeedableRng;
+    config.send_rng_seed.map_or_else(rand::rngs::StdRng::from_entropy, rand::rngs::StdRng::seed_from_u64)
+}
+
+/// When the send ticker first fires: one tick interval from now, less `send_jitter_ms` (as far
+/// as the interval allows), so that `send_jitter_delay` centres each send on its nominal tick.
+fn first_tick_delay(config: &TestConfig, tick_interval: Duration) -> Duration {
+    tick_interval.saturating_sub(Duration::from_millis(config.send_jitter_ms.unwrap_or(0)))
+}
+
+/// Delay after a tick for `send_jitter_ms`: uniform in [0, 2 * jitter]. The ticker runs
+/// `jitter` early (see `first_tick_delay`), so sends land within ±jitter of their nominal tick.
+/// The ticker itself stays fixed-rate (missed ticks are caught up), so the average send rate
+/// is unchanged.
+fn send_jitter_delay(jitter_ms: u64, rng: &mut impl rand::Rng) -> Duration {
+    Duration::from_micros(rng.gen_range(0..=jitter_ms.saturating_mul(2000)))
+}
+
+// Upper bound for a single backoff sleep, however many retries are configured.
+const MAX_SEND_BACKOFF: Duration = Duration::from_millis(100);
+
+// OS error codes of the send failures handled below. libc doesn't cover Windows, whose
+// WinSock codes are spelled out.
+#[cfg(unix)]
+use libc::{EMSGSIZE, ENOBUFS};
+#[cfg(windows)]
+const ENOBUFS: i32 = 10055; // WSAENOBUFS
+#[cfg(windows)]
+const EMSGSIZE: i32 = 10040; // WSAEMSGSIZE
+#[cfg(not(any(unix, windows)))]
+const ENOBUFS: i32 = -1;
+#[cfg(not(any(unix, windows)))]
+const EMSGSIZE: i32 = -1;
+
+/// True for send errors that mean "the socket/OS buffers are momentarily full" rather than
+/// a real failure, so the send is worth retrying.
+fn is_transient_send_error(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted)
+        || e.raw_os_error() == Some(ENOBUFS)
+}
+
+/// True for EMSGSIZE: the datagram is larger than the socket can send, e.g. over the path MTU
+/// with the Don't Fragment bit set.
+fn is_message_too_large(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(EMSGSIZE)
+}
+
+/// Runs `send` until it succeeds, retrying transient errors up to `max_retries` times with
+/// exponential backoff (capped at MAX_SEND_BACKOFF). Returns the number of retries needed.
+async fn send_with_retry<F, Fut>(mut send: F, max_retries: u32, initial_backoff: Duration) -> io::Result<u32>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = io::Result<usize>>,
+{
+    let mut retries = 0;
+    let mut backoff = initial_backoff;
+    loop {
+        match send().await {
+            Ok(_) => return Ok(retries),
+            Err(e) if is_transient_send_error(&e) && retries < max_retries => {
+                retries += 1;
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_SEND_BACKOFF);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Receive buffer size that fits any UDP datagram
+const MAX_UDP_DATAGRAM: usize = 65_536;
+// Largest UDP payload over IPv4: 65535 - 20 byte IP header - 8 byte UDP header
+pub const MAX_UDP_PAYLOAD: usize = 65_507;
+// Used for auto packet sizing when the OS can't report the path MTU
+pub const FALLBACK_PATH_MTU: usize = 1500;
+
+/// Path MTU the kernel currently has for a connected socket (IP_MTU / IPV6_MTU).
+#[cfg(target_os = "linux")]
+fn path_mtu<S: std::os::fd::AsFd>(socket: &S, is_ipv6: bool) -> Option<usize> {
+    let (level, option) = if is_ipv6 {
+        (libc::IPPROTO_IPV6, libc::IPV6_MTU)
+    } else {
+        (libc::IPPROTO_IP, libc::IP_MTU)
+    };
+    let mtu = int_socket_option(socket, level, option, None).ok()?;
+    (mtu > 0).then_some(mtu as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn path_mtu<S>(_socket: &S, _is_ipv6: bool) -> Option<usize> {
+    None
+}
+
+/// Forces the Don't Fragment bit on (`df`) or off for datagrams sent on `socket`, via
+/// IP_MTU_DISCOVER / IPV6_MTU_DISCOVER (PMTUDISC_DO or PMTUDISC_DONT).
+#[cfg(target_os = "linux")]
+fn set_dont_fragment<S: std::os::fd::AsFd>(socket: &S, is_ipv6: bool, df: bool) -> Result<(), NetworkError> {
+    let (level, option, value) = if is_ipv6 {
+        let mode = if df { libc::IPV6_PMTUDISC_DO } else { libc::IPV6_PMTUDISC_DONT };
+        (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, mode)
+    } else {
+        let mode = if df { libc::IP_PMTUDISC_DO } else { libc::IP_PMTUDISC_DONT };
+        (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, mode)
+    };
+    int_socket_option(socket, level, option, Some(value))?;
+    Ok(())
+}
+
+/// Sets the int socket option `level`/`option` to `value`, or reads it with `None`: the options
+/// above that socket2 has no setter or getter for. Returns the option's value.
+#[cfg(target_os = "linux")]
+fn int_socket_option<S: std::os::fd::AsFd>(socket: &S, level: libc::c_int, option: libc::c_int, value: Option<libc::c_int>) -> io::Result<libc::c_int> {
+    use std::os::fd::AsRawFd;
+    let fd = socket.as_fd().as_raw_fd();
+    let mut current = value.unwrap_or(0);
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ptr = &mut current as *mut libc::c_int as *mut libc::c_void;
+    // SAFETY: fd is borrowed from an open socket for the duration of the call, and ptr/len
+    // describe the c_int local above.
+    let ret = unsafe {
+        match value {
+            Some(_) => libc::setsockopt(fd, level, option, ptr, len),
+            None => libc::getsockopt(fd, level, option, ptr, &mut len),
+        }
+    };
+    if ret == 0 { Ok(current) } else { Err(io::Error::last_os_error()) }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_dont_fragment<S>(_socket: &S, _is_ipv6: bool, _df: bool) -> Result<(), NetworkError> {
+    Err(NetworkError::UnsupportedMode("set_df is only supported on Linux".to_string()))
+}
+
+/// Marks packets sent on `socket` with the DiffServ codepoint `dscp` (0-63): the upper six
+/// bits of the IPv4 ToS byte or IPv6 traffic class. The two ECN bits are left at zero.
+#[cfg(any(
+    target_os = "linux", target_os = "android", target_os = "macos", target_os = "freebsd",
+    target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"
+))]
+fn set_dscp<S: std::os::fd::AsFd>(socket: &S, is_ipv6: bool, dscp: u8) -> Result<(), NetworkError> {
+    debug_assert!(dscp <= 63, "TestConfig::validate rejects dscp above 63");
+    let socket = socket2::SockRef::from(socket);
+    let tos = u32::from(dscp) << 2;
+    if is_ipv6 {
+        socket.set_tclass_v6(tos)?;
+    } else {
+        socket.set_tos(tos)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(any(
+    target_os = "linux", target_os = "android", target_os = "macos", target_os = "freebsd",
+    target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"
+)))]
+fn set_dscp<S>(_socket: &S, _is_ipv6: bool, _dscp: u8) -> Result<(), NetworkError> {
+    Err(NetworkError::Other("dscp is not supported on this platform".to_string()))
+}
+
+/// Asks for a receive buffer (SO_RCVBUF) of `bytes` on `socket` and returns the size the OS
+/// granted for data, which may be less: Linux caps the request at net.core.rmem_max, then
+/// doubles it for its own bookkeeping and reports the doubled size, halved again here.
+fn set_recv_buffer_size(socket: &UdpSocket, bytes: usize) -> Result<usize, NetworkError> {
+    let socket = socket2::SockRef::from(socket);
+    socket.set_recv_buffer_size(bytes)?;
+    let reported = socket.recv_buffer_size()?;
+    Ok(if cfg!(target_os = "linux") { reported / 2 } else { reported })
+}
+
+/// Payload size for auto packet sizing: the largest UDP payload whose datagram fits in
+/// `mtu` without IP fragmentation, after our own packet header.
+pub fn auto_udp_payload_size(mtu: usize, is_ipv6: bool, fixint: bool) -> usize {
+    let ip_udp_headers = if is_ipv6 { 40 + 8 } else { 20 + 8 };
+    let max_datagram_payload = mtu.saturating_sub(ip_udp_headers).min(MAX_UDP_PAYLOAD);
+    CustomPacket::max_payload_len(max_datagram_payload, fixint)
+}
+
+/// Spaces a send loop's packets to average `target_bitrate_bps`: each packet pushes the next
+/// send back by its own transmission time at that rate, so mixed packet sizes still add up.
+struct BitratePacer {
+    bits_per_sec: f64,
+    next_send: tokio::time::Instant,
+}
+
+impl BitratePacer {
+    fn new(bits_per_sec: u64) -> Self {
+        println!("SendLoop: Pacing to {} bits/s", bits_per_sec);
+        BitratePacer { bits_per_sec: bits_per_sec.max(1) as f64, next_send: tokio::time::Instant::now() }
+    }
+
+    /// Waits for the send slot of a `bytes`-long packet and books its transmission time. Slots
+    /// left behind by a stall (TCP backpressure, a slow send, the scheduler) aren't made up:
+    /// at most one packet's worth of them, so the backlog isn't sent as a burst.
+    async fn wait_to_send(&mut self, bytes: usize) {
+        let packet_time = Duration::from_secs_f64(bytes as f64 * 8.0 / self.bits_per_sec);
+        if let Some(earliest) = tokio::time::Instant::now().checked_sub(packet_time) {
+            self.next_send = self.next_send.max(earliest);
+        }
+        tokio::time::sleep_until(self.next_send).await;
+        self.next_send += packet_time;
+    }
+
+    /// Starts over from now, e.g. after a pause, so the missed time isn't made up in a burst.
+    fn reset(&mut self) {
+        self.next_send = tokio::time::Instant::now();
+    }
+}
+
+/// `config` with `tick_rate_hz` set for a send loop's direction, see `TestConfig::send_tick_rate_hz`.
+fn with_direction_tick_rate(config: Arc<TestConfig>, is_primary_sender: bool) -> Arc<TestConfig> {
+    let tick_rate_hz = config.send_tick_rate_hz(is_primary_sender);
+    if tick_rate_hz == config.tick_rate_hz {
+        return config;
+    }
+    println!("SendLoop: {} tick rate {} Hz", if is_primary_sender { "tx" } else { "rx" }, tick_rate_hz);
+    Arc::new(TestConfig { tick_rate_hz, ..(*config).clone() })
+}
+
+/// Fills in `packet_size_bytes` when it is 0 (auto), from the discovered path MTU.
+fn resolve_auto_packet_size(config: Arc<TestConfig>, mtu: Option<usize>, is_ipv6: bool) -> Arc<TestConfig> {
+    if config.packet_size_bytes != 0 || config.packet_size_range.is_some() {
+        return config;
+    }
+    let mtu_used = mtu.unwrap_or(FALLBACK_PATH_MTU);
+    let packet_size_bytes = auto_udp_payload_size(mtu_used, is_ipv6, config.bincode_fixint);
+    println!(
+        "Auto packet size: path MTU {}{} -> {} byte payload",
+        mtu_used,
+        if mtu.is_none() { " (fallback)" } else { "" },
+        packet_size_bytes
+    );
+    Arc::new(TestConfig { packet_size_bytes, ..(*config).clone() })
+}
+
+/// Reads EchoReplies off a UDP send loop's socket until aborted, matching them by sequence
+/// number so a late reply for an earlier request is still credited. Requests older than
+/// `rtt_timeout` are dropped from the map as they can no longer count, and `all_replied`
+/// is notified whenever the map empties (the send loop waits on it while draining).
+async fn udp_reply_loop(
+    socket: Arc<UdpSocket>,
+    outstanding_echoes: Arc<Mutex<HashMap<u32, Instant>>>,
+    all_replied: Arc<tokio::sync::Notify>,
+    config: Arc<TestConfig>,
+    metrics: Arc<Mutex<TestMetrics>>,
+    packet_tape: Option<Arc<PacketTape>>,
+) {
+    let rtt_timeout = config.rtt_timeout();
+    let mut recv_buf = vec![0u8; MAX_UDP_DATAGRAM];
+    let mut prune = tokio::time::interval(rtt_timeout.max(Duration::from_millis(1)));
+    prune.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            received = socket.recv(&mut recv_buf) => {
+                // Errors here are ICMP unreachables on a connected socket; the request just goes unanswered
+                if let Ok(len) = received {
+                    let mut outstanding = lock_outstanding(&outstanding_echoes);
+                    record_echo_reply(&recv_buf[..len], &mut outstanding, &config, &metrics, packet_tape.as_deref());
+                    if outstanding.is_empty() {
+                        all_replied.notify_one();
+                    }
+                }
+            }
+            _ = prune.tick() => {
+                lock_outstanding(&outstanding_echoes).retain(|_, send_time| send_time.elapsed() <= rtt_timeout);
+            }
+        }
+    }
+}
+
+fn lock_outstanding(outstanding_echoes: &Mutex<HashMap<u32, Instant>>) -> std::sync::MutexGuard<'_, HashMap<u32, Instant>> {
+    outstanding_echoes.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Parses a datagram from the UDP peer and credits it if it is an EchoReply.
+fn record_echo_reply(
+    data: &[u8],
+    outstanding_echoes: &mut HashMap<u32, Instant>,
+    config: &TestConfig,
+    metrics: &Mutex<TestMetrics>,
+    packet_tape: Option<&PacketTape>,
+) {
+    match CustomPacket::from_bytes_with(data, config.bincode_fixint) {
+        Ok(reply_packet) if reply_packet.header.packet_type == crate::packet::PacketType::EchoReply => {
+            credit_echo_reply(&reply_packet, data.len(), outstanding_echoes, config, metrics, packet_tape);
+        }
+        Ok(other_packet) => {
+            println!("UDP SendLoop: Received unexpected packet type {:?} (seq {})",
+                     other_packet.header.packet_type, other_packet.header.sequence_number);
+        }
+        Err(_e) => { /* Malformed reply */ }
+    }
+}
+
+/// Matches an EchoReply of `size_bytes` on the wire against the outstanding requests and
+/// records its RTT. Replies that arrive later than `rtt_timeout` after their request are
+/// treated as lost. With `verify_echo_payload`, a reply whose payload isn't the request's
+/// pattern is recorded as a Corrupted anomaly (it still counts as received).
+fn credit_echo_reply(
+    reply_packet: &CustomPacket,
+    size_bytes: usize,
+    outstanding_echoes: &mut HashMap<u32, Instant>,
+    config: &TestConfig,
+    metrics: &Mutex<TestMetrics>,
+    packet_tape: Option<&PacketTape>,
+) {
+    if !lock_metrics(metrics).accept_reply_session(reply_packet.header.session_id) {
+        // A late reply to an earlier test's request; its sequence number may be one of ours
+        return;
+    }
+    let Some(send_time) = outstanding_echoes.remove(&reply_packet.header.sequence_number) else {
+        return; // Duplicate or stale reply for a request we no longer track
+    };
+    let rtt = send_time.elapsed();
+    if rtt <= config.rtt_timeout() {
+        let mut metrics_guard = lock_metrics(metrics);
+        metrics_guard.record_packet_received(size_bytes, rtt.as_micros());
+        metrics_guard.record_payload_received(reply_packet.payload.len());
+        if config.verify_echo_payload
+            && reply_packet.payload != CustomPacket::pattern_payload(reply_packet.header.sequence_number, reply_packet.payload.len())
+        {
+            let timestamp_ms = metrics_guard.test_start_time.map_or(0, |st| st.elapsed().as_millis());
+            metrics_guard.record_anomaly(crate::anomalies::AnomalyEvent {
+                timestamp_ms,
+                anomaly_type: crate::anomalies::AnomalyType::Corrupted,
+                description: format!(
+                    "Echo reply payload for seq {} differs from the request (return direction)",
+                    reply_packet.header.sequence_number
+                ),
+                threshold: None,
+            });
+        }
+        if reply_packet.header.echo_receive_timestamp_micros != 0 {
+            let (forward_micros, return_micros) = crate::metrics::split_one_way_delays(
+                rtt.as_micros(),
+                reply_packet.header.echo_receive_timestamp_micros,
+                crate::packet::unix_time_micros(),
+            );
+            metrics_guard.record_one_way_delays(forward_micros, return_micros);
+        }
+        if let Some(tape) = packet_tape {
+            tape.push(PacketEvent {
+                timestamp_ms: metrics_guard.test_start_time.map_or(0, |st| st.elapsed().as_millis()),
+                sequence_number: reply_packet.header.sequence_number,
+                size_bytes,
+                rtt_micros: Some(rtt.as_micros()),
+                packet_type: reply_packet.header.packet_type,
+            });
+        }
+    }
+}
+
+// Trailing data packets may still be in flight when the client's End message arrives
+const CONTROL_END_GRACE: Duration = Duration::from_millis(500);
+// End is the last thing a client sends, so it goes out a few times in case one is lost
+const CONTROL_END_COPIES: u32 = 3;
+
+/// Sends one control channel message (`TestConfig::udp_control_port`) from a throwaway socket.
+async fn send_control_message(control_addr: SocketAddr, message: ControlMessage, config: &TestConfig, session_id: u32) -> Result<(), NetworkError> {
+    let local_addr: SocketAddr = if control_addr.is_ipv6() {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local_addr).await?;
+    let bytes = CustomPacket::new_control(0, message).in_session(session_id).to_bytes_with(config.bincode_fixint)?;
+    let copies = if message == ControlMessage::End { CONTROL_END_COPIES } else { 1 };
+    for _ in 0..copies {
+        socket.send_to(&bytes, control_addr).await?;
+    }
+    Ok(())
+}
+
+/// Server side of the UDP control channel: counts control messages, separately from the
+/// data metrics, and wakes `end_of_test` when the client says it has finished sending.
+async fn udp_control_loop(socket: UdpSocket, fixint: bool, metrics: Arc<Mutex<TestMetrics>>, end_of_test: Arc<Notify>) {
+    let mut buf = vec![0u8; MAX_UDP_DATAGRAM];
+    while let Ok((len, src_addr)) = socket.recv_from(&mut buf).await {
+        let message = CustomPacket::from_bytes_with(&buf[..len], fixint)
+            .ok()
+            .and_then(|packet| packet.control_message());
+        match message {
+            Some(message) => {
+                lock_metrics(&metrics).control_messages_received += 1;
+                if message == ControlMessage::End {
+                    end_of_test.notify_one();
+                }
+            }
+            None => eprintln!("UDP Control: Ignoring a non-control datagram from {}", src_addr),
+        }
+    }
+}
+
 async fn udp_receive_loop(
     config: Arc<TestConfig>,
     socket: Arc<UdpSocket>, // Use an Arc for the socket
     metrics: Arc<Mutex<TestMetrics>>,
+    end_of_test: Option<Arc<Notify>>, // From the control channel, if there is one
+    is_primary_receiver: bool, // False for the extra loops of a dest_port_range, which leave bandwidth sampling to this one
 ) -> Result<(), NetworkError> {
     println!("UDP ReceiveLoop: Listening on {}", socket.local_addr()?);
-    let mut buf = vec![0u8; 4096]; // Increased buffer size
-    let mut highest_udp_seq_received: Option<u32> = None; // For out-of-order detection
+    if let Some(requested) = config.recv_buffer_bytes {
+        let granted = set_recv_buffer_size(&socket, requested)?;
+        println!("UDP ReceiveLoop: Requested a {} byte receive buffer, the OS granted {}", requested, granted);
+        if granted < requested {
+            eprintln!("UDP ReceiveLoop: Receive buffer capped below the request; raise the OS limit (e.g. net.core.rmem_max on Linux)");
+        }
+    }
+    let mut buf = vec![0u8; MAX_UDP_DATAGRAM]; // Fits any datagram, so auto-sized packets aren't truncated
 
-    let test_start_time = metrics.lock().unwrap().test_start_time.unwrap_or_else(Instant::now);
-    let bandwidth_sample_interval_ms = 1000; // 1 second
+    let test_start_time = lock_metrics(&metrics).test_start_time.unwrap_or_else(Instant::now);
+    let packet_tape = lock_metrics(&metrics).packet_tape.clone();
+    let control = Arc::clone(&lock_metrics(&metrics).control);
+    let bandwidth_sample_interval = config.bandwidth_sample_interval();
     let mut bandwidth_sampler = tokio::time::interval_at(
-        tokio::time::Instant::now() + Duration::from_millis(bandwidth_sample_interval_ms),
-        Duration::from_millis(bandwidth_sample_interval_ms)
+        tokio::time::Instant::now() + bandwidth_sample_interval,
+        bandwidth_sample_interval
     );
 
-    // Server loop runs for test duration + grace period to catch trailing packets
-    let server_lifetime = config.total_duration() + Duration::from_secs(5);
+    // Server loop runs for test duration + grace period to catch trailing packets. Never
+    // count-limited: TestConfig::validate only allows that in client mode.
+    let server_lifetime = config.total_duration() + RECEIVE_GRACE;
+    let mut parse_pool = (config.parse_workers > 0)
+        .then(|| ParsePool::start(&config, &socket, &metrics, packet_tape.clone()));
+    let mut ended_at: Option<Instant> = None; // When the control channel said the client is done
+    let mut last_arrival: Option<Instant> = None; // For inter-arrival gaps
 
     loop {
+        let deadline = match ended_at {
+            Some(ended_at) => ended_at + CONTROL_END_GRACE,
+            None => test_start_time + server_lifetime + control.paused_duration(),
+        };
         tokio::select! {
             biased;
 
-            _ = tokio::time::sleep_until(tokio::time::Instant::from_std(test_start_time + server_lifetime)) => {
+            _ = tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)) => {
+                if ended_at.is_none() && control.active_elapsed(test_start_time) < server_lifetime {
+                    continue; // Paused since the deadline was set; it has moved back
+                }
                 println!("UDP ReceiveLoop on {}: Test duration likely ended. Taking final bandwidth sample and shutting down.", socket.local_addr()?);
-                if let Ok(mut metrics_guard) = metrics.lock() {
-                    if let Some(start_time_instant) = metrics_guard.test_start_time { // Use the stored Instant
-                        let current_test_time_ms = Instant::now().duration_since(start_time_instant).as_millis();
-                        metrics_guard.take_bandwidth_sample(current_test_time_ms);
-                    }
+                if let Some(pool) = parse_pool.take() {
+                    pool.finish(&metrics).await; // Record what's queued before the final sample
+                }
+                if is_primary_receiver {
+                    take_final_bandwidth_sample(&config, &metrics);
                 }
                 break;
             }
 
-            result = socket.recv_from(&mut buf) => {
+            _ = control.cancelled() => {
+                println!("UDP ReceiveLoop on {}: Test cancelled, shutting down.", socket.local_addr()?);
+                if let Some(pool) = parse_pool.take() {
+                    pool.finish(&metrics).await;
+                }
+                if is_primary_receiver {
+                    take_final_bandwidth_sample(&config, &metrics);
+                }
+                break;
+            }
+
+            result = hot_span!(future "recv", socket.recv_from(&mut buf)) => {
                 match result {
                     Ok((len, src_addr)) => {
-                        let data = &buf[..len];
-                        match CustomPacket::from_bytes(data) {
-                            Ok(packet) => {
-                                let current_seq = packet.header.sequence_number;
-
-                                { // Metrics lock scope
-                                    let mut metrics_guard = metrics.lock().unwrap();
-                                    metrics_guard.record_packet_received(len, 0); // RTT 0 for server-side
-
-                                    if let Some(highest_seen) = highest_udp_seq_received {
-                                        let is_likely_wrap = current_seq < (u32::MAX / 4) && highest_seen > (u32::MAX * 3 / 4);
-                                        if current_seq < highest_seen && !is_likely_wrap {
-                                            // This is an out-of-order packet
-                                            metrics_guard.out_of_order_count += 1;
-                                            let anomaly_time_ms = metrics_guard.test_start_time
-                                                .map_or(0, |st| Instant::now().duration_since(st).as_millis());
-                                            metrics_guard.anomalies.push(crate::anomalies::AnomalyEvent {
-                                                timestamp_ms: anomaly_time_ms,
-                                                anomaly_type: crate::anomalies::AnomalyType::OutOfOrder,
-                                                description: format!("UDP Packet Seq: {} received after {}", current_seq, highest_seen),
-                                            });
-                                        }
-                                    }
-                                } // Metrics lock scope ends
-
-                                // Always update highest_udp_seq_received to the maximum sequence number seen so far.
-                                highest_udp_seq_received = Some(highest_udp_seq_received.map_or(current_seq, |h| h.max(current_seq)));
-
-                                if packet.header.packet_type == crate::packet::PacketType::EchoRequest {
-                                    let reply_packet = CustomPacket::new_echo_reply(&packet);
-                                    if let Ok(reply_bytes) = reply_packet.to_bytes() {
-                                        if let Err(e) = socket.send_to(&reply_bytes, src_addr).await {
-                                            eprintln!("UDP Server: Error sending echo reply: {}", e);
-                                        } else {
-                                            // metrics.lock().unwrap().record_packet_sent(reply_bytes.len()); // If server ACKs are counted
-                                        }
-                                    }
-                                }
-                            }
-                            Err(e) => eprintln!("UDP ReceiveLoop on {}: Failed to parse CustomPacket from {}: {:?}", socket.local_addr()?, src_addr, e),
+                        let now = Instant::now();
+                        let gap_micros = last_arrival.replace(now).map(|last| now.duration_since(last).as_micros() as u64);
+                        let arrival = DatagramArrival {
+                            src_addr,
+                            arrival_ms: now.duration_since(test_start_time).as_millis(),
+                            receive_timestamp_micros: crate::packet::unix_time_micros(),
+                            gap_micros: gap_micros.filter(|_| config.record_interarrival),
+                        };
+                        match &mut parse_pool {
+                            Some(pool) => pool.submit(&buf[..len], arrival).await,
+                            None => process_udp_datagram(&config, &socket, &metrics, packet_tape.as_deref(), &buf[..len], arrival).await,
                         }
                     }
                     Err(e) => {
@@ -471,28 +1273,376 @@ async fn udp_receive_loop(
                 }
             }
 
-            _ = bandwidth_sampler.tick() => {
-                if let Ok(mut metrics_guard) = metrics.lock() {
-                    if let Some(start_time_instant) = metrics_guard.test_start_time {
-                        let current_test_time_ms = Instant::now().duration_since(start_time_instant).as_millis();
-                        metrics_guard.take_bandwidth_sample(current_test_time_ms);
-                    }
-                }
+            _ = notified_or_pending(end_of_test.as_deref()), if ended_at.is_none() => {
+                println!("UDP ReceiveLoop: The client signalled the end of the test, stopping in {:?}.", CONTROL_END_GRACE);
+                ended_at = Some(Instant::now());
+            }
+
+            _ = bandwidth_sampler.tick(), if is_primary_receiver => {
+                take_periodic_bandwidth_sample(&config, &metrics);
             }
         }
     }
+    if let Some(pool) = parse_pool {
+        pool.finish(&metrics).await; // Datagrams already read still count
+    }
     println!("UDP ReceiveLoop on {}: Finished.", socket.local_addr()?);
     Ok(())
 }
 
-
-// --- TCP Stubs (to be fully implemented) ---
-async fn tcp_connect(remote_addr: SocketAddr) -> Result<TcpStream, NetworkError> {
-    println!("TCP: Attempting to connect to {}...", remote_addr);
-    match TcpStream::connect(remote_addr).await {
-        Ok(stream) => {
-            println!("TCP: Successfully connected to {}", remote_addr);
-            Ok(stream)
+/// Standalone reflector (netstats-echo): answers every EchoRequest arriving on `socket`
+/// the way a test server does, for remote netstats clients to test against, until
+/// `shutdown` completes. Keeps no per-client state, so it can run indefinitely. Returns
+/// how many requests it echoed.
+pub async fn run_udp_echo_server(
+    config: Arc<TestConfig>,
+    socket: Arc<UdpSocket>,
+    shutdown: impl std::future::Future<Output = ()>,
+) -> Result<u64, NetworkError> {
+    println!("UDP Echo: Listening on {}", socket.local_addr()?);
+    let mut buf = vec![0u8; MAX_UDP_DATAGRAM];
+    let mut echoed: u64 = 0;
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            result = socket.recv_from(&mut buf) => match result {
+                Ok((len, src_addr)) => {
+                    let request = match CustomPacket::from_bytes_with(&buf[..len], config.bincode_fixint) {
+                        Ok(packet) if packet.header.packet_type == PacketType::EchoRequest => packet,
+                        _ => continue, // Only EchoRequests get an answer
+                    };
+                    let arrival = DatagramArrival {
+                        src_addr,
+                        arrival_ms: 0, // Unused: nothing is recorded
+                        receive_timestamp_micros: crate::packet::unix_time_micros(),
+                        gap_micros: None,
+                    };
+                    send_echo_reply(&config, &socket, &request, &arrival).await;
+                    echoed += 1;
+                }
+                // A client that went away (ICMP Port Unreachable) doesn't stop the reflector
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {}
+                Err(e) => return Err(NetworkError::IoError(e)),
+            }
+        }
+    }
+    println!("UDP Echo on {}: Stopped after echoing {} requests.", socket.local_addr()?, echoed);
+    Ok(echoed)
+}
+
+/// How long a receive loop runs: the test duration plus a grace period for trailing packets.
+/// None in a count-limited test, which has no end time: the loop runs until the peer closes
+/// the connection or the test is cancelled.
+fn receive_lifetime(config: &TestConfig) -> Option<Duration> {
+    (!config.is_count_limited()).then(|| config.total_duration() + RECEIVE_GRACE)
+}
+
+/// Waits until `lifetime` of active test time has passed (see `receive_lifetime`), or
+/// forever without one.
+async fn sleep_until_lifetime_end(lifetime: Option<Duration>, test_start_time: Instant, control: &crate::control::TestControl) {
+    match lifetime {
+        Some(lifetime) => {
+            let deadline = test_start_time + lifetime + control.paused_duration();
+            tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)).await
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Waits for `notify`, or forever without one.
+async fn notified_or_pending(notify: Option<&Notify>) {
+    match notify {
+        Some(notify) => notify.notified().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// When and from where the UDP receive loop read a datagram.
+#[derive(Debug, Clone, Copy)]
+struct DatagramArrival {
+    src_addr: SocketAddr,
+    arrival_ms: u128,              // Since test start
+    receive_timestamp_micros: u64, // Wall clock, for echo replies
+    gap_micros: Option<u64>,       // Since the previous datagram, if record_interarrival is on
+}
+
+/// Records one received datagram: counts, sequence tracking, packet tape and the echo reply.
+async fn process_udp_datagram(
+    config: &TestConfig,
+    socket: &UdpSocket,
+    metrics: &Mutex<TestMetrics>,
+    packet_tape: Option<&PacketTape>,
+    data: &[u8],
+    arrival: DatagramArrival,
+) {
+    let packet = match hot_span!("parse", CustomPacket::from_bytes_with(data, config.bincode_fixint)) {
+        Ok(packet) => packet,
+        Err(e) => {
+            eprintln!("UDP ReceiveLoop: Failed to parse CustomPacket from {}: {:?}", arrival.src_addr, e);
+            return;
+        }
+    };
+    if let Some(advertised_port) = packet.hello_port() {
+        record_hello(&mut lock_metrics(metrics), advertised_port, arrival.src_addr, arrival.arrival_ms);
+        return;
+    }
+    if !accept_at_data_receiver(config, metrics, &packet, arrival.src_addr) {
+        return;
+    }
+    let current_seq = packet.header.sequence_number;
+
+    hot_span!("metrics_update", { // Metrics lock scope
+        let mut metrics_guard = lock_metrics(metrics);
+        metrics_guard.record_packet_received(data.len(), 0); // RTT 0 for server-side
+        metrics_guard.record_payload_received(packet.payload.len());
+        metrics_guard.record_transit(packet.header.timestamp_ms, arrival.receive_timestamp_micros);
+        if !packet.verify_integrity() {
+            record_corrupt_payload(&mut metrics_guard, &packet, arrival.arrival_ms);
+        }
+        // Reorder/late/duplicate classification and anomalies
+        metrics_guard.record_sequence(current_seq, arrival.arrival_ms);
+        if let Some(gap_micros) = arrival.gap_micros {
+            metrics_guard.record_interarrival_gap(gap_micros);
+        }
+        if config.record_size_histogram {
+            metrics_guard.record_received_size(packet.payload.len());
+        }
+    }); // Metrics lock scope ends
+
+    if let Some(tape) = packet_tape {
+        tape.push(PacketEvent {
+            timestamp_ms: arrival.arrival_ms,
+            sequence_number: current_seq,
+            size_bytes: data.len(),
+            rtt_micros: None,
+            packet_type: packet.header.packet_type,
+        });
+    }
+
+    if packet.header.packet_type == crate::packet::PacketType::EchoRequest {
+        send_echo_reply(config, socket, &packet, &arrival).await;
+    }
+}
+
+async fn send_echo_reply(config: &TestConfig, socket: &UdpSocket, request: &CustomPacket, arrival: &DatagramArrival) {
+    let reply_packet = CustomPacket::new_echo_reply(request, arrival.receive_timestamp_micros);
+    if let Ok(reply_bytes) = reply_packet.to_bytes_with(config.bincode_fixint) {
+        if let Err(e) = socket.send_to(&reply_bytes, arrival.src_addr).await {
+            eprintln!("UDP Server: Error sending echo reply: {}", e);
+        }
+    }
+}
+
+/// A packet whose payload fails its checksum still counts as received (it arrived), but
+/// the alteration is recorded as a CorruptPayload anomaly.
+fn record_corrupt_payload(metrics: &mut TestMetrics, packet: &CustomPacket, timestamp_ms: u128) {
+    metrics.record_anomaly(crate::anomalies::AnomalyEvent {
+        timestamp_ms,
+        anomaly_type: crate::anomalies::AnomalyType::CorruptPayload,
+        description: format!(
+            "Payload of seq {} ({} bytes) fails its checksum {:#010x}",
+            packet.header.sequence_number, packet.payload.len(), packet.header.checksum
+        ),
+        threshold: None,
+    });
+}
+
+/// Compares the source port a peer's Hello advertised with the one it arrived from. A
+/// difference means a NAT remapped the port on the way: recorded once per test, as
+/// `nat_mapped_port` and a NatDetected anomaly.
+fn record_hello(metrics: &mut TestMetrics, advertised_port: u16, src_addr: SocketAddr, timestamp_ms: u128) {
+    if src_addr.port() == advertised_port || metrics.nat_mapped_port.is_some() {
+        return;
+    }
+    metrics.nat_mapped_port = Some(src_addr.port());
+    metrics.record_anomaly(crate::anomalies::AnomalyEvent {
+        timestamp_ms,
+        anomaly_type: crate::anomalies::AnomalyType::NatDetected,
+        description: format!(
+            "Peer sends from port {} but arrives from {}: a NAT maps its port {} to {}",
+            advertised_port, src_addr, advertised_port, src_addr.port()
+        ),
+        threshold: None,
+    });
+}
+
+/// Whether a well-formed packet that reached a data receive loop is recorded as received.
+/// Every type is decided here explicitly, so a new one can't silently count as data: types
+/// the loops don't serve are counted in `unexpected_type_packets` and handled per
+/// `config.unexpected_packet_handling`. Prewarm and Hello packets are the control messages
+/// expected here, and are dropped without a trace (the UDP loop checks a Hello first). Data
+/// of another session than the peer's is dropped, see `TestMetrics::accept_peer_session`.
+fn accept_at_data_receiver(
+    config: &TestConfig,
+    metrics: &Mutex<TestMetrics>,
+    packet: &CustomPacket,
+    from: impl std::fmt::Display,
+) -> bool {
+    let packet_type = packet.header.packet_type;
+    match packet_type {
+        PacketType::Data | PacketType::EchoRequest => {
+            let accepted = lock_metrics(metrics).accept_peer_session(packet.header.session_id, packet.header.sequence_number);
+            if !accepted {
+                eprintln!("ReceiveLoop: Dropping a packet of another test session from {}", from);
+            }
+            accepted
+        }
+        PacketType::Control if matches!(packet.control_message(), Some(ControlMessage::Prewarm | ControlMessage::Hello)) => false,
+        PacketType::Control => {
+            // Control messages belong on the control channel (udp_control_port), never in the data metrics
+            lock_metrics(metrics).unexpected_type_packets += 1;
+            eprintln!("ReceiveLoop: Ignoring a control packet on the data socket from {}", from);
+            false
+        }
+        PacketType::Ack | PacketType::EchoReply => {
+            lock_metrics(metrics).unexpected_type_packets += 1;
+            match config.unexpected_packet_handling {
+                UnexpectedPacketHandling::Drop => {
+                    eprintln!("ReceiveLoop: Dropping an unexpected {:?} packet from {}", packet_type, from);
+                    false
+                }
+                UnexpectedPacketHandling::Accept => true,
+            }
+        }
+    }
+}
+
+// Datagrams that may wait for a parse worker before the receive loop blocks
+const PARSE_QUEUE_CAPACITY: usize = 4096;
+// Datagrams a worker takes off the queue at once, to keep contention on the queue low
+const PARSE_BATCH: usize = 64;
+// Initial capacity of pooled receive buffers: an Ethernet-sized datagram. A larger one grows
+// its buffer once, and the buffer keeps that capacity when reused.
+const POOLED_BUFFER_CAPACITY: usize = 2048;
+
+/// Worker tasks that parse and record datagrams for the UDP receive loop (`parse_workers`),
+/// so the loop only copies them off the socket. Workers finish packets in parallel, so
+/// sequence tracking can see a little reordering the network didn't cause: with several
+/// workers, set `reorder_window` to a few packets.
+struct ParsePool {
+    tx: mpsc::Sender<(Vec<u8>, DatagramArrival)>,
+    workers: Vec<tokio::task::JoinHandle<()>>,
+    free_buffers: Option<Arc<crossbeam_queue::ArrayQueue<Vec<u8>>>>, // recv_buffer_pool
+    // Kept here rather than in the metrics to keep their lock off the receive path
+    peak_depth: u64,
+    allocations: u64,
+}
+
+impl ParsePool {
+    fn start(
+        config: &Arc<TestConfig>,
+        socket: &Arc<UdpSocket>,
+        metrics: &Arc<Mutex<TestMetrics>>,
+        packet_tape: Option<Arc<PacketTape>>,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel::<(Vec<u8>, DatagramArrival)>(PARSE_QUEUE_CAPACITY);
+        let rx = Arc::new(tokio::sync::Mutex::new(rx));
+        let free_buffers = (config.recv_buffer_pool > 0).then(|| {
+            let free = crossbeam_queue::ArrayQueue::new(config.recv_buffer_pool);
+            for _ in 0..config.recv_buffer_pool {
+                let _ = free.push(Vec::with_capacity(POOLED_BUFFER_CAPACITY));
+            }
+            Arc::new(free)
+        });
+        let workers = (0..config.parse_workers)
+            .map(|_| {
+                let (config, socket, metrics, packet_tape, rx, free_buffers) =
+                    (Arc::clone(config), Arc::clone(socket), Arc::clone(metrics), packet_tape.clone(), Arc::clone(&rx), free_buffers.clone());
+                tokio::spawn(async move {
+                    let mut batch = Vec::with_capacity(PARSE_BATCH);
+                    // Lock released before parsing; 0 means the loop has finished and the queue is empty
+                    while rx.lock().await.recv_many(&mut batch, PARSE_BATCH).await > 0 {
+                        for (mut data, arrival) in batch.drain(..) {
+                            process_udp_datagram(&config, &socket, &metrics, packet_tape.as_deref(), &data, arrival).await;
+                            if let Some(free) = &free_buffers {
+                                data.clear();
+                                let _ = free.push(data); // Dropped if the pool is already full
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        ParsePool { tx, workers, free_buffers, peak_depth: 0, allocations: config.recv_buffer_pool as u64 }
+    }
+
+    /// Queues a copy of `datagram`, waiting for room if the workers are behind.
+    async fn submit(&mut self, datagram: &[u8], arrival: DatagramArrival) {
+        let data = match self.free_buffers.as_ref().and_then(|free| free.pop()) {
+            Some(mut buffer) => {
+                buffer.extend_from_slice(datagram);
+                buffer
+            }
+            None => {
+                self.allocations += 1;
+                datagram.to_vec()
+            }
+        };
+        if self.tx.send((data, arrival)).await.is_ok() { // Err: workers gone (panicked)
+            self.peak_depth = self.peak_depth.max((self.tx.max_capacity() - self.tx.capacity()) as u64);
+        }
+    }
+
+    /// Lets the workers drain the queue, waits for them and records the peak queue depth
+    /// and buffer allocations.
+    async fn finish(self, metrics: &Mutex<TestMetrics>) {
+        drop(self.tx);
+        for worker in self.workers {
+            let _ = worker.await;
+        }
+        let mut metrics_guard = lock_metrics(metrics);
+        metrics_guard.parse_queue_depth = metrics_guard.parse_queue_depth.max(self.peak_depth);
+        metrics_guard.recv_buffer_allocations += self.allocations;
+    }
+}
+
+
+/// Regular bandwidth sample from the receive loops' sampler tick. Ticks during the grace
+/// period after the test are skipped, so trailing packets fold into the final sample. A
+/// count-limited test has no configured end, so every tick samples.
+fn take_periodic_bandwidth_sample(config: &TestConfig, metrics: &Mutex<TestMetrics>) {
+    let mut metrics_guard = lock_metrics(metrics);
+    if let Some(start_time_instant) = metrics_guard.test_start_time {
+        let current_test_time_ms = Instant::now().duration_since(start_time_instant).as_millis();
+        if config.is_count_limited() || metrics_guard.control.active_elapsed(start_time_instant) <= config.total_duration() {
+            metrics_guard.take_bandwidth_sample(current_test_time_ms);
+        }
+    }
+}
+
+/// Final bandwidth sample when a receive loop shuts down (see `FinalSampleTiming`).
+fn take_final_bandwidth_sample(config: &TestConfig, metrics: &Mutex<TestMetrics>) {
+    let mut metrics_guard = lock_metrics(metrics);
+    if let Some(start_time_instant) = metrics_guard.test_start_time {
+        // Sample times are wall-clock, so the configured end moves back by any pauses. A
+        // count-limited test ends when its receive side does, and a cancelled one when it was
+        // cancelled (the receive loops stop right away), unless that was in the grace period.
+        let elapsed = start_time_instant.elapsed();
+        let configured_end = config.total_duration() + metrics_guard.control.paused_duration();
+        let configured_end = if config.is_count_limited() {
+            elapsed
+        } else if metrics_guard.control.is_cancelled() {
+            elapsed.min(configured_end)
+        } else {
+            configured_end
+        };
+        metrics_guard.take_final_bandwidth_sample(config.final_sample_timing, configured_end.as_millis());
+    }
+}
+
+
+// --- TCP Stubs (to be fully implemented) ---
+/// Connects to `remote_addr`, recording the handshake time as `metrics.connect_rtt_micros`.
+async fn tcp_connect(remote_addr: SocketAddr, metrics: &Mutex<TestMetrics>) -> Result<TcpStream, NetworkError> {
+    println!("TCP: Attempting to connect to {}...", remote_addr);
+    let connect_start = Instant::now();
+    match TcpStream::connect(remote_addr).await {
+        Ok(stream) => {
+            let connect_rtt = connect_start.elapsed();
+            println!("TCP: Successfully connected to {} in {:?}", remote_addr, connect_rtt);
+            lock_metrics(metrics).connect_rtt_micros = Some(connect_rtt.as_micros() as u64);
+            Ok(stream)
         }
         Err(e) => {
             println!("TCP: Failed to connect to {}: {}", remote_addr, e);
@@ -501,139 +1651,401 @@ async fn tcp_connect(remote_addr: SocketAddr) -> Result<TcpStream, NetworkError>
     }
 }
 
-async fn tcp_listen(listen_addr: SocketAddr) -> Result<TcpListener, NetworkError> {
+// Pending connections a TCP listener queues before accepting them, as in tokio's TcpListener::bind
+const TCP_LISTEN_BACKLOG: i32 = 1024;
+
+/// A TCP listener bound to `addr`, see `bind_socket`.
+fn bind_tcp_listener(addr: SocketAddr, reuse_addr: bool) -> Result<TcpListener, NetworkError> {
+    let socket = bind_socket(addr, socket2::Type::STREAM, reuse_addr)?;
+    socket.listen(TCP_LISTEN_BACKLOG)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// Listens on `listen_addr`. On Unix, SO_REUSEADDR is always set, as tokio's TcpListener::bind
+/// does: it only lets the listener bind over connections of an earlier one still in TIME_WAIT,
+/// never over a live listener. Elsewhere it follows `config.reuse_addr`.
+fn tcp_listen(listen_addr: SocketAddr, config: &TestConfig) -> Result<TcpListener, NetworkError> {
     println!("TCP: Attempting to listen on {}...", listen_addr);
-    match TcpListener::bind(listen_addr).await {
+    match bind_tcp_listener(listen_addr, config.reuse_addr || cfg!(unix)) {
         Ok(listener) => {
             println!("TCP: Successfully listening on {}", listen_addr);
             Ok(listener)
         }
         Err(e) => {
             println!("TCP: Failed to listen on {}: {}", listen_addr, e);
-            Err(NetworkError::IoError(e))
+            Err(e)
         }
     }
 }
 
+/// Waits for a connection on `listener`; None if the test is cancelled first.
+async fn tcp_accept(listener: &TcpListener, metrics: &Mutex<TestMetrics>) -> Result<Option<(TcpStream, SocketAddr)>, NetworkError> {
+    let control = Arc::clone(&lock_metrics(metrics).control);
+    tokio::select! {
+        accepted = listener.accept() => Ok(Some(accepted?)),
+        _ = control.cancelled() => Ok(None),
+    }
+}
+
+/// The config connection `index` of `config.parallel_streams` runs with: an even share of the
+/// target bitrate, tick rate, `stop_after_packets` and `prewarm_packets` (the first connections
+/// take the remainder), so that together they send what one connection would. A tick rate
+/// share is at least 1 Hz, so with more connections than ticks per second each sends once a second.
+fn parallel_stream_config(config: &TestConfig, index: u32) -> Arc<TestConfig> {
+    let streams = u64::from(config.parallel_streams.max(1));
+    let share = |total: u64| total / streams + u64::from(u64::from(index) < total % streams);
+    Arc::new(TestConfig {
+        target_bitrate_bps: config.target_bitrate_bps.map(|bps| share(bps).max(1)),
+        tick_rate_hz: match config.tick_rate_hz {
+            0 => 0, // As fast as possible on every connection
+            tick_rate_hz => share(tick_rate_hz.into()).max(1) as u32,
+        },
+        stop_after_packets: config.stop_after_packets.map(share),
+        prewarm_packets: share(config.prewarm_packets.into()) as u32,
+        send_rng_seed: config.send_rng_seed.map(|seed| seed.wrapping_add(index.into())), // Not in lockstep either
+        ..config.clone()
+    })
+}
+
+/// TCP client over `config.parallel_streams` connections, each with its own send and receive
+/// loop as in the single connection client. They all record into `metrics`.
+async fn tcp_parallel_client(config: Arc<TestConfig>, remote_addr: SocketAddr, metrics: Arc<Mutex<TestMetrics>>) -> Result<(), NetworkError> {
+    let mut streams = tokio::task::JoinSet::new();
+    for index in 0..config.parallel_streams {
+        let stream = tcp_connect(remote_addr, &metrics).await?;
+        println!("TCP Client: Stream {} of {} connected", index + 1, config.parallel_streams);
+        let stream_config = parallel_stream_config(&config, index);
+        let (reader, connection) = TcpConnection::split_parallel(stream, &stream_config)?;
+        let metrics = Arc::clone(&metrics);
+        streams.spawn(async move {
+            tokio::try_join!(
+                tcp_send_loop(Arc::clone(&stream_config), Arc::clone(&connection), Arc::clone(&metrics), true),
+                tcp_receive_loop(stream_config, reader, connection, metrics),
+            )
+            .map(|_| ())
+        });
+    }
+    join_parallel_streams(&config, &metrics, streams).await
+}
+
+/// TCP server for `config.parallel_streams` connections: accepts that many, receiving on each
+/// as soon as it's accepted. Stops accepting if the test is cancelled first.
+async fn tcp_parallel_server(config: Arc<TestConfig>, listen_addr: SocketAddr, metrics: Arc<Mutex<TestMetrics>>) -> Result<(), NetworkError> {
+    let listener = tcp_listen(listen_addr, &config)?;
+    println!("TCP Server: Waiting for {} connections on {}...", config.parallel_streams, listen_addr);
+    let mut streams = tokio::task::JoinSet::new();
+    for index in 0..config.parallel_streams {
+        let Some((stream, client_addr)) = tcp_accept(&listener, &metrics).await? else {
+            break; // Cancelled before every stream connected
+        };
+        println!("TCP Server: Accepted stream {} of {} from {}", index + 1, config.parallel_streams, client_addr);
+        let (reader, connection) = TcpConnection::split_parallel(stream, &config)?;
+        streams.spawn(tcp_receive_loop(Arc::clone(&config), reader, connection, Arc::clone(&metrics)));
+    }
+    join_parallel_streams(&config, &metrics, streams).await
+}
+
+/// Waits for every parallel stream to finish, taking the bandwidth samples meanwhile: one
+/// sampler for all streams, so each interval's sample holds the bytes of all of them.
+/// Returns the first stream's error, if any.
+async fn join_parallel_streams(
+    config: &TestConfig,
+    metrics: &Mutex<TestMetrics>,
+    mut streams: tokio::task::JoinSet<Result<(), NetworkError>>,
+) -> Result<(), NetworkError> {
+    let bandwidth_sample_interval = config.bandwidth_sample_interval();
+    let mut bandwidth_sampler = tokio::time::interval_at(
+        tokio::time::Instant::now() + bandwidth_sample_interval,
+        bandwidth_sample_interval
+    );
+    let mut result = Ok(());
+    loop {
+        tokio::select! {
+            joined = streams.join_next() => match joined {
+                None => break, // All streams finished
+                Some(Ok(Ok(()))) => {}
+                Some(Ok(Err(e))) => {
+                    eprintln!("TCP: A parallel stream failed: {}", e);
+                    result = result.and(Err(e));
+                }
+                Some(Err(e)) => result = result.and(Err(NetworkError::Other(format!("TCP stream task error: {}", e)))),
+            },
+            _ = bandwidth_sampler.tick() => take_periodic_bandwidth_sample(config, metrics),
+        }
+    }
+    take_final_bandwidth_sample(config, metrics);
+    result
+}
+
+/// What the send and receive loops of one TCP connection share: the write half, on which
+/// the receive loop also answers EchoRequests, and the EchoRequests of the (primary) send
+/// loop still awaiting a reply, which the receive loop credits as the replies come in.
+struct TcpConnection {
+    writer: tokio::sync::Mutex<tokio::io::WriteHalf<TcpStream>>,
+    outstanding_echoes: Mutex<HashMap<u32, Instant>>, // Sequence number -> send time, as in udp_send_loop
+    all_replied: Notify,                              // Notified whenever outstanding_echoes empties
+    takes_bandwidth_samples: bool,                    // False for parallel streams, see join_parallel_streams
+}
+
+impl TcpConnection {
+    /// Splits `stream` into the read half for `tcp_receive_loop` and the shared rest, after
+    /// marking it with `config.dscp`.
+    fn split(stream: TcpStream, config: &TestConfig) -> Result<(tokio::io::ReadHalf<TcpStream>, Arc<Self>), NetworkError> {
+        Self::split_with_sampling(stream, config, true)
+    }
+
+    /// Like `split`, for one of several parallel connections: its receive loop leaves the
+    /// bandwidth samples to the caller, which takes them for all the connections together.
+    fn split_parallel(stream: TcpStream, config: &TestConfig) -> Result<(tokio::io::ReadHalf<TcpStream>, Arc<Self>), NetworkError> {
+        Self::split_with_sampling(stream, config, false)
+    }
+
+    fn split_with_sampling(
+        stream: TcpStream,
+        config: &TestConfig,
+        takes_bandwidth_samples: bool,
+    ) -> Result<(tokio::io::ReadHalf<TcpStream>, Arc<Self>), NetworkError> {
+        if let Some(dscp) = config.dscp {
+            set_dscp(&stream, stream.peer_addr()?.is_ipv6(), dscp)?;
+        }
+        let (reader, writer) = tokio::io::split(stream);
+        let connection = TcpConnection {
+            writer: tokio::sync::Mutex::new(writer),
+            outstanding_echoes: Mutex::new(HashMap::new()),
+            all_replied: Notify::new(),
+            takes_bandwidth_samples,
+        };
+        Ok((reader, Arc::new(connection)))
+    }
+
+    /// Writes `data` as one frame: a big-endian u32 length, then the bytes. The writer stays
+    /// locked for the whole frame, so frames of the two loops never interleave.
+    async fn write_frame(&self, data: &[u8]) -> io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+        let mut writer = self.writer.lock().await;
+        writer.write_all(&(data.len() as u32).to_be_bytes()).await?;
+        writer.write_all(data).await
+    }
+
+    async fn shutdown(&self) -> io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+        self.writer.lock().await.shutdown().await
+    }
+}
+
+/// Sends on a TCP connection. The primary sender sends EchoRequests and tracks them in
+/// `connection` for the receive loop on the same connection to credit; a secondary sender
+/// sends plain Data.
 async fn tcp_send_loop(
     config: Arc<TestConfig>,
-    mut writer: tokio::io::WriteHalf<TcpStream>, // Changed to WriteHalf
+    connection: Arc<TcpConnection>,
     metrics: Arc<Mutex<TestMetrics>>,
     is_primary_sender: bool,
 ) -> Result<(), NetworkError> {
-    // Note: peer_addr might not be available from WriteHalf directly.
-    // It should be logged by the caller who has the full stream before splitting.
+    // The peer address is logged by the caller, which had the stream before splitting
     println!("TCP SendLoop: Started (is_primary_sender: {})", is_primary_sender);
+    let config = with_direction_tick_rate(config, is_primary_sender);
 
-    use tokio::io::AsyncWriteExt;
-
-    let test_start_time = metrics.lock().unwrap().test_start_time.unwrap_or_else(Instant::now);
+    let test_start_time = lock_metrics(&metrics).test_start_time.unwrap_or_else(Instant::now);
+    let control = Arc::clone(&lock_metrics(&metrics).control);
+    let session_id = lock_metrics(&metrics).session_id;
     let test_duration = config.total_duration();
     let tick_interval = config.tick_interval();
-    // use rand::rngs::StdRng; // Already imported if udp_send_loop is in the same file and parsed first
-    // use rand::SeedableRng;
-    let mut rng = if config.packet_size_range.is_some() { Some(rand::rngs::StdRng::from_entropy()) } else { None };
+    let mut rng = send_rng(&config);
     let mut sequence_number: u32 = 0;
-    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + tick_interval, tick_interval);
+    let mut pacer = config.target_bitrate_bps.filter(|_| is_primary_sender).map(BitratePacer::new);
+    if is_primary_sender && config.prewarm_packets > 0 {
+        // Uncounted, as in the UDP send loop
+        let prewarm = CustomPacket::new_prewarm(config.packet_size_bytes).in_session(session_id).to_bytes_with(config.bincode_fixint)?;
+        for _ in 0..config.prewarm_packets {
+            connection.write_frame(&prewarm).await?;
+        }
+    }
+    let mut ticker = if pacer.is_some() {
+        None // Paced by bitrate instead, as in the UDP send loop
+    } else if config.tick_rate_hz > 0 {
+        Some(tokio::time::interval_at(tokio::time::Instant::now() + first_tick_delay(&config, tick_interval), tick_interval))
+    } else { // As fast as possible, as in the UDP send loop; the socket's backpressure paces it
+        println!("TCP SendLoop: AFAP mode enabled (tick_rate_hz == 0)");
+        None
+    };
 
-    let loop_duration = if is_primary_sender { test_duration } else { Duration::MAX };
+    let loop_duration = if is_primary_sender { primary_send_duration(&config) } else { Duration::MAX };
+    let mut burst_remaining: u32 = 0; // Packets still to send for the current tick
+    let mut packets_sent: u64 = 0; // By this loop, for stop_after_packets
 
-    while Instant::now().duration_since(test_start_time) < loop_duration {
+    while !control.is_cancelled() && control.active_elapsed(test_start_time) < loop_duration {
+        if control.is_paused() {
+            control.wait_until_resumed().await; // The connection stays open meanwhile
+            if let Some(ref mut t) = ticker {
+                t.reset_after(first_tick_delay(&config, tick_interval));
+            }
+            if let Some(ref mut p) = pacer {
+                p.reset();
+            }
+            continue;
+        }
+        if is_primary_sender && reached_packet_count(&config, packets_sent) {
+            break;
+        }
          if is_primary_sender {
-            ticker.tick().await;
+            if let Some(ref mut t) = ticker {
+                if burst_remaining == 0 {
+                    tokio::select! {
+                        _ = t.tick() => {}
+                        _ = control.cancelled() => break,
+                    }
+                    if let Some(jitter_ms) = config.send_jitter_ms {
+                        tokio::select! {
+                            _ = tokio::time::sleep(send_jitter_delay(jitter_ms, &mut rng)) => {}
+                            _ = control.cancelled() => break,
+                        }
+                    }
+                    burst_remaining = config.tick_burst();
+                }
+                burst_remaining -= 1;
+            } else {
+                tokio::task::yield_now().await;
+            }
         } else {
             // Non-primary senders in TCP bidi might be event-driven (e.g. ACKs)
             // or could also send data not strictly tied to the main tickrate.
             // For now, let's assume it might also send data periodically if not primary.
             // If this loop is ONLY for ACKs, it would look very different (event-driven).
-            tokio::time::sleep(tick_interval).await;
+            tokio::select! {
+                _ = tokio::time::sleep(tick_interval) => {}
+                _ = control.cancelled() => break,
+            }
         }
 
         let current_packet_size = match config.packet_size_range {
-            Some((min_size, max_size)) => {
-                if let Some(ref mut r) = rng { use rand::Rng; r.gen_range(min_size..=max_size) }
-                else { config.packet_size_bytes }
-            }
+            Some((min_size, max_size)) => { use rand::Rng; rng.gen_range(min_size..=max_size) }
             None => config.packet_size_bytes,
         };
 
-        // TODO: Define packet type more meaningfully if not primary_sender (e.g. Ack, EchoReply)
-        let packet = CustomPacket::new_data_packet(sequence_number, current_packet_size);
-        let data = packet.to_bytes()?;
-
-        // Frame the packet: send length (u32) then data
-        let len_bytes = (data.len() as u32).to_be_bytes();
+        let packet = if is_primary_sender {
+            // Echoed by the peer's receive loop, for RTT as over UDP
+            let mut packet = CustomPacket::new_echo_request_with(sequence_number, current_packet_size, config.payload_pattern, &mut rng);
+            if config.verify_echo_payload {
+                packet.set_payload(CustomPacket::pattern_payload(sequence_number, current_packet_size));
+            }
+            packet
+        } else {
+            CustomPacket::new_data_packet_with(sequence_number, current_packet_size, config.payload_pattern, &mut rng)
+        }
+        .in_session(session_id);
+        let data = hot_span!("serialize", packet.to_bytes_with(config.bincode_fixint))?;
 
-        writer.write_all(&len_bytes).await.map_err(|e| NetworkError::IoError(e))?;
-        writer.write_all(&data).await.map_err(|e| NetworkError::IoError(e))?;
-        // Consider writer.flush().await? if timely delivery is critical and Nagle might be an issue.
+        if let Some(ref mut p) = pacer {
+            p.wait_to_send(4 + data.len()).await; // + 4 byte length prefix
+        }
+        if is_primary_sender {
+            // Tracked before sending so a fast reply can't beat its own entry to the map
+            lock_outstanding(&connection.outstanding_echoes).insert(sequence_number, Instant::now());
+        }
+        hot_span!(future "send", connection.write_frame(&data)).await.map_err(NetworkError::IoError)?;
 
-        metrics.lock().unwrap().record_packet_sent(data.len() + 4); // +4 for length prefix
+        hot_span!("metrics_update", lock_metrics(&metrics).record_packet_sent(data.len() + 4)); // +4 for length prefix
+        packets_sent += 1;
         sequence_number = sequence_number.wrapping_add(1);
 
-        if !is_primary_sender && Instant::now().duration_since(test_start_time) >= test_duration {
+        if !is_primary_sender && control.active_elapsed(test_start_time) >= test_duration {
             // If this is the secondary sender in a bidi test, stop after main duration.
             break;
         }
     }
 
-    if let Err(e) = writer.shutdown().await { // Gracefully close the write half
+    if is_primary_sender {
+        // Drain, as in udp_send_loop: up to one rtt_timeout for the last replies
+        let _ = tokio::time::timeout(config.rtt_timeout(), async {
+            while !lock_outstanding(&connection.outstanding_echoes).is_empty() {
+                connection.all_replied.notified().await;
+            }
+        })
+        .await;
+    }
+    if let Err(e) = connection.shutdown().await { // Gracefully close the write half
         eprintln!("TCP SendLoop: Error shutting down writer: {}", e);
     }
     println!("TCP SendLoop: Finished (is_primary_sender: {}).", is_primary_sender);
     Ok(())
 }
 
+/// Frame lengths a TCP receiver accepts: from an empty-payload packet up to `tcp_max_frame_bytes`.
+fn tcp_frame_length_bounds(config: &TestConfig) -> std::ops::RangeInclusive<usize> {
+    let mut smallest = CustomPacket::new_data_packet(0, 0);
+    smallest.header.timestamp_ms = 0; // Shortest varint encoding
+    let min_frame = smallest.to_bytes_with(config.bincode_fixint).map_or(1, |bytes| bytes.len());
+    min_frame..=config.tcp_max_frame_bytes.max(min_frame)
+}
+
+/// Logs a lost TCP frame boundary. There's no way to find the next frame in a byte stream,
+/// so the caller closes the connection.
+fn record_framing_error(metrics: &Mutex<TestMetrics>, description: String) {
+    eprintln!("TCP ReceiveLoop: Framing error, closing connection: {}", description);
+    let mut metrics_guard = lock_metrics(metrics);
+    let timestamp_ms = metrics_guard.test_start_time.map_or(0, |st| st.elapsed().as_millis());
+    metrics_guard.record_anomaly(crate::anomalies::AnomalyEvent {
+        timestamp_ms,
+        anomaly_type: crate::anomalies::AnomalyType::FramingError,
+        description,
+        threshold: None,
+    });
+}
+
+/// Receives on a TCP connection until the peer closes it or the test ends. EchoRequests are
+/// answered on `connection`'s write half and EchoReplies are credited to its send loop.
 async fn tcp_receive_loop(
     config: Arc<TestConfig>,
-    mut reader: tokio::io::ReadHalf<TcpStream>, // Changed to ReadHalf
+    mut reader: tokio::io::ReadHalf<TcpStream>,
+    connection: Arc<TcpConnection>,
     metrics: Arc<Mutex<TestMetrics>>,
 ) -> Result<(), NetworkError> {
     println!("TCP ReceiveLoop: Started.");
     use tokio::io::AsyncReadExt;
 
-    let test_start_time = metrics.lock().unwrap().test_start_time.unwrap_or_else(Instant::now);
-    let bandwidth_sample_interval_ms = 1000;
+    let test_start_time = lock_metrics(&metrics).test_start_time.unwrap_or_else(Instant::now);
+    let packet_tape = lock_metrics(&metrics).packet_tape.clone();
+    let control = Arc::clone(&lock_metrics(&metrics).control);
+    let bandwidth_sample_interval = config.bandwidth_sample_interval();
     let mut bandwidth_sampler = tokio::time::interval_at(
-        tokio::time::Instant::now() + Duration::from_millis(bandwidth_sample_interval_ms),
-        Duration::from_millis(bandwidth_sample_interval_ms)
+        tokio::time::Instant::now() + bandwidth_sample_interval,
+        bandwidth_sample_interval
     );
-    let server_lifetime = config.total_duration() + Duration::from_secs(5); // Grace period
-
-    // Placeholder for reading loop
-    // Actual TCP receive needs framing, e.g. send packet length first, then packet.
-    // For now, simulate activity.
-    // Similar to tcp_send_loop, this function should take an OwnedReadHalf.
-    // The current signature `stream: Arc<TcpStream>` is problematic for direct read loop
-    // if a send loop is also trying to use the same Arc directly.
-    // use tokio::io::AsyncReadExt; // Removed duplicate import, already imported at top of file or module
-    // let peer_addr = stream.peer_addr().ok(); // Not available on ReadHalf, log from caller if needed
-    println!("TCP ReceiveLoop: Placeholder section (simulating duration). Actual logic below.");
-
-    // Simulate test duration (Placeholder part)
-    // tokio::time::sleep(config.total_duration() + Duration::from_secs(5)).await; // Grace period for receiver
-    // This sleep was part of the placeholder, the actual loop is below.
+    let server_lifetime = receive_lifetime(&config);
+    let rtt_timeout = config.rtt_timeout();
+    let (reply_tx, mut reply_writer) = spawn_echo_reply_writer(Arc::clone(&connection));
 
     let mut length_buffer = [0u8; 4]; // To read the u32 length prefix
     let mut packet_buffer = Vec::with_capacity(config.packet_size_bytes.max(1024) * 2); // Initial capacity
+    let valid_frame_lengths = tcp_frame_length_bounds(&config);
+    let take_final_sample = || {
+        if connection.takes_bandwidth_samples {
+            take_final_bandwidth_sample(&config, &metrics);
+        }
+    };
 
     loop {
         tokio::select! {
             biased; // Prioritize packet reading over sampling or timeout
 
-            _ = tokio::time::sleep_until(tokio::time::Instant::from_std(test_start_time + server_lifetime)) => {
-                println!("TCP ReceiveLoop: Test duration likely ended.");
-                 if let Ok(mut metrics_guard) = metrics.lock() {
-                    if let Some(start_time_instant) = metrics_guard.test_start_time {
-                        let current_test_time_ms = Instant::now().duration_since(start_time_instant).as_millis();
-                        metrics_guard.take_bandwidth_sample(current_test_time_ms);
-                    }
+            _ = sleep_until_lifetime_end(server_lifetime, test_start_time, &control) => {
+                if server_lifetime.is_some_and(|lifetime| control.active_elapsed(test_start_time) < lifetime) {
+                    continue; // Paused since the deadline was set; it has moved back
                 }
+                println!("TCP ReceiveLoop: Test duration likely ended.");
+                take_final_sample();
                 break; // Exit loop
             }
 
+            _ = control.cancelled() => {
+                println!("TCP ReceiveLoop: Test cancelled.");
+                take_final_sample();
+                break;
+            }
+
             // 1. Read packet length (u32)
-            read_len_result = reader.read_exact(&mut length_buffer) => {
+            read_len_result = hot_span!(future "recv", reader.read_exact(&mut length_buffer)) => {
                 match read_len_result {
                     Ok(_) => {
                         let packet_len = u32::from_be_bytes(length_buffer) as usize;
@@ -642,11 +2054,16 @@ async fn tcp_receive_loop(
                             println!("TCP ReceiveLoop: Received 0-length packet, possibly EOF or keep-alive.");
                             continue; // Or break, depending on protocol for 0-len
                         }
-                        if packet_len > packet_buffer.capacity() { // Basic sanity check for length
-                             if packet_len > 10 * 1024 * 1024 { // e.g. 10MB limit
-                                eprintln!("TCP ReceiveLoop: Excessive packet length received: {}, closing connection.", packet_len);
-                                return Err(NetworkError::SerializationError("Excessive packet length".to_string()));
-                            }
+                        if !valid_frame_lengths.contains(&packet_len) {
+                            // No way to find the next frame boundary in a byte stream: give up on it
+                            record_framing_error(&metrics, format!(
+                                "Length prefix {:#010x} ({} bytes) outside {}..={}; byte order mismatch or corrupt stream",
+                                packet_len, packet_len, valid_frame_lengths.start(), valid_frame_lengths.end()
+                            ));
+                            take_final_sample();
+                            break;
+                        }
+                        if packet_len > packet_buffer.capacity() {
                             packet_buffer.reserve(packet_len); // Grow buffer if needed
                         }
                         // Ensure buffer is correctly sized for the read_exact operation
@@ -659,20 +2076,57 @@ async fn tcp_receive_loop(
 
 
                         // 2. Read packet data
-                        match reader.read_exact(&mut packet_buffer[..packet_len]).await {
+                        match hot_span!(future "recv", reader.read_exact(&mut packet_buffer[..packet_len])).await {
                             Ok(_) => {
-                                match CustomPacket::from_bytes(&packet_buffer[..packet_len]) {
-                                    Ok(_packet) => { // Prefixed with _ as it's not used beyond parsing
-                                        // TODO: Process packet (e.g., if it's an EchoRequest, need WriteHalf to reply)
-                                        // This loop currently only has ReadHalf. Echo replies would need more complex setup.
-                                        // For now, just record metrics.
-                                        let rtt_micros = 0; // Server-side receive, RTT measured by client.
-                                                          // If this is client receiving echo, then RTT is calculated here.
-                                        metrics.lock().unwrap().record_packet_received(packet_len + 4, rtt_micros);
+                                match hot_span!("parse", CustomPacket::from_bytes_with(&packet_buffer[..packet_len], config.bincode_fixint)) {
+                                    Ok(packet) if packet.header.packet_type == PacketType::EchoReply => {
+                                        // The peer answering this connection's send loop
+                                        let mut outstanding = lock_outstanding(&connection.outstanding_echoes);
+                                        credit_echo_reply(&packet, packet_len + 4, &mut outstanding, &config, &metrics, packet_tape.as_deref());
+                                        if outstanding.is_empty() {
+                                            connection.all_replied.notify_one();
+                                        }
+                                    }
+                                    Ok(packet) if !accept_at_data_receiver(&config, &metrics, &packet, "the TCP peer") => {}
+                                    Ok(packet) => {
+                                        let receive_timestamp_micros = crate::packet::unix_time_micros();
+                                        hot_span!("metrics_update", {
+                                            let mut metrics_guard = lock_metrics(&metrics);
+                                            metrics_guard.record_packet_received(packet_len + 4, 0); // + 4 byte length prefix; RTT is the sender's
+                                            metrics_guard.record_payload_received(packet.payload.len());
+                                            metrics_guard.record_transit(packet.header.timestamp_ms, receive_timestamp_micros);
+                                            if config.record_size_histogram {
+                                                metrics_guard.record_received_size(packet.payload.len());
+                                            }
+                                            if !packet.verify_integrity() {
+                                                record_corrupt_payload(&mut metrics_guard, &packet, test_start_time.elapsed().as_millis());
+                                            }
+                                        });
+
+                                        if let Some(tape) = &packet_tape {
+                                            tape.push(PacketEvent {
+                                                timestamp_ms: test_start_time.elapsed().as_millis(),
+                                                sequence_number: packet.header.sequence_number,
+                                                size_bytes: packet_len + 4,
+                                                rtt_micros: None,
+                                                packet_type: packet.header.packet_type,
+                                            });
+                                        }
+
+                                        if packet.header.packet_type == PacketType::EchoRequest {
+                                            let reply = CustomPacket::new_echo_reply(&packet, receive_timestamp_micros)
+                                                .to_bytes_with(config.bincode_fixint)?;
+                                            if reply_tx.try_send(reply).is_err() {
+                                                // The peer isn't reading its replies; never block reading on it
+                                                eprintln!("TCP ReceiveLoop: Echo reply queue full, dropping the reply to seq {}", packet.header.sequence_number);
+                                            }
+                                        }
                                     }
                                     Err(e) => {
-                                        eprintln!("TCP ReceiveLoop: Failed to parse CustomPacket: {:?}", e);
-                                        // Potentially log anomaly
+                                        // A plausible length but not a packet: the frames are out of step
+                                        record_framing_error(&metrics, format!("{}-byte frame is not a packet ({})", packet_len, e));
+                                        take_final_sample();
+                                        break;
                                     }
                                 }
                             }
@@ -688,6 +2142,7 @@ async fn tcp_receive_loop(
                     }
                     Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
                         println!("TCP ReceiveLoop: Connection closed by peer (EOF while reading length).");
+                        take_final_sample(); // How a count-limited test ends, with no deadline to sample at
                         break; // Connection closed
                     }
                     Err(e) => {
@@ -698,16 +2153,995 @@ async fn tcp_receive_loop(
             }
 
             _ = bandwidth_sampler.tick() => {
-                if let Ok(mut metrics_guard) = metrics.lock() {
-                    if let Some(start_time_instant) = metrics_guard.test_start_time {
-                        let current_test_time_ms = Instant::now().duration_since(start_time_instant).as_millis();
-                        metrics_guard.take_bandwidth_sample(current_test_time_ms);
-                    }
+                if connection.takes_bandwidth_samples {
+                    take_periodic_bandwidth_sample(&config, &metrics);
                 }
+                // Requests older than rtt_timeout can no longer count, as in udp_reply_loop
+                lock_outstanding(&connection.outstanding_echoes).retain(|_, send_time| send_time.elapsed() <= rtt_timeout);
             }
         }
     }
 
+    // Let the queued replies go out, unless the peer has stopped reading them
+    drop(reply_tx);
+    if tokio::time::timeout(rtt_timeout, &mut reply_writer).await.is_err() {
+        eprintln!("TCP ReceiveLoop: Echo replies still unsent after {:?}, giving up on them", rtt_timeout);
+        reply_writer.abort();
+    }
     println!("TCP ReceiveLoop: Finished.");
     Ok(())
 }
+
+// Echo replies a TCP receive loop can have waiting for the peer to read them
+const ECHO_REPLY_QUEUE: usize = 1024;
+
+/// Writes the echo replies queued on the returned sender to `connection`, in order. The
+/// receive loop only queues them, so a peer that stops reading can't stall it: once
+/// ECHO_REPLY_QUEUE replies are waiting, further ones are dropped. The task ends when the
+/// sender is dropped and the queue is written, or on a write error.
+fn spawn_echo_reply_writer(connection: Arc<TcpConnection>) -> (tokio::sync::mpsc::Sender<Vec<u8>>, tokio::task::JoinHandle<()>) {
+    let (reply_tx, mut reply_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(ECHO_REPLY_QUEUE);
+    let writer = tokio::spawn(async move {
+        while let Some(reply) = reply_rx.recv().await {
+            if let Err(e) = connection.write_frame(&reply).await {
+                // The peer may have closed its side already; the receive loop keeps reading what's left
+                eprintln!("TCP ReceiveLoop: Error sending echo reply: {}", e);
+                break;
+            }
+        }
+    });
+    (reply_tx, writer)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_auto_udp_payload_size() {
+        // Ethernet: 1500 - 20 (IPv4) - 8 (UDP) - 40 (fixint packet header)
+        assert_eq!(auto_udp_payload_size(1500, false, true), 1432);
+        assert_eq!(auto_udp_payload_size(1500, true, true), 1412);
+        // Loopback's 64 KiB MTU is capped at the largest possible UDP payload
+        assert_eq!(auto_udp_payload_size(65536, false, true), MAX_UDP_PAYLOAD - 40);
+    }
+
+    /// Runs a TCP receive loop against a peer that writes `frames` with little-endian length
+    /// prefixes (the receiver expects big-endian) and keeps the connection open.
+    async fn receive_little_endian_frames(payload_len: usize, frames: u32) -> TestMetrics {
+        let config = Arc::new(TestConfig { test_duration_secs: 30, ..Default::default() });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        let mut stream_bytes = Vec::new();
+        for seq in 0..frames {
+            let data = CustomPacket::new_data_packet(seq, payload_len).to_bytes_with(config.bincode_fixint).unwrap();
+            stream_bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            stream_bytes.extend_from_slice(&data);
+        }
+        use tokio::io::AsyncWriteExt;
+        peer.write_all(&stream_bytes).await.unwrap();
+
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        let (reader, connection) = TcpConnection::split(stream, &config).unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(2), tcp_receive_loop(config, reader, connection, Arc::clone(&metrics)))
+            .await
+            .expect("Receive loop stalled on a mis-framed stream");
+        assert!(result.is_ok(), "{:?}", result);
+        drop(peer);
+        Arc::try_unwrap(metrics).unwrap().into_inner().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tcp_mis_framed_stream_is_detected() {
+        // 132-byte frames: the swapped prefix reads as 0x84000000, far beyond the frame limit
+        let metrics = receive_little_endian_frames(92, 1).await;
+        assert_eq!(metrics.anomalies.len(), 1);
+        assert_eq!(metrics.anomalies[0].anomaly_type, crate::anomalies::AnomalyType::FramingError);
+        assert!(metrics.anomalies[0].description.contains("0x84000000"), "{}", metrics.anomalies[0].description);
+        assert_eq!(metrics.packets_received, 0);
+
+        // 256-byte frames: the swapped prefix reads as a plausible 65536, so the loop consumes
+        // 64 KiB of the stream as one frame; the next prefix then lands mid-frame
+        let metrics = receive_little_endian_frames(216, 300).await;
+        assert_eq!(metrics.anomalies.len(), 1);
+        assert_eq!(metrics.anomalies[0].anomaly_type, crate::anomalies::AnomalyType::FramingError);
+        assert!(metrics.packets_received <= 1);
+    }
+
+    /// Minimal UDP echo server on an ephemeral port, recording when each request arrived.
+    /// The reply to `corrupt_seq` gets one payload byte flipped.
+    async fn spawn_echo_server(corrupt_seq: Option<u32>) -> (SocketAddr, Arc<Mutex<Vec<Instant>>>, tokio::task::JoinHandle<()>) {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let arrivals = Arc::new(Mutex::new(Vec::new()));
+        let arrivals_clone = Arc::clone(&arrivals);
+        let handle = tokio::spawn(async move {
+            let mut buf = vec![0u8; MAX_UDP_DATAGRAM];
+            while let Ok((len, peer)) = server.recv_from(&mut buf).await {
+                arrivals_clone.lock().unwrap().push(Instant::now());
+                let request = CustomPacket::from_bytes(&buf[..len]).unwrap();
+                let mut reply = CustomPacket::new_echo_reply(&request, 0);
+                if Some(reply.header.sequence_number) == corrupt_seq {
+                    reply.payload[10] ^= 0xFF;
+                }
+                server.send_to(&reply.to_bytes().unwrap(), peer).await.unwrap();
+            }
+        });
+        (server_addr, arrivals, handle)
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_echo_reply_is_detected() {
+        let (server_addr, _arrivals, echo_server) = spawn_echo_server(Some(1)).await;
+
+        let config = Arc::new(TestConfig {
+            test_duration_secs: 1,
+            tick_rate_hz: 10,
+            packet_size_bytes: 64,
+            verify_echo_payload: true,
+            ..Default::default()
+        });
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        udp_send_loop(config, server_addr, Arc::clone(&metrics), true).await.unwrap();
+        echo_server.abort();
+
+        let metrics = metrics.lock().unwrap();
+        assert!(metrics.packets_received >= 5, "Only {} replies", metrics.packets_received);
+        let corrupted: Vec<_> = metrics.anomalies.iter()
+            .filter(|a| a.anomaly_type == crate::anomalies::AnomalyType::Corrupted)
+            .collect();
+        assert_eq!(corrupted.len(), 1, "{:?}", metrics.anomalies);
+        assert!(corrupted[0].description.contains("seq 1 "), "{}", corrupted[0].description);
+    }
+
+    #[tokio::test]
+    async fn test_control_channel_messages_are_not_data() {
+        let config = Arc::new(TestConfig { test_duration_secs: 30, ..Default::default() });
+        let data_socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let data_addr = data_socket.local_addr().unwrap();
+        let control_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let control_addr = control_socket.local_addr().unwrap();
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        let end_of_test = Arc::new(Notify::new());
+        let control_task = tokio::spawn(udp_control_loop(control_socket, config.bincode_fixint, Arc::clone(&metrics), Arc::clone(&end_of_test)));
+        let receiver = tokio::spawn(udp_receive_loop(Arc::clone(&config), data_socket, Arc::clone(&metrics), Some(end_of_test), true));
+
+        send_control_message(control_addr, ControlMessage::Start, &config, 0).await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for seq in 0..10 {
+            client.send_to(&CustomPacket::new_data_packet(seq, 64).to_bytes().unwrap(), data_addr).await.unwrap();
+        }
+        // A stray control packet on the data socket isn't data either
+        client.send_to(&CustomPacket::new_control(0, ControlMessage::Start).to_bytes().unwrap(), data_addr).await.unwrap();
+        send_control_message(control_addr, ControlMessage::End, &config, 0).await.unwrap();
+
+        // End stops the receiver long before its 30 s + grace lifetime
+        tokio::time::timeout(Duration::from_secs(3), receiver)
+            .await
+            .expect("The receive loop ignored the End message")
+            .unwrap()
+            .unwrap();
+        control_task.abort();
+        let metrics = metrics.lock().unwrap();
+        assert_eq!(metrics.packets_received, 10);
+        assert_eq!(metrics.control_messages_received, 1 + CONTROL_END_COPIES as u64);
+        assert_eq!(metrics.unexpected_type_packets, 1);
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_payload_is_an_anomaly() {
+        let config = Arc::new(TestConfig { test_duration_secs: 30, ..Default::default() });
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let server_addr = socket.local_addr().unwrap();
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        let receiver = tokio::spawn(udp_receive_loop(config, socket, Arc::clone(&metrics), None, true));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for seq in 0..4 {
+            let mut bytes = CustomPacket::new_data_packet(seq, 64).to_bytes().unwrap();
+            if seq == 2 {
+                let last = bytes.len() - 1;
+                bytes[last] ^= 0xff; // What a middlebox rewriting the payload would do
+            }
+            client.send_to(&bytes, server_addr).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        receiver.abort();
+
+        let metrics = metrics.lock().unwrap();
+        assert_eq!(metrics.packets_received, 4);
+        let corrupt: Vec<_> = metrics.anomalies.iter()
+            .filter(|a| a.anomaly_type == crate::anomalies::AnomalyType::CorruptPayload)
+            .collect();
+        assert_eq!(corrupt.len(), 1, "{:?}", metrics.anomalies);
+        assert!(corrupt[0].description.contains("seq 2"), "{}", corrupt[0].description);
+    }
+
+    #[tokio::test]
+    async fn test_bitrate_pacer_does_not_burst_after_a_stall() {
+        let mut pacer = BitratePacer::new(8_000); // 100-byte packets take 100 ms each
+        pacer.wait_to_send(100).await;
+        tokio::time::sleep(Duration::from_millis(500)).await; // Five slots missed
+        // One missed slot may still be used; the rest aren't sent back-to-back
+        let start = Instant::now();
+        for _ in 0..3 {
+            pacer.wait_to_send(100).await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(90), "{:?}", start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn test_packets_of_another_session_are_dropped() {
+        let config = Arc::new(TestConfig { test_duration_secs: 30, ..Default::default() });
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let server_addr = socket.local_addr().unwrap();
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        let receiver = tokio::spawn(udp_receive_loop(Arc::clone(&config), socket, Arc::clone(&metrics), None, true));
+
+        // The first packet's session is the peer's. A straggler from the middle of an earlier
+        // run comes after it; then the peer starts a new run, and a straggler of the run it
+        // left arrives.
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for (seq, session_id) in [(0, 0x1111), (1, 0x1111), (5000, 0x2222), (2, 0x1111), (0, 0x3333), (3, 0x1111), (1, 0x3333)] {
+            let packet = CustomPacket::new_data_packet(seq, 32).in_session(session_id);
+            client.send_to(&packet.to_bytes().unwrap(), server_addr).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        receiver.abort();
+        {
+            let metrics = metrics.lock().unwrap();
+            assert_eq!(metrics.packets_received, 5);
+            assert_eq!((metrics.peer_session_id, metrics.foreign_session_packets), (Some(0x3333), 2));
+            assert!(metrics.anomalies.iter().any(|a| a.anomaly_type == crate::anomalies::AnomalyType::ForeignSession));
+            // The new run's sequence numbers restarting at 0 are neither reordered nor duplicates
+            assert_eq!((metrics.out_of_order_count, metrics.duplicate_count), (0, 0));
+            assert_eq!(metrics.loss_breakdown().unwrap().lost, 0);
+        }
+
+        // A late packet of an earlier run, numbered past where the current run has got to,
+        // doesn't take over the session
+        let metrics = Mutex::new(TestMetrics::default());
+        metrics.lock().unwrap().init_start_time();
+        for (seq, session_id) in (0..6).map(|seq| (seq, 0xbbbb)).chain([(150, 0xaaaa), (6, 0xbbbb)]) {
+            if metrics.lock().unwrap().accept_peer_session(session_id, seq) {
+                metrics.lock().unwrap().record_sequence(seq, 0);
+            }
+        }
+        {
+            let metrics = metrics.lock().unwrap();
+            assert_eq!((metrics.peer_session_id, metrics.foreign_session_packets), (Some(0xbbbb), 1));
+            assert_eq!(metrics.loss_breakdown().unwrap().lost, 0);
+        }
+
+        // A client only credits replies to its own session's requests
+        let client_metrics = Mutex::new(TestMetrics::default());
+        client_metrics.lock().unwrap().start_session();
+        let session_id = client_metrics.lock().unwrap().session_id;
+        let request = CustomPacket::new_echo_request(0, 32);
+        let mut outstanding = HashMap::from([(0, Instant::now())]);
+        let stale_reply = CustomPacket::new_echo_reply(&request.clone().in_session(session_id.wrapping_add(1).max(1)), 0);
+        credit_echo_reply(&stale_reply, 80, &mut outstanding, &config, &client_metrics, None);
+        assert_eq!((client_metrics.lock().unwrap().packets_received, outstanding.len()), (0, 1));
+        let reply = CustomPacket::new_echo_reply(&request.in_session(session_id), 0);
+        credit_echo_reply(&reply, 80, &mut outstanding, &config, &client_metrics, None);
+        assert_eq!((client_metrics.lock().unwrap().packets_received, outstanding.len()), (1, 0));
+        assert_eq!(client_metrics.lock().unwrap().foreign_session_packets, 1);
+    }
+
+    #[tokio::test]
+    async fn test_ack_at_data_receiver_is_unexpected_not_data() {
+        async fn receive_data_and_ack(handling: UnexpectedPacketHandling) -> (u64, u64) {
+            let config = Arc::new(TestConfig { test_duration_secs: 30, unexpected_packet_handling: handling, ..Default::default() });
+            let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+            let server_addr = socket.local_addr().unwrap();
+            let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+            metrics.lock().unwrap().init_start_time();
+            let receiver = tokio::spawn(udp_receive_loop(config, socket, Arc::clone(&metrics), None, true));
+
+            let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            for seq in 0..5 {
+                client.send_to(&CustomPacket::new_data_packet(seq, 32).to_bytes().unwrap(), server_addr).await.unwrap();
+            }
+            let ack = CustomPacket::new(crate::packet::PacketHeader::new(5, PacketType::Ack), Vec::new());
+            client.send_to(&ack.to_bytes().unwrap(), server_addr).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            receiver.abort();
+
+            let metrics = metrics.lock().unwrap();
+            (metrics.packets_received, metrics.unexpected_type_packets)
+        }
+
+        assert_eq!(receive_data_and_ack(UnexpectedPacketHandling::Drop).await, (5, 1));
+        assert_eq!(receive_data_and_ack(UnexpectedPacketHandling::Accept).await, (6, 1));
+    }
+
+    #[tokio::test]
+    async fn test_interarrival_histogram_reflects_gaps() {
+        let config = Arc::new(TestConfig { test_duration_secs: 30, record_interarrival: true, ..Default::default() });
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let server_addr = socket.local_addr().unwrap();
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        let receiver = tokio::spawn(udp_receive_loop(config, socket, Arc::clone(&metrics), None, true));
+
+        // Short gaps and one of 300 ms. Sleeps only ever run long, so only the long gap's
+        // bucket is certain; the bucketing itself is tested in metrics.
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for (seq, gap_ms) in [0, 1, 1, 300, 1].into_iter().enumerate() {
+            tokio::time::sleep(Duration::from_millis(gap_ms)).await;
+            client.send_to(&CustomPacket::new_data_packet(seq as u32, 32).to_bytes().unwrap(), server_addr).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        receiver.abort();
+
+        let metrics = metrics.lock().unwrap();
+        let histogram = metrics.interarrival_histogram();
+        assert_eq!(histogram.iter().map(|b| b.count).sum::<u64>(), 4, "{:?}", histogram); // No gap before the first
+        let long_gaps: u64 = histogram.iter().filter(|b| b.min_micros >= 200_000).map(|b| b.count).sum();
+        assert!(long_gaps >= 1, "{:?}", histogram);
+    }
+
+    #[tokio::test]
+    async fn test_recv_buffer_pool_avoids_per_datagram_allocations() {
+        const DATAGRAMS: u32 = 20_000;
+        async fn allocations(recv_buffer_pool: usize) -> u64 {
+            let config = Arc::new(TestConfig { parse_workers: 2, recv_buffer_pool, ..Default::default() });
+            let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+            let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+            metrics.lock().unwrap().init_start_time();
+            let mut pool = ParsePool::start(&config, &socket, &metrics, None);
+            let arrival = DatagramArrival { src_addr: socket.local_addr().unwrap(), arrival_ms: 0, receive_timestamp_micros: 0, gap_micros: None };
+            for seq in 0..DATAGRAMS {
+                pool.submit(&CustomPacket::new_data_packet(seq, 32).to_bytes().unwrap(), arrival).await;
+            }
+            pool.finish(&metrics).await;
+            let metrics = metrics.lock().unwrap();
+            assert_eq!(metrics.packets_received, u64::from(DATAGRAMS));
+            metrics.recv_buffer_allocations
+        }
+
+        assert_eq!(allocations(0).await, u64::from(DATAGRAMS), "Without a pool every datagram gets its own buffer");
+        // Room for every buffer that can be in flight: the queue, a batch per worker and the one
+        // being filled. However far the workers fall behind, nothing is allocated past these.
+        let covering = PARSE_QUEUE_CAPACITY + 2 * PARSE_BATCH + 1;
+        assert_eq!(allocations(covering).await, covering as u64);
+    }
+
+    #[tokio::test]
+    async fn test_received_size_histogram_matches_sent_sizes() {
+        let config = Arc::new(TestConfig { test_duration_secs: 30, record_size_histogram: true, ..Default::default() });
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let server_addr = socket.local_addr().unwrap();
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        let receiver = tokio::spawn(udp_receive_loop(config, socket, Arc::clone(&metrics), None, true));
+
+        // 25 x 100 B, 10 x 500 B and 5 x 1400 B payloads, interleaved
+        let sizes = [100, 100, 500, 100, 100, 1400, 100, 500];
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for seq in 0..(5 * sizes.len()) {
+            let packet = CustomPacket::new_data_packet(seq as u32, sizes[seq % sizes.len()]);
+            client.send_to(&packet.to_bytes().unwrap(), server_addr).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        receiver.abort();
+
+        // Sizes of the parsed payloads, so every datagram lands in the bucket it was sent in
+        let metrics = metrics.lock().unwrap();
+        let histogram: Vec<_> = metrics.received_size_histogram().iter().map(|b| (b.label(), b.count)).collect();
+        assert_eq!(histogram, vec![("64-127 B".to_string(), 25), ("448-511 B".to_string(), 10), ("1344-1407 B".to_string(), 5)]);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_sequence_is_one_duplicate() {
+        let config = Arc::new(TestConfig { test_duration_secs: 30, ..Default::default() });
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let server_addr = socket.local_addr().unwrap();
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        let receiver = tokio::spawn(udp_receive_loop(config, socket, Arc::clone(&metrics), None, true));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for seq in [0, 1, 2, 2, 3] {
+            client.send_to(&CustomPacket::new_data_packet(seq, 32).to_bytes().unwrap(), server_addr).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        receiver.abort();
+
+        let metrics = metrics.lock().unwrap();
+        assert_eq!(metrics.packets_received, 5);
+        assert_eq!(metrics.duplicate_count, 1);
+        let duplicates: Vec<_> = metrics.anomalies.iter()
+            .filter(|a| a.anomaly_type == crate::anomalies::AnomalyType::DuplicatePacket)
+            .collect();
+        assert_eq!(duplicates.len(), 1, "{:?}", metrics.anomalies);
+        assert!(duplicates[0].description.contains("Seq: 2 "), "{}", duplicates[0].description);
+        assert_eq!(metrics.loss_breakdown().unwrap().lost, 0);
+    }
+
+    #[tokio::test]
+    async fn test_send_loop_survives_poisoned_metrics() {
+        let (server_addr, _arrivals, echo_server) = spawn_echo_server(None).await;
+        let config = Arc::new(TestConfig {
+            test_duration_secs: 1,
+            tick_rate_hz: 20,
+            packet_size_bytes: 64,
+            ..Default::default()
+        });
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        let poisoner = Arc::clone(&metrics);
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("a record_* call blew up holding the metrics lock");
+        })
+        .join();
+        assert!(metrics.is_poisoned());
+
+        let result = udp_send_loop(config, server_addr, Arc::clone(&metrics), true).await;
+        echo_server.abort();
+        assert!(result.is_ok(), "{:?}", result);
+        let metrics = lock_metrics(&metrics);
+        assert!(metrics.packets_sent >= 15, "Only {} packets sent", metrics.packets_sent);
+        assert!(metrics.packets_received > 0);
+    }
+
+    #[tokio::test]
+    async fn test_zero_duration_is_rejected_unless_count_limited() {
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        let zero = Arc::new(TestConfig { test_duration_secs: 0, ..Default::default() });
+        let result = run_network_test(zero, Arc::clone(&metrics)).await;
+        assert!(matches!(result, Err(NetworkError::InvalidConfig(ConfigError::ZeroDuration))), "{:?}", result);
+
+        // With a packet count, a zero duration sends exactly that many and stops
+        let (server_addr, arrivals, echo_server) = spawn_echo_server(None).await;
+        let counted = Arc::new(TestConfig {
+            test_duration_secs: 0,
+            stop_after_packets: Some(5),
+            tick_rate_hz: 100,
+            packet_size_bytes: 64,
+            ..Default::default()
+        });
+        metrics.lock().unwrap().init_start_time();
+        udp_send_loop(counted, server_addr, Arc::clone(&metrics), true).await.unwrap();
+        echo_server.abort();
+        assert_eq!(metrics.lock().unwrap().packets_sent, 5);
+        assert_eq!(arrivals.lock().unwrap().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_prewarm_packets_are_not_counted() {
+        let (server_addr, arrivals, echo_server) = spawn_echo_server(None).await;
+        let config = Arc::new(TestConfig {
+            test_duration_secs: 0,
+            stop_after_packets: Some(5),
+            prewarm_packets: 10,
+            tick_rate_hz: 100,
+            packet_size_bytes: 64,
+            ..Default::default()
+        });
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        udp_send_loop(Arc::clone(&config), server_addr, Arc::clone(&metrics), true).await.unwrap();
+        echo_server.abort();
+        assert_eq!(arrivals.lock().unwrap().len(), 15); // They did go out
+        assert_eq!(metrics.lock().unwrap().packets_sent, 5);
+
+        // And a receiver doesn't count them either
+        let receiver_metrics = Mutex::new(TestMetrics::default());
+        assert!(!accept_at_data_receiver(&config, &receiver_metrics, &CustomPacket::new_prewarm(64), "the test"));
+        assert_eq!(receiver_metrics.lock().unwrap().unexpected_type_packets, 0);
+    }
+
+    #[tokio::test]
+    async fn test_hello_is_resent_for_a_peer_that_listens_late() {
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        let config = Arc::new(TestConfig {
+            test_mode: TestMode::Bidirectional,
+            test_duration_ms: Some(2_500),
+            tick_rate_hz: 20,
+            rtt_timeout_ms: 100,
+            ..Default::default()
+        });
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        let started = Instant::now();
+        let sender = tokio::spawn(udp_send_loop(config, peer_addr, metrics, true));
+
+        let mut hello_times = Vec::new();
+        let mut buf = vec![0u8; MAX_UDP_DATAGRAM];
+        while let Ok(Ok((len, _))) = tokio::time::timeout(Duration::from_millis(500), peer.recv_from(&mut buf)).await {
+            if CustomPacket::from_bytes(&buf[..len]).unwrap().hello_port().is_some() {
+                hello_times.push(started.elapsed());
+            }
+        }
+        sender.await.unwrap().unwrap();
+
+        // The start-up copies, then one a second for a peer that missed them
+        assert_eq!(hello_times.iter().filter(|&&t| t < HELLO_INTERVAL / 2).count(), HELLO_COPIES as usize, "{:?}", hello_times);
+        let late = hello_times.iter().filter(|&&t| t >= HELLO_INTERVAL).count();
+        assert!((1..=3).contains(&late), "{:?}", hello_times);
+    }
+
+    #[tokio::test]
+    async fn test_remapped_source_port_is_reported_as_nat() {
+        let config = TestConfig { test_mode: TestMode::Bidirectional, ..Default::default() };
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let hello = CustomPacket::new_hello(40000).to_bytes_with(config.bincode_fixint).unwrap();
+        let arrival_from = |port: u16| DatagramArrival {
+            src_addr: SocketAddr::from(([127, 0, 0, 1], port)),
+            arrival_ms: 5,
+            receive_timestamp_micros: 0,
+            gap_micros: None,
+        };
+
+        // Sent from 40000, arrived from 61000: a NAT in between
+        let metrics = Mutex::new(TestMetrics::default());
+        for _ in 0..HELLO_COPIES {
+            process_udp_datagram(&config, &socket, &metrics, None, &hello, arrival_from(61000)).await;
+        }
+        let metrics = metrics.into_inner().unwrap();
+        assert_eq!(metrics.nat_mapped_port, Some(61000));
+        let nat_notes: Vec<_> = metrics.anomalies.iter()
+            .filter(|a| a.anomaly_type == crate::anomalies::AnomalyType::NatDetected)
+            .collect();
+        assert_eq!(nat_notes.len(), 1, "One note per test, not per Hello: {:?}", metrics.anomalies);
+        assert!(nat_notes[0].description.contains("61000"), "{}", nat_notes[0].description);
+        assert_eq!(metrics.packets_received, 0); // A Hello isn't data
+
+        // Arriving from the advertised port: no NAT
+        let metrics = Mutex::new(TestMetrics::default());
+        process_udp_datagram(&config, &socket, &metrics, None, &hello, arrival_from(40000)).await;
+        let metrics = metrics.into_inner().unwrap();
+        assert_eq!(metrics.nat_mapped_port, None);
+        assert!(metrics.anomalies.is_empty(), "{:?}", metrics.anomalies);
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_echoes_do_not_throttle_sending() {
+        // Echoes only even sequence numbers: with replies read inline, every odd one would
+        // stall the send loop for a whole rtt_timeout
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let echo_server = tokio::spawn(async move {
+            let mut buf = vec![0u8; MAX_UDP_DATAGRAM];
+            while let Ok((len, peer)) = server.recv_from(&mut buf).await {
+                let request = CustomPacket::from_bytes(&buf[..len]).unwrap();
+                if request.header.sequence_number.is_multiple_of(2) {
+                    let reply = CustomPacket::new_echo_reply(&request, 0);
+                    server.send_to(&reply.to_bytes().unwrap(), peer).await.unwrap();
+                }
+            }
+        });
+
+        let config = Arc::new(TestConfig {
+            test_duration_secs: 1,
+            tick_rate_hz: 200,
+            packet_size_bytes: 64,
+            ..Default::default()
+        });
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        udp_send_loop(config, server_addr, Arc::clone(&metrics), true).await.unwrap();
+        echo_server.abort();
+
+        let metrics = metrics.lock().unwrap();
+        assert!(metrics.packets_sent >= 180, "Only {} packets sent", metrics.packets_sent);
+        let expected_replies = metrics.packets_sent.div_ceil(2);
+        assert!(metrics.packets_received + 5 >= expected_replies, "{} of {} replies", metrics.packets_received, expected_replies);
+        assert!(metrics.packets_received <= expected_replies);
+        assert_eq!(metrics.rtt_count, metrics.packets_received);
+    }
+
+    #[test]
+    fn test_send_jitter_stays_within_jitter_of_the_tick() {
+        let jitter = Duration::from_millis(8);
+        let mut rng = send_rng(&TestConfig { send_rng_seed: Some(7), ..Default::default() });
+        // Offsets from the nominal tick, which the ticker fires `jitter` ahead of
+        let offsets_ms: Vec<f64> = (0..10_000)
+            .map(|_| (send_jitter_delay(8, &mut rng).as_secs_f64() - jitter.as_secs_f64()) * 1000.0)
+            .collect();
+        assert!(offsets_ms.iter().all(|offset| offset.abs() <= 8.0), "An offset is beyond ±8 ms");
+        let spread = offsets_ms.iter().cloned().fold(f64::MIN, f64::max) - offsets_ms.iter().cloned().fold(f64::MAX, f64::min);
+        assert!(spread > 15.0, "Offsets barely vary ({:.2} ms spread)", spread);
+        // Centred: uniform ±8 ms over 10000 sends gives a standard error under 0.05 ms
+        let mean_offset_ms = offsets_ms.iter().sum::<f64>() / offsets_ms.len() as f64;
+        assert!(mean_offset_ms.abs() < 0.5, "Sends are {:.2} ms off their ticks on average", mean_offset_ms);
+
+        let config = TestConfig { send_jitter_ms: Some(8), ..Default::default() };
+        assert_eq!(first_tick_delay(&config, Duration::from_millis(20)), Duration::from_millis(12));
+    }
+
+    #[tokio::test]
+    #[ignore = "wall-clock timing"]
+    async fn test_send_jitter_varies_gaps_but_keeps_rate() {
+        let (server_addr, arrivals, echo_server) = spawn_echo_server(None).await;
+        let config = Arc::new(TestConfig {
+            test_duration_secs: 1,
+            tick_rate_hz: 50, // 20 ms ticks
+            packet_size_bytes: 64,
+            send_jitter_ms: Some(8),
+            send_rng_seed: Some(7),
+            ..Default::default()
+        });
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        let start = Instant::now();
+        udp_send_loop(config, server_addr, Arc::clone(&metrics), true).await.unwrap();
+        echo_server.abort();
+
+        let arrivals = arrivals.lock().unwrap();
+        let gaps_ms: Vec<f64> = arrivals.windows(2)
+            .map(|pair| pair[1].duration_since(pair[0]).as_secs_f64() * 1000.0)
+            .collect();
+        assert!((45..=51).contains(&arrivals.len()), "{} sends in 1s at 50 Hz", arrivals.len());
+        let mean_gap = gaps_ms.iter().sum::<f64>() / gaps_ms.len() as f64;
+        assert!((mean_gap - 20.0).abs() < 2.0, "Mean gap {:.2} ms", mean_gap);
+        let spread = gaps_ms.iter().cloned().fold(f64::MIN, f64::max) - gaps_ms.iter().cloned().fold(f64::MAX, f64::min);
+        assert!(spread > 8.0, "Gaps barely vary ({:.2} ms spread)", spread);
+        // Centred: on average a send is on its nominal tick (the nth at n * 20 ms), not late.
+        // Uniform ±8 ms over ~50 sends gives a standard error under 1 ms.
+        let mean_offset_ms = arrivals.iter().enumerate()
+            .map(|(n, arrival)| arrival.duration_since(start).as_secs_f64() * 1000.0 - (n + 1) as f64 * 20.0)
+            .sum::<f64>() / arrivals.len() as f64;
+        assert!(mean_offset_ms.abs() < 3.0, "Sends are {:.2} ms off their ticks on average", mean_offset_ms);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_interrupts_the_send_jitter_delay() {
+        let (server_addr, _arrivals, echo_server) = spawn_echo_server(None).await;
+        // The first tick fires at once and is followed by a jitter delay of up to two minutes
+        let config = Arc::new(TestConfig { test_duration_secs: 300, tick_rate_hz: 1, send_jitter_ms: Some(60_000), send_rng_seed: Some(7), ..Default::default() });
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        let control = Arc::clone(&metrics.lock().unwrap().control);
+        let sender = tokio::spawn(udp_send_loop(config, server_addr, Arc::clone(&metrics), true));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        control.cancel();
+        tokio::time::timeout(Duration::from_secs(3), sender).await
+            .expect("The send loop didn't end on cancel during its jitter delay").unwrap().unwrap();
+        echo_server.abort();
+    }
+
+    #[test]
+    fn test_send_rng_seed_replays_the_jitter() {
+        let delays = |seed: Option<u64>| {
+            let mut rng = send_rng(&TestConfig { send_rng_seed: seed, ..Default::default() });
+            (0..20).map(|_| send_jitter_delay(8, &mut rng)).collect::<Vec<_>>()
+        };
+        assert_eq!(delays(Some(7)), delays(Some(7)));
+        assert_ne!(delays(Some(7)), delays(Some(8)));
+        assert_ne!(delays(None), delays(None));
+    }
+
+    #[test]
+    fn test_link_local_target_keeps_scope_id() {
+        let scope_id_of = |target_ip: &str| match target_socket_addr(target_ip, 5201) {
+            Ok(SocketAddr::V6(addr)) => {
+                assert_eq!(*addr.ip(), "fe80::1".parse::<Ipv6Addr>().unwrap());
+                assert_eq!(addr.port(), 5201);
+                addr.scope_id()
+            }
+            other => panic!("expected an IPv6 address for {}, got {:?}", target_ip, other),
+        };
+        assert_eq!(scope_id_of("fe80::1%3"), 3);
+        assert_eq!(scope_id_of("[fe80::1%3]"), 3);
+        assert_eq!(scope_id_of("fe80::1"), 0);
+        #[cfg(target_os = "linux")]
+        assert_eq!(scope_id_of("fe80::1%lo"), interface_index("lo").unwrap());
+
+        assert!(matches!(target_socket_addr("fe80::1%no-such-iface0", 5201), Err(NetworkError::InvalidAddress(_))));
+        assert!(matches!(target_socket_addr("10.0.0.1%eth0", 5201), Err(NetworkError::InvalidAddress(_))));
+        assert_eq!(target_socket_addr("10.0.0.1", 5201).unwrap(), "10.0.0.1:5201".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_dest_port_range_must_be_udp() {
+        let config = |protocol, dest_port_range| Arc::new(TestConfig { protocol, dest_port_range, ..Default::default() });
+        let metrics = || Arc::new(Mutex::new(TestMetrics::default()));
+        assert!(matches!(
+            run_network_test(config(Protocol::Tcp, Some((7000, 7003))), metrics()).await,
+            Err(NetworkError::UnsupportedMode(_))
+        ));
+        assert_eq!(dest_port_range(&config(Protocol::Udp, Some((7000, 7000)))), Some(7000..=7000));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_target_literals_and_hostnames() {
+        assert_eq!(resolve_target("10.0.0.1", 5201).await.unwrap(), vec!["10.0.0.1:5201".parse::<SocketAddr>().unwrap()]);
+        // A malformed literal is reported as such, not looked up as a hostname
+        assert!(matches!(resolve_target("fe80::1%no-such-iface0", 5201).await, Err(NetworkError::InvalidAddress(e)) if e.contains("unknown interface")));
+
+        let localhost = resolve_target("localhost", 5201).await.expect("localhost should resolve");
+        assert!(!localhost.is_empty());
+        assert!(localhost.iter().all(|addr| addr.ip().is_loopback() && addr.port() == 5201), "{:?}", localhost);
+        assert!(localhost.iter().enumerate().all(|(i, addr)| !localhost[..i].contains(addr)), "Duplicates in {:?}", localhost);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_df_oversized_send_is_packet_too_large() {
+        // Needs a route off the host: loopback's 64 KiB MTU fits any UDP datagram
+        let remote_addr: SocketAddr = "192.0.2.1:9".parse().unwrap(); // TEST-NET-1
+        let probe = UdpSocket::bind("0.0.0.0:0").await.unwrap();
+        if probe.connect(remote_addr).await.is_err() {
+            eprintln!("No route off this host, skipping");
+            return;
+        }
+        let mtu = path_mtu(&probe, false).unwrap_or(FALLBACK_PATH_MTU);
+
+        let config = Arc::new(TestConfig {
+            test_duration_secs: 1,
+            packet_size_bytes: mtu + 100,
+            set_df: Some(true),
+            ..Default::default()
+        });
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        match udp_send_loop(config, remote_addr, Arc::clone(&metrics), true).await {
+            Err(NetworkError::PacketTooLarge { size, mtu: Some(reported_mtu) }) => {
+                assert_eq!(reported_mtu, mtu);
+                assert!(size > mtu, "{} byte datagram fits the {} byte MTU", size, mtu);
+            }
+            other => panic!("Expected PacketTooLarge, got {:?}", other),
+        }
+        assert_eq!(metrics.lock().unwrap().packets_sent, 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_dscp_marks_sockets_and_sends_succeed() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        set_dscp(&socket, false, 46).unwrap(); // EF
+        assert_eq!(socket2::SockRef::from(&socket).tos().unwrap(), 46 << 2);
+
+        let (server_addr, arrivals, echo_server) = spawn_echo_server(None).await;
+        let config = Arc::new(TestConfig {
+            test_duration_secs: 0,
+            stop_after_packets: Some(5),
+            tick_rate_hz: 100,
+            packet_size_bytes: 64,
+            dscp: Some(46),
+            ..Default::default()
+        });
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        udp_send_loop(Arc::clone(&config), server_addr, Arc::clone(&metrics), true).await.unwrap();
+        echo_server.abort();
+        assert_eq!(metrics.lock().unwrap().packets_sent, 5);
+        assert_eq!(arrivals.lock().unwrap().len(), 5);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (_reader, connection) = TcpConnection::split(stream, &config).unwrap();
+        connection.write_frame(b"marked").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_peer_not_reading_echo_replies_does_not_stall_receiving() {
+        let config = Arc::new(TestConfig { test_duration_secs: 30, rtt_timeout_ms: 200, ..Default::default() });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, connection) = TcpConnection::split(stream, &config).unwrap();
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        let control = Arc::clone(&metrics.lock().unwrap().control);
+        let receiver = tokio::spawn(tcp_receive_loop(config, reader, connection, Arc::clone(&metrics)));
+
+        // 16 MiB of requests, whose echoed payloads overflow the socket buffers: the peer never
+        // reads a reply, yet every request still gets read
+        use tokio::io::AsyncWriteExt;
+        let frames: u32 = 256;
+        for seq in 0..frames {
+            let request = CustomPacket::new_echo_request(seq, 64 * 1024).to_bytes().unwrap();
+            let frame = [&(request.len() as u32).to_be_bytes()[..], &request].concat();
+            tokio::time::timeout(Duration::from_secs(10), peer.write_all(&frame)).await
+                .expect("The receive loop stopped reading while its replies were stuck").unwrap();
+        }
+        while metrics.lock().unwrap().packets_received < frames as u64 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        control.cancel();
+        tokio::time::timeout(Duration::from_secs(3), receiver).await
+            .expect("The receive loop didn't end on cancel").unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_parallel_streams_split_the_send_rate_and_counts() {
+        let config = TestConfig {
+            parallel_streams: 4,
+            tick_rate_hz: 10,
+            stop_after_packets: Some(10),
+            prewarm_packets: 5,
+            target_bitrate_bps: Some(1_000_002),
+            ..Default::default()
+        };
+        let shares: Vec<_> = (0..4).map(|index| parallel_stream_config(&config, index)).collect();
+        let sum = |field: fn(&TestConfig) -> u64| shares.iter().map(|c| field(c)).sum::<u64>();
+        assert_eq!(shares.iter().map(|c| c.tick_rate_hz).collect::<Vec<_>>(), [3, 3, 2, 2]);
+        assert_eq!(sum(|c| c.stop_after_packets.unwrap()), 10);
+        assert_eq!(sum(|c| c.prewarm_packets.into()), 5);
+        assert_eq!(sum(|c| c.target_bitrate_bps.unwrap()), 1_000_002);
+
+        // Never below 1 Hz, and AFAP stays AFAP
+        let slow = TestConfig { parallel_streams: 4, tick_rate_hz: 2, ..Default::default() };
+        assert!((0..4).all(|index| parallel_stream_config(&slow, index).tick_rate_hz >= 1));
+        let afap = TestConfig { parallel_streams: 4, tick_rate_hz: 0, ..Default::default() };
+        assert_eq!(parallel_stream_config(&afap, 0).tick_rate_hz, 0);
+    }
+
+    #[tokio::test]
+    async fn test_binding_a_port_in_use_is_address_in_use() {
+        let config = TestConfig::default();
+        let listener = tcp_listen("127.0.0.1:0".parse().unwrap(), &config).unwrap();
+        let tcp_addr = listener.local_addr().unwrap();
+        match tcp_listen(tcp_addr, &config) {
+            Err(e @ NetworkError::AddressInUse(addr)) => {
+                assert_eq!(addr, tcp_addr);
+                assert!(e.is_transient());
+                assert!(e.to_string().contains(&tcp_addr.to_string()), "{}", e);
+            }
+            other => panic!("Expected AddressInUse, got {:?}", other.map(|_| ())),
+        }
+
+        let socket = bind_udp_listener(&config, "127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let udp_addr = socket.local_addr().unwrap();
+        assert!(matches!(bind_udp_listener(&config, udp_addr).await, Err(NetworkError::AddressInUse(addr)) if addr == udp_addr));
+
+        // Both sockets opting in may share the port (BSDs only allow it for multicast)
+        #[cfg(target_os = "linux")]
+        {
+            let reusing = TestConfig { reuse_addr: true, ..Default::default() };
+            let first = bind_udp_listener(&reusing, "127.0.0.1:0".parse().unwrap()).await.unwrap();
+            let shared_addr = first.local_addr().unwrap();
+            assert!(bind_udp_listener(&reusing, shared_addr).await.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recv_buffer_size_is_granted() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let default_size = socket2::SockRef::from(&socket).recv_buffer_size().unwrap();
+        let requested = (default_size * 2).max(1024 * 1024);
+        let granted = set_recv_buffer_size(&socket, requested).unwrap();
+        // Linux caps the request at net.core.rmem_max (the doubling isn't counted)
+        #[cfg(target_os = "linux")]
+        let expected = std::fs::read_to_string("/proc/sys/net/core/rmem_max")
+            .ok()
+            .and_then(|max| max.trim().parse::<usize>().ok())
+            .map_or(requested, |max| requested.min(max));
+        #[cfg(not(target_os = "linux"))]
+        let expected = requested;
+        assert!(granted >= expected, "Asked for {} bytes, got {} (expected at least {})", requested, granted, expected);
+        assert!(granted <= requested, "Asked for {} bytes, reported {}: not the data size", requested, granted);
+
+        // Applied by the receive loop before it reads anything
+        let config = Arc::new(TestConfig { test_duration_secs: 30, recv_buffer_bytes: Some(requested), ..Default::default() });
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+        let receiver = tokio::spawn(udp_receive_loop(config, Arc::clone(&socket), metrics, None, true));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        receiver.abort();
+        let reported = socket2::SockRef::from(socket.as_ref()).recv_buffer_size().unwrap();
+        assert_eq!(if cfg!(target_os = "linux") { reported / 2 } else { reported }, granted);
+    }
+
+    #[tokio::test]
+    async fn test_bidirectional_tick_rates_apply_per_direction() {
+        // One TCP stream, as in single-stream bidi: the initiator's primary loop sends at tx,
+        // the listener's secondary loop answers at rx
+        let config = Arc::new(TestConfig {
+            test_mode: TestMode::Bidirectional,
+            test_duration_secs: 2,
+            tick_rate_hz: 20,
+            tx_tick_rate_hz: Some(50),
+            rx_tick_rate_hz: Some(10),
+            packet_size_bytes: 64,
+            ..Default::default()
+        });
+        assert_eq!(with_direction_tick_rate(Arc::clone(&config), true).tick_rate_hz, 50);
+        assert_eq!(with_direction_tick_rate(Arc::clone(&config), false).tick_rate_hz, 10);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let initiator_stream = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (listener_stream, _) = listener.accept().await.unwrap();
+
+        let new_metrics = || {
+            let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+            metrics.lock().unwrap().init_start_time();
+            metrics
+        };
+        let (initiator_metrics, listener_metrics) = (new_metrics(), new_metrics());
+        let (initiator_reader, initiator_connection) = TcpConnection::split(initiator_stream, &config).unwrap();
+        let (listener_reader, listener_connection) = TcpConnection::split(listener_stream, &config).unwrap();
+        let (tx, rx, initiator_recv, listener_recv) = tokio::join!(
+            tcp_send_loop(Arc::clone(&config), Arc::clone(&initiator_connection), Arc::clone(&initiator_metrics), true),
+            tcp_send_loop(Arc::clone(&config), Arc::clone(&listener_connection), Arc::clone(&listener_metrics), false),
+            tcp_receive_loop(Arc::clone(&config), initiator_reader, initiator_connection, Arc::clone(&initiator_metrics)),
+            tcp_receive_loop(Arc::clone(&config), listener_reader, listener_connection, Arc::clone(&listener_metrics)),
+        );
+        for result in [tx, rx, initiator_recv, listener_recv] {
+            assert!(result.is_ok(), "{:?}", result);
+        }
+
+        // 2s at 50 Hz upstream, at 10 Hz downstream. The initiator also receives an echo reply
+        // (with an RTT) for each of its packets, which isn't downstream data. A slow host can
+        // delay ticks but not add any, so only the upper bounds are tight.
+        let upstream = listener_metrics.lock().unwrap().packets_received;
+        let downstream = {
+            let initiator = initiator_metrics.lock().unwrap();
+            initiator.packets_received - initiator.rtt_count
+        };
+        assert!(upstream <= 101 && (1..=21).contains(&downstream), "{} packets upstream, {} downstream", upstream, downstream);
+        assert!(upstream >= 3 * downstream, "{} packets upstream vs {} downstream, expected about 5x", upstream, downstream);
+        assert_eq!(upstream, initiator_metrics.lock().unwrap().packets_sent);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_recovers_from_would_block() {
+        // Simulates a saturated socket: the first 3 sends report WouldBlock, then the buffer drains.
+        let attempts = Cell::new(0u32);
+        let retries = send_with_retry(
+            || {
+                attempts.set(attempts.get() + 1);
+                let result = if attempts.get() <= 3 {
+                    Err(io::Error::new(io::ErrorKind::WouldBlock, "socket buffer full"))
+                } else {
+                    Ok(64)
+                };
+                std::future::ready(result)
+            },
+            5,
+            Duration::from_millis(1),
+        ).await;
+
+        assert_eq!(retries.unwrap(), 3);
+        assert_eq!(attempts.get(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_gives_up_after_limit() {
+        let attempts = Cell::new(0u32);
+        let result = send_with_retry(
+            || {
+                attempts.set(attempts.get() + 1);
+                std::future::ready(Err::<usize, _>(io::Error::new(io::ErrorKind::WouldBlock, "socket buffer full")))
+            },
+            2,
+            Duration::from_millis(1),
+        ).await;
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(attempts.get(), 3); // First try + 2 retries
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_does_not_retry_hard_errors() {
+        let attempts = Cell::new(0u32);
+        let result = send_with_retry(
+            || {
+                attempts.set(attempts.get() + 1);
+                std::future::ready(Err::<usize, _>(io::Error::new(io::ErrorKind::PermissionDenied, "nope")))
+            },
+            5,
+            Duration::from_millis(1),
+        ).await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+}
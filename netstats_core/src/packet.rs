@@ -16,15 +16,60 @@ pub enum PacketType {
     EchoReply,    // Reply to an EchoRequest
 }
 
+/// What test packet payloads are filled with (see `TestConfig::payload_pattern`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PayloadPattern {
+    Zeros,       // Every byte 0
+    Random,      // Fresh random bytes for every packet
+    Incrementing, // 0, 1, 2, ... wrapping after 255
+    Fixed(u8),   // Every byte the given value
+}
+
+impl PayloadPattern {
+    /// A payload of `len` bytes in this pattern. Random draws its bytes from `rng`, the send
+    /// loop's, so a seeded run sends the same payloads again.
+    pub fn fill(self, len: usize, rng: &mut impl rand::RngCore) -> Vec<u8> {
+        match self {
+            PayloadPattern::Zeros => vec![0u8; len],
+            PayloadPattern::Random => {
+                let mut payload = vec![0u8; len];
+                rng.fill_bytes(&mut payload);
+                payload
+            }
+            PayloadPattern::Incrementing => (0..len).map(|i| i as u8).collect(),
+            PayloadPattern::Fixed(byte) => vec![byte; len],
+        }
+    }
+}
+
+/// Signals carried by Control packets on the UDP control channel
+/// (`TestConfig::udp_control_port`), as the first payload byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlMessage {
+    Start, // The client is about to send data
+    End,   // The client has sent its last data packet
+    Prewarm, // Link warmup before the test (`TestConfig::prewarm_packets`), sent on the data socket
+    Hello,   // UDP bidirectional handshake on the data socket: the sender's local port follows (big-endian)
+}
+
 /// The header part of our custom packet.
 /// Contains metadata for sequencing, timing, and type identification.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PacketHeader {
     pub sequence_number: u32,
     pub timestamp_ms: u64,    // Sender's timestamp in milliseconds since a common epoch (e.g., test start or Unix epoch)
+    // Only set on EchoReply: when the echo server received the request, in microseconds since
+    // the Unix epoch. Lets the client split RTT into forward/return delay. 0 = not set.
+    // (A plain u64 rather than Option keeps the fixint header size constant.)
+    pub echo_receive_timestamp_micros: u64,
     pub packet_type: PacketType,
-    // pub session_id: u32, // Could be useful for managing multiple concurrent tests or sessions
-    // pub integrity_checksum: u32, // Optional: For payload integrity if not relying solely on UDP/TCP checksums
+    // Random id of the test run that sent the packet (TestMetrics::session_id), so receivers can
+    // drop stragglers of an earlier run on the same port. EchoReplies carry the request's.
+    // 0 = no session, e.g. the loopback benchmark's packets; accepted by every receiver.
+    pub session_id: u32,
+    // CRC32 of the payload, set by the CustomPacket constructors (and set_payload). Catches
+    // payloads altered in transit that still pass the UDP/TCP checksum, e.g. by a middlebox.
+    pub checksum: u32,
 }
 
 impl PacketHeader {
@@ -35,11 +80,22 @@ impl PacketHeader {
                 .duration_since(UNIX_EPOCH)
                 .expect("Time went backwards")
                 .as_millis() as u64,
+            echo_receive_timestamp_micros: 0,
             packet_type,
+            session_id: 0,
+            checksum: 0, // CRC32 of an empty payload
         }
     }
 }
 
+/// Current wall-clock time in microseconds since the Unix epoch.
+pub fn unix_time_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_micros() as u64
+}
+
 /// The full packet structure including header and payload.
 /// The payload is generic to allow different types of data.
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -49,32 +105,127 @@ pub struct CustomPacket {
 }
 
 impl CustomPacket {
-    /// Creates a new data packet with the given sequence number and payload.
+    /// A packet with `header` and `payload`, its checksum set to match the payload.
+    pub fn new(header: PacketHeader, payload: Vec<u8>) -> Self {
+        let mut packet = CustomPacket { header, payload: Vec::new() };
+        packet.set_payload(payload);
+        packet
+    }
+
+    /// Creates a new data packet with the given sequence number and an all-zero payload.
     pub fn new_data_packet(sequence_number: u32, payload_size_bytes: usize) -> Self {
-        CustomPacket {
-            header: PacketHeader::new(sequence_number, PacketType::Data),
-            payload: vec![0u8; payload_size_bytes], // Dummy payload
+        CustomPacket::new(PacketHeader::new(sequence_number, PacketType::Data), vec![0u8; payload_size_bytes])
+    }
+
+    /// Creates a new data packet whose payload is filled with `pattern`, see `PayloadPattern::fill`.
+    pub fn new_data_packet_with(sequence_number: u32, payload_size_bytes: usize, pattern: PayloadPattern, rng: &mut impl rand::RngCore) -> Self {
+        CustomPacket::new(PacketHeader::new(sequence_number, PacketType::Data), pattern.fill(payload_size_bytes, rng))
+    }
+
+    /// The packet, stamped with the sending test run's `session_id`.
+    pub fn in_session(mut self, session_id: u32) -> Self {
+        self.header.session_id = session_id;
+        self
+    }
+
+    /// Replaces the payload and updates the checksum to match.
+    pub fn set_payload(&mut self, payload: Vec<u8>) {
+        self.header.checksum = crc32fast::hash(&payload);
+        self.payload = payload;
+    }
+
+    /// Whether the payload still matches the header's checksum, i.e. it wasn't altered in transit.
+    pub fn verify_integrity(&self) -> bool {
+        crc32fast::hash(&self.payload) == self.header.checksum
+    }
+
+    /// Largest payload for which the encoded packet still fits in `max_encoded_len` bytes.
+    /// Measured with worst-case header values, so it holds for every sequence number/timestamp.
+    pub fn max_payload_len(max_encoded_len: usize, fixint: bool) -> usize {
+        let mut probe = CustomPacket::new_echo_reply(&CustomPacket::new_echo_request(u32::MAX, max_encoded_len), u64::MAX);
+        probe.header.timestamp_ms = u64::MAX;
+        probe.header.checksum = u32::MAX;
+        probe.header.session_id = u32::MAX;
+        let overhead = probe
+            .to_bytes_with(fixint)
+            .map_or(0, |bytes| bytes.len() - max_encoded_len);
+        max_encoded_len.saturating_sub(overhead)
+    }
+
+    /// Creates a control channel packet carrying `message`.
+    pub fn new_control(sequence_number: u32, message: ControlMessage) -> Self {
+        CustomPacket::new(PacketHeader::new(sequence_number, PacketType::Control), vec![message as u8])
+    }
+
+    /// A Prewarm control packet padded to a data packet's size, so it wakes the link like one.
+    pub fn new_prewarm(payload_size_bytes: usize) -> Self {
+        let mut payload = vec![0u8; payload_size_bytes.max(1)];
+        payload[0] = ControlMessage::Prewarm as u8;
+        CustomPacket::new(PacketHeader::new(0, PacketType::Control), payload)
+    }
+
+    /// A Hello control packet advertising `local_port`, the port its sender sends from.
+    pub fn new_hello(local_port: u16) -> Self {
+        let [high, low] = local_port.to_be_bytes();
+        CustomPacket::new(PacketHeader::new(0, PacketType::Control), vec![ControlMessage::Hello as u8, high, low])
+    }
+
+    /// The port a Hello packet advertises; None for any other packet or a truncated Hello.
+    pub fn hello_port(&self) -> Option<u16> {
+        if self.control_message() != Some(ControlMessage::Hello) {
+            return None;
+        }
+        match self.payload[1..] {
+            [high, low, ..] => Some(u16::from_be_bytes([high, low])),
+            _ => None,
         }
     }
 
-    /// Creates a new echo request packet.
-    pub fn new_echo_request(sequence_number: u32, payload_size_bytes: usize) -> Self {
-        CustomPacket {
-            header: PacketHeader::new(sequence_number, PacketType::EchoRequest),
-            payload: vec![0u8; payload_size_bytes], // Can include a small payload
+    /// The message of a Control packet; None for other packet types or unknown messages.
+    pub fn control_message(&self) -> Option<ControlMessage> {
+        if self.header.packet_type != PacketType::Control {
+            return None;
+        }
+        match self.payload.first()? {
+            0 => Some(ControlMessage::Start),
+            1 => Some(ControlMessage::End),
+            2 => Some(ControlMessage::Prewarm),
+            3 => Some(ControlMessage::Hello),
+            _ => None,
         }
     }
 
+    /// Creates a new echo request packet with an all-zero payload.
+    pub fn new_echo_request(sequence_number: u32, payload_size_bytes: usize) -> Self {
+        CustomPacket::new(PacketHeader::new(sequence_number, PacketType::EchoRequest), vec![0u8; payload_size_bytes])
+    }
+
+    /// Creates a new echo request packet whose payload is filled with `pattern`, see `PayloadPattern::fill`.
+    pub fn new_echo_request_with(sequence_number: u32, payload_size_bytes: usize, pattern: PayloadPattern, rng: &mut impl rand::RngCore) -> Self {
+        CustomPacket::new(PacketHeader::new(sequence_number, PacketType::EchoRequest), pattern.fill(payload_size_bytes, rng))
+    }
+
+    /// Deterministic payload bytes for `sequence_number`, so an echo reply can be checked
+    /// against what was sent without remembering every request.
+    pub fn pattern_payload(sequence_number: u32, len: usize) -> Vec<u8> {
+        let seed = sequence_number.to_le_bytes();
+        (0..len).map(|i| (i as u8).wrapping_add(seed[i % seed.len()])).collect()
+    }
+
     /// Creates an echo reply packet based on an echo request.
-    pub fn new_echo_reply(request_packet: &CustomPacket) -> Self {
-        CustomPacket {
-            header: PacketHeader { // Keep original sequence and timestamp for RTT calculation
+    /// `receive_timestamp_micros` is when the request arrived (see `unix_time_micros`).
+    pub fn new_echo_reply(request_packet: &CustomPacket, receive_timestamp_micros: u64) -> Self {
+        CustomPacket::new(
+            PacketHeader { // Keep original sequence and timestamp for RTT calculation
                 sequence_number: request_packet.header.sequence_number,
                 timestamp_ms: request_packet.header.timestamp_ms,
+                echo_receive_timestamp_micros: receive_timestamp_micros,
                 packet_type: PacketType::EchoReply,
+                session_id: request_packet.header.session_id,
+                checksum: 0, // Set by new
             },
-            payload: request_packet.payload.clone(), // Echo the payload
-        }
+            request_packet.payload.clone(), // Echo the payload
+        )
     }
 
     /// Serializes the packet into a byte vector using bincode.
@@ -86,6 +237,43 @@ impl CustomPacket {
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, bincode::Error> {
         bincode::deserialize(bytes)
     }
+
+    /// Serializes with an explicit integer encoding (see `TestConfig::bincode_fixint`).
+    /// `fixint = true` produces the same bytes as `to_bytes`.
+    pub fn to_bytes_with(&self, fixint: bool) -> Result<Vec<u8>, bincode::Error> {
+        use bincode::Options;
+        if fixint {
+            fixint_options().serialize(self)
+        } else {
+            varint_options().serialize(self)
+        }
+    }
+
+    /// Deserializes with an explicit integer encoding. Must match the sender's setting.
+    pub fn from_bytes_with(bytes: &[u8], fixint: bool) -> Result<Self, bincode::Error> {
+        use bincode::Options;
+        if fixint {
+            fixint_options().deserialize(bytes)
+        } else {
+            varint_options().deserialize(bytes)
+        }
+    }
+}
+
+// Fixint writes every integer at its full width, so the header is a constant size (sequence 4 +
+// timestamp 8 + echo receive timestamp 8 + type tag 4 + session 4 + checksum 4 + payload length 8 = 40 bytes
+// before the payload).
+// Varint packs small values into fewer bytes, so the size depends on the sequence/timestamp values.
+// The two encodings are NOT wire compatible: both peers must use the same setting.
+// `allow_trailing_bytes` matches the behaviour of `bincode::serialize`/`deserialize`.
+fn fixint_options() -> impl bincode::Options {
+    use bincode::Options;
+    bincode::DefaultOptions::new().with_fixint_encoding().allow_trailing_bytes()
+}
+
+fn varint_options() -> impl bincode::Options {
+    use bincode::Options;
+    bincode::DefaultOptions::new().with_varint_encoding().allow_trailing_bytes()
 }
 
 
@@ -99,6 +287,7 @@ pub struct DataPacket {
     pub payload: Vec<u8>,
 }
 
+#[allow(deprecated)] // The legacy type's own methods
 impl DataPacket {
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
@@ -132,6 +321,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(deprecated)] // Covers the legacy DataPacket
     fn test_data_packet_serialization_deserialization() {
         let packet = DataPacket {
             sequence_number: 123,
@@ -162,8 +352,8 @@ mod tests {
         assert_eq!(packet.payload, deserialized_packet.payload);
 
 
-        let echo_req = CustomPacket::new_echo_request(1002, 32);
-        let echo_reply = CustomPacket::new_echo_reply(&echo_req);
+        let echo_req = CustomPacket::new_echo_request(1002, 32).in_session(0xC0FFEE);
+        let echo_reply = CustomPacket::new_echo_reply(&echo_req, 1_700_000_000_123_456);
 
         let reply_bytes = echo_reply.to_bytes().unwrap();
         let deserialized_reply = CustomPacket::from_bytes(&reply_bytes).unwrap();
@@ -171,12 +361,126 @@ mod tests {
         assert_eq!(echo_reply.header.sequence_number, deserialized_reply.header.sequence_number);
         assert_eq!(echo_reply.header.timestamp_ms, deserialized_reply.header.timestamp_ms); // Important for RTT
         assert_eq!(echo_reply.header.packet_type, PacketType::EchoReply);
+        assert_eq!(deserialized_reply.header.echo_receive_timestamp_micros, 1_700_000_000_123_456);
+        assert_eq!(echo_req.header.echo_receive_timestamp_micros, 0);
+        assert_eq!(deserialized_reply.header.session_id, 0xC0FFEE, "A reply belongs to its request's session");
         assert_eq!(echo_reply.payload, deserialized_reply.payload);
     }
 
     #[test]
+    fn test_fixint_encoding_has_constant_header_size() {
+        let payload_size = 64;
+        let small_seq = CustomPacket::new_data_packet(1, payload_size);
+        let large_seq = CustomPacket::new_data_packet(u32::MAX, payload_size);
+
+        // 4 (seq) + 8 (timestamp) + 8 (echo receive timestamp) + 4 (type tag) + 4 (session) + 4 (checksum) + 8 (payload length) + payload
+        let expected_len = 40 + payload_size;
+        let small_bytes = small_seq.to_bytes_with(true).unwrap();
+        let large_bytes = large_seq.to_bytes_with(true).unwrap();
+        assert_eq!(small_bytes.len(), expected_len);
+        assert_eq!(large_bytes.len(), expected_len);
+        assert_eq!(small_bytes, small_seq.to_bytes().unwrap()); // Same as the legacy encoding
+
+        // Varint is smaller for small values and varies with the sequence number
+        let small_varint = small_seq.to_bytes_with(false).unwrap();
+        let large_varint = large_seq.to_bytes_with(false).unwrap();
+        assert!(small_varint.len() < expected_len);
+        assert_ne!(small_varint.len(), large_varint.len());
+
+        let decoded = CustomPacket::from_bytes_with(&large_varint, false).unwrap();
+        assert_eq!(decoded.header.sequence_number, u32::MAX);
+        assert_eq!(decoded.payload.len(), payload_size);
+    }
+
+    #[test]
+    #[allow(deprecated)] // Covers the legacy DataPacket
     fn test_short_packet_from_bytes() {
         let short_data = vec![1,2,3];
         assert!(DataPacket::from_bytes(&short_data).is_err());
     }
+
+    #[test]
+    fn test_max_payload_len_fits_budget() {
+        for fixint in [true, false] {
+            let payload_len = CustomPacket::max_payload_len(1472, fixint);
+            let mut packet = CustomPacket::new_echo_request(u32::MAX, payload_len);
+            packet.header.timestamp_ms = u64::MAX;
+            packet.header.echo_receive_timestamp_micros = u64::MAX;
+            packet.header.checksum = u32::MAX;
+            packet.header.session_id = u32::MAX;
+            let encoded_len = packet.to_bytes_with(fixint).unwrap().len();
+            assert!(encoded_len <= 1472, "fixint={}: {} bytes", fixint, encoded_len);
+            assert!(encoded_len >= 1472 - 16, "fixint={}: wasted space ({} bytes)", fixint, encoded_len);
+        }
+        assert_eq!(CustomPacket::max_payload_len(1472, true), 1472 - 40);
+        assert_eq!(CustomPacket::max_payload_len(8, true), 0);
+    }
+
+    #[test]
+    fn test_checksum_detects_corrupted_payload() {
+        let mut packet = CustomPacket::new_echo_request(9, 0);
+        packet.set_payload(CustomPacket::pattern_payload(9, 128));
+        for fixint in [true, false] {
+            let mut bytes = packet.to_bytes_with(fixint).unwrap();
+            let decoded = CustomPacket::from_bytes_with(&bytes, fixint).unwrap();
+            assert!(decoded.verify_integrity());
+            assert_eq!(decoded.header.checksum, crc32fast::hash(&packet.payload));
+
+            // Flip one payload bit in transit; the packet still parses
+            let last = bytes.len() - 1;
+            bytes[last] ^= 0x01;
+            let corrupted = CustomPacket::from_bytes_with(&bytes, fixint).unwrap();
+            assert_ne!(corrupted.payload, packet.payload);
+            assert!(!corrupted.verify_integrity(), "fixint={}: corruption went unnoticed", fixint);
+        }
+
+        let reply = CustomPacket::new_echo_reply(&packet, 1);
+        assert!(reply.verify_integrity());
+        assert!(CustomPacket::new_data_packet(1, 64).verify_integrity());
+        assert!(CustomPacket::new_control(1, ControlMessage::End).verify_integrity());
+    }
+
+    #[test]
+    fn test_payload_patterns() {
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let mut payload = |pattern| CustomPacket::new_data_packet_with(3, 300, pattern, &mut rng).payload;
+        assert!(payload(PayloadPattern::Zeros).iter().all(|&b| b == 0));
+        assert!(payload(PayloadPattern::Fixed(0xA5)).iter().all(|&b| b == 0xA5));
+        let incrementing = payload(PayloadPattern::Incrementing);
+        assert_eq!(&incrementing[..3], &[0, 1, 2]);
+        assert_eq!(incrementing[256], 0); // Wraps
+        assert!(incrementing.windows(2).all(|w| w[1] == w[0].wrapping_add(1)));
+        // 300 random bytes are neither uniform nor the same twice
+        let random = payload(PayloadPattern::Random);
+        assert!(random.iter().any(|&b| b != random[0]));
+        assert_ne!(random, payload(PayloadPattern::Random));
+        // Only Random draws from the RNG, so the same seed gives the first random payload again
+        assert_eq!(PayloadPattern::Random.fill(300, &mut rand::rngs::StdRng::seed_from_u64(7)), random);
+
+        let expected_len = CustomPacket::new_data_packet(3, 300).to_bytes_with(true).unwrap().len();
+        for pattern in [PayloadPattern::Random, PayloadPattern::Incrementing, PayloadPattern::Fixed(0xFF)] {
+            for packet in [CustomPacket::new_data_packet_with(3, 300, pattern, &mut rng), CustomPacket::new_echo_request_with(3, 300, pattern, &mut rng)] {
+                assert_eq!(packet.payload.len(), 300, "{:?}", pattern);
+                assert!(packet.verify_integrity(), "{:?}: checksum must cover the patterned payload", pattern);
+                assert_eq!(packet.to_bytes_with(true).unwrap().len(), expected_len, "{:?} changed the size", pattern);
+            }
+        }
+    }
+
+    #[test]
+    fn test_control_message_round_trip() {
+        for message in [ControlMessage::Start, ControlMessage::End, ControlMessage::Prewarm, ControlMessage::Hello] {
+            let bytes = CustomPacket::new_control(7, message).to_bytes().unwrap();
+            assert_eq!(CustomPacket::from_bytes(&bytes).unwrap().control_message(), Some(message));
+        }
+        assert_eq!(CustomPacket::new_data_packet(7, 1).control_message(), None);
+        assert_eq!(CustomPacket::new_prewarm(64).control_message(), Some(ControlMessage::Prewarm));
+
+        let hello = CustomPacket::from_bytes(&CustomPacket::new_hello(50123).to_bytes().unwrap()).unwrap();
+        assert_eq!(hello.control_message(), Some(ControlMessage::Hello));
+        assert_eq!(hello.hello_port(), Some(50123));
+        assert_eq!(CustomPacket::new_control(7, ControlMessage::Hello).hello_port(), None); // No port
+        assert_eq!(CustomPacket::new_prewarm(64).hello_port(), None);
+    }
 }
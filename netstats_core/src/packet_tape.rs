@@ -0,0 +1,108 @@
+// Lock-free "packet tape": the most recent N packet events, for live views
+
+use crate::packet::PacketType;
+use crossbeam_queue::ArrayQueue;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PacketEvent {
+    pub timestamp_ms: u128, // Since test start
+    pub sequence_number: u32,
+    pub size_bytes: usize,
+    pub rtt_micros: Option<u128>, // Only for echo replies matched on the client
+    pub packet_type: PacketType,
+}
+
+/// Fixed-size ring of the latest packet events. The receive loops push without touching the
+/// metrics lock; once full, each push evicts the oldest event. A reader (e.g. the GUI thread)
+/// drains it periodically and keeps whatever history it wants to display.
+pub struct PacketTape {
+    events: ArrayQueue<PacketEvent>,
+}
+
+impl PacketTape {
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        PacketTape { events: ArrayQueue::new(capacity) }
+    }
+
+    pub fn push(&self, event: PacketEvent) {
+        self.events.force_push(event);
+    }
+
+    /// Removes and returns the buffered events, oldest first.
+    pub fn drain(&self) -> Vec<PacketEvent> {
+        let mut drained = Vec::with_capacity(self.events.len());
+        while let Some(event) = self.events.pop() {
+            drained.push(event);
+        }
+        drained
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.events.capacity()
+    }
+}
+
+impl std::fmt::Debug for PacketTape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PacketTape")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(sequence_number: u32) -> PacketEvent {
+        PacketEvent {
+            timestamp_ms: sequence_number as u128,
+            sequence_number,
+            size_bytes: 64,
+            rtt_micros: None,
+            packet_type: PacketType::Data,
+        }
+    }
+
+    #[test]
+    fn test_tape_retains_last_n_events() {
+        let tape = PacketTape::new(4);
+        for seq in 0..10 {
+            tape.push(event(seq));
+        }
+        assert_eq!(tape.len(), 4);
+
+        let sequences: Vec<u32> = tape.drain().iter().map(|e| e.sequence_number).collect();
+        assert_eq!(sequences, vec![6, 7, 8, 9]);
+        assert!(tape.is_empty());
+    }
+
+    #[test]
+    fn test_tape_concurrent_writers_never_exceed_capacity() {
+        let tape = std::sync::Arc::new(PacketTape::new(16));
+        let writers: Vec<_> = (0..4)
+            .map(|w| {
+                let tape = std::sync::Arc::clone(&tape);
+                std::thread::spawn(move || {
+                    for seq in 0..1000 {
+                        tape.push(event(w * 1000 + seq));
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(tape.drain().len(), 16);
+    }
+}
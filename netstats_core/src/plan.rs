@@ -0,0 +1,273 @@
+// Test plans: a campaign of test phases from one TOML file, with per-phase assertions and a combined report
+
+use crate::config::{ConfigError, Protocol, TestConfig};
+use crate::metrics::TestMetrics;
+use crate::network::NetworkError;
+use crate::reporter::{create_run_output_dir, write_reports, TestSummary};
+use crate::runner::{run_prepared_test, TestHooks};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A test campaign: phases run one after another, each a full test. In TOML:
+///
+/// ```toml
+/// name = "nightly link check"
+/// stop_on_failure = true
+///
+/// [defaults]                      # TestConfig keys shared by every phase
+/// target_ip = "10.0.0.5"
+/// output_formats = ["Json", "Markdown"]
+///
+/// [[phases]]
+/// label = "Game traffic"          # Then TestConfig keys for this phase, over the defaults
+/// tick_rate_hz = 60
+/// assertions = ["loss < 1%", "p95_rtt <= 30ms"]
+///
+/// [[phases]]
+/// label = "Bulk TCP"
+/// protocol = "Tcp"
+/// assertions = ["throughput >= 50Mbps"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TestPlan {
+    pub name: String,
+    pub defaults: toml::Table, // TestConfig keys every phase starts from
+    pub phases: Vec<PlanPhase>,
+    // Skip the remaining phases once one fails its assertions, e.g. when later phases
+    // only make sense on a healthy link
+    pub stop_on_failure: bool,
+}
+
+/// One phase of a `TestPlan`: a label and the TestConfig keys that differ from the plan's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlanPhase {
+    pub label: String,
+    #[serde(flatten)]
+    pub settings: toml::Table,
+}
+
+impl TestPlan {
+    pub fn from_toml_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path)?;
+        Self::from_toml_str(&contents)
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<Self, ConfigError> {
+        toml::from_str(contents).map_err(|e| ConfigError::InvalidConfigFile(e.to_string()))
+    }
+
+    /// The validated config of every phase, in order: the defaults with the phase's keys on top.
+    /// A key that isn't a TestConfig field, in the defaults or a phase, is an error.
+    pub fn phase_configs(&self) -> Result<Vec<TestConfig>, ConfigError> {
+        toml::Value::Table(self.defaults.clone())
+            .try_into::<TestConfig>()
+            .map_err(|e| ConfigError::InvalidConfigFile(format!("defaults: {}", e)))?;
+        self.phases
+            .iter()
+            .map(|phase| {
+                let mut table = self.defaults.clone();
+                table.extend(phase.settings.clone());
+                let config: TestConfig = toml::Value::Table(table)
+                    .try_into()
+                    .map_err(|e| ConfigError::InvalidConfigFile(format!("phase '{}': {}", phase.label, e)))?;
+                config.validate()?;
+                Ok(config)
+            })
+            .collect()
+    }
+}
+
+/// Outcome of one phase that ran; its assertion results are in the summary.
+#[derive(Debug)]
+pub struct PhaseResult {
+    pub number: usize, // 1-based position in the plan
+    pub label: String,
+    pub summary: TestSummary,
+}
+
+/// A phase that failed with a network error, so has no summary.
+#[derive(Debug)]
+pub struct FailedPhase {
+    pub number: usize, // 1-based position in the plan
+    pub label: String,
+    pub protocol: Protocol,
+    pub error: NetworkError,
+}
+
+/// Outcome of `run_test_plan`.
+#[derive(Debug)]
+pub struct TestPlanResult {
+    pub name: String,
+    pub phases: Vec<PhaseResult>, // The phases that ran, in order
+    pub failed: Vec<FailedPhase>, // The phases that ended in a network error, in order
+    pub skipped: Vec<String>,     // Labels of the phases skipped after a failure (stop_on_failure)
+}
+
+impl TestPlanResult {
+    /// True if every phase ran and passed all of its assertions.
+    pub fn passed(&self) -> bool {
+        self.failed.is_empty()
+            && self.skipped.is_empty()
+            && self.phases.iter().all(|phase| phase.summary.assertions_passed())
+    }
+
+    /// (passed, total) assertions over all phases that ran.
+    pub fn assertion_counts(&self) -> (usize, usize) {
+        self.phases.iter().fold((0, 0), |(passed, total), phase| {
+            let results = &phase.summary.assertion_results;
+            (passed + results.iter().filter(|result| result.passed).count(), total + results.len())
+        })
+    }
+
+    /// Combined Markdown report: one row per phase, then each phase's assertions.
+    pub fn markdown(&self) -> String {
+        let (passed, total) = self.assertion_counts();
+        let mut md = format!("# Test Plan: {}\n\n", self.name);
+        md.push_str(&format!(
+            "**{}**: {} of {} assertions passed over {} phases.\n\n",
+            if self.passed() { "PASSED" } else { "FAILED" }, passed, total,
+            self.phases.len() + self.failed.len() + self.skipped.len()
+        ));
+        md.push_str("| Phase | Protocol | Duration | Packet Loss | Throughput | Result |\n|---|---|---|---|---|---|\n");
+        // The phases that ran and those that failed, in plan order
+        let mut rows: Vec<(usize, String)> = Vec::new();
+        for phase in &self.phases {
+            let summary = &phase.summary;
+            let throughput_mbps = summary.throughput_mbps();
+            rows.push((phase.number, format!(
+                "| {} | {} | {:.2} s | {:.2}% | {:.2} Mbps | {} |\n",
+                markdown_cell(&phase.label), summary.test_config.protocol, summary.test_duration_actual_secs,
+                summary.overall_metrics.packet_loss_percentage(), throughput_mbps,
+                if summary.assertions_passed() { "Passed" } else { "Failed" }
+            )));
+        }
+        for failed in &self.failed {
+            rows.push((failed.number, format!(
+                "| {} | {} | | | | Error: {} |\n",
+                markdown_cell(&failed.label), failed.protocol, markdown_cell(&failed.error.to_string())
+            )));
+        }
+        rows.sort_by_key(|(number, _)| *number);
+        for (_, row) in rows {
+            md.push_str(&row);
+        }
+        for label in &self.skipped {
+            md.push_str(&format!("| {} | | | | | Skipped |\n", markdown_cell(label)));
+        }
+
+        for phase in self.phases.iter().filter(|phase| !phase.summary.assertion_results.is_empty()) {
+            md.push_str(&format!("\n## {}\n\n", phase.label));
+            for result in &phase.summary.assertion_results {
+                let actual = result.actual.map_or("N/A".to_string(), |value| format!("{:.3}", value));
+                md.push_str(&format!(
+                    "- [{}] {} (actual {})\n",
+                    if result.passed { "x" } else { " " }, result.assertion, actual
+                ));
+            }
+        }
+        md
+    }
+
+    /// Writes the combined report (`plan.md`) and, in a subdirectory per phase, that phase's
+    /// reports in its `output_formats`, into a fresh run directory under `output_dir`.
+    pub fn write_reports(&self, output_dir: &Path) -> io::Result<PathBuf> {
+        let run_dir = create_run_output_dir(output_dir)?;
+        fs::write(run_dir.join("plan.md"), self.markdown())?;
+        for phase in &self.phases {
+            let phase_dir = run_dir.join(format!("phase_{}", phase.number));
+            fs::create_dir_all(&phase_dir)?;
+            write_reports(&phase.summary, &phase_dir, &phase.summary.test_config.output_formats)?;
+        }
+        Ok(run_dir)
+    }
+}
+
+/// Runs the phases of `plan` in order. Every phase config is checked before the first one
+/// starts; an invalid one is the only error. A phase failing with a network error is recorded
+/// in `failed` and the plan goes on, unless `stop_on_failure` skips the rest.
+pub async fn run_test_plan(plan: &TestPlan) -> Result<TestPlanResult, NetworkError> {
+    let configs = plan.phase_configs()?;
+    let mut result = TestPlanResult { name: plan.name.clone(), phases: Vec::new(), failed: Vec::new(), skipped: Vec::new() };
+    for (index, (phase, config)) in plan.phases.iter().zip(&configs).enumerate() {
+        if plan.stop_on_failure && !result.passed() {
+            result.skipped.push(phase.label.clone());
+            continue;
+        }
+        println!("Test plan '{}': starting phase '{}'", plan.name, phase.label);
+        // Labels the phase's own report and chart with the phase it ran as
+        let mut metrics = TestMetrics::default();
+        metrics.mark_phase(phase.label.as_str());
+        match run_prepared_test(config, metrics, &TestHooks::default()).await {
+            Ok(summary) => result.phases.push(PhaseResult { number: index + 1, label: phase.label.clone(), summary }),
+            Err(error) => {
+                eprintln!("Test plan '{}': phase '{}' failed: {}", plan.name, phase.label, error);
+                result.failed.push(FailedPhase { number: index + 1, label: phase.label.clone(), protocol: config.protocol, error });
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// `text` for a Markdown table cell: `|` would end the cell and a line break the row.
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phase_configs_layer_phase_keys_over_defaults() {
+        let plan = TestPlan::from_toml_str(
+            r#"
+            name = "two phases"
+
+            [defaults]
+            target_ip = "192.0.2.7"
+            test_duration_secs = 3
+
+            [[phases]]
+            label = "UDP"
+            assertions = ["loss < 1%"]
+
+            [[phases]]
+            label = "TCP"
+            protocol = "Tcp"
+            test_duration_secs = 5
+            "#,
+        )
+        .unwrap();
+
+        let configs = plan.phase_configs().unwrap();
+        assert_eq!(configs.len(), 2);
+        assert_eq!((configs[0].target_ip.as_str(), configs[0].protocol, configs[0].test_duration_secs), ("192.0.2.7", Protocol::Udp, 3));
+        assert_eq!(configs[0].assertions.len(), 1);
+        assert_eq!((configs[1].target_ip.as_str(), configs[1].protocol, configs[1].test_duration_secs), ("192.0.2.7", Protocol::Tcp, 5));
+        assert!(configs[1].assertions.is_empty());
+
+        let bad_key_type = TestPlan::from_toml_str("[[phases]]\nlabel = \"x\"\ntick_rate_hz = \"fast\"").unwrap();
+        match bad_key_type.phase_configs() {
+            Err(ConfigError::InvalidConfigFile(reason)) => assert!(reason.starts_with("phase 'x'"), "{}", reason),
+            other => panic!("Expected InvalidConfigFile, got {:?}", other),
+        }
+        let invalid = TestPlan::from_toml_str("[[phases]]\nlabel = \"x\"\ntest_duration_secs = 0").unwrap();
+        assert!(matches!(invalid.phase_configs(), Err(ConfigError::ZeroDuration)));
+
+        // Misspelled keys are errors rather than silently left at their defaults
+        let misspelled = TestPlan::from_toml_str("[[phases]]\nlabel = \"x\"\nassertion = [\"loss < 1%\"]").unwrap();
+        match misspelled.phase_configs() {
+            Err(ConfigError::InvalidConfigFile(reason)) => assert!(reason.contains("`assertion`"), "{}", reason),
+            other => panic!("Expected InvalidConfigFile, got {:?}", other),
+        }
+        let misspelled_default = TestPlan::from_toml_str("[defaults]\ntick_rate = 60\n\n[[phases]]\nlabel = \"x\"").unwrap();
+        match misspelled_default.phase_configs() {
+            Err(ConfigError::InvalidConfigFile(reason)) => assert!(reason.starts_with("defaults") && reason.contains("`tick_rate`"), "{}", reason),
+            other => panic!("Expected InvalidConfigFile, got {:?}", other),
+        }
+        assert!(TestPlan::from_toml_str("stop_on_fail = true").is_err());
+    }
+}
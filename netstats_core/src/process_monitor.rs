@@ -0,0 +1,86 @@
+// Self-monitoring of the netstats process (CPU, memory) while a test runs
+
+use crate::metrics::{lock_metrics, TestMetrics};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+use tokio::task::JoinHandle;
+
+/// One reading of this process's resource usage.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ProcessSample {
+    pub timestamp_ms: u128, // Since test start
+    pub cpu_percent: f32,   // Of one core, so a busy multi-threaded process can exceed 100
+    pub rss_bytes: u64,
+}
+
+/// Samples the process into `TestMetrics::process_samples` on a fixed interval until dropped.
+/// CPU usage is measured between consecutive samples, so the first one lands one interval in.
+pub struct ProcessMonitor {
+    task: JoinHandle<()>,
+}
+
+impl ProcessMonitor {
+    pub fn start(metrics: Arc<Mutex<TestMetrics>>, interval: Duration) -> Self {
+        let task = tokio::spawn(async move {
+            let Ok(pid) = sysinfo::get_current_pid() else {
+                eprintln!("ProcessMonitor: Current PID unavailable on this platform, not sampling.");
+                return;
+            };
+            let mut system = System::new();
+            refresh(&mut system, pid); // CPU baseline
+
+            let test_start_time = lock_metrics(&metrics).test_start_time.unwrap_or_else(Instant::now);
+            let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+            loop {
+                ticker.tick().await;
+                refresh(&mut system, pid);
+                if let Some(process) = system.process(pid) {
+                    lock_metrics(&metrics).process_samples.push(ProcessSample {
+                        timestamp_ms: test_start_time.elapsed().as_millis(),
+                        cpu_percent: process.cpu_usage(),
+                        rss_bytes: process.memory(),
+                    });
+                }
+            }
+        });
+        ProcessMonitor { task }
+    }
+}
+
+impl Drop for ProcessMonitor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn refresh(system: &mut System, pid: Pid) {
+    system.refresh_processes_specifics(
+        ProcessesToUpdate::Some(&[pid]),
+        false,
+        ProcessRefreshKind::nothing().with_cpu().with_memory(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_monitor_samples_until_dropped() {
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        metrics.lock().unwrap().init_start_time();
+
+        let monitor = ProcessMonitor::start(Arc::clone(&metrics), Duration::from_millis(50));
+        tokio::time::sleep(Duration::from_millis(280)).await;
+        drop(monitor);
+        let samples = metrics.lock().unwrap().process_samples.clone();
+        assert!(samples.len() >= 3, "{} samples", samples.len());
+        assert!(samples.iter().all(|s| s.rss_bytes > 0 && s.cpu_percent >= 0.0), "{:?}", samples);
+        assert!(samples.windows(2).all(|w| w[0].timestamp_ms < w[1].timestamp_ms));
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(metrics.lock().unwrap().process_samples.len(), samples.len(), "sampling must stop on drop");
+    }
+}
@@ -1,10 +1,17 @@
 // Data aggregation and preparing data for reports
 
-use crate::metrics::TestMetrics;
+use crate::metrics::{nearest_rank, LossBreakdown, TestMetrics};
 use crate::anomalies::AnomalyEvent;
-use crate::config::TestConfig; // Protocol, TestMode, TcpBidirectionalMode were unused directly by this file's code
+use crate::assertions::AssertionResult;
+use crate::config::{Protocol, ReportFormat, TestConfig, TestMode};
+use crate::process_monitor::ProcessSample;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 use askama::Template; // Import Askama
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
 use serde_json; // For serializing data to JSON for JS charts - used by macro serde_json::json!
 
 #[derive(Template)]
@@ -14,9 +21,13 @@ pub struct HtmlReport<'a> {
     // Additional fields needed specifically for the template can be added here
     // For example, pre-formatted strings or chart data.
     bandwidth_chart_data_json: String,
+    phase_annotations_json: String, // Vertical phase boundary lines, see phase_annotations_json()
+    process_chart_data_json: String, // [{time, cpu, rss_mb}], empty unless monitor_process was on
+    latency_groups: Vec<LatencyStatGroup>, // Columns of the latency table
+    latency_rows: Vec<(&'static str, Vec<f64>)>, // Its rows, see LatencyStatGroup::table
 }
 
-#[derive(Debug)] // Keep TestSummary as a plain data struct
+#[derive(Debug, Serialize, Deserialize)] // Keep TestSummary as a plain data struct
 pub struct TestSummary {
     pub test_config: TestConfig,
     pub overall_metrics: TestMetrics,
@@ -25,26 +36,256 @@ pub struct TestSummary {
     pub end_time_utc: String,
     pub test_duration_actual_secs: f64,
     pub bandwidth_over_time: Vec<(f64, f64)>, // (time_sec_since_start, mbps)
+    pub pps_over_time: Vec<(f64, f64)>,       // (time_sec_since_start, packets_per_sec), same intervals
+    pub headline_rtt_micros: Option<f64>, // RTT computed with test_config.latency_stat
+    pub headline_rtt_label: String,       // e.g. "Median", "Trimmed Mean (10%)"
+    // Post-warmup throughput, headlined when a warmup is configured.
+    // bandwidth_over_time still covers the whole test, warmup ramp included.
+    pub steady_state_throughput_mbps: Option<f64>,
+    pub warmup_end_secs: Option<f64>,
+    pub loss_breakdown: Option<LossBreakdown>, // Receiver-side sequence tracking (UDP)
+    pub assertion_results: Vec<AssertionResult>, // One per test_config.assertions entry
+    pub phase_boundaries: Vec<PhaseBoundary>, // Sorted by time; includes the warmup end
+    pub paused_secs: f64, // Time spent paused; not part of test_duration_actual_secs
+    // Set when the test was shorter than one bandwidth sample interval, so its throughput
+    // comes from a single forced sample
+    pub short_test_note: Option<String>,
+    pub direction_rates: Option<DirectionRates>, // Bidirectional mode only
+    pub bottleneck: Bottleneck, // From the process samples; see with_loopback_ceiling
+    pub bandwidth_stats: Option<BandwidthStats>, // Over bandwidth_over_time; None without samples
     // pub latency_over_time: Vec<(f64, f64)>, // (time_sec, latency_ms) - for later if needed
 }
 
+/// A transition between test phases, drawn as a labelled vertical line on the charts.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhaseBoundary {
+    pub time_secs: f64, // Since test start
+    pub label: String,  // Name of the phase that starts here
+}
+
+/// Achieved packet rates of the two directions of a bidirectional test, over the active duration.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DirectionRates {
+    pub tx_pps: f64, // Packets this side sent per second
+    pub rx_pps: f64, // Packets this side received per second
+}
+
+/// What most likely limited a test, each with a human-readable reason.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Bottleneck {
+    RateLimited(String), // Reached the configured send rate, so the link may have more to give
+    Cpu(String),
+    Loopback(String),   // Near this host's loopback packet rate ceiling
+    PacketLoss(String),
+    Network(String),    // None of the above: the path itself
+}
+
+impl Bottleneck {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Bottleneck::RateLimited(_) => "Configured rate",
+            Bottleneck::Cpu(_) => "CPU",
+            Bottleneck::Loopback(_) => "Host packet rate (loopback ceiling)",
+            Bottleneck::PacketLoss(_) => "Packet loss",
+            Bottleneck::Network(_) => "Network",
+        }
+    }
+
+    pub fn reason(&self) -> &str {
+        match self {
+            Bottleneck::RateLimited(reason)
+            | Bottleneck::Cpu(reason)
+            | Bottleneck::Loopback(reason)
+            | Bottleneck::PacketLoss(reason)
+            | Bottleneck::Network(reason) => reason,
+        }
+    }
+}
+
+// Sending at this share of the configured rate counts as having reached it
+const RATE_REACHED_FRACTION: f64 = 0.95;
+// A process sample at or above this CPU% (of one core) means a saturated send/receive loop
+const CPU_SATURATED_PERCENT: f32 = 90.0;
+
+/// Verdict on what limited a test, checked in order: packet loss over the configured
+/// threshold (1% without one), reaching the configured send rate, a saturated CPU in
+/// `system_samples`, the packet rate nearing `loopback_max_pps` (see
+/// `benchmark::estimate_max_pps`), and otherwise the network.
+pub fn diagnose_bottleneck(summary: &TestSummary, system_samples: &[ProcessSample], loopback_max_pps: Option<u64>) -> Bottleneck {
+    let config = &summary.test_config;
+    let metrics = &summary.overall_metrics;
+
+    let loss_percent = metrics.packet_loss_percentage();
+    let loss_threshold_percent = config.packet_loss_threshold_percent.unwrap_or(1.0);
+    if loss_percent >= loss_threshold_percent {
+        return Bottleneck::PacketLoss(format!(
+            "{:.2}% of packets were lost (threshold {}%)", loss_percent, loss_threshold_percent
+        ));
+    }
+
+    let duration_secs = summary.test_duration_actual_secs;
+    let rate = |packets: u64| if duration_secs > 0.0 { packets as f64 / duration_secs } else { 0.0 };
+    let sent_pps = rate(metrics.packets_sent);
+    let achieved_pps = rate(metrics.packets_sent.max(metrics.packets_received)); // Either side of the test
+    let configured_pps = config.send_tick_rate_hz(true) as f64 * config.tick_burst() as f64; // 0 = as fast as possible
+    if configured_pps > 0.0 && sent_pps >= configured_pps * RATE_REACHED_FRACTION {
+        return Bottleneck::RateLimited(format!(
+            "Sent {:.0} packets/s of the configured {:.0}: raise the tick rate to find the link's limit",
+            sent_pps, configured_pps
+        ));
+    }
+
+    if let Some(busiest) = system_samples.iter().map(|sample| sample.cpu_percent).reduce(f32::max) {
+        if busiest >= CPU_SATURATED_PERCENT {
+            return Bottleneck::Cpu(format!("The test process peaked at {:.0}% CPU", busiest));
+        }
+    }
+
+    if let Some(note) = loopback_max_pps.and_then(|max_pps| crate::benchmark::host_limit_note(achieved_pps, max_pps)) {
+        return Bottleneck::Loopback(note);
+    }
+
+    Bottleneck::Network(format!(
+        "{:.0} packets/s with {:.2}% loss and no host limit reached", achieved_pps, loss_percent
+    ))
+}
+
+impl TestSummary {
+    /// Re-diagnoses `bottleneck` against `max_pps`, this machine's loopback packet rate from
+    /// `benchmark::estimate_max_pps`, which `generate_summary` doesn't measure.
+    pub fn with_loopback_ceiling(mut self, max_pps: u64) -> Self {
+        self.bottleneck = diagnose_bottleneck(&self, &self.overall_metrics.process_samples, Some(max_pps));
+        self
+    }
+
+    /// Evaluates `test_config.assertions` against this test's results.
+    pub fn check_assertions(&self) -> Vec<AssertionResult> {
+        crate::assertions::evaluate(&self.test_config.assertions, &self.overall_metrics, self.test_duration_actual_secs)
+    }
+
+    /// Received throughput over the actual test duration, in Mbps.
+    pub fn throughput_mbps(&self) -> f64 {
+        self.overall_metrics.overall_throughput_bps(self.test_duration_actual_secs) / 1_000_000.0
+    }
+
+    /// True if every configured assertion passed (vacuously true without assertions).
+    pub fn assertions_passed(&self) -> bool {
+        self.assertion_results.iter().all(|result| result.passed)
+    }
+
+    /// The columns of the reports' latency table: RTT, then the forward and return one-way
+    /// delays if `report_one_way_delays` is on. Kinds without samples are left out.
+    pub fn latency_groups(&self) -> Vec<LatencyStatGroup> {
+        let metrics = &self.overall_metrics;
+        let mut groups = Vec::new();
+        if let (Some(min), Some(avg), Some(p95), Some(max)) =
+            (metrics.min_rtt_micros, metrics.average_rtt_micros(), metrics.rtt_p95(), metrics.max_rtt_micros)
+        {
+            groups.push(LatencyStatGroup { label: "RTT", min_ms: min as f64 / 1000.0, avg_ms: avg / 1000.0, p95_ms: p95 / 1000.0, max_ms: max as f64 / 1000.0 });
+        }
+        if self.test_config.report_one_way_delays {
+            groups.extend(LatencyStatGroup::from_samples("Forward One-Way", &metrics.forward_delay_samples_micros));
+            groups.extend(LatencyStatGroup::from_samples("Return One-Way", &metrics.return_delay_samples_micros));
+        }
+        groups
+    }
+}
+
+/// Average, median, p95 and peak of the per-interval throughput (`bandwidth_over_time`).
+/// Every interval counts once, whatever its length.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BandwidthStats {
+    pub avg_mbps: f64,
+    pub median_mbps: f64,
+    pub p95_mbps: f64, // Nearest rank
+    pub peak_mbps: f64,
+    pub peak_time_secs: f64, // End of the peak interval, since test start
+}
+
+impl BandwidthStats {
+    /// The statistics of a (time_secs, mbps) series; None if it is empty.
+    pub fn from_series(series: &[(f64, f64)]) -> Option<Self> {
+        let &(peak_time_secs, peak_mbps) = series.iter().max_by(|a, b| a.1.total_cmp(&b.1))?;
+        let mut sorted: Vec<f64> = series.iter().map(|&(_, mbps)| mbps).collect();
+        sorted.sort_unstable_by(f64::total_cmp);
+        let n = sorted.len();
+        let median_mbps = (sorted[(n - 1) / 2] + sorted[n / 2]) / 2.0; // The middle one, or the mean of the middle two
+        Some(BandwidthStats {
+            avg_mbps: sorted.iter().sum::<f64>() / n as f64,
+            median_mbps,
+            p95_mbps: sorted[nearest_rank(95.0, n) - 1],
+            peak_mbps,
+            peak_time_secs,
+        })
+    }
+}
+
+/// Min, average, p95 and max of one kind of latency, in milliseconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyStatGroup {
+    pub label: &'static str,
+    pub min_ms: f64,
+    pub avg_ms: f64,
+    pub p95_ms: f64, // Nearest rank
+    pub max_ms: f64,
+}
+
+impl LatencyStatGroup {
+    /// The statistics of `samples_micros`; None if there are none. Every sample is kept, so
+    /// p95 is the exact value at the same nearest rank `rtt_histogram` reports for RTT.
+    fn from_samples(label: &'static str, samples_micros: &[i64]) -> Option<Self> {
+        let mut sorted = samples_micros.to_vec();
+        sorted.sort_unstable();
+        let (&min, &max) = (sorted.first()?, sorted.last()?);
+        let avg = sorted.iter().map(|&v| v as f64).sum::<f64>() / sorted.len() as f64;
+        Some(LatencyStatGroup {
+            label,
+            min_ms: min as f64 / 1000.0,
+            avg_ms: avg / 1000.0,
+            p95_ms: sorted[nearest_rank(95.0, sorted.len()) - 1] as f64 / 1000.0,
+            max_ms: max as f64 / 1000.0,
+        })
+    }
+
+    /// The rows of a latency table with `groups` as its columns: each statistic's label and
+    /// its value in every group, in ms.
+    pub fn table(groups: &[LatencyStatGroup]) -> Vec<(&'static str, Vec<f64>)> {
+        let column = |stat: fn(&LatencyStatGroup) -> f64| groups.iter().map(stat).collect();
+        vec![
+            ("Min", column(|group| group.min_ms)),
+            ("Avg", column(|group| group.avg_ms)),
+            ("P95", column(|group| group.p95_ms)),
+            ("Max", column(|group| group.max_ms)),
+        ]
+    }
+}
+
 /// Processes raw bandwidth samples from TestMetrics into a Vec<(f64, f64)>
 /// representing (time_seconds_since_start, megabits_per_second).
-fn process_bandwidth_samples(metrics: &TestMetrics) -> Vec<(f64, f64)> {
+pub(crate) fn process_bandwidth_samples(metrics: &TestMetrics) -> Vec<(f64, f64)> {
+    per_interval_rates(metrics, |bytes_in_interval, _| bytes_in_interval as f64 * 8.0 / 1_000_000.0)
+}
+
+/// Packets per second for each bandwidth sample interval, as (time_seconds_since_start, pps).
+/// Independent of packet size, unlike the Mbps series.
+fn process_pps_samples(metrics: &TestMetrics) -> Vec<(f64, f64)> {
+    per_interval_rates(metrics, |_, packets_in_interval| packets_in_interval as f64)
+}
+
+/// Turns the per-interval counts in `metrics.bandwidth_samples` into per-second rates.
+/// `amount` maps an interval's (bytes, packets) to the quantity being rated.
+fn per_interval_rates(metrics: &TestMetrics, amount: impl Fn(u64, u64) -> f64) -> Vec<(f64, f64)> {
     let mut processed_samples = Vec::new();
-    if metrics.bandwidth_samples.is_empty() {
-        return processed_samples;
-    }
 
     // The first timestamp in bandwidth_samples is the time of the end of the first interval.
-    // The bytes are for that interval.
-    // Example: [(1000ms, 125000 bytes), (2000ms, 130000 bytes)]
+    // The bytes and packets are for that interval.
+    // Example: [(1000ms, 125000 bytes, 100 packets), (2000ms, 130000 bytes, 104 packets)]
     // Sample 1: from 0 to 1000ms, 125000 bytes were received. Interval duration = 1000ms. Mbps = (125000*8)/(1000/1000)/1_000_000
     // Sample 2: from 1000ms to 2000ms, 130000 bytes. Interval duration = 1000ms. Mbps = (130000*8)/(1000/1000)/1_000_000
 
     let mut last_sample_time_ms = 0;
 
-    for (sample_end_time_ms, bytes_in_interval) in &metrics.bandwidth_samples {
+    for (sample_end_time_ms, bytes_in_interval, packets_in_interval) in &metrics.bandwidth_samples {
         let interval_duration_ms = sample_end_time_ms.saturating_sub(last_sample_time_ms);
         if interval_duration_ms == 0 {
             // Avoid division by zero if multiple samples at the same millisecond,
@@ -57,8 +298,6 @@ fn process_bandwidth_samples(metrics: &TestMetrics) -> Vec<(f64, f64)> {
                 eprintln!("Warning: Zero duration interval with {} bytes at {}ms", bytes_in_interval, sample_end_time_ms);
                 // Or assign a very high value, or average with next if possible.
             }
-            // if *bytes_in_interval == 0, then 0 mbps is fine.
-            // processed_samples.push((*sample_end_time_ms as f64 / 1000.0, 0.0));
             // Let's just update last_sample_time_ms and continue, the bytes will add to next interval.
             // This shouldn't happen often with current sampling logic.
             last_sample_time_ms = *sample_end_time_ms;
@@ -66,10 +305,10 @@ fn process_bandwidth_samples(metrics: &TestMetrics) -> Vec<(f64, f64)> {
         }
 
         let interval_duration_secs = interval_duration_ms as f64 / 1000.0;
-        let megabits_per_second = (*bytes_in_interval as f64 * 8.0) / interval_duration_secs / 1_000_000.0;
+        let per_second = amount(*bytes_in_interval, *packets_in_interval) / interval_duration_secs;
 
         // The timestamp for the graph point should represent the end of the interval
-        processed_samples.push((*sample_end_time_ms as f64 / 1000.0, megabits_per_second));
+        processed_samples.push((*sample_end_time_ms as f64 / 1000.0, per_second));
 
         last_sample_time_ms = *sample_end_time_ms;
     }
@@ -80,57 +319,701 @@ fn process_bandwidth_samples(metrics: &TestMetrics) -> Vec<(f64, f64)> {
 
 pub fn generate_summary(
     config: &TestConfig,
-    metrics: TestMetrics, // metrics itself contains the anomalies
+    mut metrics: TestMetrics, // metrics itself contains the anomalies
     actual_duration: std::time::Duration,
 ) -> TestSummary {
-    // For now, using simple string representation of time.
-    // Consider chrono crate for more robust time handling and formatting.
-    let now_utc = || {
-        humantime::format_rfc3339_seconds(SystemTime::now()).to_string()
-    };
+    metrics.anomaly_tx = None; // The run is over: close our end of the live feed
+    // RFC3339 in UTC, to the second
+    let format_utc = |time: SystemTime| humantime::format_rfc3339_seconds(time).to_string();
+    let now_utc = || format_utc(SystemTime::now());
+    let start_time_utc = metrics.test_start_wall_time.map(format_utc);
 
     let processed_bandwidth = process_bandwidth_samples(&metrics);
+    let processed_pps = process_pps_samples(&metrics);
+    let headline_rtt_micros = metrics.headline_rtt_micros(config.latency_stat);
+    let steady_state_throughput_mbps = metrics
+        .steady_state_throughput_bps(actual_duration.as_secs_f64())
+        .map(|bps| bps / 1_000_000.0);
+    let warmup_end_secs = metrics.warmup_end_ms.map(|ms| ms as f64 / 1000.0);
+    let loss_breakdown = metrics.loss_breakdown();
+    let mut phase_boundaries: Vec<PhaseBoundary> = metrics
+        .phase_marks
+        .iter()
+        .map(|(ms, label)| PhaseBoundary { time_secs: *ms as f64 / 1000.0, label: label.clone() })
+        .collect();
+    if let Some(warmup_end) = warmup_end_secs {
+        phase_boundaries.push(PhaseBoundary { time_secs: warmup_end, label: "Steady state (warmup end)".to_string() });
+    }
+    phase_boundaries.sort_by(|a, b| a.time_secs.total_cmp(&b.time_secs));
+    let paused_secs = metrics.control.paused_duration().as_secs_f64();
+    let direction_rates = (config.test_mode == TestMode::Bidirectional && !actual_duration.is_zero()).then(|| DirectionRates {
+        tx_pps: metrics.packets_sent as f64 / actual_duration.as_secs_f64(),
+        rx_pps: metrics.packets_received as f64 / actual_duration.as_secs_f64(),
+    });
+    let short_test_note = config.is_shorter_than_sample_interval().then(|| format!(
+        "Short test: {} ms is less than one {} ms sample interval, so bandwidth is a single sample over the whole test",
+        config.total_duration().as_millis(), config.bandwidth_sample_interval().as_millis()
+    ));
+    let bandwidth_stats = BandwidthStats::from_series(&processed_bandwidth);
     let anomalies_cloned = metrics.anomalies.clone(); // Clone before metrics is moved
 
-    TestSummary {
-        test_config: config.clone(),
+    let mut summary = TestSummary {
+        test_config: TestConfig { anomaly_tx: None, ..config.clone() },
         overall_metrics: metrics, // Note: metrics is moved here (including its .anomalies field)
         anomalies: anomalies_cloned, // Store the cloned list in TestSummary
-        start_time_utc: String::from("N/A (TODO)"), // Will be set at actual test start
+        start_time_utc: start_time_utc.unwrap_or_else(|| String::from("N/A")), // Metrics never started
         end_time_utc: now_utc(), // Set at test end
         test_duration_actual_secs: actual_duration.as_secs_f64(),
         bandwidth_over_time: processed_bandwidth,
-    }
+        pps_over_time: processed_pps,
+        headline_rtt_micros,
+        headline_rtt_label: config.latency_stat.to_string(),
+        steady_state_throughput_mbps,
+        warmup_end_secs,
+        loss_breakdown,
+        assertion_results: Vec::new(),
+        phase_boundaries,
+        paused_secs,
+        short_test_note,
+        direction_rates,
+        bottleneck: Bottleneck::Network(String::new()), // Diagnosed below, from the finished summary
+        bandwidth_stats,
+    };
+    summary.assertion_results = summary.check_assertions();
+    summary.bottleneck = diagnose_bottleneck(&summary, &summary.overall_metrics.process_samples, None);
+    summary
 }
 
-// Later, this module will have functions to format TestSummary into HTML
-// or other report formats.
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::{Protocol, TestConfig, TestMode, TcpBidirectionalMode}; // Added more imports
-    use crate::metrics::TestMetrics; // Ensure TestMetrics is in scope
-    use std::time::{Duration, Instant}; // Added Instant for metrics.test_start_time
-
 // Function to generate HTML report string
 pub fn generate_html_report_string(summary: &TestSummary) -> Result<String, askama::Error> {
-    // Prepare data for Chart.js
-    // Chart.js expects an array of objects like {time: seconds, mbps: value}
-    let chart_data_points: Vec<_> = summary.bandwidth_over_time.iter()
+    let latency_groups = summary.latency_groups();
+    let report_template = HtmlReport {
+        summary,
+        latency_rows: LatencyStatGroup::table(&latency_groups),
+        latency_groups,
+        bandwidth_chart_data_json: bandwidth_chart_data_json(summary),
+        phase_annotations_json: phase_annotations_json(summary),
+        process_chart_data_json: process_chart_data_json(summary),
+    };
+    report_template.render()
+}
+
+/// Bandwidth chart data for Chart.js: `[{"time": secs, "mbps": value}]`, decimated to
+/// `max_chart_points` so long tests still render quickly.
+pub fn bandwidth_chart_data_json(summary: &TestSummary) -> String {
+    let series = match summary.test_config.max_chart_points {
+        Some(max_points) => decimate_lttb(&summary.bandwidth_over_time, max_points),
+        None => summary.bandwidth_over_time.clone(),
+    };
+    let chart_data_points: Vec<_> = series.iter()
         .map(|(time_sec, mbps_val)| serde_json::json!({"time": time_sec, "mbps": mbps_val}))
         .collect();
+    script_json(&chart_data_points)
+}
 
-    let bandwidth_chart_data_json = serde_json::to_string(&chart_data_points)
-        .unwrap_or_else(|_| "[]".to_string()); // Default to empty array on serialization error
+/// `value` as JSON that is safe to embed in a `<script>` element: `<`, `>` and `&` are
+/// escaped, so a string like "</script>" in it can't end the element. "[]" if it won't serialize.
+fn script_json(value: &impl Serialize) -> String {
+    match serde_json::to_string(value) {
+        Ok(json) => json.replace('<', "\\u003c").replace('>', "\\u003e").replace('&', "\\u0026"),
+        Err(_) => "[]".to_string(),
+    }
+}
 
-    let report_template = HtmlReport {
-        summary,
-        bandwidth_chart_data_json,
+/// Largest-Triangle-Three-Buckets downsampling: keeps the first and last points and, from
+/// each of `max_points - 2` buckets in between, the point forming the largest triangle with
+/// the previously kept point and the next bucket's average. Peaks and dips survive, unlike
+/// with a plain stride.
+pub fn decimate_lttb(points: &[(f64, f64)], max_points: usize) -> Vec<(f64, f64)> {
+    let max_points = max_points.max(3);
+    let n = points.len();
+    if n <= max_points {
+        return points.to_vec();
+    }
+
+    let bucket_size = (n - 2) as f64 / (max_points - 2) as f64;
+    let bucket_start = |bucket: usize| ((bucket as f64 * bucket_size) as usize + 1).min(n - 1);
+    let mut sampled = Vec::with_capacity(max_points);
+    sampled.push(points[0]);
+    let mut previous = points[0];
+    for bucket in 0..max_points - 2 {
+        let next_start = bucket_start(bucket + 1);
+        let next = &points[next_start..bucket_start(bucket + 2).max(next_start + 1)]; // The last is just the final point
+        let next_avg = (
+            next.iter().map(|p| p.0).sum::<f64>() / next.len() as f64,
+            next.iter().map(|p| p.1).sum::<f64>() / next.len() as f64,
+        );
+        let triangle_area = |p: &(f64, f64)| {
+            ((previous.0 - next_avg.0) * (p.1 - previous.1) - (previous.0 - p.0) * (next_avg.1 - previous.1)).abs()
+        };
+        let chosen = points[bucket_start(bucket)..bucket_start(bucket + 1)]
+            .iter()
+            .max_by(|a, b| triangle_area(a).total_cmp(&triangle_area(b)))
+            .copied()
+            .unwrap_or(previous);
+        sampled.push(chosen);
+        previous = chosen;
+    }
+    sampled.push(points[n - 1]);
+    sampled
+}
+
+/// Chart annotation data for the phase boundaries: `[{"time": secs, "label": "..."}]`.
+pub fn phase_annotations_json(summary: &TestSummary) -> String {
+    let annotations: Vec<_> = summary.phase_boundaries.iter()
+        .map(|boundary| serde_json::json!({"time": boundary.time_secs, "label": boundary.label}))
+        .collect();
+    script_json(&annotations)
+}
+
+/// Process usage chart data: `[{"time": secs, "cpu": percent, "rss_mb": megabytes}]`.
+pub fn process_chart_data_json(summary: &TestSummary) -> String {
+    let points: Vec<_> = summary.overall_metrics.process_samples.iter()
+        .map(|sample| serde_json::json!({
+            "time": sample.timestamp_ms as f64 / 1000.0,
+            "cpu": sample.cpu_percent,
+            "rss_mb": sample.rss_bytes as f64 / 1_000_000.0,
+        }))
+        .collect();
+    script_json(&points)
+}
+
+/// The whole summary (config, overall metrics, time series, anomalies) as pretty-printed JSON.
+pub fn generate_json_report_string(summary: &TestSummary) -> Result<String, serde_json::Error> {
+    serde_json::to_string_pretty(summary)
+}
+
+/// Bandwidth-over-time series as CSV with a `time_sec,mbps` header.
+pub fn generate_bandwidth_csv(summary: &TestSummary) -> String {
+    let mut csv = String::from("time_sec,mbps\n");
+    for (time_sec, mbps) in &summary.bandwidth_over_time {
+        csv.push_str(&format!("{:.3},{:.6}\n", time_sec, mbps));
+    }
+    csv
+}
+
+/// Anomalies as CSV with a `timestamp_ms,type,description` header. The type is the
+/// variant name, as in the JSON export; descriptions are quoted since they contain commas.
+pub fn generate_anomalies_csv(summary: &TestSummary) -> String {
+    let mut csv = String::from("timestamp_ms,type,description\n");
+    for anomaly in &summary.anomalies {
+        csv.push_str(&format!(
+            "{},{:?},\"{}\"\n",
+            anomaly.timestamp_ms,
+            anomaly.anomaly_type,
+            anomaly.description.replace('"', "\"\"")
+        ));
+    }
+    csv
+}
+
+/// Writes `generate_bandwidth_csv` to `path`; needs no templates, so it suits headless runs.
+pub fn write_bandwidth_csv(summary: &TestSummary, path: &Path) -> io::Result<()> {
+    fs::write(path, generate_bandwidth_csv(summary))
+}
+
+/// Writes `generate_anomalies_csv` to `path`.
+pub fn write_anomalies_csv(summary: &TestSummary, path: &Path) -> io::Result<()> {
+    fs::write(path, generate_anomalies_csv(summary))
+}
+
+// Upper bounds of the OpenMetrics RTT histogram buckets, in seconds (+Inf is added)
+const OPENMETRICS_RTT_BUCKETS_SECS: [f64; 12] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5];
+
+/// The run's counters and RTT histogram in the OpenMetrics text format. The histogram is
+/// built from `rtt_histogram`, so it covers every RTT; the most recent retained sample at
+/// or above p95 is attached as an exemplar to its bucket, timestamped when the test's
+/// wall-clock start is known, so a scraper can point at when the outlier happened.
+pub fn to_openmetrics(summary: &TestSummary) -> String {
+    let metrics = &summary.overall_metrics;
+    let mut out = String::new();
+    let mut counter = |name: &str, help: &str, value: u64| {
+        out.push_str(&format!("# TYPE {0} counter\n# HELP {0} {1}\n{0}_total {2}\n", name, help, value));
     };
-    report_template.render()
+    counter("netstats_packets_sent", "Packets sent.", metrics.packets_sent);
+    counter("netstats_packets_received", "Packets received.", metrics.packets_received);
+    counter("netstats_bytes_sent", "Bytes sent.", metrics.bytes_sent);
+    counter("netstats_bytes_received", "Bytes received.", metrics.bytes_received);
+    out.push_str("# TYPE netstats_packet_loss_ratio gauge\n# HELP netstats_packet_loss_ratio Share of sent packets not received.\n");
+    out.push_str(&format!("netstats_packet_loss_ratio {}\n", metrics.packet_loss_percentage() / 100.0));
+
+    // Exemplar: (rtt_micros, time_ms) of the latest retained sample at or above p95
+    let exemplar = metrics.rtt_p95().and_then(|p95| {
+        metrics.rtt_samples.iter().zip(&metrics.rtt_sample_times_ms)
+            .filter(|(&rtt, _)| rtt as f64 >= p95)
+            .max_by_key(|(_, &time_ms)| time_ms)
+            .map(|(&rtt, &time_ms)| (rtt, time_ms))
+    });
+    let exemplar_text = exemplar.map(|(rtt, time_ms)| {
+        let timestamp = metrics.test_start_wall_time
+            .and_then(|start| start.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|start| format!(" {:.3}", start.as_secs_f64() + time_ms as f64 / 1000.0))
+            .unwrap_or_default();
+        format!(" # {{sample_time_ms=\"{}\"}} {}{}", time_ms, rtt as f64 / 1_000_000.0, timestamp)
+    });
+    // The exemplar goes on the first bucket that holds it; None (too slow for all) is +Inf
+    let bucket_micros = OPENMETRICS_RTT_BUCKETS_SECS.map(|le| (le * 1_000_000.0).round() as u128);
+    let exemplar_bucket = exemplar.and_then(|(rtt, _)| bucket_micros.iter().position(|&le_micros| rtt <= le_micros));
+
+    out.push_str("# TYPE netstats_rtt_seconds histogram\n# HELP netstats_rtt_seconds Round-trip time of echoed packets.\n");
+    for (index, (le, le_micros)) in OPENMETRICS_RTT_BUCKETS_SECS.iter().zip(bucket_micros).enumerate() {
+        let count = metrics.rtt_histogram.count_at_most(le_micros as u64);
+        let exemplar = exemplar_text.as_deref().filter(|_| exemplar_bucket == Some(index)).unwrap_or_default();
+        out.push_str(&format!("netstats_rtt_seconds_bucket{{le=\"{}\"}} {}{}\n", le, count, exemplar));
+    }
+    let exemplar = exemplar_text.as_deref().filter(|_| exemplar_bucket.is_none()).unwrap_or_default();
+    out.push_str(&format!("netstats_rtt_seconds_bucket{{le=\"+Inf\"}} {}{}\n", metrics.rtt_histogram.count(), exemplar));
+    out.push_str(&format!("netstats_rtt_seconds_count {}\n", metrics.rtt_histogram.count()));
+    out.push_str(&format!("netstats_rtt_seconds_sum {}\n", metrics.total_rtt_micros as f64 / 1_000_000.0));
+    out.push_str("# EOF\n");
+    out
+}
+
+// Rows of the Markdown bandwidth table; longer tests are decimated like the charts, so a
+// ticket gets a glanceable table rather than one row per sample interval
+const MARKDOWN_BANDWIDTH_ROWS: usize = 30;
+
+/// Plain Markdown version of the report: overall metrics, anomalies and a compact
+/// bandwidth table. Needs no templates, so it works anywhere the summary does.
+pub fn generate_markdown_report(summary: &TestSummary) -> String {
+    let config = &summary.test_config;
+    let metrics = &summary.overall_metrics;
+    let rtt = summary.headline_rtt_micros.map_or("N/A".to_string(), |rtt| format!("{:.3} ms", rtt / 1000.0));
+    let throughput_mbps = summary.throughput_mbps();
+
+    let mut md = String::from("# NetStats Test Report\n\n");
+    md.push_str(&format!("**Limiting factor: {}.** {}\n\n", summary.bottleneck.label(), summary.bottleneck.reason()));
+    if let Some(note) = &summary.short_test_note {
+        md.push_str(&format!("> {}\n\n", note));
+    }
+    md.push_str("| Metric | Value |\n|---|---|\n");
+    let rtt_label = format!("RTT ({})", summary.headline_rtt_label);
+    let mut rows = vec![
+        ("Target", format!("{}:{}", config.target_ip, config.target_port)),
+        ("Protocol", config.protocol.to_string()),
+        ("Duration", format!("{:.2} s", summary.test_duration_actual_secs)),
+        ("Packets Sent", metrics.packets_sent.to_string()),
+        ("Packets Received", metrics.packets_received.to_string()),
+        ("Packet Loss", format!("{:.2}%", metrics.packet_loss_percentage())),
+        (rtt_label.as_str(), rtt),
+    ];
+    if let Some(connect_rtt_ms) = metrics.connect_rtt_ms() {
+        // Next to the data RTT, which is from echo replies and may be N/A (e.g. plain TCP data)
+        rows.push(("Connect RTT (TCP handshake)", format!("{:.3} ms", connect_rtt_ms)));
+    }
+    rows.push(("Throughput", format!("{:.2} Mbps", throughput_mbps)));
+    if let Some(stats) = &summary.bandwidth_stats {
+        rows.push(("Interval Throughput (Avg / Median / P95)", format!(
+            "{:.2} / {:.2} / {:.2} Mbps", stats.avg_mbps, stats.median_mbps, stats.p95_mbps
+        )));
+        rows.push(("Peak Interval", format!("{:.2} Mbps at {:.2} s", stats.peak_mbps, stats.peak_time_secs)));
+    }
+    if let (Some(cv), Some(stability)) = (metrics.throughput_cv(), metrics.throughput_stability()) {
+        rows.push(("Throughput Stability (CV)", format!("{:.1}% ({})", cv, stability)));
+    }
+    for (label, value) in rows {
+        md.push_str(&format!("| {} | {} |\n", label, value));
+    }
+
+    let latency_groups = summary.latency_groups();
+    if !latency_groups.is_empty() {
+        md.push_str("\n## Latency (ms)\n\n| Statistic |");
+        for group in &latency_groups {
+            md.push_str(&format!(" {} |", group.label));
+        }
+        md.push_str(&format!("\n|---|{}\n", "---|".repeat(latency_groups.len())));
+        for (label, values) in LatencyStatGroup::table(&latency_groups) {
+            md.push_str(&format!("| {} |", label));
+            for value in values {
+                md.push_str(&format!(" {:.3} |", value));
+            }
+            md.push('\n');
+        }
+    }
+
+    md.push_str(&format!("\n## Anomalies ({})\n\n", summary.anomalies.len()));
+    if summary.anomalies.is_empty() {
+        md.push_str("No anomalies detected during this test.\n");
+    }
+    for anomaly in &summary.anomalies {
+        md.push_str(&format!(
+            "- [{:.3}s] {}: {}\n",
+            anomaly.timestamp_ms as f64 / 1000.0, anomaly.anomaly_type, anomaly.explanation()
+        ));
+    }
+
+    if !summary.bandwidth_over_time.is_empty() {
+        md.push_str("\n## Bandwidth Over Time\n\n| Time (s) | Mbps |\n|---|---|\n");
+        for (time_sec, mbps) in decimate_lttb(&summary.bandwidth_over_time, MARKDOWN_BANDWIDTH_ROWS) {
+            md.push_str(&format!("| {:.2} | {:.2} |\n", time_sec, mbps));
+        }
+    }
+    md
+}
+
+/// Side-by-side plain-text table of two runs of the same link, e.g. UDP vs TCP
+/// from `runner::run_combined`. Column headers are the runs' protocols.
+pub fn generate_protocol_comparison(a: &TestSummary, b: &TestSummary) -> String {
+    let avg_rtt = |s: &TestSummary| {
+        s.overall_metrics.average_rtt_micros()
+            .map_or("N/A".to_string(), |rtt| format!("{:.3}", rtt / 1000.0))
+    };
+
+    let efficiency = |s: &TestSummary| {
+        s.overall_metrics.efficiency_percentage().map_or("N/A".to_string(), |pct| format!("{:.2}", pct))
+    };
+
+    let rows = [
+        ("Throughput (Mbps)", format!("{:.2}", a.throughput_mbps()), format!("{:.2}", b.throughput_mbps())),
+        ("Packets Sent", a.overall_metrics.packets_sent.to_string(), b.overall_metrics.packets_sent.to_string()),
+        ("Packets Received", a.overall_metrics.packets_received.to_string(), b.overall_metrics.packets_received.to_string()),
+        ("Packet Loss (%)", format!("{:.2}", a.overall_metrics.packet_loss_percentage()), format!("{:.2}", b.overall_metrics.packet_loss_percentage())),
+        ("Avg. RTT (ms)", avg_rtt(a), avg_rtt(b)),
+        ("Efficiency (%)", efficiency(a), efficiency(b)),
+        ("Anomalies", a.anomalies.len().to_string(), b.anomalies.len().to_string()),
+    ];
+
+    let mut out = format!(
+        "Protocol Comparison ({} vs {})\n{:<20} {:>12} {:>12}\n",
+        a.test_config.protocol, b.test_config.protocol,
+        "Metric", a.test_config.protocol.to_string(), b.test_config.protocol.to_string()
+    );
+    for (label, a_val, b_val) in rows {
+        out.push_str(&format!("{:<20} {:>12} {:>12}\n", label, a_val, b_val));
+    }
+    out
+}
+
+// Loss figures (in percentage points) closer than this are considered to agree
+const RECONCILE_TOLERANCE_PERCENT: f64 = 0.1;
+
+/// Both sides of one client+server run, cross-checked. The client knows what it sent and the
+/// round-trip (echo) loss; the server knows what actually arrived. See `ReconciledSummary::new`.
+#[derive(Debug)]
+pub struct ReconciledSummary {
+    pub client: TestSummary,
+    pub server: TestSummary,
+    // Client sent vs server received: loss on the way to the server only
+    pub forward_loss_percentage: f64,
+    // Requests that reached the server but whose reply never made it back to the client
+    pub return_loss_percentage: f64,
+    pub discrepancies: Vec<Discrepancy>, // Empty if the two sides agree
+}
+
+/// A cross-check on which the client and server disagree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discrepancy {
+    pub metric: &'static str,
+    pub client_value: f64,
+    pub server_value: f64,
+    pub description: String,
+}
+
+impl ReconciledSummary {
+    /// Combines the client's and the server's summary of the same run.
+    pub fn new(client: TestSummary, server: TestSummary) -> Self {
+        let client_sent = client.overall_metrics.packets_sent;
+        let client_received = client.overall_metrics.packets_received; // Echo replies
+        let server_received = server.overall_metrics.packets_received;
+        let percent_of = |part: u64, whole: u64| if whole == 0 { 0.0 } else { part as f64 / whole as f64 * 100.0 };
+        let forward_loss_percentage = percent_of(client_sent.saturating_sub(server_received), client_sent);
+        let return_loss_percentage = percent_of(server_received.saturating_sub(client_received), server_received);
+
+        let mut discrepancies = Vec::new();
+        if server_received > client_sent {
+            discrepancies.push(Discrepancy {
+                metric: "Packets",
+                client_value: client_sent as f64,
+                server_value: server_received as f64,
+                description: format!(
+                    "Server received {} packets but the client only sent {} (duplicates, or summaries of different runs)",
+                    server_received, client_sent
+                ),
+            });
+        }
+        // The server's own estimate only sees gaps in the sequence numbers, so it misses loss
+        // at the tail of the test
+        if let Some(breakdown) = &server.loss_breakdown {
+            let expected = breakdown.lost + server_received.saturating_sub(breakdown.duplicated);
+            let server_loss_percentage = percent_of(breakdown.lost, expected);
+            if (server_loss_percentage - forward_loss_percentage).abs() > RECONCILE_TOLERANCE_PERCENT {
+                discrepancies.push(Discrepancy {
+                    metric: "Forward Loss (%)",
+                    client_value: forward_loss_percentage,
+                    server_value: server_loss_percentage,
+                    description: format!(
+                        "Server saw {:.2}% loss from sequence gaps, but {:.2}% of the client's packets never arrived",
+                        server_loss_percentage, forward_loss_percentage
+                    ),
+                });
+            }
+        }
+        let client_loss_percentage = client.overall_metrics.packet_loss_percentage();
+        if client_loss_percentage - forward_loss_percentage > RECONCILE_TOLERANCE_PERCENT {
+            discrepancies.push(Discrepancy {
+                metric: "Round-Trip Loss (%)",
+                client_value: client_loss_percentage,
+                server_value: forward_loss_percentage,
+                description: format!(
+                    "Client saw {:.2}% loss but only {:.2}% was on the way out: {:.2}% of replies were lost on the return path",
+                    client_loss_percentage, forward_loss_percentage, return_loss_percentage
+                ),
+            });
+        }
+
+        ReconciledSummary { client, server, forward_loss_percentage, return_loss_percentage, discrepancies }
+    }
+
+    /// True if no cross-check flagged a discrepancy.
+    pub fn is_consistent(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+/// Plain-text client vs server table for a `ReconciledSummary`, followed by its discrepancies.
+pub fn generate_reconciliation_report(reconciled: &ReconciledSummary) -> String {
+    let (client, server) = (&reconciled.client.overall_metrics, &reconciled.server.overall_metrics);
+    let rows = [
+        ("Packets Sent", client.packets_sent.to_string(), server.packets_sent.to_string()),
+        ("Packets Received", client.packets_received.to_string(), server.packets_received.to_string()),
+        ("Bytes Sent", client.bytes_sent.to_string(), server.bytes_sent.to_string()),
+        ("Bytes Received", client.bytes_received.to_string(), server.bytes_received.to_string()),
+    ];
+
+    let mut out = format!("Client vs Server\n{:<20} {:>12} {:>12}\n", "Metric", "Client", "Server");
+    for (label, client_val, server_val) in rows {
+        out.push_str(&format!("{:<20} {:>12} {:>12}\n", label, client_val, server_val));
+    }
+    out.push_str(&format!(
+        "Forward loss: {:.2}%, return loss: {:.2}%\n",
+        reconciled.forward_loss_percentage, reconciled.return_loss_percentage
+    ));
+    for discrepancy in &reconciled.discrepancies {
+        out.push_str(&format!("DISCREPANCY {}: {}\n", discrepancy.metric, discrepancy.description));
+    }
+    out
 }
 
+/// Playout loss of a fixed de-jitter buffer, as a percentage of `latency_samples`.
+/// Samples are per-packet latencies in microseconds, in send order (e.g. `rtt_samples`).
+/// Playout is scheduled from the first packet: every packet is due `buffer_ms` after the
+/// first packet's latency, so one arriving later than `first + buffer` misses its slot
+/// and counts as lost even though it was received. Returns 0.0 for no samples.
+pub fn simulate_jitter_buffer(latency_samples: &[u128], buffer_ms: u64) -> f64 {
+    let Some(&first_micros) = latency_samples.first() else {
+        return 0.0;
+    };
+    let playout_micros = first_micros + buffer_ms as u128 * 1000;
+    let late = latency_samples.iter().filter(|&&latency| latency > playout_micros).count();
+    late as f64 / latency_samples.len() as f64 * 100.0
+}
+
+/// File name of the HTML report inside a run's output directory.
+pub const HTML_REPORT_FILE_NAME: &str = "report.html";
+
+/// Files written for one run by `write_run_artifacts`.
+#[derive(Debug, Clone)]
+pub struct RunArtifacts {
+    pub run_dir: PathBuf,
+    pub files: Vec<(ReportFormat, PathBuf)>, // In the order the formats were requested
+}
+
+impl RunArtifacts {
+    /// Path of the report written in `format`, if it was requested.
+    pub fn path(&self, format: ReportFormat) -> Option<&Path> {
+        self.files.iter().find(|(f, _)| *f == format).map(|(_, path)| path.as_path())
+    }
+}
+
+/// Creates a fresh, uniquely named directory for one run's artifacts under `output_dir`.
+/// The name is `run_<UTC timestamp>_<random suffix>`, so two runs started within the
+/// same second still get distinct directories instead of overwriting each other.
+pub fn create_run_output_dir(output_dir: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(output_dir)?;
+    // RFC3339 without separators that are awkward (or invalid on Windows) in file names
+    let timestamp: String = humantime::format_rfc3339_seconds(SystemTime::now())
+        .to_string()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+
+    let mut attempts = 0;
+    loop {
+        let suffix: u32 = rand::random();
+        let run_dir = output_dir.join(format!("run_{}_{:08x}", timestamp, suffix));
+        match fs::create_dir(&run_dir) {
+            Ok(()) => return Ok(run_dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 8 => attempts += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Binary archive format: magic + format version, then the bincode-encoded TestSummary.
+// bincode is positional, so bump the version whenever TestSummary (or anything it contains)
+// changes shape, and replace tests/fixtures/report_v<N>.bin with one written by the new version.
+// Version 1 predates interval packet counts, anomaly thresholds, session ids and others;
+// version 2 predates TestConfig::send_rng_seed.
+const BINARY_REPORT_MAGIC: &[u8; 4] = b"NSBR";
+const BINARY_REPORT_VERSION: u8 = 3;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Compact binary encoding of `summary` for archiving many runs; `from_binary` restores it,
+/// e.g. to re-render the HTML report later. Run-time only state (timers, packet tape,
+/// pause control) is not stored.
+pub fn to_binary(summary: &TestSummary) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(1024);
+    bytes.extend_from_slice(BINARY_REPORT_MAGIC);
+    bytes.push(BINARY_REPORT_VERSION);
+    bincode::serialize_into(&mut bytes, summary)
+        .map_err(|e| io::Error::other(format!("Failed to encode binary report: {}", e)))?;
+    Ok(bytes)
+}
+
+/// `to_binary`, gzip-compressed. RTT and bandwidth sample arrays compress well.
+pub fn to_binary_gzip(summary: &TestSummary) -> io::Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&to_binary(summary)?)?;
+    encoder.finish()
+}
+
+/// Decodes a report written by `to_binary` or `to_binary_gzip` (detected automatically).
+pub fn from_binary(bytes: &[u8]) -> io::Result<TestSummary> {
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+        return from_binary(&decompressed);
+    }
+
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let body = bytes
+        .strip_prefix(BINARY_REPORT_MAGIC.as_slice())
+        .ok_or_else(|| invalid("Not a netstats binary report".to_string()))?;
+    match body.split_first() {
+        Some((&BINARY_REPORT_VERSION, encoded)) => {
+            bincode::deserialize(encoded).map_err(|e| invalid(format!("Corrupt binary report: {}", e)))
+        }
+        Some((version, _)) => Err(invalid(format!("Unsupported binary report version {}", version))),
+        None => Err(invalid("Truncated binary report".to_string())),
+    }
+}
+
+/// Writes the report in every format in `test_config.output_formats` into a new per-run
+/// directory under `output_dir`.
+pub fn write_run_artifacts(summary: &TestSummary, output_dir: &Path) -> io::Result<RunArtifacts> {
+    let run_dir = create_run_output_dir(output_dir)?;
+    let files = write_reports(summary, &run_dir, &summary.test_config.output_formats)?;
+    Ok(RunArtifacts { run_dir, files })
+}
+
+/// Renders `summary` in each of `formats` (duplicates ignored) into `dir`, named by
+/// `ReportFormat::file_name`, and returns the written paths.
+pub fn write_reports(summary: &TestSummary, dir: &Path, formats: &[ReportFormat]) -> io::Result<Vec<(ReportFormat, PathBuf)>> {
+    let mut written: Vec<(ReportFormat, PathBuf)> = Vec::new();
+    for &format in formats {
+        if written.iter().any(|(f, _)| *f == format) {
+            continue;
+        }
+        let content = match format {
+            ReportFormat::Html => generate_html_report_string(summary)
+                .map_err(|e| io::Error::other(format!("Failed to render HTML report: {}", e)))?,
+            ReportFormat::Json => generate_json_report_string(summary)
+                .map_err(|e| io::Error::other(format!("Failed to render JSON report: {}", e)))?,
+            ReportFormat::Csv => generate_bandwidth_csv(summary),
+            ReportFormat::Markdown => generate_markdown_report(summary),
+            ReportFormat::OpenMetrics => to_openmetrics(summary),
+        };
+        let path = dir.join(format.file_name());
+        fs::write(&path, content)?;
+        written.push((format, path));
+    }
+    Ok(written)
+}
+
+/// Errors from the results database, see `append_to_sqlite`.
+#[derive(Debug)]
+pub enum DbError {
+    Sqlite(rusqlite::Error),
+}
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbError::Sqlite(e) => write!(f, "Results database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(err: rusqlite::Error) -> Self {
+        DbError::Sqlite(err)
+    }
+}
+
+// One row per run. Columns are only ever added, so old databases keep working.
+const SQLITE_RUNS_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    start_time_utc TEXT NOT NULL,
+    end_time_utc TEXT NOT NULL,
+    target TEXT NOT NULL,
+    protocol TEXT NOT NULL,
+    test_mode TEXT NOT NULL,
+    duration_secs REAL NOT NULL,
+    packets_sent INTEGER NOT NULL,
+    packets_received INTEGER NOT NULL,
+    loss_percent REAL NOT NULL,
+    throughput_mbps REAL NOT NULL,
+    avg_rtt_ms REAL,
+    p95_rtt_ms REAL,
+    anomaly_count INTEGER NOT NULL,
+    assertions_passed INTEGER NOT NULL
+)";
+
+/// Appends one row for `summary` to the `runs` table of the SQLite database at `db_path`,
+/// creating the file and table if needed. For trends across runs, e.g.
+/// `SELECT date(start_time_utc), AVG(throughput_mbps) FROM runs GROUP BY 1`.
+pub fn append_to_sqlite(summary: &TestSummary, db_path: &Path) -> Result<(), DbError> {
+    let connection = rusqlite::Connection::open(db_path)?;
+    connection.execute(SQLITE_RUNS_SCHEMA, [])?;
+
+    let config = &summary.test_config;
+    let metrics = &summary.overall_metrics;
+    let micros_to_ms = |micros: f64| micros / 1000.0;
+    connection.execute(
+        "INSERT INTO runs (start_time_utc, end_time_utc, target, protocol, test_mode, duration_secs,
+            packets_sent, packets_received, loss_percent, throughput_mbps, avg_rtt_ms, p95_rtt_ms,
+            anomaly_count, assertions_passed)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+        rusqlite::params![
+            summary.start_time_utc,
+            summary.end_time_utc,
+            format!("{}:{}", config.target_ip, config.target_port),
+            config.protocol.to_string(),
+            format!("{:?}", config.test_mode),
+            summary.test_duration_actual_secs,
+            metrics.packets_sent as i64,
+            metrics.packets_received as i64,
+            metrics.packet_loss_percentage(),
+            summary.throughput_mbps(),
+            metrics.average_rtt_micros().map(micros_to_ms),
+            metrics.rtt_p95().map(micros_to_ms),
+            summary.anomalies.len() as i64,
+            summary.assertions_passed(),
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Protocol, TestConfig, TestMode}; // Added more imports
+    use crate::metrics::TestMetrics; // Ensure TestMetrics is in scope
+    use std::time::{Duration, Instant}; // Added Instant for metrics.test_start_time
+
+
     #[test]
     fn test_generate_summary_and_process_bandwidth() {
         let config = TestConfig {
@@ -143,6 +1026,7 @@ pub fn generate_html_report_string(summary: &TestSummary) -> Result<String, aska
             protocol: Protocol::Udp,
             test_mode: TestMode::Client,
             tcp_bidirectional_mode: None,
+            ..Default::default()
         };
 
         let mut metrics = TestMetrics::default(); // Use default and populate
@@ -158,14 +1042,14 @@ pub fn generate_html_report_string(summary: &TestSummary) -> Result<String, aska
         metrics.inter_arrival_jitter_micros_sum = 1000;
         metrics.jitter_count = 44;
 
-        // Raw samples: (timestamp_ms_since_start, bytes_in_interval)
+        // Raw samples: (timestamp_ms_since_start, bytes_in_interval, packets_in_interval)
         // Sample 1: At 1s (1000ms), 125000 bytes received in the interval (0-1000ms)
         // Sample 2: At 2s (2000ms), 130000 bytes received in the interval (1000-2000ms)
         // Sample 3: At 2.5s (2500ms), 60000 bytes received in the interval (2000-2500ms)
         metrics.bandwidth_samples = vec![
-            (1000, 125000), // 125000 B in 1s  -> 1 Mbps
-            (2000, 130000), // 130000 B in 1s  -> 1.04 Mbps
-            (2500, 60000),  // 60000 B in 0.5s -> 0.96 Mbps
+            (1000, 125000, 20), // 125000 B in 1s  -> 1 Mbps
+            (2000, 130000, 20), // 130000 B in 1s  -> 1.04 Mbps
+            (2500, 60000, 10),  // 60000 B in 0.5s -> 0.96 Mbps
         ];
 
         let anomalies = vec![
@@ -173,6 +1057,7 @@ pub fn generate_html_report_string(summary: &TestSummary) -> Result<String, aska
                 timestamp_ms: 1500,
                 anomaly_type: crate::anomalies::AnomalyType::PacketLoss,
                 description: "Packet sequence 23 lost".to_string(),
+                threshold: None,
             }
         ];
         // metrics.anomalies is not populated in this specific test setup directly,
@@ -219,7 +1104,7 @@ pub fn generate_html_report_string(summary: &TestSummary) -> Result<String, aska
         assert!(html_content.contains("<h2>Overall Metrics</h2>"));
         assert!(html_content.contains("id=\"bandwidthChart\""));
         assert!(html_content.contains("127.0.0.1")); // Check if config data is rendered
-        assert!(html_content.contains("1.00 Mbps")); // Check if a bandwidth value is rendered (approx)
+        assert!(html_content.contains("1.04 Mbps")); // The peak interval is rendered
 
         // Optionally, write to a file for manual inspection:
         // use std::fs::File;
@@ -228,4 +1113,592 @@ pub fn generate_html_report_string(summary: &TestSummary) -> Result<String, aska
         // file.write_all(html_content.as_bytes()).unwrap();
         // println!("Test report written to test_report.html");
     }
+
+    #[test]
+    fn test_steady_state_throughput_in_summary() {
+        let config = TestConfig { warmup_secs: 1, ..Default::default() };
+        let mut metrics = TestMetrics::default();
+        metrics.configure_warmup(&config);
+        metrics.init_start_time();
+        metrics.record_packet_received(250_000, 0); // Warmup burst
+        metrics.test_start_time = Instant::now().checked_sub(Duration::from_secs(2));
+        metrics.record_packet_received(125_000, 0); // Post-warmup
+        metrics.take_bandwidth_sample(2000);
+
+        let summary = generate_summary(&config, metrics, Duration::from_secs(2));
+
+        // Headline: 125000 B over the 1s after warmup = 1 Mbps
+        let steady = summary.steady_state_throughput_mbps.expect("Steady-state throughput missing");
+        assert!((steady - 1.0).abs() < 0.001);
+        assert_eq!(summary.warmup_end_secs, Some(1.0));
+        // Chart still includes the warmup bytes: 375000 B over 2s = 1.5 Mbps
+        assert_eq!(summary.bandwidth_over_time.len(), 1);
+        assert!((summary.bandwidth_over_time[0].1 - 1.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_pps_series_is_independent_of_packet_size() {
+        let mut metrics = TestMetrics::default();
+        metrics.test_start_time = Some(Instant::now());
+        for _ in 0..100 {
+            metrics.record_packet_received(1000, 0); // Large packets
+        }
+        metrics.take_bandwidth_sample(1000);
+        for _ in 0..100 {
+            metrics.record_packet_received(100, 0); // Same rate, a tenth of the size
+        }
+        metrics.take_bandwidth_sample(2000);
+        metrics.take_bandwidth_sample(2500); // Nothing received
+
+        let summary = generate_summary(&TestConfig::default(), metrics, Duration::from_millis(2500));
+
+        assert_eq!(summary.bandwidth_over_time, vec![(1.0, 0.8), (2.0, 0.08), (2.5, 0.0)]);
+        assert_eq!(summary.pps_over_time, vec![(1.0, 100.0), (2.0, 100.0), (2.5, 0.0)]);
+    }
+
+    #[test]
+    fn test_loss_breakdown_rendered_in_report() {
+        let mut metrics = TestMetrics::default();
+        metrics.configure_anomaly_detection(&TestConfig { rtt_timeout_ms: 200, ..Default::default() });
+        // Gaps at 2 and 5, 3 reordered, 1 and 6 duplicated, 4 late
+        for (seq, arrival_ms) in [(0, 0), (1, 10), (1, 12), (6, 20), (3, 25), (6, 30), (7, 40), (4, 500)] {
+            metrics.record_packet_received(64, 0);
+            metrics.record_sequence(seq, arrival_ms);
+        }
+
+        let summary = generate_summary(&TestConfig::default(), metrics, Duration::from_secs(1));
+        assert_eq!(summary.loss_breakdown, Some(LossBreakdown { lost: 2, reordered: 1, duplicated: 2, late: 1 }));
+
+        let html = generate_html_report_string(&summary).expect("Failed to render HTML");
+        assert!(html.contains("<tr><th>Lost</th><td>2</td></tr>"), "Lost count missing");
+        assert!(html.contains("<tr><th>Reordered (arrived)</th><td>1</td></tr>"), "Reordered count missing");
+        assert!(html.contains("<tr><th>Duplicated</th><td>2</td></tr>"), "Duplicated count missing");
+        assert!(html.contains("<tr><th>Late</th><td>1</td></tr>"), "Late count missing");
+    }
+
+    #[test]
+    fn test_summary_checks_configured_assertions() {
+        let config = TestConfig {
+            assertions: vec!["loss < 1%".parse().unwrap(), "throughput >= 2mbps".parse().unwrap()],
+            ..Default::default()
+        };
+        let mut metrics = TestMetrics::default();
+        metrics.packets_sent = 100;
+        metrics.packets_received = 100;
+        metrics.bytes_received = 125_000; // 1 Mbps over 1s
+
+        let summary = generate_summary(&config, metrics, Duration::from_secs(1));
+        assert_eq!(summary.assertion_results.len(), 2);
+        assert!(summary.assertion_results[0].passed);
+        assert!(!summary.assertion_results[1].passed);
+        assert!(!summary.assertions_passed());
+        assert_eq!(summary.check_assertions(), summary.assertion_results);
+
+        let html = generate_html_report_string(&summary).expect("Failed to render HTML");
+        assert!(html.contains("throughput &gt;= 2mbps"), "Assertion missing from report");
+        assert!(html.contains("FAIL"));
+    }
+
+    #[test]
+    fn test_protocol_comparison_mentions_both_protocols() {
+        let mut udp_metrics = TestMetrics::default();
+        udp_metrics.packets_sent = 100;
+        udp_metrics.packets_received = 90;
+        udp_metrics.bytes_received = 125_000;
+        let udp_config = TestConfig { protocol: Protocol::Udp, ..Default::default() };
+        let udp = generate_summary(&udp_config, udp_metrics, Duration::from_secs(1));
+
+        let mut tcp_metrics = TestMetrics::default();
+        tcp_metrics.packets_sent = 100;
+        tcp_metrics.packets_received = 100;
+        let tcp_config = TestConfig { protocol: Protocol::Tcp, ..Default::default() };
+        let tcp = generate_summary(&tcp_config, tcp_metrics, Duration::from_secs(1));
+
+        let comparison = generate_protocol_comparison(&udp, &tcp);
+        assert!(comparison.contains("UDP"), "{}", comparison);
+        assert!(comparison.contains("TCP"), "{}", comparison);
+        let loss_row = comparison.lines().find(|l| l.starts_with("Packet Loss")).unwrap();
+        assert!(loss_row.contains("10.00") && loss_row.contains("0.00"), "{}", loss_row);
+        let throughput_row = comparison.lines().find(|l| l.starts_with("Throughput")).unwrap();
+        assert!(throughput_row.contains("1.00"), "{}", throughput_row);
+    }
+
+    #[test]
+    fn test_reconciled_summary_splits_forward_and_return_loss() {
+        let mut client_metrics = TestMetrics::default();
+        client_metrics.packets_sent = 1000;
+        client_metrics.packets_received = 990; // Echo replies
+        let client = generate_summary(&TestConfig::default(), client_metrics, Duration::from_secs(10));
+
+        let mut server_metrics = TestMetrics::default();
+        for seq in 0..1000u32 {
+            if ![10, 20, 30].contains(&seq) {
+                server_metrics.record_packet_received(64, 0);
+                server_metrics.record_sequence(seq, seq as u128 * 10);
+            }
+        }
+        let server_config = TestConfig { test_mode: crate::config::TestMode::Server, ..Default::default() };
+        let server = generate_summary(&server_config, server_metrics, Duration::from_secs(15));
+
+        let reconciled = ReconciledSummary::new(client, server);
+        // Sent 1000, 997 arrived: 0.3% forward; 7 of the 997 replies went missing on the way back
+        assert!((reconciled.forward_loss_percentage - 0.3).abs() < 1e-9);
+        assert!((reconciled.return_loss_percentage - 7.0 / 997.0 * 100.0).abs() < 1e-9);
+        // The server's sequence gaps agree with the forward loss; the client's 1% doesn't
+        assert_eq!(reconciled.discrepancies.len(), 1, "{:?}", reconciled.discrepancies);
+        let discrepancy = &reconciled.discrepancies[0];
+        assert_eq!(discrepancy.metric, "Round-Trip Loss (%)");
+        assert!((discrepancy.client_value - 1.0).abs() < 1e-9);
+        assert!((discrepancy.server_value - 0.3).abs() < 1e-9);
+        assert!(!reconciled.is_consistent());
+
+        let report = generate_reconciliation_report(&reconciled);
+        assert!(report.contains("Forward loss: 0.30%"), "{}", report);
+        assert!(report.contains("DISCREPANCY Round-Trip Loss"), "{}", report);
+    }
+
+    #[test]
+    fn test_append_to_sqlite_adds_a_row_per_run() {
+        let db_path = std::env::temp_dir().join(format!("netstats_runs_test_{}.sqlite", std::process::id()));
+        let _ = fs::remove_file(&db_path);
+        for bytes_received in [125_000, 250_000] {
+            let mut metrics = TestMetrics::default();
+            metrics.packets_sent = 100;
+            metrics.packets_received = 100;
+            metrics.bytes_received = bytes_received;
+            let summary = generate_summary(&TestConfig::default(), metrics, Duration::from_secs(1));
+            append_to_sqlite(&summary, &db_path).unwrap();
+        }
+
+        let connection = rusqlite::Connection::open(&db_path).unwrap();
+        let row_count: i64 = connection.query_row("SELECT COUNT(*) FROM runs", [], |row| row.get(0)).unwrap();
+        let (protocol, throughput): (String, f64) = connection
+            .query_row("SELECT protocol, throughput_mbps FROM runs ORDER BY id DESC LIMIT 1", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        let avg_throughput: f64 = connection.query_row("SELECT AVG(throughput_mbps) FROM runs", [], |row| row.get(0)).unwrap();
+        drop(connection);
+        let _ = fs::remove_file(&db_path);
+
+        assert_eq!(row_count, 2);
+        assert_eq!(protocol, "UDP");
+        assert!((throughput - 2.0).abs() < 1e-9, "{}", throughput);
+        assert!((avg_throughput - 1.5).abs() < 1e-9, "{}", avg_throughput);
+    }
+
+    #[test]
+    fn test_bottleneck_diagnosis() {
+        let config = TestConfig { tick_rate_hz: 100, packets_per_tick: 1, ..Default::default() };
+        let summary_with = |sent: u64, received: u64| {
+            let mut metrics = TestMetrics::default();
+            metrics.packets_sent = sent;
+            metrics.packets_received = received;
+            generate_summary(&config, metrics, Duration::from_secs(10))
+        };
+        let busy = [ProcessSample { timestamp_ms: 1000, cpu_percent: 99.0, rss_bytes: 0 }];
+
+        // 100 Hz for 10 s: exactly the configured rate, even with a busy CPU
+        let summary = summary_with(1000, 1000);
+        assert!(matches!(summary.bottleneck, Bottleneck::RateLimited(_)), "{:?}", summary.bottleneck);
+        assert!(matches!(diagnose_bottleneck(&summary, &busy, Some(1)), Bottleneck::RateLimited(_)));
+
+        let summary = summary_with(600, 600);
+        assert!(matches!(summary.bottleneck, Bottleneck::Network(_)), "{:?}", summary.bottleneck);
+        assert!(matches!(diagnose_bottleneck(&summary, &busy, None), Bottleneck::Cpu(_)));
+        assert!(matches!(diagnose_bottleneck(&summary, &[], Some(65)), Bottleneck::Loopback(_)));
+        let summary = summary.with_loopback_ceiling(65);
+        assert!(matches!(summary.bottleneck, Bottleneck::Loopback(_)), "{:?}", summary.bottleneck);
+
+        let summary = summary_with(1000, 900);
+        assert!(matches!(summary.bottleneck, Bottleneck::PacketLoss(_)), "{:?}", summary.bottleneck);
+        assert!(summary.bottleneck.reason().contains("10.00%"), "{}", summary.bottleneck.reason());
+
+        let html = generate_html_report_string(&summary).unwrap();
+        assert!(html.contains("Limiting Factor: Packet loss"));
+    }
+
+    #[test]
+    fn test_direction_rates_only_for_bidirectional() {
+        let metrics = || {
+            let mut metrics = TestMetrics::default();
+            metrics.packets_sent = 100;
+            metrics.packets_received = 20;
+            metrics
+        };
+        let config = TestConfig { test_mode: TestMode::Bidirectional, ..Default::default() };
+        let summary = generate_summary(&config, metrics(), Duration::from_secs(2));
+        assert_eq!(summary.direction_rates, Some(DirectionRates { tx_pps: 50.0, rx_pps: 10.0 }));
+
+        let summary = generate_summary(&TestConfig::default(), metrics(), Duration::from_secs(2));
+        assert_eq!(summary.direction_rates, None);
+    }
+
+    #[test]
+    fn test_jitter_buffer_playout_loss() {
+        // 20ms base latency with spikes; a 30ms buffer plays out anything up to 50ms
+        let latencies: Vec<u128> = [20, 25, 45, 50, 51, 80, 22, 20, 60, 30]
+            .iter()
+            .map(|ms| ms * 1000)
+            .collect();
+        assert_eq!(simulate_jitter_buffer(&latencies, 30), 30.0); // 51, 80 and 60ms are late
+        assert_eq!(simulate_jitter_buffer(&latencies, 50), 10.0); // Only 80ms
+        assert_eq!(simulate_jitter_buffer(&latencies, 0), 80.0); // Only the 20ms packets play
+        assert_eq!(simulate_jitter_buffer(&[], 30), 0.0);
+    }
+
+    #[test]
+    fn test_phase_boundaries_in_chart_annotations() {
+        let mut metrics = TestMetrics::default();
+        metrics.phase_marks.push((0, "Baseline".to_string()));
+        metrics.phase_marks.push((3_000, "Congested".to_string()));
+        metrics.bandwidth_samples = vec![(1000, 125_000, 100), (2000, 125_000, 100), (3000, 125_000, 100), (4000, 12_500, 10)];
+        let summary = generate_summary(&TestConfig::default(), metrics, Duration::from_secs(4));
+
+        assert_eq!(summary.phase_boundaries.len(), 2);
+        assert_eq!(summary.phase_boundaries[1], PhaseBoundary { time_secs: 3.0, label: "Congested".to_string() });
+
+        let annotations: serde_json::Value = serde_json::from_str(&phase_annotations_json(&summary)).unwrap();
+        assert_eq!(annotations[1]["time"], 3.0);
+        assert_eq!(annotations[1]["label"], "Congested");
+
+        let html = generate_html_report_string(&summary).unwrap();
+        assert!(html.contains(r#"{"label":"Congested","time":3.0}"#), "Annotation data missing from the chart script");
+
+        // A label can't close the chart's <script> element early
+        let mut metrics = TestMetrics::default();
+        metrics.phase_marks.push((0, "</script><b>&x".to_string()));
+        let summary = generate_summary(&TestConfig::default(), metrics, Duration::from_secs(1));
+        let annotations = phase_annotations_json(&summary);
+        assert!(annotations.contains(r"\u003c/script\u003e\u003cb\u003e\u0026x"), "{}", annotations);
+        let parsed: serde_json::Value = serde_json::from_str(&annotations).unwrap();
+        assert_eq!(parsed[0]["label"], "</script><b>&x");
+        assert!(!generate_html_report_string(&summary).unwrap().contains("</script><b>"));
+    }
+
+    #[test]
+    fn test_chart_is_decimated_but_exports_are_complete() {
+        let config = TestConfig { max_chart_points: Some(500), ..Default::default() };
+        let mut metrics = TestMetrics::default();
+        // 10000 samples, 100 ms apart, with one sharp spike that must survive decimation
+        metrics.bandwidth_samples = (1..=10_000u128)
+            .map(|i| (i * 100, if i == 4_321 { 1_000_000 } else { 10_000 + (i as u64 % 7) * 100 }, 10))
+            .collect();
+        let summary = generate_summary(&config, metrics, Duration::from_secs(1_000));
+        assert_eq!(summary.bandwidth_over_time.len(), 10_000);
+
+        let chart: Vec<serde_json::Value> = serde_json::from_str(&bandwidth_chart_data_json(&summary)).unwrap();
+        assert!(chart.len() <= 500, "{} chart points", chart.len());
+        assert_eq!(chart.first().unwrap()["time"], summary.bandwidth_over_time[0].0);
+        assert_eq!(chart.last().unwrap()["time"], summary.bandwidth_over_time[9_999].0);
+        let peak = summary.bandwidth_over_time.iter().map(|p| p.1).fold(0.0, f64::max);
+        assert!(chart.iter().any(|point| point["mbps"] == peak), "The spike was decimated away");
+
+        assert_eq!(generate_bandwidth_csv(&summary).lines().count(), 10_001); // Header + every point
+        let json: serde_json::Value = serde_json::from_str(&generate_json_report_string(&summary).unwrap()).unwrap();
+        assert_eq!(json["bandwidth_over_time"].as_array().unwrap().len(), 10_000);
+
+        assert_eq!(decimate_lttb(&summary.bandwidth_over_time[..100], 500).len(), 100);
+    }
+
+    #[test]
+    fn test_json_report_round_trips_key_fields() {
+        let config = TestConfig { target_ip: "192.0.2.7".to_string(), protocol: Protocol::Tcp, ..Default::default() };
+        let mut metrics = TestMetrics::default();
+        metrics.packets_sent = 20;
+        metrics.packets_received = 19;
+        metrics.bandwidth_samples = vec![(1000, 125_000, 10), (2000, 250_000, 9)];
+        metrics.record_anomaly(AnomalyEvent {
+            timestamp_ms: 1500,
+            anomaly_type: crate::anomalies::AnomalyType::HighLatencySpike,
+            description: "RTT 250 ms".to_string(),
+            threshold: None,
+        });
+        let summary = generate_summary(&config, metrics, Duration::from_secs(2));
+
+        let json: serde_json::Value = serde_json::from_str(&generate_json_report_string(&summary).unwrap()).unwrap();
+        assert_eq!(json["overall_metrics"]["packets_sent"], 20);
+        assert_eq!(json["overall_metrics"]["packets_received"], 19);
+        assert_eq!(json["bandwidth_over_time"].as_array().unwrap().len(), 2);
+        assert_eq!(json["bandwidth_over_time"][1][0], summary.bandwidth_over_time[1].0);
+        assert_eq!(json["anomalies"][0]["anomaly_type"], "HighLatencySpike");
+        assert_eq!(json["anomalies"][0]["timestamp_ms"], 1500);
+        assert_eq!(json["test_config"]["target_ip"], "192.0.2.7");
+        assert_eq!(json["test_config"]["protocol"], "Tcp");
+    }
+
+    #[test]
+    fn test_bandwidth_stats_over_sampled_intervals() {
+        let mut metrics = TestMetrics::default();
+        metrics.bandwidth_samples = vec![(1000, 125000, 20), (2000, 130000, 20), (2500, 60000, 10)]; // 1, 1.04, 0.96 Mbps
+        let summary = generate_summary(&TestConfig::default(), metrics, Duration::from_millis(2500));
+
+        let stats = summary.bandwidth_stats.expect("No stats from three samples");
+        assert!((stats.avg_mbps - 1.0).abs() < 1e-9, "{:?}", stats);
+        assert!((stats.median_mbps - 1.0).abs() < 1e-9, "{:?}", stats);
+        assert!((stats.p95_mbps - 1.04).abs() < 1e-9, "{:?}", stats);
+        assert!((stats.peak_mbps - 1.04).abs() < 1e-9, "{:?}", stats);
+        assert_eq!(stats.peak_time_secs, 2.0);
+
+        let markdown = generate_markdown_report(&summary);
+        assert!(markdown.contains("| Interval Throughput (Avg / Median / P95) | 1.00 / 1.00 / 1.04 Mbps |"), "{}", markdown);
+        assert!(markdown.contains("| Peak Interval | 1.04 Mbps at 2.00 s |"), "{}", markdown);
+
+        // An even count takes the middle two for the median
+        let even = BandwidthStats::from_series(&[(1.0, 4.0), (2.0, 1.0), (3.0, 2.0), (4.0, 3.0)]).unwrap();
+        assert_eq!(even.median_mbps, 2.5);
+        assert!(BandwidthStats::from_series(&[]).is_none());
+    }
+
+    #[test]
+    fn test_json_report_exports_received_sequence_ranges() {
+        let config = TestConfig { record_sequence_ranges: true, ..Default::default() };
+        let mut metrics = TestMetrics::default();
+        metrics.configure_sequence_ranges(&config);
+        for seq in [0, 1, 2, 5, 6] {
+            metrics.record_sequence(seq, seq as u128);
+        }
+        let summary = generate_summary(&config, metrics, Duration::from_secs(1));
+
+        let json: serde_json::Value = serde_json::from_str(&generate_json_report_string(&summary).unwrap()).unwrap();
+        assert_eq!(json["overall_metrics"]["received_sequence_ranges"], serde_json::json!([[0, 2], [5, 6]]));
+    }
+
+    #[test]
+    fn test_latency_groups_are_distinct_columns() {
+        let mut metrics = TestMetrics::default();
+        metrics.test_start_time = Some(Instant::now());
+        for i in 1..=20u128 {
+            metrics.record_packet_received(512, i * 1000); // RTT 1..=20 ms
+            metrics.record_one_way_delays(i as i64 * 600, i as i64 * 400);
+        }
+        metrics.packets_sent = 20;
+        let summary = generate_summary(&TestConfig::default(), metrics, Duration::from_secs(1));
+
+        let groups = summary.latency_groups();
+        let labels: Vec<_> = groups.iter().map(|group| group.label).collect();
+        assert_eq!(labels, ["RTT", "Forward One-Way", "Return One-Way"]);
+        assert_eq!((groups[1].min_ms, groups[1].p95_ms, groups[1].max_ms), (0.6, 11.4, 12.0));
+        assert!((groups[2].avg_ms - 4.2).abs() < 1e-9);
+
+        let html = generate_html_report_string(&summary).unwrap();
+        assert!(html.contains("<tr><th>Statistic</th><th>RTT</th><th>Forward One-Way</th><th>Return One-Way</th></tr>"), "{}", html);
+        assert!(html.contains("<td>11.400 ms</td>"));
+        let markdown = generate_markdown_report(&summary);
+        assert!(markdown.contains("| Statistic | RTT | Forward One-Way | Return One-Way |\n|---|---|---|---|\n"), "{}", markdown);
+        assert!(markdown.contains("| Min | 1.000 | 0.600 | 0.400 |"), "{}", markdown);
+
+        // Turned off, or without samples, a group has no column
+        let rtt_only = TestSummary {
+            test_config: TestConfig { report_one_way_delays: false, ..Default::default() },
+            ..summary
+        };
+        assert_eq!(rtt_only.latency_groups().len(), 1);
+        assert!(generate_markdown_report(&rtt_only).contains("| Statistic | RTT |\n"));
+        let idle = generate_summary(&TestConfig::default(), TestMetrics::default(), Duration::from_secs(1));
+        assert!(idle.latency_groups().is_empty());
+        assert!(!generate_html_report_string(&idle).unwrap().contains("<h2>Latency</h2>"));
+    }
+
+    #[test]
+    fn test_binary_report_round_trip_renders_identically() {
+        let config = TestConfig { assertions: vec!["loss < 20%".parse().unwrap()], ..Default::default() };
+        let mut metrics = TestMetrics::default();
+        metrics.configure_anomaly_detection(&config);
+        metrics.test_start_time = Some(Instant::now());
+        for rtt_ms in 1..=50u128 {
+            metrics.record_packet_received(512, rtt_ms * 1000);
+            metrics.record_sequence(rtt_ms as u32, rtt_ms * 20);
+        }
+        metrics.packets_sent = 55;
+        metrics.bandwidth_samples = vec![(1000, 12_800, 25), (2000, 12_800, 25)];
+        metrics.phase_marks.push((1_000, "Second half".to_string()));
+        let summary = generate_summary(&config, metrics, Duration::from_secs(2));
+        let html = generate_html_report_string(&summary).unwrap();
+
+        let plain = to_binary(&summary).unwrap();
+        let restored = from_binary(&plain).unwrap();
+        assert_eq!(generate_html_report_string(&restored).unwrap(), html);
+        assert_eq!(restored.overall_metrics.rtt_samples, summary.overall_metrics.rtt_samples);
+        assert_eq!(restored.test_config.assertions, config.assertions);
+
+        let gzipped = to_binary_gzip(&summary).unwrap();
+        assert!(gzipped.len() < plain.len());
+        assert_eq!(generate_html_report_string(&from_binary(&gzipped).unwrap()).unwrap(), html);
+
+        assert!(from_binary(b"not a report").is_err());
+        let mut future_version = plain.clone();
+        future_version[4] = BINARY_REPORT_VERSION + 1;
+        assert!(from_binary(&future_version).is_err());
+    }
+
+    #[test]
+    fn test_stored_binary_reports_decode_by_version() {
+        // Written by the current version: if this stops decoding, TestSummary changed shape
+        // and BINARY_REPORT_VERSION needs a bump (and a new fixture).
+        let current = include_bytes!("../tests/fixtures/report_v3.bin");
+        assert_eq!(current[4], BINARY_REPORT_VERSION);
+        let summary = from_binary(current).expect("Current-version fixture failed to decode");
+        assert_eq!(summary.test_config.target_ip, "127.0.0.1");
+        assert_eq!(summary.overall_metrics.packets_sent, 12);
+        assert_eq!(summary.overall_metrics.packets_received, 10);
+        assert_eq!(summary.overall_metrics.bandwidth_samples, vec![(1000, 5_120, 10)]);
+
+        // Older versions have other layouts and are refused rather than misread.
+        let older: [(u8, &[u8]); 2] = [
+            (1, include_bytes!("../tests/fixtures/report_v1.bin")),
+            (2, include_bytes!("../tests/fixtures/report_v2.bin")),
+        ];
+        for (version, bytes) in older {
+            let err = from_binary(bytes).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains(&format!("Unsupported binary report version {}", version)), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_write_run_artifacts_in_multiple_formats() {
+        let config = TestConfig {
+            output_formats: vec![ReportFormat::Html, ReportFormat::Json, ReportFormat::Html],
+            ..Default::default()
+        };
+        let mut metrics = TestMetrics::default();
+        metrics.packets_sent = 40;
+        metrics.packets_received = 38;
+        let summary = generate_summary(&config, metrics, Duration::from_secs(2));
+        let output_dir = std::env::temp_dir().join(format!("netstats_formats_test_{}", std::process::id()));
+
+        let artifacts = write_run_artifacts(&summary, &output_dir).expect("Writing artifacts failed");
+        assert_eq!(artifacts.files.len(), 2, "Duplicate formats are written once");
+        let html = std::fs::read_to_string(artifacts.path(ReportFormat::Html).unwrap()).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(artifacts.path(ReportFormat::Json).unwrap()).unwrap()).unwrap();
+        assert!(artifacts.path(ReportFormat::Csv).is_none());
+
+        assert_eq!(json["overall_metrics"]["packets_sent"], 40);
+        assert_eq!(json["overall_metrics"]["packets_received"], 38);
+        assert!(html.contains("<td>40</td>") && html.contains("<td>38</td>"));
+
+        let _ = std::fs::remove_dir_all(&output_dir);
+    }
+
+    #[test]
+    fn test_write_bandwidth_and_anomalies_csv() {
+        let mut metrics = TestMetrics::default();
+        metrics.bandwidth_samples = vec![(1000, 125_000, 10), (2000, 250_000, 20), (3000, 125_000, 10)];
+        metrics.record_anomaly(AnomalyEvent {
+            timestamp_ms: 1500,
+            anomaly_type: crate::anomalies::AnomalyType::PacketLoss,
+            description: "Lost 3 packets, \"burst\" of 3".to_string(),
+            threshold: None,
+        });
+        let summary = generate_summary(&TestConfig::default(), metrics, Duration::from_secs(3));
+        let bandwidth_path = std::env::temp_dir().join(format!("netstats_bandwidth_{}.csv", std::process::id()));
+        let anomalies_path = std::env::temp_dir().join(format!("netstats_anomalies_{}.csv", std::process::id()));
+
+        write_bandwidth_csv(&summary, &bandwidth_path).expect("Writing the bandwidth CSV failed");
+        write_anomalies_csv(&summary, &anomalies_path).expect("Writing the anomalies CSV failed");
+        let bandwidth = std::fs::read_to_string(&bandwidth_path).unwrap();
+        let anomalies = std::fs::read_to_string(&anomalies_path).unwrap();
+        let _ = std::fs::remove_file(&bandwidth_path);
+        let _ = std::fs::remove_file(&anomalies_path);
+
+        assert_eq!(bandwidth.lines().count(), 4, "Header + one line per sample:\n{}", bandwidth);
+        assert_eq!(bandwidth.lines().next(), Some("time_sec,mbps"));
+        assert_eq!(bandwidth.lines().nth(2), Some("2.000,2.000000"));
+        assert_eq!(anomalies.lines().collect::<Vec<_>>(), vec![
+            "timestamp_ms,type,description",
+            "1500,PacketLoss,\"Lost 3 packets, \"\"burst\"\" of 3\"",
+        ]);
+    }
+
+    #[test]
+    fn test_markdown_report_has_target_loss_and_anomalies() {
+        let config = TestConfig { target_ip: "192.0.2.7".to_string(), target_port: 6100, ..Default::default() };
+        let mut metrics = TestMetrics::default();
+        metrics.packets_sent = 200;
+        metrics.packets_received = 190;
+        metrics.bandwidth_samples = (1..=120).map(|i| (i * 1000, 125_000, 10)).collect();
+        metrics.record_anomaly(AnomalyEvent {
+            timestamp_ms: 1500,
+            anomaly_type: crate::anomalies::AnomalyType::PacketLoss,
+            description: "Lost 10 packets".to_string(),
+            threshold: None,
+        });
+        let summary = generate_summary(&config, metrics, Duration::from_secs(120));
+
+        let markdown = generate_markdown_report(&summary);
+        assert!(markdown.contains("| Target | 192.0.2.7:6100 |"), "{}", markdown);
+        assert!(markdown.contains("| Packet Loss | 5.00% |"), "{}", markdown);
+        assert!(markdown.contains("## Anomalies (1)\n\n- [1.500s] "), "{}", markdown);
+        let bandwidth_rows = markdown.split("## Bandwidth Over Time").nth(1).unwrap().lines().filter(|l| l.starts_with("| ")).count();
+        assert_eq!(bandwidth_rows, 1 + MARKDOWN_BANDWIDTH_ROWS, "Header + decimated samples:\n{}", markdown);
+    }
+
+    #[test]
+    fn test_summary_of_a_test_that_ended_as_it_started() {
+        let config = TestConfig { test_mode: TestMode::Bidirectional, ..Default::default() };
+        let summary = generate_summary(&config, TestMetrics::default(), Duration::ZERO);
+        assert!(summary.direction_rates.is_none());
+        assert!(summary.bandwidth_over_time.is_empty());
+        assert_eq!(summary.overall_metrics.overall_throughput_bps(summary.test_duration_actual_secs), 0.0);
+        let markdown = generate_markdown_report(&summary);
+        assert!(!markdown.contains("NaN") && !markdown.contains("inf"), "{}", markdown);
+    }
+
+    #[test]
+    fn test_openmetrics_histogram_carries_outlier_exemplar() {
+        let mut metrics = TestMetrics::default();
+        metrics.init_start_time();
+        for _ in 0..99 {
+            metrics.record_packet_received(100, 1_000);
+        }
+        metrics.record_packet_received(100, 40_000); // The recent outlier
+        let summary = generate_summary(&TestConfig::default(), metrics, Duration::from_secs(1));
+
+        let text = to_openmetrics(&summary);
+        assert!(text.contains("# TYPE netstats_rtt_seconds histogram\n"), "{}", text);
+        assert!(text.contains("netstats_packets_received_total 100\n"), "{}", text);
+        assert!(text.contains("netstats_rtt_seconds_bucket{le=\"0.001\"} 99\n"), "{}", text);
+        let exemplar_line = text.lines().find(|line| line.contains(" # {")).expect("No exemplar");
+        assert!(exemplar_line.starts_with("netstats_rtt_seconds_bucket{le=\"0.05\"} 100 # {sample_time_ms=\""), "{}", exemplar_line);
+        assert!(exemplar_line.split(' ').nth(4) == Some("0.04"), "{}", exemplar_line);
+        assert_eq!(text.lines().filter(|line| line.contains(" # {")).count(), 1);
+        assert!(text.contains("netstats_rtt_seconds_count 100\n"));
+        assert!(text.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_summary_start_time_is_rfc3339_before_end() {
+        let mut metrics = TestMetrics::default();
+        metrics.init_start_time();
+        metrics.test_start_wall_time = metrics.test_start_wall_time.map(|t| t - Duration::from_secs(5)); // Test began 5 s ago
+        let summary = generate_summary(&TestConfig::default(), metrics, Duration::from_secs(5));
+
+        assert_ne!(summary.start_time_utc, "N/A");
+        let start = humantime::parse_rfc3339(&summary.start_time_utc).expect("start_time_utc is not RFC3339");
+        let end = humantime::parse_rfc3339(&summary.end_time_utc).expect("end_time_utc is not RFC3339");
+        assert!(start < end, "{} should be before {}", summary.start_time_utc, summary.end_time_utc);
+
+        let never_started = generate_summary(&TestConfig::default(), TestMetrics::default(), Duration::ZERO);
+        assert_eq!(never_started.start_time_utc, "N/A");
+    }
+
+    #[test]
+    fn test_write_run_artifacts_creates_distinct_dirs() {
+        let summary = generate_summary(&TestConfig::default(), TestMetrics::default(), Duration::from_secs(1));
+        let output_dir = std::env::temp_dir().join(format!("netstats_run_dirs_test_{}", std::process::id()));
+
+        // Two back-to-back runs (same second) must not share, and clobber, an output directory
+        let first_run_dir = write_run_artifacts(&summary, &output_dir).expect("First run artifacts failed").run_dir;
+        let second_run_dir = write_run_artifacts(&summary, &output_dir).expect("Second run artifacts failed").run_dir;
+
+        assert_ne!(first_run_dir, second_run_dir);
+        assert!(first_run_dir.starts_with(&output_dir));
+        assert!(second_run_dir.starts_with(&output_dir));
+        assert!(first_run_dir.join(HTML_REPORT_FILE_NAME).is_file());
+        assert!(second_run_dir.join(HTML_REPORT_FILE_NAME).is_file());
+
+        let _ = std::fs::remove_dir_all(&output_dir);
+    }
 }
@@ -0,0 +1,493 @@
+// High-level test runs: network phase, end-of-test checks and summary generation
+
+use crate::anomalies::{AnomalyEvent, AnomalyThreshold, AnomalyType};
+use crate::config::{ConfigError, FinalSampleTiming, MultiAddressPolicy, MultiTargetConfig, Protocol, TargetSpec, TestConfig, TestMode};
+use crate::control::TestControl;
+use crate::metrics::{lock_metrics, MetricsSnapshot, TestMetrics};
+use crate::network::{resolve_target, run_network_test, NetworkError};
+use crate::reporter::{generate_protocol_comparison, generate_summary, TestSummary};
+use std::net::SocketAddr;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// In a combined run the TCP test uses target_port + this offset, so its listener
+// never collides with the UDP one (the UDP server lingers for a grace period).
+pub const COMBINED_TCP_PORT_OFFSET: u16 = 1;
+
+// Wait before the first retry of a test that failed to start (TestConfig::max_test_retries),
+// doubled for each further retry
+const TEST_RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Result of `run_combined`: one summary per protocol plus a side-by-side comparison.
+#[derive(Debug)]
+pub struct CombinedSummary {
+    pub udp: TestSummary,
+    pub tcp: TestSummary,
+    pub comparison: String, // Plain-text table from reporter::generate_protocol_comparison
+}
+
+pub type StartHook = Box<dyn Fn(&TestConfig) + Send + Sync>;
+pub type FinishHook = Box<dyn Fn(&TestSummary) + Send + Sync>;
+pub type SampleHook = Box<dyn Fn(MetricsSnapshot) + Send + Sync>;
+
+/// Callbacks run around a test, e.g. to start a packet capture before it and collect logs after.
+///
+/// `on_sample` gets a snapshot every `bandwidth_sample_interval_ms` while the network phase
+/// runs, for live displays. It is called from the task that drives the test, one call at a
+/// time and without the metrics lock held, so it may do anything `Send` but should return
+/// quickly: a UI should hand the snapshot to its own thread (e.g. `slint::invoke_from_event_loop`).
+#[derive(Default)]
+pub struct TestHooks {
+    pub on_start: Option<StartHook>,   // Right before the network phase
+    pub on_sample: Option<SampleHook>, // During the network phase, see above
+    pub on_finish: Option<FinishHook>, // After the summary is built; not called if the test fails
+}
+
+impl TestHooks {
+    /// Hooks that run shell commands (`sh -c` / `cmd /C`) and wait for them to exit.
+    /// The post command gets the headline results as NETSTATS_* environment variables.
+    /// Commands that fail to start or exit non-zero are logged, not treated as test errors.
+    pub fn shell_commands(pre_cmd: Option<String>, post_cmd: Option<String>) -> Self {
+        TestHooks {
+            on_start: pre_cmd.map(|cmd| -> StartHook {
+                Box::new(move |_config: &TestConfig| run_hook_command(&cmd, &[]))
+            }),
+            on_sample: None,
+            on_finish: post_cmd.map(|cmd| -> FinishHook {
+                Box::new(move |summary: &TestSummary| {
+                    let metrics = &summary.overall_metrics;
+                    let env = [
+                        ("NETSTATS_PACKETS_SENT", metrics.packets_sent.to_string()),
+                        ("NETSTATS_PACKETS_RECEIVED", metrics.packets_received.to_string()),
+                        ("NETSTATS_LOSS_PERCENT", format!("{:.2}", metrics.packet_loss_percentage())),
+                        ("NETSTATS_AVG_RTT_MS", metrics.average_rtt_micros().map_or(String::new(), |rtt| format!("{:.3}", rtt / 1000.0))),
+                        ("NETSTATS_DURATION_SECS", format!("{:.2}", summary.test_duration_actual_secs)),
+                    ];
+                    run_hook_command(&cmd, &env)
+                })
+            }),
+        }
+    }
+}
+
+fn run_hook_command(cmd: &str, env: &[(&str, String)]) {
+    let mut command = if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.args(["/C", cmd]);
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.args(["-c", cmd]);
+        c
+    };
+    command.envs(env.iter().map(|(k, v)| (*k, v.as_str())));
+    // The hooks run inside run_prepared_test: on a multi-threaded runtime, hand this worker's
+    // other tasks to the rest while the command runs, so a slow one doesn't stall them
+    let status = match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| command.status())
+        }
+        _ => command.status(),
+    };
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("Hook command '{}' exited with {}", cmd, status),
+        Err(e) => eprintln!("Failed to run hook command '{}': {}", cmd, e),
+    }
+}
+
+/// The runtime for a test run from blocking code: multi-threaded, with `worker_threads`
+/// workers (`TestConfig::runtime_worker_threads`) or tokio's default of one per core.
+/// Some(0) is an InvalidInput error, as `TestConfig::validate` rejects it.
+pub fn build_runtime(worker_threads: Option<usize>) -> std::io::Result<tokio::runtime::Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    match worker_threads {
+        Some(0) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, ConfigError::ZeroWorkerThreads.to_string())),
+        Some(threads) => {
+            builder.worker_threads(threads);
+        }
+        None => {}
+    }
+    builder.enable_all().build()
+}
+
+/// Runs a single test with `config` and builds its summary.
+pub async fn run_test(config: &TestConfig) -> Result<TestSummary, NetworkError> {
+    run_test_with_hooks(config, &TestHooks::default()).await
+}
+
+/// Like `run_test`, calling `hooks.on_start` before the network phase and
+/// `hooks.on_finish` with the finished summary.
+pub async fn run_test_with_hooks(config: &TestConfig, hooks: &TestHooks) -> Result<TestSummary, NetworkError> {
+    run_prepared_test(config, TestMetrics::default(), hooks).await
+}
+
+/// Like `run_test_with_hooks`, starting from caller-prepared metrics, e.g. with a packet
+/// tape attached or after cloning `metrics.control` to pause or cancel the test from elsewhere.
+/// A cancelled test returns the summary of the part that ran.
+///
+/// A network phase failing with a transient error is run again from scratch, up to
+/// `config.max_test_retries` times; the retries keep the caller's control and packet tape.
+pub async fn run_prepared_test(config: &TestConfig, metrics: TestMetrics, hooks: &TestHooks) -> Result<TestSummary, NetworkError> {
+    if let Some(on_start) = &hooks.on_start {
+        on_start(config);
+    }
+
+    let config = Arc::new(config.clone());
+    let metrics = Arc::new(Mutex::new(metrics));
+
+    let had_traffic = || {
+        let metrics_guard = lock_metrics(&metrics);
+        metrics_guard.packets_sent > 0 || metrics_guard.packets_received > 0
+    };
+    retry_transient(config.max_test_retries, had_traffic, |attempt| {
+        let (config, metrics) = (Arc::clone(&config), Arc::clone(&metrics));
+        async move {
+            if attempt > 0 {
+                lock_metrics(&metrics).reset_for_retry();
+            }
+            let network_phase = run_network_test(Arc::clone(&config), Arc::clone(&metrics));
+            match &hooks.on_sample {
+                Some(on_sample) => sample_until_done(network_phase, &config, &metrics, on_sample).await,
+                None => network_phase.await,
+            }
+        }
+    })
+    .await?;
+
+    let final_metrics = std::mem::take(&mut *lock_metrics(&metrics));
+    let summary = finish_test(&config, final_metrics);
+
+    if let Some(on_finish) = &hooks.on_finish {
+        on_finish(&summary);
+    }
+    Ok(summary)
+}
+
+/// Runs `attempt` (passed the attempt number, from 0) until it succeeds, fails with an error
+/// that isn't `NetworkError::is_transient`, or `max_retries` retries are used up. Only a
+/// failure to start is retried: once `had_traffic` says packets went out or came in, the
+/// error ends the test, rather than restarting one that was part way through.
+async fn retry_transient<T, F, Fut>(max_retries: u32, had_traffic: impl Fn() -> bool, mut attempt: F) -> Result<T, NetworkError>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: std::future::Future<Output = Result<T, NetworkError>>,
+{
+    let mut delay = TEST_RETRY_BASE_DELAY;
+    let mut attempt_number = 0;
+    loop {
+        match attempt(attempt_number).await {
+            Err(e) if attempt_number < max_retries && e.is_transient() && !had_traffic() => {
+                eprintln!("Test attempt {} failed to start ({:?}), retrying in {:?}", attempt_number + 1, e, delay);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt_number += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Drives `network_phase` to completion, passing `on_sample` a snapshot of `metrics` every
+/// bandwidth sample interval meanwhile.
+async fn sample_until_done<F>(network_phase: F, config: &TestConfig, metrics: &Mutex<TestMetrics>, on_sample: &SampleHook) -> F::Output
+where
+    F: std::future::Future,
+{
+    let interval = config.bandwidth_sample_interval();
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    let mut previous: Option<MetricsSnapshot> = None;
+    tokio::pin!(network_phase);
+    loop {
+        tokio::select! {
+            result = &mut network_phase => return result,
+            _ = ticker.tick() => {
+                let snapshot = lock_metrics(metrics).snapshot(previous.as_ref());
+                on_sample(snapshot);
+                previous = Some(snapshot);
+            }
+        }
+    }
+}
+
+/// End-of-test checks that need the whole run (overall loss threshold, `detect_anomalies`), then the summary.
+/// The reported duration is active time: pauses are excluded.
+pub fn finish_test(config: &TestConfig, mut final_metrics: TestMetrics) -> TestSummary {
+    let actual_duration = if let Some(start_time) = final_metrics.test_start_time {
+        final_metrics.control.active_elapsed(start_time)
+    } else {
+        config.total_duration() // Fallback
+    };
+
+    // A test shorter than one sample interval can end before any bandwidth sample is taken
+    // (and client-side metrics are never sampled), which would report an empty series. A
+    // cancelled test ends at the active time it ran for, not its configured end.
+    if config.is_shorter_than_sample_interval() && final_metrics.bandwidth_samples.is_empty() {
+        let end = if final_metrics.control.is_cancelled() { actual_duration.min(config.total_duration()) } else { config.total_duration() };
+        final_metrics.take_final_bandwidth_sample(FinalSampleTiming::ConfiguredDuration, end.as_millis().max(1));
+    }
+
+    if config.anomaly_detection_enabled {
+        // High overall packet loss, timestamped at the end of the test for summary purposes
+        let overall_loss = config.packet_loss_threshold_percent.and_then(|loss_threshold_percent| {
+            let loss_percentage = final_metrics.packet_loss_percentage();
+            (loss_percentage >= loss_threshold_percent).then(|| AnomalyEvent {
+                timestamp_ms: actual_duration.as_millis(),
+                anomaly_type: AnomalyType::PacketLoss,
+                description: format!("High packet loss detected: {:.2}%", loss_percentage),
+                threshold: Some(AnomalyThreshold::new("packet_loss_threshold_percent", loss_threshold_percent, loss_percentage)),
+            })
+        });
+        crate::anomalies::merge_detected_anomalies(&mut final_metrics, overall_loss);
+    }
+    final_metrics.anomalies.sort_by_key(|anomaly| anomaly.timestamp_ms);
+
+    generate_summary(config, final_metrics, actual_duration)
+}
+
+/// The UDP and TCP configs of a combined run: same parameters, TCP on the next port.
+pub fn combined_configs(config: &TestConfig) -> Result<(TestConfig, TestConfig), NetworkError> {
+    let tcp_port = config.target_port.checked_add(COMBINED_TCP_PORT_OFFSET).ok_or_else(|| {
+        NetworkError::InvalidAddress(format!(
+            "Port {} leaves no room for the TCP test port (+{})",
+            config.target_port, COMBINED_TCP_PORT_OFFSET
+        ))
+    })?;
+    let udp_config = TestConfig { protocol: Protocol::Udp, ..config.clone() };
+    let tcp_config = TestConfig { protocol: Protocol::Tcp, target_port: tcp_port, ..config.clone() };
+    Ok((udp_config, tcp_config))
+}
+
+/// Characterizes a link with both protocols: a UDP test, then a TCP test with the same
+/// parameters on `target_port + COMBINED_TCP_PORT_OFFSET`. `config.protocol` is ignored.
+///
+/// In server mode both listeners run concurrently instead, because the peer's TCP phase
+/// starts while the UDP server is still in its grace period.
+pub async fn run_combined(config: &TestConfig) -> Result<CombinedSummary, NetworkError> {
+    let (udp_config, tcp_config) = combined_configs(config)?;
+
+    let (udp, tcp) = if config.test_mode == TestMode::Server {
+        let (udp, tcp) = tokio::join!(run_test(&udp_config), run_test(&tcp_config));
+        (udp?, tcp?)
+    } else {
+        let udp = run_test(&udp_config).await?;
+        let tcp = run_test(&tcp_config).await?;
+        (udp, tcp)
+    };
+
+    let comparison = generate_protocol_comparison(&udp, &tcp);
+    Ok(CombinedSummary { udp, tcp, comparison })
+}
+
+/// Runs `config` against its target per `config.multi_address_policy`: one summary for
+/// `First`, or one per resolved address of a hostname target, in resolver order.
+/// Servers listen on the wildcard address, so they always run a single test.
+pub async fn run_test_per_address(config: &TestConfig) -> Result<Vec<TestSummary>, NetworkError> {
+    run_test_per_address_with_hooks(config, &Arc::new(TestControl::new()), &Arc::default()).await
+}
+
+/// Like `run_test_per_address`, running `hooks` around each address's test. Cancelling
+/// `control` ends the test in progress (each address's summary covers the part that ran)
+/// and skips the addresses not yet started. Every address's test keeps its own pause
+/// clock, so pausing `control` has no effect.
+pub async fn run_test_per_address_with_hooks(
+    config: &TestConfig,
+    control: &Arc<TestControl>,
+    hooks: &Arc<TestHooks>,
+) -> Result<Vec<TestSummary>, NetworkError> {
+    if config.multi_address_policy == MultiAddressPolicy::First || config.test_mode == TestMode::Server {
+        let mut metrics = TestMetrics::default();
+        metrics.control = Arc::clone(control);
+        return Ok(vec![run_prepared_test(config, metrics, hooks).await?]);
+    }
+    let addresses = resolve_target(&config.target_ip, config.target_port).await?;
+    run_at_addresses(config, &addresses, control, hooks).await
+}
+
+/// `run_test_per_address` with the target already resolved to `addresses`.
+pub async fn run_test_at_addresses(config: &TestConfig, addresses: &[SocketAddr]) -> Result<Vec<TestSummary>, NetworkError> {
+    run_at_addresses(config, addresses, &Arc::new(TestControl::new()), &Arc::default()).await
+}
+
+async fn run_at_addresses(
+    config: &TestConfig,
+    addresses: &[SocketAddr],
+    control: &Arc<TestControl>,
+    hooks: &Arc<TestHooks>,
+) -> Result<Vec<TestSummary>, NetworkError> {
+    let configs = address_configs(config, addresses);
+    if config.multi_address_policy != MultiAddressPolicy::AllParallel {
+        let mut summaries = Vec::with_capacity(configs.len());
+        for config in &configs {
+            if control.is_cancelled() {
+                break;
+            }
+            summaries.push(run_cancellable_test(config, control, hooks).await?);
+        }
+        return Ok(summaries);
+    }
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for (index, config) in configs.into_iter().enumerate() {
+        let (control, hooks) = (Arc::clone(control), Arc::clone(hooks));
+        tasks.spawn(async move { (index, run_cancellable_test(&config, &control, &hooks).await) });
+    }
+    let mut summaries: Vec<Option<TestSummary>> = (0..addresses.len()).map(|_| None).collect();
+    while let Some(joined) = tasks.join_next().await {
+        let (index, result) = joined.map_err(|e| NetworkError::IoError(std::io::Error::other(e)))?;
+        summaries[index] = Some(result?);
+    }
+    Ok(summaries.into_iter().flatten().collect())
+}
+
+/// `run_test_with_hooks` with its own control, cancelled along with `parent`.
+async fn run_cancellable_test(config: &TestConfig, parent: &Arc<TestControl>, hooks: &TestHooks) -> Result<TestSummary, NetworkError> {
+    let metrics = TestMetrics::default();
+    let (parent, control) = (Arc::clone(parent), Arc::clone(&metrics.control));
+    let forward_cancel = tokio::spawn(async move {
+        parent.cancelled().await;
+        control.cancel();
+    });
+    let result = run_prepared_test(config, metrics, hooks).await;
+    forward_cancel.abort();
+    result
+}
+
+/// One config per address, as a multi-target run labelled with the hostname.
+fn address_configs(config: &TestConfig, addresses: &[SocketAddr]) -> Vec<TestConfig> {
+    let targets = addresses
+        .iter()
+        .map(|addr| TargetSpec {
+            host: match addr {
+                SocketAddr::V6(v6) if v6.scope_id() != 0 => format!("{}%{}", v6.ip(), v6.scope_id()),
+                _ => addr.ip().to_string(),
+            },
+            port: addr.port(),
+            label: Some(config.target_ip.clone()),
+        })
+        .collect();
+    MultiTargetConfig { targets }.to_test_configs(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combined_configs_use_distinct_ports() {
+        let config = TestConfig { target_port: 7000, protocol: Protocol::Tcp, ..Default::default() };
+        let (udp_config, tcp_config) = combined_configs(&config).unwrap();
+        assert_eq!(udp_config.protocol, Protocol::Udp);
+        assert_eq!(udp_config.target_port, 7000);
+        assert_eq!(tcp_config.protocol, Protocol::Tcp);
+        assert_eq!(tcp_config.target_port, 7001);
+        assert_eq!(tcp_config.test_duration_secs, config.test_duration_secs);
+
+        let config = TestConfig { target_port: u16::MAX, ..Default::default() };
+        assert!(combined_configs(&config).is_err());
+    }
+
+    #[test]
+    fn test_short_test_still_gets_a_bandwidth_sample() {
+        let config = TestConfig { test_duration_ms: Some(200), bandwidth_sample_interval_ms: 1000, ..Default::default() };
+        let mut metrics = TestMetrics::default();
+        metrics.init_start_time();
+        for _ in 0..10 {
+            metrics.record_packet_received(1250, 0);
+        }
+        assert!(metrics.bandwidth_samples.is_empty(), "Ended before the first sampler tick");
+
+        let summary = finish_test(&config, metrics);
+        // 12500 B over the configured 200 ms = 0.5 Mbps
+        assert_eq!(summary.bandwidth_over_time.len(), 1);
+        let (time_secs, mbps) = summary.bandwidth_over_time[0];
+        assert_eq!(time_secs, 0.2);
+        assert!((mbps - 0.5).abs() < 1e-9, "{}", mbps);
+        let note = summary.short_test_note.expect("Short duration not noted");
+        assert!(note.contains("200 ms"), "{}", note);
+
+        let summary = finish_test(&TestConfig::default(), TestMetrics::default());
+        assert!(summary.short_test_note.is_none());
+
+        // Cancelled straight away: the sample ends when the test did, not at 200 ms
+        let mut metrics = TestMetrics::default();
+        metrics.init_start_time();
+        metrics.record_packet_received(1250, 0);
+        metrics.control.cancel();
+        let summary = finish_test(&config, metrics);
+        let (time_secs, _) = summary.bandwidth_over_time[0];
+        assert!(time_secs < 0.1, "Final sample at {} s", time_secs);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shell_command_hooks_export_summary() {
+        let out_file = std::env::temp_dir().join(format!("netstats_hook_test_{}", std::process::id()));
+        let hooks = TestHooks::shell_commands(
+            Some(format!("echo start > {}", out_file.display())),
+            Some(format!("echo \"$NETSTATS_PACKETS_SENT $NETSTATS_LOSS_PERCENT\" >> {}", out_file.display())),
+        );
+
+        let mut metrics = TestMetrics::default();
+        metrics.packets_sent = 10;
+        metrics.packets_received = 9;
+        let summary = finish_test(&TestConfig::default(), metrics);
+        (hooks.on_start.as_ref().unwrap())(&TestConfig::default());
+        (hooks.on_finish.as_ref().unwrap())(&summary);
+
+        let output = std::fs::read_to_string(&out_file).expect("Hooks did not write output");
+        let _ = std::fs::remove_file(&out_file);
+        assert_eq!(output, "start\n10 10.00\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_slow_hook_command_does_not_stall_the_runtime() {
+        // On the only worker, a timer task has to keep running while the hook waits
+        let timer = tokio::spawn(async {
+            let start = std::time::Instant::now();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            start.elapsed()
+        });
+        let hook = tokio::spawn(async { run_hook_command("sleep 0.5", &[]) });
+        let timer_elapsed = timer.await.unwrap();
+        hook.await.unwrap();
+        assert!(timer_elapsed < Duration::from_millis(400), "Timer ran after {:?}", timer_elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_transient_failure_is_retried_until_success() {
+        let refused = || NetworkError::IoError(std::io::Error::from(std::io::ErrorKind::ConnectionRefused));
+        let attempts = std::cell::Cell::new(0);
+        let result = retry_transient(2, || false, |attempt| {
+            attempts.set(attempts.get() + 1);
+            async move { if attempt == 0 { Err(refused()) } else { Ok(attempt) } }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 1, "Should succeed on the retry");
+        assert_eq!(attempts.get(), 2);
+
+        // Neither a non-transient error nor one past max_retries is retried
+        let result: Result<(), _> = retry_transient(2, || false, |_| async { Err(NetworkError::Timeout) }).await;
+        assert!(matches!(result, Err(NetworkError::Timeout)));
+        attempts.set(0);
+        let result: Result<(), _> = retry_transient(0, || false, |_| {
+            attempts.set(attempts.get() + 1);
+            async { Err(refused()) }
+        })
+        .await;
+        assert!(result.unwrap_err().is_transient());
+        assert_eq!(attempts.get(), 1);
+
+        // Nor is one after the test got going, e.g. the peer going away mid-test
+        attempts.set(0);
+        let result: Result<(), _> = retry_transient(2, || attempts.get() > 0, |_| {
+            attempts.set(attempts.get() + 1);
+            async { Err(refused()) }
+        })
+        .await;
+        assert!(result.unwrap_err().is_transient());
+        assert_eq!(attempts.get(), 1, "A mid-test failure should not restart the test");
+    }
+}
@@ -0,0 +1,163 @@
+// Human-friendly sizes and rates ("1MiB", "100Mbps", "50kHz") for config files and the CLI
+
+use crate::config::ConfigError;
+use serde::{Deserialize, Deserializer};
+
+// Suffixes are matched case-insensitively; a bare number is in the base unit
+const SIZE_UNITS: &[(&str, f64)] = &[
+    ("b", 1.0),
+    ("kb", 1e3),
+    ("mb", 1e6),
+    ("gb", 1e9),
+    ("kib", 1024.0),
+    ("mib", 1024.0 * 1024.0),
+    ("gib", 1024.0 * 1024.0 * 1024.0),
+];
+const BITRATE_UNITS: &[(&str, f64)] = &[("bps", 1.0), ("kbps", 1e3), ("mbps", 1e6), ("gbps", 1e9)];
+const FREQUENCY_UNITS: &[(&str, f64)] = &[("hz", 1.0), ("khz", 1e3), ("mhz", 1e6)];
+
+/// Parses a size in bytes: "1400", "1400B", "64KiB", "1.5MB" (decimal) or "1MiB" (binary).
+pub fn parse_size_bytes(input: &str) -> Result<usize, ConfigError> {
+    let bytes = parse_with_units(input, SIZE_UNITS, "B, KB, MB, GB, KiB, MiB, GiB")?;
+    to_integer(input, bytes)
+}
+
+/// Parses a bit rate in bits per second: "100Mbps", "2.5Gbps", "64kbps" (all decimal).
+pub fn parse_bitrate_bps(input: &str) -> Result<u64, ConfigError> {
+    let bps = parse_with_units(input, BITRATE_UNITS, "bps, kbps, Mbps, Gbps")?;
+    to_integer(input, bps)
+}
+
+/// Parses a frequency in Hz, e.g. a tick rate: "100", "100Hz", "50kHz".
+pub fn parse_frequency_hz(input: &str) -> Result<u32, ConfigError> {
+    let hz = parse_with_units(input, FREQUENCY_UNITS, "Hz, kHz, MHz")?;
+    to_integer(input, hz)
+}
+
+fn parse_with_units(input: &str, units: &[(&str, f64)], expected: &str) -> Result<f64, ConfigError> {
+    let invalid = |reason: String| ConfigError::InvalidUnit { input: input.to_string(), reason };
+
+    let trimmed = input.trim();
+    let split_at = trimmed.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split_at);
+    let value: f64 = number.parse().map_err(|_| invalid("expected a number followed by an optional unit".to_string()))?;
+
+    let unit = unit.trim();
+    let factor = if unit.is_empty() {
+        1.0
+    } else {
+        units
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(unit))
+            .map(|(_, factor)| *factor)
+            .ok_or_else(|| invalid(format!("unknown unit '{}', expected one of {}", unit, expected)))?
+    };
+    Ok(value * factor)
+}
+
+fn to_integer<T: TryFrom<u64>>(input: &str, value: f64) -> Result<T, ConfigError> {
+    let too_large = || ConfigError::InvalidUnit { input: input.to_string(), reason: "value is too large".to_string() };
+    let rounded = value.round();
+    if rounded >= u64::MAX as f64 {
+        return Err(too_large()); // `as u64` would saturate
+    }
+    T::try_from(rounded as u64).map_err(|_| too_large())
+}
+
+/// Config file values may be plain numbers or strings with units. Only for human-readable
+/// formats: binary ones (bincode reports) can't tell the two apart and always hold the number.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrText {
+    Number(u64),
+    Text(String),
+}
+
+/// serde `deserialize_with` for byte sizes: `1400` or `"1MiB"`.
+pub(crate) fn deserialize_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    if !deserializer.is_human_readable() {
+        return usize::deserialize(deserializer);
+    }
+    match NumberOrText::deserialize(deserializer)? {
+        NumberOrText::Number(bytes) => usize::try_from(bytes).map_err(serde::de::Error::custom),
+        NumberOrText::Text(text) => parse_size_bytes(&text).map_err(serde::de::Error::custom),
+    }
+}
+
+/// serde `deserialize_with` for optional byte sizes: `4194304` or `"4MiB"`.
+pub(crate) fn deserialize_size_opt<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<usize>, D::Error> {
+    if !deserializer.is_human_readable() {
+        return Option::<usize>::deserialize(deserializer);
+    }
+    match Option::<NumberOrText>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(NumberOrText::Number(bytes)) => usize::try_from(bytes).map(Some).map_err(serde::de::Error::custom),
+        Some(NumberOrText::Text(text)) => parse_size_bytes(&text).map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+/// serde `deserialize_with` for frequencies: `100` or `"50kHz"`.
+pub(crate) fn deserialize_frequency<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    if !deserializer.is_human_readable() {
+        return u32::deserialize(deserializer);
+    }
+    match NumberOrText::deserialize(deserializer)? {
+        NumberOrText::Number(hz) => u32::try_from(hz).map_err(serde::de::Error::custom),
+        NumberOrText::Text(text) => parse_frequency_hz(&text).map_err(serde::de::Error::custom),
+    }
+}
+
+/// serde `deserialize_with` for optional bit rates: `10000000` or `"10Mbps"`.
+pub(crate) fn deserialize_bitrate_opt<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    if !deserializer.is_human_readable() {
+        return Option::<u64>::deserialize(deserializer);
+    }
+    match Option::<NumberOrText>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(NumberOrText::Number(bps)) => Ok(Some(bps)),
+        Some(NumberOrText::Text(text)) => parse_bitrate_bps(&text).map(Some).map_err(serde::de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sizes() {
+        assert_eq!(parse_size_bytes("1400").unwrap(), 1400);
+        assert_eq!(parse_size_bytes("1400B").unwrap(), 1400);
+        assert_eq!(parse_size_bytes("64 KiB").unwrap(), 65_536);
+        assert_eq!(parse_size_bytes("1MiB").unwrap(), 1_048_576);
+        assert_eq!(parse_size_bytes("1.5MB").unwrap(), 1_500_000);
+        assert_eq!(parse_size_bytes("2gib").unwrap(), 2 * 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_parse_rates() {
+        assert_eq!(parse_bitrate_bps("100Mbps").unwrap(), 100_000_000);
+        assert_eq!(parse_bitrate_bps("2.5Gbps").unwrap(), 2_500_000_000);
+        assert_eq!(parse_bitrate_bps("64 kbps").unwrap(), 64_000);
+        assert_eq!(parse_frequency_hz("50kHz").unwrap(), 50_000);
+        assert_eq!(parse_frequency_hz("20Hz").unwrap(), 20);
+        assert_eq!(parse_frequency_hz("20").unwrap(), 20);
+    }
+
+    #[test]
+    fn test_invalid_units_are_rejected() {
+        for (result, expected) in [
+            (parse_size_bytes("10 furlongs").map(|_| ()), "unknown unit 'furlongs'"),
+            (parse_size_bytes("MiB").map(|_| ()), "expected a number"),
+            (parse_size_bytes("-5B").map(|_| ()), "expected a number"),
+            (parse_bitrate_bps("100MB").map(|_| ()), "unknown unit 'MB'"), // Bytes are not a rate
+            (parse_frequency_hz("10GHz").map(|_| ()), "unknown unit 'GHz'"),
+            (parse_frequency_hz("5MHz5").map(|_| ()), "unknown unit"),
+            (parse_frequency_hz("5000MHz").map(|_| ()), "too large"),
+        ] {
+            match result {
+                Err(ConfigError::InvalidUnit { reason, .. }) => assert!(reason.contains(expected), "{}", reason),
+                other => panic!("expected InvalidUnit ({}), got {:?}", expected, other),
+            }
+        }
+    }
+}
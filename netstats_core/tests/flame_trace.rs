@@ -0,0 +1,43 @@
+// Own test binary: FlameTrace installs a process-wide tracing subscriber.
+#![cfg(feature = "flame")]
+
+use netstats_core::config::{Protocol, TestConfig, TestMode};
+use netstats_core::flame::FlameTrace;
+use netstats_core::metrics::TestMetrics;
+use netstats_core::network::run_network_test;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_flame_trace_records_hot_loop_spans() {
+    let trace_path = std::env::temp_dir().join(format!("netstats_flame_{}.folded", std::process::id()));
+    let trace = FlameTrace::start(&trace_path).expect("Failed to start the flame trace");
+
+    let port = 6018; // Unique port
+    let config = |test_mode| Arc::new(TestConfig {
+        target_ip: "127.0.0.1".to_string(),
+        target_port: port,
+        test_duration_secs: 1,
+        tick_rate_hz: 100,
+        packet_size_bytes: 64,
+        protocol: Protocol::Udp,
+        test_mode,
+        ..Default::default()
+    });
+    let server_config = config(TestMode::Server);
+    let server_handle = tokio::spawn(async move {
+        run_network_test(server_config, Arc::new(Mutex::new(TestMetrics::default()))).await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+    run_network_test(config(TestMode::Client), Arc::new(Mutex::new(TestMetrics::default()))).await.unwrap();
+    server_handle.await.unwrap().unwrap();
+
+    trace.flush().unwrap();
+    let folded = std::fs::read_to_string(&trace_path).unwrap();
+    let _ = std::fs::remove_file(&trace_path);
+    assert!(!folded.is_empty(), "The flame trace is empty");
+    for span in ["serialize", "send", "recv", "parse", "metrics_update"] {
+        assert!(folded.contains(span), "No {} span in the flame trace:\n{}", span, folded);
+    }
+}
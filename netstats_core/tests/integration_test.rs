@@ -1,6 +1,11 @@
-use netstats_core::config::{TestConfig, Protocol, TestMode, TcpBidirectionalMode};
+use netstats_core::anomalies::AnomalyType;
+use netstats_core::benchmark::run_tcp_loopback_benchmark;
+use netstats_core::config::{FinalSampleTiming, MultiAddressPolicy, TestConfig, Protocol, TestMode};
+use netstats_core::control::TestControl;
 use netstats_core::metrics::TestMetrics;
 use netstats_core::network::run_network_test;
+use netstats_core::plan::{run_test_plan, TestPlan};
+use netstats_core::runner::{run_combined, run_prepared_test, run_test_at_addresses, run_test_per_address, run_test_per_address_with_hooks, run_test_with_hooks, TestHooks};
 
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
@@ -11,7 +16,6 @@ fn create_test_config(
     mode: TestMode,
     duration_secs: u64,
     target_port: u16,
-    tcp_bidi_mode: Option<TcpBidirectionalMode>
 ) -> Arc<TestConfig> {
     Arc::new(TestConfig {
         target_ip: "127.0.0.1".to_string(),
@@ -22,7 +26,7 @@ fn create_test_config(
         packet_size_range: None,
         protocol,
         test_mode: mode,
-        tcp_bidirectional_mode: tcp_bidi_mode,
+        ..Default::default()
     })
 }
 
@@ -31,10 +35,10 @@ async fn test_udp_client_server_basic() {
     let test_duration_secs = 1;
     let port = 6001; // Unique port for this test
 
-    let server_config = create_test_config(Protocol::Udp, TestMode::Server, test_duration_secs, port, None);
+    let server_config = create_test_config(Protocol::Udp, TestMode::Server, test_duration_secs, port);
     let server_metrics = Arc::new(Mutex::new(TestMetrics::default()));
 
-    let client_config = create_test_config(Protocol::Udp, TestMode::Client, test_duration_secs, port, None);
+    let client_config = create_test_config(Protocol::Udp, TestMode::Client, test_duration_secs, port);
     let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
 
     let server_metrics_clone = Arc::clone(&server_metrics);
@@ -65,7 +69,7 @@ async fn test_udp_client_server_basic() {
     // Assertions
     // Client should have sent packets
     assert!(final_client_metrics.packets_sent > 0, "Client should send packets");
-    assert_eq!(final_client_metrics.packets_sent, test_duration_secs as u64 * 10, "Client sent packet count mismatch"); // 10 ticks/sec * duration
+    assert_eq!(final_client_metrics.packets_sent, test_duration_secs * 10, "Client sent packet count mismatch"); // 10 ticks/sec * duration
 
     // Server should have received packets. Allow for some loss in UDP, though on loopback it should be 0.
     // For a robust test, we might not check exact equality for received packets in UDP.
@@ -77,11 +81,8 @@ async fn test_udp_client_server_basic() {
     assert!(final_client_metrics.bytes_sent > 0);
     assert!(final_server_metrics.bytes_received > 0);
 
-    // If server echoes EchoRequest, client might receive them.
-    // Current UDP server echoes EchoRequest, but client sends DataPacket.
-    // So client.packets_received would be 0 unless it's also setup to receive/process those echoes.
-    // For now, client doesn't process incoming UDP packets in send_loop.
-    assert_eq!(final_client_metrics.packets_received, 0, "Client should not receive UDP packets in this basic test");
+    // The client sends EchoRequests and counts the server's replies as received
+    assert_eq!(final_client_metrics.packets_received, final_client_metrics.packets_sent, "Client should receive an echo reply per packet on loopback");
 
     // Check bandwidth samples were recorded on server
     assert!(!final_server_metrics.bandwidth_samples.is_empty(), "Server should have bandwidth samples");
@@ -93,10 +94,10 @@ async fn test_tcp_client_server_basic() {
     let test_duration_secs = 1;
     let port = 6002; // Unique port
 
-    let server_config = create_test_config(Protocol::Tcp, TestMode::Server, test_duration_secs, port, None);
+    let server_config = create_test_config(Protocol::Tcp, TestMode::Server, test_duration_secs, port);
     let server_metrics = Arc::new(Mutex::new(TestMetrics::default()));
 
-    let client_config = create_test_config(Protocol::Tcp, TestMode::Client, test_duration_secs, port, None);
+    let client_config = create_test_config(Protocol::Tcp, TestMode::Client, test_duration_secs, port);
     let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
 
     let server_metrics_clone = Arc::clone(&server_metrics);
@@ -124,24 +125,896 @@ async fn test_tcp_client_server_basic() {
     println!("TCP Server Metrics: {:?}", final_server_metrics);
 
     assert!(final_client_metrics.packets_sent > 0, "Client should send TCP packets");
-    assert_eq!(final_client_metrics.packets_sent, test_duration_secs as u64 * 10, "Client TCP sent packet count mismatch");
+    assert_eq!(final_client_metrics.packets_sent, test_duration_secs * 10, "Client TCP sent packet count mismatch");
 
     // TCP is reliable, so server should receive all packets sent by client in this simple case.
     assert!(final_server_metrics.packets_received > 0, "Server should receive TCP packets");
     assert_eq!(final_server_metrics.packets_received, final_client_metrics.packets_sent, "TCP packet count mismatch between client and server");
 
     assert!(final_client_metrics.bytes_sent > 0);
-    // +4 for length prefix per packet
-    assert_eq!(final_server_metrics.bytes_received, final_client_metrics.bytes_sent + (final_client_metrics.packets_sent * 4));
+    // Both sides count each packet's 4-byte length prefix
+    assert_eq!(final_server_metrics.bytes_received, final_client_metrics.bytes_sent);
+    // The server echoes every packet, and the client counts the replies as received
+    assert_eq!(final_client_metrics.packets_received, final_client_metrics.packets_sent, "TCP client should receive an echo reply per packet");
 
     assert!(!final_server_metrics.bandwidth_samples.is_empty(), "Server should have TCP bandwidth samples");
 }
 
 
+#[tokio::test]
+async fn test_udp_client_loopback_reports_zero_loss() {
+    let test_duration_secs = 1;
+    let port = 6003; // Unique port
+
+    let server_config = create_test_config(Protocol::Udp, TestMode::Server, test_duration_secs, port);
+    let server_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+
+    // High tick rate so plenty of packets are still in flight when the send loop stops.
+    let client_config = Arc::new(TestConfig {
+        tick_rate_hz: 500,
+        ..(*create_test_config(Protocol::Udp, TestMode::Client, test_duration_secs, port)).clone()
+    });
+    let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+
+    let server_metrics_clone = Arc::clone(&server_metrics);
+    let server_handle = tokio::spawn(async move {
+        run_network_test(server_config, server_metrics_clone).await
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    let client_result = run_network_test(client_config, Arc::clone(&client_metrics)).await;
+    assert!(client_result.is_ok(), "Client error: {:?}", client_result.err());
+
+    {
+        let final_client_metrics = client_metrics.lock().unwrap();
+        println!("UDP Client Metrics (loss test): {:?}", final_client_metrics);
+
+        assert!(final_client_metrics.packets_sent > 0, "Client should send packets");
+        assert_eq!(final_client_metrics.packets_received, final_client_metrics.packets_sent,
+                   "Every echo reply on loopback should be received, including the final in-flight ones");
+        assert_eq!(final_client_metrics.packet_loss_percentage(), 0.0);
+    }
+
+    let server_result = server_handle.await.unwrap();
+    assert!(server_result.is_ok(), "Server error: {:?}", server_result.err());
+}
+
+#[tokio::test]
+async fn test_combined_udp_then_tcp_produces_both_summaries() {
+    let test_duration_secs = 1;
+    let port = 6004; // Unique port; the TCP phase uses 6005
+
+    let server_config = create_test_config(Protocol::Udp, TestMode::Server, test_duration_secs, port);
+    let client_config = create_test_config(Protocol::Udp, TestMode::Client, test_duration_secs, port);
+
+    let server_handle = tokio::spawn(async move { run_combined(&server_config).await });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    let client_result = run_combined(&client_config).await;
+    let combined = client_result.expect("Combined client run failed");
+
+    assert_eq!(combined.udp.test_config.protocol, Protocol::Udp);
+    assert_eq!(combined.tcp.test_config.protocol, Protocol::Tcp);
+    assert_eq!(combined.tcp.test_config.target_port, port + 1);
+    assert!(combined.udp.overall_metrics.packets_sent > 0, "UDP phase should send packets");
+    assert!(combined.tcp.overall_metrics.packets_sent > 0, "TCP phase should send packets");
+    assert!(combined.comparison.contains("UDP"), "{}", combined.comparison);
+    assert!(combined.comparison.contains("TCP"), "{}", combined.comparison);
+
+    let server_result = server_handle.await.unwrap();
+    assert!(server_result.is_ok(), "Server error: {:?}", server_result.err());
+}
+
+#[tokio::test]
+async fn test_start_and_finish_hooks_fire_in_order() {
+    let test_duration_secs = 1;
+    let port = 6006; // Unique port
+
+    let server_config = create_test_config(Protocol::Udp, TestMode::Server, test_duration_secs, port);
+    let server_handle = tokio::spawn(async move {
+        run_network_test(server_config, Arc::new(Mutex::new(TestMetrics::default()))).await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let start_events = Arc::clone(&events);
+    let finish_events = Arc::clone(&events);
+    let hooks = TestHooks {
+        on_start: Some(Box::new(move |config: &TestConfig| {
+            start_events.lock().unwrap().push(format!("start:{}", config.target_port));
+        })),
+        on_sample: None,
+        on_finish: Some(Box::new(move |summary| {
+            finish_events.lock().unwrap().push(format!("finish:{}", summary.overall_metrics.packets_sent));
+        })),
+    };
+
+    let client_config = create_test_config(Protocol::Udp, TestMode::Client, test_duration_secs, port);
+    let summary = run_test_with_hooks(&client_config, &hooks).await.expect("Client run failed");
+
+    let events = events.lock().unwrap().clone();
+    assert_eq!(events, vec![
+        format!("start:{}", port),
+        format!("finish:{}", summary.overall_metrics.packets_sent),
+    ]);
+    assert!(summary.overall_metrics.packets_sent > 0);
+
+    server_handle.abort(); // No need to wait out the server's grace period
+}
+
+#[tokio::test]
+async fn test_sample_hook_streams_snapshots_during_run() {
+    let port = 6036; // Unique port
+    let config = |mode| TestConfig {
+        bandwidth_sample_interval_ms: 100,
+        test_duration_ms: Some(1500),
+        ..(*create_test_config(Protocol::Udp, mode, 1, port)).clone()
+    };
+    let server_config = config(TestMode::Server);
+    let server_handle = tokio::spawn(async move {
+        run_network_test(Arc::new(server_config), Arc::new(Mutex::new(TestMetrics::default()))).await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    let snapshots = Arc::new(Mutex::new(Vec::new()));
+    let collected = Arc::clone(&snapshots);
+    let hooks = TestHooks {
+        on_sample: Some(Box::new(move |snapshot| collected.lock().unwrap().push(snapshot))),
+        ..Default::default()
+    };
+    let summary = run_test_with_hooks(&config(TestMode::Client), &hooks).await.expect("Client run failed");
+    server_handle.abort();
+
+    let snapshots = snapshots.lock().unwrap().clone();
+    assert!(snapshots.len() >= 10, "Only {} snapshots in 1.5 s at 100 ms", snapshots.len());
+    assert!(snapshots.windows(2).all(|pair| pair[0].elapsed_ms <= pair[1].elapsed_ms && pair[0].packets_sent <= pair[1].packets_sent));
+    assert!(snapshots.last().unwrap().packets_sent <= summary.overall_metrics.packets_sent);
+    assert!(snapshots.iter().any(|snapshot| snapshot.throughput_mbps > 0.0 && snapshot.average_rtt_ms.is_some()));
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_udp_auto_packet_size_uses_loopback_mtu() {
+    let test_duration_secs = 1;
+    let port = 6007; // Unique port
+
+    let server_config = create_test_config(Protocol::Udp, TestMode::Server, test_duration_secs, port);
+    let server_handle = tokio::spawn(async move {
+        run_network_test(server_config, Arc::new(Mutex::new(TestMetrics::default()))).await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    let client_config = Arc::new(TestConfig {
+        packet_size_bytes: 0, // Auto: largest payload that fits the path MTU
+        ..(*create_test_config(Protocol::Udp, TestMode::Client, test_duration_secs, port)).clone()
+    });
+    let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    let client_result = run_network_test(client_config, Arc::clone(&client_metrics)).await;
+    assert!(client_result.is_ok(), "Client error: {:?}", client_result.err());
+
+    let final_client_metrics = client_metrics.lock().unwrap();
+    assert!(final_client_metrics.packets_sent > 0);
+    let avg_datagram_bytes = final_client_metrics.bytes_sent / final_client_metrics.packets_sent;
+    assert!(avg_datagram_bytes > 60_000, "Expected ~64KB datagrams on loopback, got {}", avg_datagram_bytes);
+    assert!(avg_datagram_bytes <= 65_507, "Datagram exceeds the maximum UDP payload: {}", avg_datagram_bytes);
+    // Every full-size echo made it back untruncated
+    assert_eq!(final_client_metrics.packets_received, final_client_metrics.packets_sent);
+
+    server_handle.abort();
+}
+
 // TODO: Add more integration tests:
-// - UDP Bidirectional
 // - TCP Bidirectional (Dual Stream)
 // - TCP Bidirectional (Single Stream)
 // - Tests with randomized packet sizes
 // - Tests with longer durations or higher tick rates (might need to be marked `#[ignore]` for CI)
 // - Tests verifying specific anomaly detection (once implemented)
+
+#[tokio::test]
+async fn test_pause_is_excluded_from_active_duration() {
+    let test_duration_secs = 2;
+    let port = 6008; // Unique port
+
+    let server_config = create_test_config(Protocol::Udp, TestMode::Server, test_duration_secs, port);
+    let server_handle = tokio::spawn(async move {
+        run_network_test(server_config, Arc::new(Mutex::new(TestMetrics::default()))).await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    let client_metrics = TestMetrics::default();
+    let control = Arc::clone(&client_metrics.control);
+    let pauser = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        control.pause();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        control.resume();
+    });
+
+    let client_config = create_test_config(Protocol::Udp, TestMode::Client, test_duration_secs, port);
+    let wall_start = std::time::Instant::now();
+    let summary = run_prepared_test(&client_config, client_metrics, &TestHooks::default())
+        .await
+        .expect("Client run failed");
+    let wall_secs = wall_start.elapsed().as_secs_f64();
+    pauser.await.unwrap();
+
+    assert!(wall_secs >= 2.9, "Test should run 2s active + 1s paused, took {:.2}s", wall_secs);
+    assert!((summary.paused_secs - 1.0).abs() < 0.2, "Paused for {:.2}s", summary.paused_secs);
+    assert!(
+        summary.test_duration_actual_secs < wall_secs - 0.8,
+        "Active duration {:.2}s should exclude the pause (wall {:.2}s)",
+        summary.test_duration_actual_secs, wall_secs
+    );
+    assert!(summary.test_duration_actual_secs >= 2.0);
+    assert!(summary.overall_metrics.packets_sent > 0);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_cancel_ends_both_peers_early_with_partial_summaries() {
+    let test_duration_secs = 30;
+    let port = 6035; // Unique port
+
+    let server_metrics = TestMetrics::default();
+    let server_control = Arc::clone(&server_metrics.control);
+    // Ends the last bandwidth interval at the configured end, which a cancel cuts short
+    let server_config = TestConfig {
+        final_sample_timing: FinalSampleTiming::ConfiguredDuration,
+        ..(*create_test_config(Protocol::Udp, TestMode::Server, test_duration_secs, port)).clone()
+    };
+    let server_handle = tokio::spawn(async move {
+        run_prepared_test(&server_config, server_metrics, &TestHooks::default()).await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    let client_metrics = TestMetrics::default();
+    let client_control = Arc::clone(&client_metrics.control);
+    let canceller = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        client_control.cancel();
+        server_control.cancel();
+    });
+
+    let client_config = create_test_config(Protocol::Udp, TestMode::Client, test_duration_secs, port);
+    let wall_start = std::time::Instant::now();
+    let client_summary = run_prepared_test(&client_config, client_metrics, &TestHooks::default())
+        .await
+        .expect("A cancelled client still succeeds");
+    let server_summary = tokio::time::timeout(Duration::from_secs(3), server_handle)
+        .await
+        .expect("The cancelled server kept running")
+        .unwrap()
+        .expect("A cancelled server still succeeds");
+    let wall_secs = wall_start.elapsed().as_secs_f64();
+    canceller.await.unwrap();
+
+    assert!(wall_secs < 5.0, "Cancelled at 0.5s of 30s but took {:.2}s", wall_secs);
+    assert!(client_summary.test_duration_actual_secs < 2.0, "Client ran {:.2}s", client_summary.test_duration_actual_secs);
+    let sent = client_summary.overall_metrics.packets_sent;
+    assert!(sent > 0 && sent < 50, "Expected ~5 of 300 packets at 10 Hz, sent {}", sent);
+    assert!(server_summary.overall_metrics.packets_received > 0);
+    let final_sample_ms = server_summary.overall_metrics.bandwidth_samples.last().expect("No final sample on cancel").0;
+    assert!(final_sample_ms < 2_000, "Final sample at {} ms of a test cancelled at ~600 ms", final_sample_ms);
+}
+
+#[tokio::test]
+async fn test_udp_multicast_group_receives_packets() {
+    let test_duration_secs = 1;
+    let port = 6010; // Unique port
+    let group: std::net::IpAddr = "239.255.42.99".parse().unwrap();
+    let with_group = |config: Arc<TestConfig>| Arc::new(TestConfig { multicast_group: Some(group), ..(*config).clone() });
+
+    let server_config = with_group(create_test_config(Protocol::Udp, TestMode::Server, test_duration_secs, port));
+    let server_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    let server_metrics_clone = Arc::clone(&server_metrics);
+    let server_handle = tokio::spawn(async move { run_network_test(server_config, server_metrics_clone).await });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    // target_ip is ignored in favour of the group; multicast loopback delivers to the local member
+    let client_config = with_group(create_test_config(Protocol::Udp, TestMode::Client, test_duration_secs, port));
+    let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    let client_result = run_network_test(client_config, Arc::clone(&client_metrics)).await;
+    assert!(client_result.is_ok(), "Client error: {:?}", client_result.err());
+
+    let packets_sent = client_metrics.lock().unwrap().packets_sent;
+    let server_received = server_metrics.lock().unwrap().packets_received;
+    assert!(packets_sent > 0);
+    assert_eq!(server_received, packets_sent, "Server should receive every packet sent to the group");
+    // Unicast echo replies still reach the (unconnected) multicast sender
+    assert!(client_metrics.lock().unwrap().packets_received > 0);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_udp_packets_per_tick_sends_bursts() {
+    let test_duration_secs = 2;
+    let port = 6011; // Unique port
+
+    let server_config = create_test_config(Protocol::Udp, TestMode::Server, test_duration_secs, port);
+    let server_handle = tokio::spawn(async move {
+        run_network_test(server_config, Arc::new(Mutex::new(TestMetrics::default()))).await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    let client_config = Arc::new(TestConfig {
+        packets_per_tick: 5,
+        ..(*create_test_config(Protocol::Udp, TestMode::Client, test_duration_secs, port)).clone()
+    });
+    let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    let client_result = run_network_test(Arc::clone(&client_config), Arc::clone(&client_metrics)).await;
+    assert!(client_result.is_ok(), "Client error: {:?}", client_result.err());
+
+    // 10 Hz for 2 s, 5 packets per tick; the first tick fires one interval in
+    let expected = client_config.tick_rate_hz as u64 * test_duration_secs * 5;
+    let packets_sent = client_metrics.lock().unwrap().packets_sent;
+    assert!(
+        packets_sent >= expected - 10 && packets_sent <= expected,
+        "Expected about {} packets, sent {}", expected, packets_sent
+    );
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_anomalies_stream_live_during_run() {
+    let port = 6012; // Unique port
+    let (anomaly_tx, mut anomaly_rx) = tokio::sync::mpsc::channel(16);
+    let server_config = Arc::new(TestConfig {
+        anomaly_tx: Some(anomaly_tx),
+        ..(*create_test_config(Protocol::Udp, TestMode::Server, 2, port)).clone()
+    });
+    let server_handle = tokio::spawn(async move {
+        run_network_test(server_config, Arc::new(Mutex::new(TestMetrics::default()))).await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    // Impaired link by hand: 4 is lost, 3 is overtaken by 5 and then delivered twice
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    for seq in [0, 1, 2, 5, 3, 3] {
+        let bytes = netstats_core::packet::CustomPacket::new_data_packet(seq, 32).to_bytes().unwrap();
+        socket.send_to(&bytes, ("127.0.0.1", port)).await.unwrap();
+    }
+
+    let mut received = Vec::new();
+    while received.len() < 2 {
+        let anomaly = tokio::time::timeout(Duration::from_secs(1), anomaly_rx.recv())
+            .await
+            .expect("Anomaly not streamed while the test is running")
+            .expect("Feed closed early");
+        received.push(anomaly.anomaly_type);
+    }
+    assert!(!server_handle.is_finished(), "Anomalies should arrive before the test completes");
+    assert_eq!(received, vec![AnomalyType::OutOfOrder, AnomalyType::DuplicatePacket]);
+
+    server_handle.abort();
+}
+
+#[tokio::test]
+async fn test_monitor_process_collects_samples_during_run() {
+    let port = 6013; // Unique port; no server needed, UDP sends regardless
+    let config = Arc::new(TestConfig {
+        monitor_process: true,
+        ..(*create_test_config(Protocol::Udp, TestMode::Client, 2, port)).clone()
+    });
+    let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    let result = run_network_test(config, Arc::clone(&metrics)).await;
+    assert!(result.is_ok(), "Client error: {:?}", result.err());
+
+    let samples = metrics.lock().unwrap().process_samples.clone();
+    assert!(!samples.is_empty(), "Expected a CPU/memory sample per second of the run");
+    assert!(samples.iter().all(|s| s.rss_bytes > 0), "{:?}", samples);
+}
+
+// Load test: floods a UDP receiver on loopback as fast as one socket can send, with and
+// without parse workers, and compares how many datagrams survived the socket buffer.
+// Timing-dependent, so not part of the default run: `cargo test -- --ignored parse_pool`
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+#[ignore = "load test"]
+async fn test_parse_pool_reduces_socket_buffer_loss_at_high_pps() {
+    async fn flood_receiver(port: u16, parse_workers: usize) -> (u64, u64) {
+        const PACKETS: u32 = 200_000;
+        let server_config = Arc::new(TestConfig {
+            parse_workers,
+            ..(*create_test_config(Protocol::Udp, TestMode::Server, 1, port)).clone()
+        });
+        let server_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        let server_handle = {
+            let server_metrics = Arc::clone(&server_metrics);
+            tokio::spawn(async move { run_network_test(server_config, server_metrics).await })
+        };
+        tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(("127.0.0.1", port)).await.unwrap();
+        for seq in 0..PACKETS {
+            let bytes = netstats_core::packet::CustomPacket::new_data_packet(seq, 32).to_bytes().unwrap();
+            let _ = socket.send(&bytes).await; // Sender-side drops are fine: both runs face them
+        }
+        // Runs out its grace period, then drains the parse queue
+        server_handle.await.unwrap().unwrap();
+        let metrics = server_metrics.lock().unwrap();
+        (metrics.packets_received, metrics.parse_queue_depth)
+    }
+
+    let (inline_received, _) = flood_receiver(6014, 0).await;
+    let (pooled_received, peak_depth) = flood_receiver(6015, 4).await;
+    println!("Received inline: {}, with 4 parse workers: {} (peak queue depth {})", inline_received, pooled_received, peak_depth);
+    assert!(pooled_received >= inline_received, "The parse pool should lose no more than inline parsing");
+}
+
+#[tokio::test]
+async fn test_ipv6_loopback_client_server() {
+    async fn run_over_ipv6(protocol: Protocol, port: u16) -> (u64, u64) {
+        let with_ipv6 = |config: Arc<TestConfig>| Arc::new(TestConfig { target_ip: "::1".to_string(), ..(*config).clone() });
+        let server_config = with_ipv6(create_test_config(protocol, TestMode::Server, 1, port));
+        let server_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        let server_metrics_clone = Arc::clone(&server_metrics);
+        let server_handle = tokio::spawn(async move { run_network_test(server_config, server_metrics_clone).await });
+        tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+        let client_config = with_ipv6(create_test_config(protocol, TestMode::Client, 1, port));
+        let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        let client_result = run_network_test(client_config, Arc::clone(&client_metrics)).await;
+        let server_result = server_handle.await.unwrap();
+        assert!(client_result.is_ok(), "{:?} client error over IPv6: {:?}", protocol, client_result.err());
+        assert!(server_result.is_ok(), "{:?} server error over IPv6: {:?}", protocol, server_result.err());
+
+        let packets_sent = client_metrics.lock().unwrap().packets_sent;
+        let packets_received = server_metrics.lock().unwrap().packets_received;
+        (packets_sent, packets_received)
+    }
+
+    let (udp_sent, udp_received) = run_over_ipv6(Protocol::Udp, 6016).await;
+    assert!(udp_sent > 0, "UDP client should send packets over IPv6");
+    assert!(udp_received > 0, "UDP server should receive packets over IPv6");
+
+    let (tcp_sent, tcp_received) = run_over_ipv6(Protocol::Tcp, 6017).await;
+    assert!(tcp_sent > 0, "TCP client should send packets over IPv6");
+    assert_eq!(tcp_received, tcp_sent, "TCP packet count mismatch over IPv6");
+}
+
+#[tokio::test]
+async fn test_tcp_loopback_benchmark_server_receives_everything_sent() {
+    let summary = run_tcp_loopback_benchmark(1, 64).await.expect("TCP loopback benchmark failed");
+    println!("TCP Benchmark: {:?}", summary);
+
+    assert_eq!(summary.protocol, Protocol::Tcp);
+    assert!(summary.client_packets_sent > 0, "Client should send TCP packets");
+    assert_eq!(summary.server_packets_received, summary.client_packets_sent, "TCP packet count mismatch between client and server");
+    // Both sides count the length prefix of every frame
+    assert_eq!(summary.server_bytes_received, summary.client_bytes_sent);
+    assert_eq!(summary.framing_bytes, summary.server_packets_received * 4);
+    assert!(summary.server_mbps > 0.0);
+    // TCP on loopback is lossless, and the benchmark is clean
+    assert_eq!(summary.loss_percentage, 0.0);
+    assert!(summary.avg_rtt_micros.is_some(), "The server echoes over TCP too");
+    assert!(!summary.server_bandwidth_samples.is_empty(), "No server bandwidth samples");
+}
+
+#[tokio::test]
+async fn test_all_address_policy_tests_each_resolved_address() {
+    // What a hostname with an IPv4 loopback alias would resolve to; the sandbox's
+    // resolver can't be made to return two addresses, so the resolution is given.
+    let port = 6021; // Unique port; nothing listens, the UDP clients just send
+    let addresses: Vec<std::net::SocketAddr> = vec![([127, 0, 0, 1], port).into(), ([127, 0, 0, 2], port).into()];
+    for policy in [MultiAddressPolicy::AllSequential, MultiAddressPolicy::AllParallel] {
+        let config = TestConfig {
+            target_ip: "loopback-pair.test".to_string(),
+            test_duration_ms: Some(300),
+            multi_address_policy: policy,
+            ..(*create_test_config(Protocol::Udp, TestMode::Client, 1, port)).clone()
+        };
+        let summaries = run_test_at_addresses(&config, &addresses).await.expect("Per-address test failed");
+        assert_eq!(summaries.len(), 2, "{:?}: one summary per address", policy);
+        for (summary, expected_ip) in summaries.iter().zip(["127.0.0.1", "127.0.0.2"]) {
+            assert_eq!(summary.test_config.target_ip, expected_ip, "{:?}", policy);
+            assert!(summary.overall_metrics.packets_sent > 0, "{:?}: nothing sent to {}", policy, expected_ip);
+        }
+    }
+
+    // First (the default) runs one test, against the first address
+    let config = TestConfig {
+        target_ip: "localhost".to_string(),
+        test_duration_ms: Some(300),
+        ..(*create_test_config(Protocol::Udp, TestMode::Client, 1, port)).clone()
+    };
+    assert_eq!(run_test_per_address(&config).await.unwrap().len(), 1);
+
+    // Cancelling ends the address under test and skips the rest, as Ctrl-C does in netstats-cli
+    let config = TestConfig { test_duration_ms: Some(30_000), multi_address_policy: MultiAddressPolicy::AllSequential, ..config };
+    let control = Arc::new(TestControl::new());
+    let canceller = Arc::clone(&control);
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        canceller.cancel();
+    });
+    let started = std::time::Instant::now();
+    let summaries = run_test_per_address_with_hooks(&config, &control, &Arc::default()).await.expect("Cancelled test failed");
+    assert_eq!(summaries.len(), 1, "the addresses after the cancelled one are skipped");
+    assert!(started.elapsed() < Duration::from_secs(10), "took {:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn test_dest_port_range_spreads_packets_over_ports() {
+    // Plain listeners on each port of the range, counting what lands on them
+    let ports = 6022..=6025u16; // Unique ports
+    let mut listeners = Vec::new();
+    for port in ports.clone() {
+        let socket = tokio::net::UdpSocket::bind(("127.0.0.1", port)).await.unwrap();
+        listeners.push(tokio::spawn(async move {
+            let mut buf = vec![0u8; 2048];
+            let mut received = 0u64;
+            while let Ok(Ok(_)) = tokio::time::timeout(Duration::from_millis(500), socket.recv(&mut buf)).await {
+                received += 1;
+            }
+            received
+        }));
+    }
+    let client_config = Arc::new(TestConfig {
+        tick_rate_hz: 100,
+        test_duration_ms: Some(500),
+        rtt_timeout_ms: 1, // Nothing echoes here
+        dest_port_range: Some((*ports.start(), *ports.end())),
+        ..(*create_test_config(Protocol::Udp, TestMode::Client, 1, *ports.start())).clone()
+    });
+    let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    run_network_test(client_config, Arc::clone(&client_metrics)).await.expect("Client failed");
+
+    let mut per_port = Vec::new();
+    for listener in listeners {
+        per_port.push(listener.await.unwrap());
+    }
+    let sent = client_metrics.lock().unwrap().packets_sent;
+    println!("{} packets over ports {:?}: {:?}", sent, ports, per_port);
+    assert!(sent > 10, "Client only sent {} packets", sent);
+    assert_eq!(per_port.iter().sum::<u64>(), sent, "Every packet lands on a port in the range");
+    assert!(per_port.iter().filter(|&&count| count > 0).count() >= 2, "Packets all went to one port: {:?}", per_port);
+
+    // A server with the same range listens on all of it and echoes from each port
+    let port = 6026; // Unique ports 6026-6028
+    let config = |test_mode| Arc::new(TestConfig {
+        dest_port_range: Some((port, port + 2)),
+        ..(*create_test_config(Protocol::Udp, test_mode, 1, port)).clone()
+    });
+    let server_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    let server_handle = {
+        let (server_config, server_metrics) = (config(TestMode::Server), Arc::clone(&server_metrics));
+        tokio::spawn(async move { run_network_test(server_config, server_metrics).await })
+    };
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+    let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    run_network_test(config(TestMode::Client), Arc::clone(&client_metrics)).await.expect("Client failed");
+    server_handle.await.unwrap().expect("Server failed");
+
+    let client = client_metrics.lock().unwrap();
+    let server = server_metrics.lock().unwrap();
+    assert_eq!(server.packets_received, client.packets_sent, "The server missed packets sent to the range");
+    assert_eq!(client.rtt_count, client.packets_sent, "Echo replies from every port reach the client");
+}
+
+#[tokio::test]
+async fn test_target_bitrate_paces_sends() {
+    const TARGET_BPS: u64 = 1_000_000;
+    let expected_bytes = TARGET_BPS as f64 / 8.0; // Over the 1 s test
+
+    // UDP into a sink that never echoes: a short RTT timeout keeps the wait for replies from
+    // slowing the loop (it still takes ~2 ms a packet, so the target stays well below that)
+    let udp_sink = tokio::net::UdpSocket::bind("127.0.0.1:6029").await.unwrap(); // Unique port
+    let udp_drain = tokio::spawn(async move {
+        let mut buf = vec![0u8; 2048];
+        while let Ok(Ok(_)) = tokio::time::timeout(Duration::from_millis(500), udp_sink.recv(&mut buf)).await {}
+    });
+    // TCP into a listener that just reads
+    let tcp_sink = tokio::net::TcpListener::bind("127.0.0.1:6030").await.unwrap(); // Unique port
+    let tcp_drain = tokio::spawn(async move {
+        let (mut stream, _) = tcp_sink.accept().await.unwrap();
+        let _ = tokio::io::copy(&mut stream, &mut tokio::io::sink()).await;
+    });
+
+    for (protocol, port) in [(Protocol::Udp, 6029), (Protocol::Tcp, 6030)] {
+        let config = Arc::new(TestConfig {
+            tick_rate_hz: 10, // 10 x 500 B would be only 40 kbps: the bitrate overrides it
+            packet_size_bytes: 500,
+            packet_size_range: Some((200, 800)),
+            rtt_timeout_ms: 1,
+            target_bitrate_bps: Some(TARGET_BPS),
+            ..(*create_test_config(protocol, TestMode::Client, 1, port)).clone()
+        });
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        run_network_test(config, Arc::clone(&metrics)).await.expect("Client failed");
+        let bytes_sent = metrics.lock().unwrap().bytes_sent as f64;
+        println!("{:?}: {} bytes sent, {} expected", protocol, bytes_sent, expected_bytes);
+        assert!(
+            (bytes_sent - expected_bytes).abs() < expected_bytes * 0.1,
+            "{:?}: sent {} bytes in 1 s at a {} bps target",
+            protocol, bytes_sent, TARGET_BPS
+        );
+    }
+    udp_drain.await.unwrap();
+    tcp_drain.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_tcp_connect_rtt_is_reported_apart_from_data_rtt() {
+    // A listener that doesn't echo leaves the client no data RTT, only the handshake
+    let sink = tokio::net::TcpListener::bind("127.0.0.1:6031").await.unwrap(); // Unique port
+    let drain = tokio::spawn(async move {
+        let (mut stream, _) = sink.accept().await.unwrap();
+        let _ = tokio::io::copy(&mut stream, &mut tokio::io::sink()).await;
+    });
+    let config = TestConfig {
+        test_duration_ms: Some(300),
+        ..(*create_test_config(Protocol::Tcp, TestMode::Client, 1, 6031)).clone()
+    };
+    let summary = run_test_with_hooks(&config, &TestHooks::default()).await.expect("TCP client failed");
+    drain.await.unwrap();
+
+    let metrics = &summary.overall_metrics;
+    let connect_rtt_micros = metrics.connect_rtt_micros.expect("No connect RTT recorded");
+    assert!(connect_rtt_micros < 1_000_000, "Loopback handshake took {} us", connect_rtt_micros);
+    assert_eq!(metrics.rtt_count, 0, "No data RTT samples expected");
+    assert!(summary.headline_rtt_micros.is_none());
+
+    let markdown = netstats_core::reporter::generate_markdown_report(&summary);
+    assert!(markdown.contains("| Connect RTT (TCP handshake) | "), "{}", markdown);
+    assert!(markdown.contains(&format!("| RTT ({}) | N/A |\n| Connect RTT (TCP handshake) | ", summary.headline_rtt_label)), "{}", markdown);
+    let html = netstats_core::reporter::generate_html_report_string(&summary).unwrap();
+    assert!(html.contains(&format!("<th>Connect RTT (TCP handshake)</th><td>{:.3} ms</td>", connect_rtt_micros as f64 / 1000.0)));
+}
+
+#[tokio::test]
+async fn test_echo_server_answers_echo_requests() {
+    use netstats_core::packet::{CustomPacket, PacketType};
+
+    // As netstats-echo listens by default: on both IPv4 and IPv6
+    let socket = Arc::new(netstats_core::network::bind_udp_dual_stack(6032).unwrap()); // Unique port
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let echo_server = tokio::spawn(netstats_core::network::run_udp_echo_server(
+        Arc::new(TestConfig::default()),
+        socket,
+        async move {
+            let _ = stop_rx.await;
+        },
+    ));
+
+    let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect("127.0.0.1:6032").await.unwrap();
+    let mut buf = vec![0u8; 2048];
+    let ipv6_client = tokio::net::UdpSocket::bind("[::1]:0").await.unwrap();
+    ipv6_client.connect("[::1]:6032").await.unwrap();
+    ipv6_client.send(&CustomPacket::new_echo_request(6, 64).to_bytes().unwrap()).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(2), ipv6_client.recv(&mut buf)).await.expect("No EchoReply over IPv6").unwrap();
+    for seq in [7, 8] {
+        let request = CustomPacket::new_echo_request(seq, 64);
+        client.send(&request.to_bytes().unwrap()).await.unwrap();
+        let len = tokio::time::timeout(Duration::from_secs(2), client.recv(&mut buf))
+            .await
+            .expect("No EchoReply from the echo server")
+            .unwrap();
+        let reply = CustomPacket::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(reply.header.packet_type, PacketType::EchoReply);
+        assert_eq!(reply.header.sequence_number, seq);
+        assert_eq!(reply.payload, request.payload);
+    }
+    // Anything else is ignored
+    client.send(&CustomPacket::new_data_packet(9, 64).to_bytes().unwrap()).await.unwrap();
+    assert!(tokio::time::timeout(Duration::from_millis(200), client.recv(&mut buf)).await.is_err());
+
+    stop_tx.send(()).unwrap();
+    let echoed = echo_server.await.unwrap().expect("Echo server failed");
+    assert_eq!(echoed, 3);
+}
+
+#[tokio::test]
+async fn test_udp_bidirectional_on_loopback_with_separate_listen_ports() {
+    // Each peer listens on its own port and targets the other's
+    let peer_config = |target_port: u16, local_listen_port: u16| TestConfig {
+        local_listen_port: Some(local_listen_port),
+        ..(*create_test_config(Protocol::Udp, TestMode::Bidirectional, 1, target_port)).clone()
+    };
+    let run_peer = |config: TestConfig| async move {
+        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
+        run_network_test(Arc::new(config), Arc::clone(&metrics)).await.map(|()| metrics)
+    };
+    let (a, b) = tokio::join!(run_peer(peer_config(6034, 6033)), run_peer(peer_config(6033, 6034))); // Unique ports
+
+    for (name, result) in [("A", a), ("B", b)] {
+        let metrics = result.unwrap_or_else(|e| panic!("Peer {} failed: {:?}", name, e));
+        let metrics = metrics.lock().unwrap();
+        assert!(metrics.packets_sent >= 8, "Peer {} sent only {}", name, metrics.packets_sent);
+        // The other peer's requests arrive on the listener and its echoes of ours come back
+        assert!(metrics.packets_received > 0, "Peer {} received nothing", name);
+        assert!(metrics.rtt_count > 0, "Peer {} has no RTT samples", name);
+        assert_eq!(metrics.nat_mapped_port, None, "No NAT on loopback, peer {}", name);
+    }
+}
+
+#[tokio::test]
+async fn test_tcp_client_measures_rtt_from_server_echoes() {
+    let port = 6037; // Unique port
+    let server_config = create_test_config(Protocol::Tcp, TestMode::Server, 1, port);
+    let server_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    let server_metrics_clone = Arc::clone(&server_metrics);
+    let server_handle = tokio::spawn(async move { run_network_test(server_config, server_metrics_clone).await });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    let client_config = create_test_config(Protocol::Tcp, TestMode::Client, 1, port);
+    let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    let client_result = run_network_test(client_config, Arc::clone(&client_metrics)).await;
+    let server_result = server_handle.await.unwrap();
+    assert!(client_result.is_ok(), "Client error: {:?}", client_result.err());
+    assert!(server_result.is_ok(), "Server error: {:?}", server_result.err());
+
+    let client = client_metrics.lock().unwrap();
+    assert!(client.packets_sent > 0, "Client should send TCP packets");
+    assert!(client.rtt_count > 0, "No RTT samples from the server's echo replies");
+    assert_eq!(client.packets_received, client.rtt_count, "Only echo replies come back to a client");
+    let average_rtt_micros = client.average_rtt_micros().unwrap();
+    assert!(average_rtt_micros < 1_000_000.0, "Loopback RTT of {} us", average_rtt_micros);
+    assert_eq!(server_metrics.lock().unwrap().packets_received, client.packets_sent);
+}
+
+#[tokio::test]
+async fn test_client_retries_until_late_server_is_up() {
+    let port = 6038; // Unique port
+    let mut client_config = (*create_test_config(Protocol::Tcp, TestMode::Client, 1, port)).clone();
+    client_config.max_test_retries = 3;
+    // The first connect is refused; the retry (after 500 ms) finds the server listening
+    let server_config = create_test_config(Protocol::Tcp, TestMode::Server, 1, port);
+    let server_handle = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        run_network_test(server_config, Arc::new(Mutex::new(TestMetrics::default()))).await
+    });
+
+    let summary = run_test_with_hooks(&client_config, &TestHooks::default()).await;
+    let server_result = server_handle.await.unwrap();
+    let summary = summary.expect("Client should succeed once the server is up");
+    assert!(server_result.is_ok(), "Server error: {:?}", server_result.err());
+    assert!(summary.overall_metrics.packets_sent > 0);
+    assert!(summary.overall_metrics.connect_rtt_micros.is_some());
+}
+
+#[tokio::test]
+async fn test_two_phase_plan_aggregates_assertions() {
+    let port = 6039; // Unique port
+    // One UDP server outlives both 1 s client phases
+    let server_config = create_test_config(Protocol::Udp, TestMode::Server, 6, port);
+    let server_handle = tokio::spawn(async move {
+        run_network_test(server_config, Arc::new(Mutex::new(TestMetrics::default()))).await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    let plan = TestPlan::from_toml_str(&format!(
+        r#"
+        name = "loopback"
+
+        [defaults]
+        target_ip = "127.0.0.1"
+        target_port = {}
+        test_duration_secs = 1
+        tick_rate_hz = 20
+        packet_size_bytes = 64
+
+        [[phases]]
+        label = "Healthy"
+        assertions = ["loss < 50%"]
+
+        [[phases]]
+        label = "Impossible"
+        assertions = ["loss < 50%", "p95_rtt < 0ms"]
+
+        [[phases]]
+        label = "No | listener"
+        protocol = "Tcp"
+        target_port = 1
+        "#,
+        port
+    ))
+    .unwrap();
+    let result = run_test_plan(&plan).await.expect("Plan should run");
+    server_handle.await.unwrap().unwrap();
+
+    assert_eq!(result.phases.len(), 2, "The phases before a failed one are kept");
+    assert_eq!(result.failed.len(), 1);
+    assert_eq!(result.failed[0].label, "No | listener");
+    assert!(result.phases[0].summary.assertions_passed(), "{:?}", result.phases[0].summary.assertion_results);
+    assert!(!result.phases[1].summary.assertions_passed());
+    assert_eq!(result.assertion_counts(), (2, 3));
+    assert!(!result.passed(), "One failed assertion fails the plan");
+    // Each phase's own report is labelled with the phase
+    assert_eq!(result.phases[1].summary.phase_boundaries[0].label, "Impossible");
+    let markdown = result.markdown();
+    assert!(markdown.contains("| Healthy | UDP |") && markdown.contains("| Impossible | UDP |"), "{}", markdown);
+    assert!(markdown.contains("2 of 3 assertions passed over 3 phases"), "{}", markdown);
+    assert!(markdown.contains("| No \\| listener | TCP | | | | Error: "), "{}", markdown);
+}
+
+#[tokio::test]
+async fn test_parallel_tcp_streams_aggregate_bytes() {
+    let port = 6040; // Unique port
+    let parallel = |mode| TestConfig { parallel_streams: 4, ..(*create_test_config(Protocol::Tcp, mode, 1, port)).clone() };
+    let server_config = Arc::new(parallel(TestMode::Server));
+    let server_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    let server_metrics_clone = Arc::clone(&server_metrics);
+    let server_handle = tokio::spawn(async move { run_network_test(server_config, server_metrics_clone).await });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    let client_result = run_network_test(Arc::new(parallel(TestMode::Client)), Arc::clone(&client_metrics)).await;
+    let server_result = server_handle.await.unwrap();
+    assert!(client_result.is_ok(), "Client error: {:?}", client_result.err());
+    assert!(server_result.is_ok(), "Server error: {:?}", server_result.err());
+
+    let client = client_metrics.lock().unwrap();
+    let server = server_metrics.lock().unwrap();
+    // 10 Hz for 1 s, shared by the 4 connections
+    assert!(client.packets_sent >= 8, "Only {} packets sent over 4 streams", client.packets_sent);
+    assert_eq!(server.packets_received, client.packets_sent);
+    assert_eq!(server.bytes_received, client.bytes_sent, "The server should receive every byte of every stream");
+    assert_eq!(client.rtt_count, client.packets_sent, "Each stream's echo replies should come back on it");
+    let sampled_bytes: u64 = server.bandwidth_samples.iter().map(|&(_, bytes, _)| bytes).sum();
+    assert_eq!(sampled_bytes, server.bytes_received, "One set of bandwidth samples should cover all streams");
+}
+
+#[tokio::test]
+async fn test_count_limited_tcp_client_samples_until_the_count_is_sent() {
+    let port = 6041; // Unique port
+    let server_config = create_test_config(Protocol::Tcp, TestMode::Server, 5, port);
+    let server_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    let server_metrics_clone = Arc::clone(&server_metrics);
+    let server_handle = tokio::spawn(async move { run_network_test(server_config, server_metrics_clone).await });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    // 40 packets at 20 Hz: 2 s of sending with no configured duration
+    let client_config = Arc::new(TestConfig {
+        stop_after_packets: Some(40),
+        tick_rate_hz: 20,
+        bandwidth_sample_interval_ms: 500,
+        ..(*create_test_config(Protocol::Tcp, TestMode::Client, 0, port)).clone()
+    });
+    let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    let client_result = run_network_test(client_config, Arc::clone(&client_metrics)).await;
+    let server_result = server_handle.await.unwrap();
+    assert!(client_result.is_ok(), "Client error: {:?}", client_result.err());
+    assert!(server_result.is_ok(), "Server error: {:?}", server_result.err());
+
+    let client = client_metrics.lock().unwrap();
+    assert_eq!(client.packets_sent, 40);
+    assert_eq!(server_metrics.lock().unwrap().packets_received, 40);
+    assert_eq!(client.rtt_count, 40, "The receive side should stay up for every echo reply");
+    // Sampled over the whole send, not folded into one sample at a zero configured end
+    assert!(client.bandwidth_samples.len() >= 3, "Samples: {:?}", client.bandwidth_samples);
+    let last_sample_ms = client.bandwidth_samples.last().unwrap().0;
+    assert!(last_sample_ms >= 1_500, "Last sample at {} ms", last_sample_ms);
+    let sampled_bytes: u64 = client.bandwidth_samples.iter().map(|&(_, bytes, _)| bytes).sum();
+    assert_eq!(sampled_bytes, client.bytes_received);
+}
+
+#[tokio::test]
+async fn test_parallel_tcp_streams_share_the_packet_count() {
+    let port = 6042; // Unique port
+    let parallel = |mode, duration_secs| TestConfig {
+        parallel_streams: 4,
+        tick_rate_hz: 20,
+        ..(*create_test_config(Protocol::Tcp, mode, duration_secs, port)).clone()
+    };
+    let server_config = Arc::new(parallel(TestMode::Server, 5));
+    let server_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    let server_metrics_clone = Arc::clone(&server_metrics);
+    let server_handle = tokio::spawn(async move { run_network_test(server_config, server_metrics_clone).await });
+    tokio::time::sleep(Duration::from_millis(100)).await; // Server startup grace
+
+    // -n 10 -P 4 sends 10 packets in all, not 10 per connection
+    let client_config = Arc::new(TestConfig { stop_after_packets: Some(10), ..parallel(TestMode::Client, 0) });
+    let client_metrics = Arc::new(Mutex::new(TestMetrics::default()));
+    let client_result = run_network_test(client_config, Arc::clone(&client_metrics)).await;
+    let server_result = server_handle.await.unwrap();
+    assert!(client_result.is_ok(), "Client error: {:?}", client_result.err());
+    assert!(server_result.is_ok(), "Server error: {:?}", server_result.err());
+
+    assert_eq!(client_metrics.lock().unwrap().packets_sent, 10);
+    assert_eq!(server_metrics.lock().unwrap().packets_received, 10);
+}
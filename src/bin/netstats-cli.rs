@@ -0,0 +1,142 @@
+// Headless entry point: one test from command-line arguments (one per address with
+// --addresses) or a test plan (--plan), summaries on stdout.
+// Builds without Slint: cargo build --no-default-features --bin netstats-cli
+
+use netstats_core::benchmark::estimate_max_pps;
+use netstats_core::cli::{parse_args, CliArgs, LoopbackCeiling, USAGE};
+use netstats_core::control::TestControl;
+use netstats_core::plan::{run_test_plan, TestPlan};
+use netstats_core::reporter::{generate_html_report_string, generate_markdown_report, TestSummary};
+use netstats_core::runner::{build_runtime, run_test_per_address_with_hooks, TestHooks};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let cli = match parse_args(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    if cli.help {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    // A plan's phase configs are validated when it is loaded
+    if let (None, Err(e)) = (&cli.plan_path, cli.config.validate()) {
+        eprintln!("{}\n\n{}", e, USAGE);
+        return ExitCode::from(2);
+    }
+
+    let runtime = match build_runtime(cli.config.runtime_worker_threads) {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to start the async runtime: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    runtime.block_on(run(cli))
+}
+
+async fn run(cli: CliArgs) -> ExitCode {
+    if let Some(plan_path) = &cli.plan_path {
+        return run_plan(plan_path, cli.report_path.as_deref()).await;
+    }
+
+    // Ctrl-C ends the test early; the summary then covers the part that ran
+    let control = Arc::new(TestControl::new());
+    let ctrl_c_control = Arc::clone(&control);
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("Cancelling the test...");
+            ctrl_c_control.cancel();
+        }
+    });
+
+    // Measured before the test, so the burst doesn't compete with it
+    let loopback_max_pps = match cli.loopback_ceiling {
+        Some(LoopbackCeiling::Measure) => match tokio::task::spawn_blocking(estimate_max_pps).await {
+            Ok(max_pps) => Some(max_pps),
+            Err(e) => {
+                eprintln!("Loopback PPS estimate failed: {}", e);
+                None
+            }
+        },
+        Some(LoopbackCeiling::Pps(max_pps)) => Some(max_pps),
+        None => None,
+    };
+
+    let hooks = Arc::new(TestHooks::shell_commands(cli.pre_cmd.clone(), cli.post_cmd.clone()));
+    let mut summaries = match run_test_per_address_with_hooks(&cli.config, &control, &hooks).await {
+        Ok(summaries) => summaries,
+        Err(e) => {
+            eprintln!("Test failed: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(max_pps) = loopback_max_pps {
+        summaries = summaries.into_iter().map(|summary| summary.with_loopback_ceiling(max_pps)).collect();
+    }
+    for summary in &summaries {
+        println!("{}", generate_markdown_report(summary));
+    }
+
+    if let Some(report_path) = &cli.report_path {
+        for (index, summary) in summaries.iter().enumerate() {
+            let path = if summaries.len() > 1 { numbered_path(report_path, index + 1) } else { report_path.clone() };
+            if let Err(e) = write_html_report(summary, &path) {
+                eprintln!("Failed to write the HTML report to {}: {}", path.display(), e);
+                return ExitCode::FAILURE;
+            }
+            println!("HTML report written to {}", path.display());
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Runs the plan in `plan_path`; exits non-zero unless every phase passes.
+async fn run_plan(plan_path: &Path, report_dir: Option<&Path>) -> ExitCode {
+    let plan = match TestPlan::from_toml_file(plan_path).and_then(|plan| plan.phase_configs().map(|_| plan)) {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("Invalid test plan {}: {}", plan_path.display(), e);
+            return ExitCode::from(2);
+        }
+    };
+    let result = match run_test_plan(&plan).await {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Test plan failed: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("{}", result.markdown());
+
+    if let Some(report_dir) = report_dir {
+        match result.write_reports(report_dir) {
+            Ok(run_dir) => println!("Reports written to {}", run_dir.display()),
+            Err(e) => {
+                eprintln!("Failed to write the reports to {}: {}", report_dir.display(), e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if result.passed() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
+
+fn write_html_report(summary: &TestSummary, path: &Path) -> Result<(), String> {
+    let html = generate_html_report_string(summary).map_err(|e| e.to_string())?;
+    std::fs::write(path, html).map_err(|e| e.to_string())
+}
+
+/// `report.html` -> `report-2.html`, for the report of the second address.
+fn numbered_path(path: &Path, number: usize) -> PathBuf {
+    let stem = path.file_stem().map_or_else(|| "report".into(), |stem| stem.to_string_lossy());
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, number),
+    };
+    path.with_file_name(name)
+}
@@ -0,0 +1,50 @@
+// Standalone reflector: echoes netstats UDP EchoRequests until Ctrl-C.
+// Builds without Slint: cargo build --no-default-features --bin netstats-echo
+
+use netstats_core::cli::{parse_echo_args, ECHO_USAGE};
+use netstats_core::config::TestConfig;
+use netstats_core::network::{bind_udp_dual_stack, run_udp_echo_server, NetworkError};
+use std::process::ExitCode;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match parse_echo_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, ECHO_USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    if args.help {
+        println!("{}", ECHO_USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    let bound = match args.bind_ip {
+        Some(ip) => UdpSocket::bind((ip, args.port)).await.map_err(NetworkError::from),
+        None => bind_udp_dual_stack(args.port),
+    };
+    let socket = match bound {
+        Ok(socket) => Arc::new(socket),
+        Err(e) => {
+            eprintln!("Failed to listen on UDP port {}: {}", args.port, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let shutdown = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            // Ctrl-C then still ends the process, just without the clean shutdown
+            eprintln!("Failed to listen for Ctrl-C ({}); echoing until the process is stopped", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    match run_udp_echo_server(Arc::new(TestConfig::default()), socket, shutdown).await {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Echo server failed: {:?}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
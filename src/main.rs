@@ -1,16 +1,77 @@
 // Import the generated Rust code from the .slint file
 slint::include_modules!();
 
-use netstats_core::config::{TestConfig, Protocol, TestMode, TcpBidirectionalMode};
-use netstats_core::metrics::TestMetrics; // For potential real-time updates
+use netstats_core::anomalies::AnomalyEvent;
+use netstats_core::config::{TestConfig, Protocol, ReportFormat, TestMode, TcpBidirectionalMode};
+use netstats_core::control::TestControl;
+use netstats_core::metrics::{MetricsSnapshot, TestMetrics};
+use netstats_core::network::NetworkError;
 use netstats_core::reporter::TestSummary; // For displaying summary
-use netstats_core::anomalies::AnomalyEvent; // For displaying anomalies
+use netstats_core::runner::TestHooks;
 
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration; // For actual test duration, not GUI value
 use slint::SharedString;
 
+// Each run gets its own subdirectory in here (see reporter::write_run_artifacts)
+const REPORTS_DIR: &str = "netstats_reports";
+
+/// The protocol selected in the UI.
+fn protocol_from_ui(ui: &AppWindow) -> Protocol {
+    match ui.get_protocol_options().get(ui.get_selected_protocol_idx() as usize).unwrap().id.as_str() {
+        "udp" => Protocol::Udp,
+        "tcp" => Protocol::Tcp,
+        _ => Protocol::Udp, // Default
+    }
+}
+
+/// Builds a TestConfig from the current UI inputs, or an error message for the status line.
+fn config_from_ui(ui: &AppWindow) -> Result<TestConfig, String> {
+    let target_ip = ui.get_target_ip().to_string();
+    let target_port = ui.get_target_port() as u16;
+    let duration_secs = ui.get_duration_secs() as u64;
+    let tick_rate_hz = ui.get_tick_rate_hz() as u32;
+    let packet_size_bytes = ui.get_packet_size_bytes() as usize;
+
+    let packet_size_range = ui
+        .get_use_random_packet_size()
+        .then(|| (ui.get_random_min_size().max(0) as usize, ui.get_random_max_size().max(0) as usize));
+
+    let protocol = protocol_from_ui(ui);
+
+    let test_mode = match ui.get_test_mode_options().get(ui.get_selected_test_mode_idx() as usize).unwrap().id.as_str() {
+        "client" => TestMode::Client,
+        "server" => TestMode::Server,
+        "bidi" => TestMode::Bidirectional,
+        _ => TestMode::Client, // Default
+    };
+
+    let tcp_bidi_mode = if protocol == Protocol::Tcp && test_mode == TestMode::Bidirectional {
+        match ui.get_tcp_bidi_mode_options().get(ui.get_selected_tcp_bidi_mode_idx() as usize).unwrap().id.as_str() {
+            "dual" => Some(TcpBidirectionalMode::DualStream),
+            "single" => Some(TcpBidirectionalMode::SingleStream),
+            _ => Some(TcpBidirectionalMode::DualStream), // Default
+        }
+    } else {
+        None
+    };
+
+    let config = TestConfig {
+        target_ip,
+        target_port,
+        test_duration_secs: duration_secs,
+        tick_rate_hz,
+        packet_size_bytes,
+        packet_size_range,
+        protocol,
+        test_mode,
+        tcp_bidirectional_mode: tcp_bidi_mode,
+        ..Default::default()
+    };
+    config.validate().map_err(|e| format!("Error: {}", e))?;
+    Ok(config)
+}
 
 fn main() -> Result<(), slint::PlatformError> {
     let ui = AppWindow::new()?;
@@ -20,7 +81,19 @@ fn main() -> Result<(), slint::PlatformError> {
     // --- State for Core Logic ---
     // Use Arc<Mutex<Option<TestSummary>>> to store the latest test result
     let latest_summary: Arc<Mutex<Option<TestSummary>>> = Arc::new(Mutex::new(None));
-
+    // Pause/resume and cancel switch of the running single test, if any
+    let active_control: Arc<Mutex<Option<Arc<TestControl>>>> = Arc::new(Mutex::new(None));
+    let active_control_pause = Arc::clone(&active_control);
+    let active_control_stop = Arc::clone(&active_control);
+
+    // Built with the flame feature, NETSTATS_FLAME_TRACE=<file> records the hot loops of every
+    // test until the window closes, for inferno-flamegraph
+    #[cfg(feature = "flame")]
+    let _flame_trace = std::env::var_os("NETSTATS_FLAME_TRACE").and_then(|path| {
+        netstats_core::flame::FlameTrace::start(std::path::Path::new(&path))
+            .map_err(|e| eprintln!("Failed to start the flame trace: {}", e))
+            .ok()
+    });
 
     // --- Callbacks ---
     ui.on_start_test_clicked(move || {
@@ -31,135 +104,93 @@ fn main() -> Result<(), slint::PlatformError> {
         ui.set_html_report_path("".into());
 
 
-        // Gather config from UI properties
-        let target_ip = ui.get_target_ip().to_string();
-        let target_port = ui.get_target_port() as u16;
-        let duration_secs = ui.get_duration_secs() as u64;
-        let tick_rate_hz = ui.get_tick_rate_hz() as u32;
-        let packet_size_bytes = ui.get_packet_size_bytes() as usize;
-
-        let packet_size_range = if ui.get_use_random_packet_size() {
-            let min_size = ui.get_random_min_size() as usize;
-            let max_size = ui.get_random_max_size() as usize;
-            if min_size > 0 && max_size > 0 && min_size <= max_size {
-                Some((min_size, max_size))
-            } else {
-                // TODO: Show error in UI if range is invalid
-                ui.set_status_text("Error: Invalid random packet size range.".into());
+        let mut config = match config_from_ui(&ui) {
+            Ok(config) => config,
+            Err(msg) => {
+                ui.set_status_text(msg.into());
                 ui.set_test_in_progress(false);
                 return;
             }
-        } else {
-            None
         };
+        // Anomalies are shown in the status line as they happen
+        let (anomaly_tx, mut anomaly_rx) = tokio::sync::mpsc::channel::<AnomalyEvent>(64);
+        config.anomaly_tx = Some(anomaly_tx);
+        let config = Arc::new(config);
 
-        let protocol = match ui.get_protocol_options().get(ui.get_selected_protocol_idx() as usize).unwrap().id.as_str() {
-            "udp" => Protocol::Udp,
-            "tcp" => Protocol::Tcp,
-            _ => Protocol::Udp, // Default
-        };
-
-        let test_mode = match ui.get_test_mode_options().get(ui.get_selected_test_mode_idx() as usize).unwrap().id.as_str() {
-            "client" => TestMode::Client,
-            "server" => TestMode::Server,
-            "bidi" => TestMode::Bidirectional,
-            _ => TestMode::Client, // Default
-        };
-
-        let tcp_bidi_mode = if protocol == Protocol::Tcp && test_mode == TestMode::Bidirectional {
-            match ui.get_tcp_bidi_mode_options().get(ui.get_selected_tcp_bidi_mode_idx() as usize).unwrap().id.as_str() {
-                "dual" => Some(TcpBidirectionalMode::DualStream),
-                "single" => Some(TcpBidirectionalMode::SingleStream),
-                _ => Some(TcpBidirectionalMode::DualStream), // Default
-            }
-        } else {
-            None
-        };
-
-        let config = Arc::new(TestConfig {
-            target_ip,
-            target_port,
-            test_duration_secs: duration_secs,
-            tick_rate_hz,
-            packet_size_bytes,
-            packet_size_range,
-            protocol,
-            test_mode,
-            tcp_bidirectional_mode: tcp_bidi_mode,
-        });
-
-        let metrics = Arc::new(Mutex::new(TestMetrics::default()));
         let summary_clone = Arc::clone(&latest_summary); // Clone Arc for thread
         let ui_handle_thread = ui.as_weak();
+        let metrics = TestMetrics::default();
+        *active_control.lock().unwrap() = Some(Arc::clone(&metrics.control));
+        let control_clone = Arc::clone(&active_control);
 
         // Spawn a new thread for the network test to avoid blocking the UI
         thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            let core_config = Arc::clone(&config);
-            let core_metrics = Arc::clone(&metrics);
+            let rt = match netstats_core::runner::build_runtime(config.runtime_worker_threads) {
+                Ok(rt) => rt,
+                Err(e) => {
+                    eprintln!("Failed to start the test runtime: {}", e);
+                    *control_clone.lock().unwrap() = None;
+                    let _ = slint::invoke_from_event_loop(move || {
+                        let ui = ui_handle_thread.unwrap();
+                        ui.set_test_in_progress(false);
+                        ui.set_status_text(SharedString::from(format!("Test Error: failed to start the runtime: {}", e)));
+                    });
+                    return;
+                }
+            };
 
             rt.block_on(async {
-                match netstats_core::network::run_network_test(core_config, core_metrics).await {
-                    Ok(()) => {
-                        // Make final_metrics mutable to potentially add a high packet loss anomaly.
-                        let mut final_metrics = Arc::try_unwrap(metrics)
-                            .expect("Metrics Arc should be unique after test")
-                            .into_inner()
-                            .expect("Failed to unlock metrics");
-
-                        let actual_duration = if let Some(start_time) = final_metrics.test_start_time {
-                            start_time.elapsed()
-                        } else {
-                            Duration::from_secs(config.test_duration_secs) // Fallback
-                        };
-
-                        // Check for high packet loss anomaly based on config threshold
-                        if let Some(loss_threshold_percent) = config.packet_loss_threshold_percent {
-                            let loss_percentage = final_metrics.packet_loss_percentage();
-                            if loss_percentage >= loss_threshold_percent {
-                                // Timestamp the anomaly as occurring at the end of the test for summary purposes
-                                let anomaly_timestamp_ms = final_metrics.test_start_time
-                                    .map_or(0, |st| actual_duration.as_millis() as u128); // or st.elapsed().as_millis() before summary
-
-                                final_metrics.anomalies.push(AnomalyEvent {
-                                    timestamp_ms: anomaly_timestamp_ms,
-                                    anomaly_type: netstats_core::anomalies::AnomalyType::PacketLoss, // Using existing PacketLoss type
-                                    description: format!(
-                                        "High packet loss detected: {:.2}% (threshold: {}%)",
-                                        loss_percentage, loss_threshold_percent
-                                    ),
-                                });
+                let ui_handle_anomalies = ui_handle_thread.clone();
+                tokio::spawn(async move {
+                    while let Some(anomaly) = anomaly_rx.recv().await {
+                        let ui_handle_anomalies = ui_handle_anomalies.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(ui) = ui_handle_anomalies.upgrade() {
+                                ui.set_status_text(SharedString::from(format!(
+                                    "Running... anomaly at {:.1}s: {}: {}",
+                                    anomaly.timestamp_ms as f64 / 1000.0, anomaly.anomaly_type, anomaly.description
+                                )));
                             }
-                        }
-
-                        // Call the updated generate_summary from reporter.rs
-                        // It now takes (config, metrics, actual_duration)
-                        // Anomalies are expected to be within final_metrics.anomalies
-                        let summary = netstats_core::reporter::generate_summary(
-                            &config,
-                            final_metrics, // final_metrics is moved here
-                            actual_duration,
-                        );
-
-                        let report_path_str = format!("netstats_report_{}.html", chrono::Local::now().format("%Y%m%d_%H%M%S"));
-                        match netstats_core::reporter::generate_html_report_string(&summary) {
-                            Ok(html_content) => {
-                                if let Err(e) = std::fs::write(&report_path_str, html_content) {
-                                    eprintln!("Failed to write HTML report: {}", e);
-                                     let _ = slint::invoke_from_event_loop(move || {
-                                        ui_handle_thread.unwrap().set_status_text(SharedString::from(format!("Test complete. Failed to write report: {}",e)));
-                                    });
-                                } else {
-                                    let _ = slint::invoke_from_event_loop(move || {
-                                        ui_handle_thread.unwrap().set_html_report_path(report_path_str.clone().into());
-                                        ui_handle_thread.unwrap().set_status_text(SharedString::from(format!("Test complete! Report: {}", report_path_str)));
-                                    });
-                                }
+                        });
+                    }
+                });
+
+                // Live numbers in the statistics area until the final summary replaces them
+                let ui_handle_samples = ui_handle_thread.clone();
+                let hooks = TestHooks {
+                    on_sample: Some(Box::new(move |snapshot: MetricsSnapshot| {
+                        let ui_handle_samples = ui_handle_samples.clone();
+                        let _ = slint::invoke_from_event_loop(move || {
+                            if let Some(ui) = ui_handle_samples.upgrade() {
+                                ui.set_results_summary(SharedString::from(format!(
+                                    "{:.1}s: {:.3} Mbps, {} sent, {} received, {:.2}% loss, avg RTT {}",
+                                    snapshot.elapsed_ms as f64 / 1000.0,
+                                    snapshot.throughput_mbps,
+                                    snapshot.packets_sent,
+                                    snapshot.packets_received,
+                                    snapshot.packet_loss_percent,
+                                    snapshot.average_rtt_ms.map_or("n/a".to_string(), |ms| format!("{:.3} ms", ms)),
+                                )));
+                            }
+                        });
+                    })),
+                    ..Default::default()
+                };
+                match netstats_core::runner::run_prepared_test(&config, metrics, &hooks).await {
+                    Ok(summary) => {
+                        match netstats_core::reporter::write_run_artifacts(&summary, Path::new(REPORTS_DIR)) {
+                            Ok(artifacts) => {
+                                let report_path = artifacts.path(ReportFormat::Html).unwrap_or(&artifacts.run_dir);
+                                let report_path_str = report_path.display().to_string();
+                                let _ = slint::invoke_from_event_loop(move || {
+                                    ui_handle_thread.unwrap().set_html_report_path(report_path_str.clone().into());
+                                    ui_handle_thread.unwrap().set_status_text(SharedString::from(format!("Test complete! Report: {}", report_path_str)));
+                                });
                             }
                             Err(e) => {
-                                eprintln!("Failed to generate HTML report: {}", e);
-                                 let _ = slint::invoke_from_event_loop(move || {
-                                    ui_handle_thread.unwrap().set_status_text(SharedString::from(format!("Test complete. Failed to gen HTML: {}",e)));
+                                eprintln!("Failed to write report artifacts: {}", e);
+                                let _ = slint::invoke_from_event_loop(move || {
+                                    ui_handle_thread.unwrap().set_status_text(SharedString::from(format!("Test complete. Failed to write report: {}", e)));
                                 });
                             }
                         }
@@ -169,8 +200,8 @@ fn main() -> Result<(), slint::PlatformError> {
 
                     }
                     Err(e) => {
-                        eprintln!("Network test error: {:?}", e);
-                        let error_msg = format!("Test Error: {:?}", e);
+                        eprintln!("Network test error: {}", e);
+                        let error_msg = format!("Test Error: {}", e);
                          let _ = slint::invoke_from_event_loop(move || {
                             ui_handle_thread.unwrap().set_status_text(SharedString::from(error_msg));
                         });
@@ -178,9 +209,12 @@ fn main() -> Result<(), slint::PlatformError> {
                 }
             });
 
+            *control_clone.lock().unwrap() = None;
+
             // Update UI after test completion (back on main thread via Slint event loop)
             let _ = slint::invoke_from_event_loop(move || {
                  ui_handle_thread.unwrap().set_test_in_progress(false);
+                 ui_handle_thread.unwrap().set_test_paused(false);
                 // Update summary text view if needed, or rely on report.
                 if let Some(summary_data) = summary_clone.lock().unwrap().as_ref() {
                      ui_handle_thread.unwrap().set_results_summary(SharedString::from(format!("{:#?}", summary_data.overall_metrics)));
@@ -189,6 +223,91 @@ fn main() -> Result<(), slint::PlatformError> {
         });
     });
 
+    let ui_handle_pause = ui.as_weak();
+    ui.on_pause_resume_clicked(move || {
+        let ui = ui_handle_pause.unwrap();
+        if let Some(control) = active_control_pause.lock().unwrap().as_ref() {
+            if control.is_paused() {
+                control.resume();
+                ui.set_status_text("Test resumed.".into());
+            } else {
+                control.pause();
+                ui.set_status_text("Test paused (paused time is excluded from the duration).".into());
+            }
+            ui.set_test_paused(control.is_paused());
+        }
+    });
+
+    let ui_handle_stop = ui.as_weak();
+    ui.on_stop_clicked(move || {
+        if let Some(control) = active_control_stop.lock().unwrap().as_ref() {
+            control.cancel();
+            ui_handle_stop.unwrap().set_status_text("Stopping test...".into());
+        }
+    });
+
+    let ui_handle_combined = ui.as_weak();
+    ui.on_run_combined_clicked(move || {
+        let ui = ui_handle_combined.unwrap();
+        let config = match config_from_ui(&ui) {
+            Ok(config) => config,
+            Err(msg) => {
+                ui.set_status_text(msg.into());
+                return;
+            }
+        };
+        ui.set_test_in_progress(true);
+        ui.set_status_text("Running UDP test, then TCP test...".into());
+        ui.set_results_summary("".into());
+        ui.set_html_report_path("".into());
+
+        let ui_handle_thread = ui.as_weak();
+
+        thread::spawn(move || {
+            // A runtime that can't be built is shown like any other error below
+            let combined_result = netstats_core::runner::build_runtime(config.runtime_worker_threads)
+                .map_err(NetworkError::from)
+                .and_then(|rt| rt.block_on(netstats_core::runner::run_combined(&config)));
+
+            // One report per protocol; the comparison goes to the results view
+            let outcome = combined_result.map(|combined| {
+                let report_paths: Vec<String> = [&combined.udp, &combined.tcp]
+                    .into_iter()
+                    .filter_map(|summary| {
+                        match netstats_core::reporter::write_run_artifacts(summary, Path::new(REPORTS_DIR)) {
+                            Ok(artifacts) => Some(artifacts.path(ReportFormat::Html).unwrap_or(&artifacts.run_dir).display().to_string()),
+                            Err(e) => {
+                                eprintln!("Failed to write {} report artifacts: {}", summary.test_config.protocol, e);
+                                None
+                            }
+                        }
+                    })
+                    .collect();
+                (combined.comparison, report_paths)
+            });
+
+            let _ = slint::invoke_from_event_loop(move || {
+                let ui = ui_handle_thread.unwrap();
+                ui.set_test_in_progress(false);
+                match outcome {
+                    Ok((comparison, report_paths)) => {
+                        // "Open Last Report" opens the TCP one; both paths are listed in the status
+                        if let Some(last_path) = report_paths.last() {
+                            ui.set_html_report_path(last_path.clone().into());
+                        }
+                        ui.set_status_text(SharedString::from(format!("Combined test complete! Reports: {}", report_paths.join(", "))));
+                        ui.set_results_summary(comparison.into());
+                    }
+                    Err(e) => {
+                        let error_msg = format!("Combined Test Error: {:?}", e);
+                        ui.set_status_text(error_msg.clone().into());
+                        ui.set_results_summary(error_msg.into());
+                    }
+                }
+            });
+        });
+    });
+
     ui.on_open_report_clicked(move || {
         let ui = ui_handle.unwrap();
         let report_path = ui.get_html_report_path();
@@ -206,6 +325,14 @@ fn main() -> Result<(), slint::PlatformError> {
 
     ui.on_run_benchmark_clicked(move || {
         let ui = ui_handle.unwrap();
+        // Benchmarks the selected protocol over loopback, on the configured runtime
+        let config = match config_from_ui(&ui) {
+            Ok(config) => config,
+            Err(msg) => {
+                ui.set_status_text(msg.into());
+                return;
+            }
+        };
         ui.set_test_in_progress(true);
         ui.set_status_text("Running benchmark...".into());
         ui.set_results_summary("".into());
@@ -214,32 +341,43 @@ fn main() -> Result<(), slint::PlatformError> {
         let ui_handle_thread = ui.as_weak();
 
         thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().unwrap();
             let benchmark_duration_secs = 10; // Standard duration for this benchmark
             let benchmark_packet_payload_size = 64;   // Standard small packet size
 
-            let benchmark_result = rt.block_on(async {
-                netstats_core::benchmark::run_udp_loopback_benchmark(
-                    benchmark_duration_secs,
-                    benchmark_packet_payload_size,
-                )
-                .await
-            });
+            // Errors (runtime creation included) are shown in the UI below
+            let benchmark_result = netstats_core::benchmark::run_loopback_benchmark_blocking(
+                config.protocol,
+                benchmark_duration_secs,
+                benchmark_packet_payload_size,
+                config.runtime_worker_threads,
+            );
 
             let _ = slint::invoke_from_event_loop(move || {
                 let ui = ui_handle_thread.unwrap();
                 ui.set_test_in_progress(false);
                 match benchmark_result {
                     Ok(summary) => {
+                        let rtt = summary.avg_rtt_micros.map_or("N/A".to_string(), |rtt| format!("{:.1} us", rtt));
+                        let sample_mbps = summary.server_bandwidth_samples.iter().map(|(_, mbps)| *mbps);
+                        let min_mbps = sample_mbps.clone().reduce(f64::min).unwrap_or(0.0);
+                        let max_mbps = sample_mbps.reduce(f64::max).unwrap_or(0.0);
                         let result_text = format!(
-                            "Benchmark Complete ({}s, {}B payload):\nClient Sent: {} packets ({:.2} PPS)\nServer Received: {} packets ({:.2} PPS)\nServer Throughput: {:.2} Mbps",
+                            "{} Benchmark Complete ({}s, {}B payload):\nClient Sent: {} packets ({:.2} PPS)\nServer Received: {} packets ({:.2} PPS)\nServer Throughput: {:.2} Mbps ({} bytes of framing)\nLoss: {:.2}%, Avg RTT: {}\nServer Throughput Range: {:.2} - {:.2} Mbps over {} samples\nRuntime: {} worker threads",
+                            summary.protocol,
                             summary.duration_secs,
                             summary.packet_payload_size_bytes,
                             summary.client_packets_sent,
                             summary.client_pps,
                             summary.server_packets_received,
                             summary.server_pps,
-                            summary.server_mbps
+                            summary.server_mbps,
+                            summary.framing_bytes,
+                            summary.loss_percentage,
+                            rtt,
+                            min_mbps,
+                            max_mbps,
+                            summary.server_bandwidth_samples.len(),
+                            summary.runtime_worker_threads
                         );
                         ui.set_status_text("Benchmark complete!".into());
                         ui.set_results_summary(result_text.into());